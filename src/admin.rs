@@ -0,0 +1,354 @@
+use std::fmt::Write as _;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::dedup::{Dedup, DedupFilter, DedupMode};
+use crate::error::ChatError;
+use crate::permissions::Role;
+use crate::server::{AsyncFilter, Server};
+use crate::settings;
+use crate::wordlist::WordFilter;
+
+const HELP: &str = "users, kick <user> [reason], notice <text>, reload, reload config, stats, \
+                    filters <room>, filter add <room> <name> <kind> [args], \
+                    filter remove <room> <name>, tokens, token issue <user> [role], \
+                    token revoke <user>, help, quit";
+
+const TOKEN_USAGE: &str = "usage: token issue <user> [guest|user|op|admin] | token revoke <user>";
+
+const FILTER_USAGE: &str = "usage: filter add <room> <name> words <word>... \
+                            | filter add <room> <name> dedup <secs> \
+                            | filter remove <room> <name>";
+
+/// What the admin console understands.
+///
+/// Not chat commands: the console isn't a user, sits in no room and
+/// needs no `/oper`. Whoever can open the socket is trusted, which is
+/// why it's a Unix socket only its owner may open.
+enum AdminCommand {
+    /// Everyone connected, with address, role and rooms.
+    Users,
+    Kick {
+        target: String,
+        reason: Option<String>,
+    },
+    /// A line to every connected user.
+    Notice(String),
+    /// Re-read the accounts and bans from storage.
+    Reload,
+    /// Re-read the config file.
+    ReloadConfig,
+    /// A room's own filters, by name.
+    Filters(String),
+    /// Add a filter to one room: `kind` and `args` say which and how.
+    AddFilter {
+        room: String,
+        name: String,
+        kind: String,
+        args: Vec<String>,
+    },
+    RemoveFilter {
+        room: String,
+        name: String,
+    },
+    /// Every token's user and role. Never the tokens: only digests are
+    /// kept.
+    Tokens,
+    /// A token for a bot to sign in with, shown this once.
+    IssueToken {
+        username: String,
+        role: Role,
+    },
+    RevokeTokens(String),
+    Stats,
+    Help,
+    Quit,
+}
+
+impl AdminCommand {
+    fn parse(line: &str) -> Result<Self, String> {
+        let (word, args) = line
+            .split_once(' ')
+            .map(|(word, args)| (word, args.trim()))
+            .unwrap_or((line, ""));
+        match word {
+            "users" => Ok(AdminCommand::Users),
+            "kick" if !args.is_empty() => {
+                let (target, reason) = match args.split_once(' ') {
+                    Some((target, reason)) => (target, Some(reason.trim().to_string())),
+                    None => (args, None),
+                };
+                Ok(AdminCommand::Kick {
+                    target: target.to_string(),
+                    reason,
+                })
+            }
+            "kick" => Err("usage: kick <user> [reason]".into()),
+            "notice" if !args.is_empty() => Ok(AdminCommand::Notice(args.to_string())),
+            "notice" => Err("usage: notice <text>".into()),
+            "reload" if args == "config" => Ok(AdminCommand::ReloadConfig),
+            "reload" => Ok(AdminCommand::Reload),
+            "filters" if !args.is_empty() => Ok(AdminCommand::Filters(room_arg(args))),
+            "filters" => Err("usage: filters <room>".into()),
+            "filter" => {
+                let words: Vec<&str> = args.split_whitespace().collect();
+                match words.as_slice() {
+                    ["add", room, name, kind, rest @ ..] => Ok(AdminCommand::AddFilter {
+                        room: room_arg(room),
+                        name: name.to_string(),
+                        kind: kind.to_string(),
+                        args: rest.iter().map(|arg| arg.to_string()).collect(),
+                    }),
+                    ["remove", room, name] => Ok(AdminCommand::RemoveFilter {
+                        room: room_arg(room),
+                        name: name.to_string(),
+                    }),
+                    _ => Err(FILTER_USAGE.into()),
+                }
+            }
+            "tokens" => Ok(AdminCommand::Tokens),
+            "token" => {
+                let words: Vec<&str> = args.split_whitespace().collect();
+                match words.as_slice() {
+                    ["issue", user, rest @ ..] if rest.len() <= 1 => {
+                        // A bot is a user unless said otherwise.
+                        let role = match rest.first() {
+                            Some(role) => Role::parse(role).ok_or(TOKEN_USAGE)?,
+                            None => Role::User,
+                        };
+                        Ok(AdminCommand::IssueToken {
+                            username: user.to_string(),
+                            role,
+                        })
+                    }
+                    ["revoke", user] => Ok(AdminCommand::RevokeTokens(user.to_string())),
+                    _ => Err(TOKEN_USAGE.into()),
+                }
+            }
+            "stats" => Ok(AdminCommand::Stats),
+            "help" => Ok(AdminCommand::Help),
+            "quit" => Ok(AdminCommand::Quit),
+            other => Err(format!("unknown command: {other} (try help)")),
+        }
+    }
+}
+
+/// Rooms may be named with their `#` or without.
+fn room_arg(room: &str) -> String {
+    room.trim_start_matches('#').to_string()
+}
+
+/// Build a room filter from its console description. Only kinds that
+/// can be described in a line of text: a script filter, say, lives in
+/// its own file and is server-wide.
+fn make_filter(kind: &str, args: &[String]) -> Result<Box<dyn AsyncFilter>, String> {
+    match (kind, args) {
+        ("words", []) => Err("words needs at least one word".into()),
+        ("words", words) => Ok(Box::new(WordFilter::new(words))),
+        ("dedup", [secs]) => {
+            let secs = secs
+                .parse()
+                .map_err(|_| format!("not a number of seconds: {secs}"))?;
+            Ok(Box::new(DedupFilter::new(Dedup {
+                window: Duration::from_secs(secs),
+                mode: DedupMode::Drop,
+            })))
+        }
+        _ => Err(FILTER_USAGE.into()),
+    }
+}
+
+/// Open the console's socket at `path`. Done before the server starts
+/// so a bad path stops it, rather than leaving it running without one.
+pub fn bind(path: &Path) -> Result<UnixListener, ChatError> {
+    // A socket left behind by the last run would make bind fail. Only a
+    // socket, though: a typo in the config mustn't delete someone's file.
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    info!(path = %path.display(), "admin console listening");
+    Ok(listener)
+}
+
+/// Accept console sessions until the server stops.
+pub async fn serve(server: Arc<Mutex<Server>>, listener: UnixListener) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(session(Arc::clone(&server), stream));
+            }
+            Err(e) => warn!(error = %e, "admin console accept failed"),
+        }
+    }
+}
+
+/// One operator at the console: a command per line, the answer after
+/// it. Plain text, for `nc -U` or `socat` as much as for scripts.
+async fn session(server: Arc<Mutex<Server>>, stream: UnixStream) {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let reply = match AdminCommand::parse(line) {
+            Ok(AdminCommand::Quit) => break,
+            Ok(command) => run(&server, command).await,
+            Err(e) => format!("error: {e}\n"),
+        };
+        if write.write_all(reply.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn run(server: &Arc<Mutex<Server>>, command: AdminCommand) -> String {
+    let mut srv = server.lock().await;
+    let mut out = String::new();
+    match command {
+        AdminCommand::Users => {
+            let users = srv.users();
+            let _ = writeln!(out, "{} online", users.len());
+            for user in users {
+                let rooms: Vec<String> = user.rooms.iter().map(|r| format!("#{r}")).collect();
+                let _ = writeln!(
+                    out,
+                    "{}  {}  {}  {}  {}",
+                    user.user_id,
+                    user.username,
+                    user.peer,
+                    user.role,
+                    rooms.join(" ")
+                );
+            }
+        }
+        AdminCommand::Kick { target, reason } => {
+            let reason = reason.unwrap_or_else(|| "no reason given".to_string());
+            if srv.disconnect_user(&target, &reason) {
+                info!(user = %target, %reason, "disconnected from the admin console");
+                let _ = writeln!(out, "disconnected {target}");
+            } else {
+                let _ = writeln!(out, "error: nobody called {target} is online");
+            }
+        }
+        AdminCommand::Notice(text) => {
+            let sent = srv.notice_all(&text);
+            let _ = writeln!(out, "sent to {sent} users");
+        }
+        AdminCommand::Reload => match srv.reload_storage() {
+            Ok(()) => out.push_str("reloaded accounts and bans\n"),
+            Err(e) => {
+                let _ = writeln!(out, "error: {e}");
+            }
+        },
+        AdminCommand::ReloadConfig => {
+            out.push_str(&settings::describe_reload(srv.reload_config()));
+        }
+        AdminCommand::Filters(room) => match srv.room_filters(&room) {
+            Ok(names) if names.is_empty() => {
+                let _ = writeln!(out, "#{room} has no filters of its own");
+            }
+            Ok(names) => {
+                let _ = writeln!(out, "#{room}: {}", names.join(", "));
+            }
+            Err(e) => {
+                let _ = writeln!(out, "error: {e}");
+            }
+        },
+        AdminCommand::AddFilter {
+            room,
+            name,
+            kind,
+            args,
+        } => {
+            let added = make_filter(&kind, &args).and_then(|filter| {
+                srv.add_room_filter(&room, &name, filter)
+                    .map_err(|e| e.to_string())
+            });
+            match added {
+                Ok(true) => {
+                    info!(%room, filter = %name, %kind, "room filter added from the admin console");
+                    let _ = writeln!(out, "added {name} to #{room}");
+                }
+                Ok(false) => {
+                    let _ = writeln!(out, "error: #{room} already has a filter called {name}");
+                }
+                Err(e) => {
+                    let _ = writeln!(out, "error: {e}");
+                }
+            }
+        }
+        AdminCommand::RemoveFilter { room, name } => match srv.remove_room_filter(&room, &name) {
+            Ok(true) => {
+                info!(%room, filter = %name, "room filter removed from the admin console");
+                let _ = writeln!(out, "removed {name} from #{room}");
+            }
+            Ok(false) => {
+                let _ = writeln!(out, "error: #{room} has no filter called {name}");
+            }
+            Err(e) => {
+                let _ = writeln!(out, "error: {e}");
+            }
+        },
+        AdminCommand::Tokens => {
+            let grants = srv.token_grants();
+            let _ = writeln!(out, "{} tokens", grants.len());
+            for grant in grants {
+                let _ = writeln!(out, "{}  {}", grant.username, grant.role);
+            }
+        }
+        AdminCommand::IssueToken { username, role } => {
+            match srv.issue_token(&username, role).await {
+                Ok(token) => {
+                    let _ = writeln!(out, "token for {username} ({role}): {token}");
+                    out.push_str("it won't be shown again\n");
+                }
+                Err(e) => {
+                    let _ = writeln!(out, "error: {e}");
+                }
+            }
+        }
+        AdminCommand::RevokeTokens(username) => match srv.revoke_tokens(&username).await {
+            Ok(0) => {
+                let _ = writeln!(out, "error: {username} has no tokens");
+            }
+            Ok(revoked) => {
+                let _ = writeln!(out, "revoked {revoked} tokens for {username}");
+            }
+            Err(e) => {
+                let _ = writeln!(out, "error: {e}");
+            }
+        },
+        AdminCommand::Stats => {
+            let users = srv.users().len();
+            let rooms = srv.all_room_stats().await;
+            let _ = writeln!(out, "{users} users online, {} rooms", rooms.len());
+            for stats in rooms {
+                let _ = writeln!(
+                    out,
+                    "#{}  {} here  {} messages  {} in the last hour  {} in the last day",
+                    stats.room,
+                    stats.members_now,
+                    stats.total_messages,
+                    stats.last_hour.messages,
+                    stats.last_day.messages
+                );
+            }
+        }
+        AdminCommand::Help => {
+            let _ = writeln!(out, "{HELP}");
+        }
+        AdminCommand::Quit => {}
+    }
+    out
+}
@@ -0,0 +1,243 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::filter::{FilterAction, FilterContext};
+use crate::trust;
+use crate::types::UserId;
+
+/// Once this many senders are remembered, quiet ones are swept.
+const PRUNE_AT: usize = 1_000;
+
+/// Settings for the built-in spam filter. Each check can be turned off
+/// on its own by setting its limit to zero.
+///
+///   .enable_antispam(AntiSpamConfig {
+///       max_mentions: 3,
+///       ..AntiSpamConfig::default()
+///   })
+#[derive(Debug, Clone, Copy)]
+pub struct AntiSpamConfig {
+    /// The same message this many times within `repeat_window` is spam,
+    /// however much else was said in between.
+    pub max_repeats: usize,
+    pub repeat_window: Duration,
+    /// Share of capital letters, out of all letters, that counts as
+    /// shouting. Only checked once a message has `caps_min_letters`:
+    /// "OK" and "NASA" are fine.
+    pub caps_ratio: f32,
+    pub caps_min_letters: usize,
+    /// Most links in one message.
+    pub max_links: usize,
+    /// Most `@name` mentions in one message.
+    pub max_mentions: usize,
+    /// Strikes answered with a warning, before any mute.
+    pub warnings: u32,
+    /// Strikes after the warnings answered with a mute, before a kick.
+    pub mutes: u32,
+    pub mute_for: Duration,
+    /// A sender with no strike for this long starts again from a
+    /// warning.
+    pub forget_after: Duration,
+}
+
+impl Default for AntiSpamConfig {
+    fn default() -> Self {
+        Self {
+            max_repeats: 3,
+            repeat_window: Duration::from_secs(60),
+            caps_ratio: 0.7,
+            caps_min_letters: 12,
+            max_links: 3,
+            max_mentions: 5,
+            warnings: 1,
+            mutes: 1,
+            mute_for: Duration::from_secs(5 * 60),
+            forget_after: Duration::from_secs(30 * 60),
+        }
+    }
+}
+
+/// Which heuristic a message tripped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Spam {
+    Repeated,
+    Caps,
+    Links,
+    Mentions,
+}
+
+impl Spam {
+    /// Told to the sender with the block, and with any penalty.
+    pub fn reason(self) -> &'static str {
+        match self {
+            Spam::Repeated => "the same message over and over",
+            Spam::Caps => "too many capital letters",
+            Spam::Links => "too many links",
+            Spam::Mentions => "too many mentions",
+        }
+    }
+}
+
+/// What a strike earns, worst last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Penalty {
+    Warn,
+    Mute(Duration),
+    Kick,
+}
+
+/// One caught message, for the server to act on: a filter can refuse a
+/// message, but only the server can mute or disconnect someone.
+#[derive(Debug)]
+pub struct Strike {
+    pub user_id: UserId,
+    pub spam: Spam,
+    pub penalty: Penalty,
+}
+
+/// What's remembered per sender.
+#[derive(Default)]
+struct Record {
+    /// Their recent messages, lowercased, oldest first.
+    recent: VecDeque<(String, Instant)>,
+    strikes: u32,
+    last_strike: Option<Instant>,
+}
+
+/// Catches the common kinds of spam: the same line pasted again and
+/// again, shouting, walls of links, and pinging half the room.
+///
+/// Unlike DedupFilter, which only compares a message with the one just
+/// before it, this remembers a short window of what each sender said,
+/// and counts strikes against them. The penalty grows with each: a
+/// warning, then a mute, then a kick. Every caught message is blocked,
+/// whatever the penalty.
+pub struct AntiSpam {
+    config: AntiSpamConfig,
+    senders: HashMap<String, Record>,
+}
+
+impl AntiSpam {
+    pub fn new(config: AntiSpamConfig) -> Self {
+        Self {
+            config,
+            senders: HashMap::new(),
+        }
+    }
+
+    /// Check one message from `username`. Spam comes back with what
+    /// this strike earns them.
+    pub fn check(&mut self, username: &str, body: &str) -> Option<(Spam, Penalty)> {
+        let now = Instant::now();
+        if self.senders.len() >= PRUNE_AT {
+            let keep = self.config.repeat_window.max(self.config.forget_after);
+            self.senders.retain(|_, record| {
+                let last_said = record.recent.back().map(|&(_, at)| at);
+                last_said
+                    .max(record.last_strike)
+                    .is_some_and(|at| now - at < keep)
+            });
+        }
+
+        let config = self.config;
+        let record = self.senders.entry(username.to_string()).or_default();
+        let spam = remember(record, &config, body, now).or_else(|| shape(&config, body))?;
+
+        if record
+            .last_strike
+            .is_some_and(|at| now - at >= config.forget_after)
+        {
+            record.strikes = 0;
+        }
+        record.strikes += 1;
+        record.last_strike = Some(now);
+        let penalty = if record.strikes <= config.warnings {
+            Penalty::Warn
+        } else if record.strikes <= config.warnings + config.mutes {
+            Penalty::Mute(config.mute_for)
+        } else {
+            Penalty::Kick
+        };
+        Some((spam, penalty))
+    }
+}
+
+/// Add `body` to what the sender said lately, and say whether it's one
+/// repeat too many.
+fn remember(
+    record: &mut Record,
+    config: &AntiSpamConfig,
+    body: &str,
+    now: Instant,
+) -> Option<Spam> {
+    if config.max_repeats == 0 {
+        return None;
+    }
+    let window = config.repeat_window;
+    while record
+        .recent
+        .front()
+        .is_some_and(|&(_, at)| now - at >= window)
+    {
+        record.recent.pop_front();
+    }
+    let body = body.trim().to_lowercase();
+    let repeats = record
+        .recent
+        .iter()
+        .filter(|(said, _)| *said == body)
+        .count()
+        + 1;
+    record.recent.push_back((body, now));
+    (repeats >= config.max_repeats).then_some(Spam::Repeated)
+}
+
+/// The checks that need only the message itself.
+fn shape(config: &AntiSpamConfig, body: &str) -> Option<Spam> {
+    let words = || body.split_whitespace();
+    let links = words().filter(|word| trust::has_link(word)).count();
+    if config.max_links > 0 && links > config.max_links {
+        return Some(Spam::Links);
+    }
+    let mentions = words()
+        .filter(|word| word.len() > 1 && word.starts_with('@'))
+        .count();
+    if config.max_mentions > 0 && mentions > config.max_mentions {
+        return Some(Spam::Mentions);
+    }
+    let (letters, upper) = body
+        .chars()
+        .filter(|c| c.is_alphabetic())
+        .fold((0, 0), |(letters, upper), c| {
+            (letters + 1, upper + usize::from(c.is_uppercase()))
+        });
+    if config.caps_min_letters > 0
+        && letters >= config.caps_min_letters
+        && upper as f32 >= letters as f32 * config.caps_ratio
+    {
+        return Some(Spam::Caps);
+    }
+    None
+}
+
+/// The filter for a FilterRegistry: blocks what `AntiSpam` catches, and
+/// hands the strike to the server through `strikes`.
+pub fn filter(
+    config: AntiSpamConfig,
+    strikes: UnboundedSender<Strike>,
+) -> impl FnMut(&FilterContext<'_>, &str) -> FilterAction + Send + 'static {
+    let mut antispam = AntiSpam::new(config);
+    move |ctx, body| {
+        let Some((spam, penalty)) = antispam.check(ctx.username, body) else {
+            return FilterAction::Allow;
+        };
+        let _ = strikes.send(Strike {
+            user_id: ctx.sender,
+            spam,
+            penalty,
+        });
+        FilterAction::Block(spam.reason().to_string())
+    }
+}
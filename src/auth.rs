@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::num::NonZeroU32;
+
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::error::ChatError;
+
+static ALGORITHM: pbkdf2::Algorithm = pbkdf2::PBKDF2_HMAC_SHA256;
+
+/// PBKDF2 rounds for new passwords. Slow on purpose: it's what makes a
+/// stolen accounts file expensive to crack. Stored with each hash, so
+/// raising it later doesn't lock anyone out.
+const ITERATIONS: u32 = 100_000;
+
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+
+/// Shortest password `REGISTER:` accepts.
+pub const MIN_PASSWORD: usize = 8;
+
+/// A salted password hash. Never the password itself.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    iterations: NonZeroU32,
+    salt: [u8; SALT_LEN],
+    hash: [u8; HASH_LEN],
+}
+
+impl Credentials {
+    /// Hash `password` with a fresh random salt.
+    ///
+    /// Deliberately slow — call it off the async threads, with
+    /// `spawn_blocking`.
+    pub fn new(password: &str) -> Result<Self, ChatError> {
+        let mut salt = [0u8; SALT_LEN];
+        SystemRandom::new()
+            .fill(&mut salt)
+            .map_err(|_| ChatError::Config("no randomness for a password salt".into()))?;
+        let iterations = NonZeroU32::new(ITERATIONS).expect("ITERATIONS is not zero");
+        let mut hash = [0u8; HASH_LEN];
+        pbkdf2::derive(ALGORITHM, iterations, &salt, password.as_bytes(), &mut hash);
+        Ok(Self {
+            iterations,
+            salt,
+            hash,
+        })
+    }
+
+    /// Whether `password` is the one these were made from. As slow as
+    /// `new`, and for the same reason.
+    pub fn verify(&self, password: &str) -> bool {
+        pbkdf2::verify(
+            ALGORITHM,
+            self.iterations,
+            &self.salt,
+            password.as_bytes(),
+            &self.hash,
+        )
+        .is_ok()
+    }
+
+    /// The credentials as text: `iterations:salt:hash`, the last two in
+    /// hex. What a storage backend keeps.
+    pub fn encode(&self) -> String {
+        format!(
+            "{}:{}:{}",
+            self.iterations,
+            hex(&self.salt),
+            hex(&self.hash)
+        )
+    }
+
+    pub fn decode(text: &str) -> Option<Self> {
+        let mut fields = text.split(':');
+        let iterations = NonZeroU32::new(fields.next()?.parse().ok()?)?;
+        let salt = unhex(fields.next()?)?;
+        let hash = unhex(fields.next()?)?;
+        if fields.next().is_some() {
+            return None;
+        }
+        Some(Self {
+            iterations,
+            salt,
+            hash,
+        })
+    }
+
+    /// One line of the accounts file: the name, then `encode`.
+    pub fn to_line(&self, name: &str) -> String {
+        format!("{name}:{}\n", self.encode())
+    }
+
+    pub fn from_line(line: &str) -> Option<(String, Self)> {
+        let (name, credentials) = line.split_once(':')?;
+        if name.is_empty() {
+            return None;
+        }
+        Some((name.to_string(), Self::decode(credentials)?))
+    }
+}
+
+/// Registered accounts by username.
+///
+/// Only the running server's copy: keeping them across a restart is
+/// the storage backend's job (see `Storage`), which fills this at
+/// startup and hears of each registration.
+pub struct Accounts {
+    accounts: HashMap<String, Credentials>,
+}
+
+impl Accounts {
+    pub fn new() -> Self {
+        Self {
+            accounts: HashMap::new(),
+        }
+    }
+
+    /// The accounts a storage backend loaded.
+    pub fn from_list(list: Vec<(String, Credentials)>) -> Self {
+        Self {
+            accounts: list.into_iter().collect(),
+        }
+    }
+
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.accounts.contains_key(name)
+    }
+
+    /// A copy of `name`'s credentials, to check a password against
+    /// without holding the server lock.
+    pub fn credentials(&self, name: &str) -> Option<Credentials> {
+        self.accounts.get(name).cloned()
+    }
+
+    /// Add an account. The caller has stored it already.
+    pub fn register(&mut self, name: &str, credentials: Credentials) {
+        self.accounts.insert(name.to_string(), credentials);
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(text, "{byte:02x}");
+    }
+    text
+}
+
+fn unhex<const N: usize>(text: &str) -> Option<[u8; N]> {
+    if text.len() != N * 2 {
+        return None;
+    }
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(text.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(bytes)
+}
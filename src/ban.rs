@@ -0,0 +1,103 @@
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A ban: the name and address the user had when an operator banned them.
+#[derive(Debug, Clone)]
+pub struct Ban {
+    pub username: String,
+    pub ip: IpAddr,
+    pub reason: String,
+    pub by: String,
+    pub at: SystemTime,
+}
+
+/// What a new connection has in common with the ban list.
+pub enum BanMatch<'a> {
+    Clear,
+    /// The banned name itself. Always refused.
+    Name(&'a Ban),
+    /// A different name from a banned address — likely the same person
+    /// back under a new nick, but a shared address (a campus, a café)
+    /// can be innocent, so this only alerts unless configured otherwise.
+    Address(&'a Ban),
+}
+
+impl Ban {
+    /// Seconds since the Unix epoch, for storage.
+    pub fn timestamp(&self) -> u64 {
+        self.at
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+
+    /// One line of the bans file, tab-separated, reason last:
+    /// `name  ip  seconds  by  reason`.
+    pub fn to_line(&self) -> String {
+        let at = self.timestamp();
+        let reason = self.reason.replace(['\n', '\r'], " ");
+        format!(
+            "{}\t{}\t{at}\t{}\t{reason}\n",
+            self.username, self.ip, self.by
+        )
+    }
+
+    pub fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(5, '\t');
+        let username = fields.next()?.to_string();
+        let ip = fields.next()?.parse().ok()?;
+        let at = UNIX_EPOCH + Duration::from_secs(fields.next()?.parse().ok()?);
+        let by = fields.next()?.to_string();
+        let reason = fields.next()?.to_string();
+        (!username.is_empty()).then_some(Self {
+            username,
+            ip,
+            reason,
+            by,
+            at,
+        })
+    }
+}
+
+/// Everyone banned. The running server's copy, like `Accounts`: the
+/// storage backend fills it at startup and is handed the whole list
+/// after every change.
+pub struct BanList {
+    bans: Vec<Ban>,
+}
+
+impl BanList {
+    pub fn new() -> Self {
+        Self { bans: Vec::new() }
+    }
+
+    /// The bans a storage backend loaded.
+    pub fn from_list(bans: Vec<Ban>) -> Self {
+        Self { bans }
+    }
+
+    pub fn bans(&self) -> &[Ban] {
+        &self.bans
+    }
+
+    pub fn add(&mut self, ban: Ban) {
+        self.bans.push(ban);
+    }
+
+    /// Lift the ban on `username`. Returns false if there wasn't one.
+    pub fn remove(&mut self, username: &str) -> bool {
+        let before = self.bans.len();
+        self.bans.retain(|ban| ban.username != username);
+        self.bans.len() != before
+    }
+
+    pub fn check(&self, username: &str, ip: IpAddr) -> BanMatch<'_> {
+        if let Some(ban) = self.bans.iter().find(|ban| ban.username == username) {
+            return BanMatch::Name(ban);
+        }
+        match self.bans.iter().find(|ban| ban.ip == ip) {
+            Some(ban) => BanMatch::Address(ban),
+            None => BanMatch::Clear,
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc};
+
+use crate::server::Event;
+use crate::types::UserId;
+
+/// What a bot asks the server to do on its behalf.
+#[derive(Debug)]
+pub enum BotAction {
+    /// Say something in a room, or in the bot's current one.
+    Say {
+        room: Option<String>,
+        body: String,
+    },
+    Join {
+        room: String,
+    },
+    Leave {
+        room: String,
+    },
+    Msg {
+        to: String,
+        body: String,
+    },
+}
+
+/// A user with no connection behind it, driven from code.
+///
+/// On the server's side a bot is a client like any other: it has a
+/// name, sits in rooms, shows up in /users and /who, and is sent the
+/// same events. What would be written to a socket is read here with
+/// `recv` instead, and what would be typed goes in through `say`,
+/// `join` and the rest.
+///
+/// Made with `Server::add_bot` before the server runs. Dropping the
+/// Bot signs it off, as if its connection had closed.
+pub struct Bot {
+    pub user_id: UserId,
+    pub name: String,
+    events: broadcast::Receiver<Event>,
+    actions: mpsc::UnboundedSender<BotAction>,
+}
+
+impl Bot {
+    pub fn new(
+        user_id: UserId,
+        name: String,
+        events: broadcast::Receiver<Event>,
+        actions: mpsc::UnboundedSender<BotAction>,
+    ) -> Self {
+        Self {
+            user_id,
+            name,
+            events,
+            actions,
+        }
+    }
+
+    /// The next thing the bot would have been shown: messages in its
+    /// rooms, DMs, replies to what it did. Events it was too slow for
+    /// are skipped. `None` once it's been signed off: kicked, or the
+    /// server going down.
+    pub async fn recv(&mut self) -> Option<Event> {
+        loop {
+            match self.events.recv().await {
+                Ok(Event::Close(_)) | Err(RecvError::Closed) => return None,
+                Ok(event) => return Some(event),
+                Err(RecvError::Lagged(_)) => continue,
+            }
+        }
+    }
+
+    /// Say `body` in the room the bot is talking in.
+    pub fn say(&self, body: impl Into<String>) {
+        self.act(BotAction::Say {
+            room: None,
+            body: body.into(),
+        });
+    }
+
+    /// Say `body` in `room`, which the bot must have joined.
+    pub fn say_in(&self, room: impl Into<String>, body: impl Into<String>) {
+        self.act(BotAction::Say {
+            room: Some(room.into()),
+            body: body.into(),
+        });
+    }
+
+    /// Join `room`, making it if need be, and talk there from now on.
+    pub fn join(&self, room: impl Into<String>) {
+        self.act(BotAction::Join { room: room.into() });
+    }
+
+    pub fn leave(&self, room: impl Into<String>) {
+        self.act(BotAction::Leave { room: room.into() });
+    }
+
+    /// A direct message, like `/msg`.
+    pub fn msg(&self, to: impl Into<String>, body: impl Into<String>) {
+        self.act(BotAction::Msg {
+            to: to.into(),
+            body: body.into(),
+        });
+    }
+
+    /// Actions are queued and done in order. One the server can't do
+    /// comes back through `recv` as an error, as it would to a person.
+    fn act(&self, action: BotAction) {
+        // Only fails once the server has stopped: nothing to do then.
+        let _ = self.actions.send(action);
+    }
+}
@@ -0,0 +1,99 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+
+use crate::hooks::DisconnectReason;
+use crate::types::{RoomId, UserId};
+
+/// Something that happened inside the server.
+///
+/// `server::Event` is what a *client* sees on its socket. A ServerEvent
+/// is what the rest of the program sees: plugins, metrics, persistence.
+/// Events are published after the fact and carry owned data, so a
+/// subscriber never needs the server lock to make sense of one.
+#[derive(Debug, Clone)]
+pub enum ServerEvent {
+    UserConnected {
+        user_id: UserId,
+        username: String,
+        peer: SocketAddr,
+    },
+    UserDisconnected {
+        user_id: UserId,
+        username: String,
+        session: Duration,
+        reason: DisconnectReason,
+    },
+    RoomCreated {
+        room_id: RoomId,
+        name: String,
+    },
+    /// An empty room expired. An archived one may be created again.
+    RoomRemoved {
+        room_id: RoomId,
+        name: String,
+        archived: bool,
+    },
+    UserJoined {
+        user_id: UserId,
+        username: String,
+        room: String,
+    },
+    UserLeft {
+        user_id: UserId,
+        username: String,
+        room: String,
+    },
+    NickChanged {
+        user_id: UserId,
+        old: String,
+        new: String,
+    },
+    MessageBroadcast {
+        room_id: RoomId,
+        room: String,
+        from: String,
+        body: String,
+    },
+    UserKicked {
+        user_id: UserId,
+        username: String,
+        room: String,
+        by: String,
+    },
+    UserMuted {
+        user_id: UserId,
+        username: String,
+        duration: Duration,
+    },
+    UserUnmuted {
+        user_id: UserId,
+        username: String,
+    },
+}
+
+/// Pub/sub fan-out for ServerEvents.
+///
+/// Built on tokio's broadcast channel: every subscriber gets every event,
+/// and a subscriber that falls too far behind sees `RecvError::Lagged`
+/// instead of slowing the server down.
+pub struct EventBus {
+    tx: broadcast::Sender<ServerEvent>,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity);
+        Self { tx }
+    }
+
+    /// Publish an event. Having no subscribers is fine — the event is dropped.
+    pub fn publish(&self, event: ServerEvent) {
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ServerEvent> {
+        self.tx.subscribe()
+    }
+}
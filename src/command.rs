@@ -1,5 +1,10 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
 use crate::error::ChatError;
-use crate::types::RoomId;
+use crate::invite;
+use crate::poll::{self, MAX_OPTIONS, MIN_OPTIONS};
+use crate::types::{RoomId, UserId};
 
 /// Commands are a closed set — we know every variant at compile time.
 /// Enum dispatch: match on variants, no vtable, no dynamic dispatch.
@@ -7,25 +12,349 @@ use crate::types::RoomId;
 /// tells you if you miss a case.
 #[derive(Debug)]
 pub enum Command {
-    Join { room: String },
+    Join {
+        room: String,
+        password: Option<String>,
+    },
+    Leave { room: Option<String> },
+    Switch { room: String },
     Nick { name: String },
-    Kick { target: String },
+    Kick {
+        target: String,
+        reason: Option<String>,
+    },
+    Mute {
+        target: String,
+        duration: Duration,
+    },
+    Op {
+        target: String,
+    },
+    Deop {
+        target: String,
+    },
+    Topic {
+        text: Option<String>,
+    },
+    SetPass {
+        password: Option<String>,
+    },
+    Set {
+        setting: Setting,
+        on: bool,
+    },
+    Oper {
+        password: String,
+    },
+    Drain,
+    Shutdown,
+    Stats {
+        room: Option<String>,
+    },
+    Ban {
+        target: String,
+        reason: Option<String>,
+    },
+    Unban {
+        target: String,
+    },
+    RoomBan {
+        target: String,
+        reason: Option<String>,
+    },
+    RoomUnban {
+        target: String,
+    },
+    ShadowMute {
+        target: String,
+    },
+    Unshadowmute {
+        target: String,
+    },
+    Poll {
+        question: String,
+        options: Vec<String>,
+    },
+    ClosePoll,
+    Vote {
+        choice: usize,
+    },
+    Remind {
+        target: RemindTarget,
+        after: Duration,
+        when: String,
+        text: String,
+    },
+    InviteCode {
+        room: String,
+        uses: u32,
+        ttl: Duration,
+    },
+    Msg {
+        target: String,
+        body: String,
+    },
+    Ignore {
+        target: Option<String>,
+    },
+    Unignore {
+        target: String,
+    },
+    Away {
+        message: Option<String>,
+    },
+    Back,
+    Who {
+        room: Option<String>,
+    },
     Quit,
     Help,
-    List,
+    Lang {
+        code: Option<String>,
+    },
+    List {
+        pattern: Option<String>,
+    },
+    /// `room` is None for the room the user is talking in.
+    Edit {
+        room: Option<String>,
+        id: u64,
+        body: String,
+    },
+    Delete {
+        room: Option<String>,
+        id: u64,
+    },
+}
+
+/// Who a `/remind` is for.
+#[derive(Debug, Clone)]
+pub enum RemindTarget {
+    /// The user who set it, wherever they are when it fires.
+    Me,
+    /// Everyone in a room, by name.
+    Room(String),
+}
+
+/// Per-connection preferences a user can flip with `/set`.
+#[derive(Debug, Clone, Copy)]
+pub enum Setting {
+    /// Hide join/leave/nick announcements.
+    Quiet,
+    /// Colour names, system lines and mentions with ANSI codes.
+    Color,
+    /// Be sent NOTIFY for mentions in rooms you're not in, too.
+    Mentions,
+}
+
+impl Setting {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "quiet" => Some(Setting::Quiet),
+            "color" => Some(Setting::Color),
+            "mentions" => Some(Setting::Mentions),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Setting::Quiet => "quiet",
+            Setting::Color => "color",
+            Setting::Mentions => "mentions",
+        }
+    }
 }
 
 /// The result of executing a command.
 pub enum CommandResult {
-    JoinRoom { room: String },
+    /// Join `room`, giving its password if it has one. Creating it,
+    /// the password is set.
+    JoinRoom {
+        room: String,
+        password: Option<String>,
+    },
+    /// Leave this room, or the active one if None.
+    LeaveRoom {
+        room: Option<String>,
+    },
+    /// Send to this room from now on.
+    SwitchRoom { room: String },
     ChangeNick { new_name: String },
-    #[allow(dead_code)]
-    KickUser { target: String, room_id: RoomId },
+    KickUser {
+        target: String,
+        room_id: RoomId,
+        reason: Option<String>,
+    },
+    MuteUser {
+        target: String,
+        duration: Duration,
+    },
+    /// Make `target` an operator of `room_id`, or take it away.
+    SetRoomOperator {
+        target: String,
+        room_id: RoomId,
+        on: bool,
+    },
+    /// Show `room_id`'s topic, or set it if there's text.
+    Topic {
+        room_id: RoomId,
+        text: Option<String>,
+    },
+    /// Put a password on `room_id`, or take it off with None.
+    SetPassword {
+        room_id: RoomId,
+        password: Option<String>,
+    },
+    Set {
+        setting: Setting,
+        on: bool,
+    },
+    Oper {
+        password: String,
+    },
+    Drain,
+    Shutdown,
+    Stats {
+        room: Option<String>,
+    },
+    Ban {
+        target: String,
+        reason: Option<String>,
+    },
+    Unban {
+        target: String,
+    },
+    /// Keep `target` out of `room_id`, removing them if they're in it.
+    RoomBan {
+        target: String,
+        room_id: RoomId,
+        reason: Option<String>,
+    },
+    RoomUnban {
+        target: String,
+        room_id: RoomId,
+    },
+    /// Let `target` talk to nobody but themselves, or stop.
+    ShadowMute {
+        target: String,
+        on: bool,
+    },
+    OpenPoll {
+        question: String,
+        options: Vec<String>,
+    },
+    ClosePoll,
+    Vote {
+        choice: usize,
+    },
+    Remind {
+        target: RemindTarget,
+        after: Duration,
+        when: String,
+        text: String,
+    },
+    InviteCode {
+        room: String,
+        uses: u32,
+        ttl: Duration,
+    },
+    DirectMessage {
+        target: String,
+        body: String,
+    },
+    /// Stop seeing `target`'s messages, or list who's ignored if None.
+    Ignore {
+        target: Option<String>,
+    },
+    Unignore {
+        target: String,
+    },
+    /// Mark the user away, with what to tell anyone who writes to them.
+    Away {
+        message: Option<String>,
+    },
+    Back,
+    /// Who's in `room_id`, away or not.
+    Who {
+        room_id: RoomId,
+        room: Option<String>,
+    },
+    ListRooms {
+        pattern: Option<String>,
+    },
+    /// Change message `id` in `room`, or in `room_id` if None.
+    EditMessage {
+        room_id: RoomId,
+        room: Option<String>,
+        id: u64,
+        body: String,
+    },
+    DeleteMessage {
+        room_id: RoomId,
+        room: Option<String>,
+        id: u64,
+    },
     Quit,
+    /// The command list, in the user's language.
+    Help,
+    /// Speak `code` from now on, or say which languages there are.
+    Language {
+        code: Option<String>,
+    },
     Reply(String),
+    /// Show this line to everyone in the invoker's room.
+    Broadcast(String),
 }
 
 impl Command {
+    /// Names the parser recognises. Plugins can't register these.
+    pub const BUILTIN: &[&str] = &[
+        "join",
+        "leave",
+        "switch",
+        "nick",
+        "kick",
+        "mute",
+        "op",
+        "deop",
+        "topic",
+        "setpass",
+        "set",
+        "oper",
+        "drain",
+        "shutdown",
+        "stats",
+        "ban",
+        "unban",
+        "roomban",
+        "roomunban",
+        "shadowmute",
+        "unshadowmute",
+        "poll",
+        "vote",
+        "remind",
+        "invitecode",
+        "msg",
+        "ignore",
+        "unignore",
+        "away",
+        "back",
+        "who",
+        "quit",
+        "help",
+        "lang",
+        "list",
+        "edit",
+        "delete",
+    ];
+
+    /// The command word of a "/" prefixed line, without the slash.
+    pub fn name(input: &str) -> &str {
+        let input = input.trim().trim_start_matches('/');
+        input.split(' ').next().unwrap_or(input)
+    }
+
     /// Parse a command from a "/" prefixed line.
     pub fn parse(input: &str) -> Result<Self, ChatError> {
         let input = input.trim();
@@ -44,8 +373,25 @@ impl Command {
                 if args.is_empty() {
                     return Err(ChatError::Parse("/join requires a room name".into()));
                 }
+                let (room, password) = match args.split_once(' ') {
+                    Some((room, password)) => (room, Some(password.trim().to_string())),
+                    None => (args, None),
+                };
                 Ok(Command::Join {
-                    room: args.to_string(),
+                    room: room.to_string(),
+                    password,
+                })
+            }
+            "leave" => Ok(Command::Leave {
+                room: (!args.is_empty()).then(|| args.trim_start_matches('#').to_string()),
+            }),
+            "switch" => {
+                let room = args.trim_start_matches('#');
+                if room.is_empty() {
+                    return Err(ChatError::Parse("/switch requires a room name".into()));
+                }
+                Ok(Command::Switch {
+                    room: room.to_string(),
                 })
             }
             "nick" => {
@@ -60,13 +406,252 @@ impl Command {
                 if args.is_empty() {
                     return Err(ChatError::Parse("/kick requires a username".into()));
                 }
+                let (target, reason) = match args.split_once(' ') {
+                    Some((target, reason)) => (target, Some(reason.trim().to_string())),
+                    None => (args, None),
+                };
                 Ok(Command::Kick {
+                    target: target.to_string(),
+                    reason,
+                })
+            }
+            "mute" => {
+                let (target, duration) = args.split_once(' ').ok_or_else(|| {
+                    ChatError::Parse("/mute requires a username and a duration".into())
+                })?;
+                let duration = duration_arg(duration)?;
+                Ok(Command::Mute {
+                    target: target.to_string(),
+                    duration,
+                })
+            }
+            "op" | "deop" => {
+                if args.is_empty() || args.contains(' ') {
+                    return Err(ChatError::Parse(format!("usage: /{cmd} <user>")));
+                }
+                let target = args.to_string();
+                Ok(if cmd == "op" {
+                    Command::Op { target }
+                } else {
+                    Command::Deop { target }
+                })
+            }
+            "topic" => Ok(Command::Topic {
+                text: (!args.is_empty()).then(|| args.to_string()),
+            }),
+            "setpass" => Ok(Command::SetPass {
+                password: (!args.is_empty()).then(|| args.to_string()),
+            }),
+            "set" => {
+                let usage = || ChatError::Parse("usage: /set <setting> on|off".into());
+                let (name, value) = args.split_once(' ').ok_or_else(usage)?;
+                let setting = Setting::parse(name)
+                    .ok_or_else(|| ChatError::Parse(format!("unknown setting: {name}")))?;
+                let on = match value.trim() {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(usage()),
+                };
+                Ok(Command::Set { setting, on })
+            }
+            "oper" => {
+                if args.is_empty() {
+                    return Err(ChatError::Parse("/oper requires a password".into()));
+                }
+                Ok(Command::Oper {
+                    password: args.to_string(),
+                })
+            }
+            "drain" => Ok(Command::Drain),
+            "shutdown" => Ok(Command::Shutdown),
+            "ban" => {
+                if args.is_empty() {
+                    return Err(ChatError::Parse("/ban requires a username".into()));
+                }
+                let (target, reason) = match args.split_once(' ') {
+                    Some((target, reason)) => (target, Some(reason.trim().to_string())),
+                    None => (args, None),
+                };
+                Ok(Command::Ban {
+                    target: target.to_string(),
+                    reason,
+                })
+            }
+            "unban" => {
+                if args.is_empty() {
+                    return Err(ChatError::Parse("/unban requires a username".into()));
+                }
+                Ok(Command::Unban {
                     target: args.to_string(),
                 })
             }
+            "roomban" => {
+                if args.is_empty() {
+                    return Err(ChatError::Parse("/roomban requires a username".into()));
+                }
+                let (target, reason) = match args.split_once(' ') {
+                    Some((target, reason)) => (target, Some(reason.trim().to_string())),
+                    None => (args, None),
+                };
+                Ok(Command::RoomBan {
+                    target: target.to_string(),
+                    reason,
+                })
+            }
+            "roomunban" => {
+                if args.is_empty() {
+                    return Err(ChatError::Parse("/roomunban requires a username".into()));
+                }
+                Ok(Command::RoomUnban {
+                    target: args.to_string(),
+                })
+            }
+            "shadowmute" | "unshadowmute" => {
+                if args.is_empty() || args.contains(' ') {
+                    return Err(ChatError::Parse(format!("usage: /{cmd} <user>")));
+                }
+                let target = args.to_string();
+                Ok(if cmd == "shadowmute" {
+                    Command::ShadowMute { target }
+                } else {
+                    Command::Unshadowmute { target }
+                })
+            }
+            "stats" => Ok(Command::Stats {
+                room: (!args.is_empty()).then(|| args.trim_start_matches('#').to_string()),
+            }),
+            "poll" if args == "close" => Ok(Command::ClosePoll),
+            "poll" => {
+                let mut words = poll::split_quoted(args);
+                if words.len() < 1 + MIN_OPTIONS || words.len() > 1 + MAX_OPTIONS {
+                    return Err(ChatError::Parse(format!(
+                        "usage: /poll \"question\" option1 option2 ... \
+                         ({MIN_OPTIONS} to {MAX_OPTIONS} options)"
+                    )));
+                }
+                let question = words.remove(0);
+                Ok(Command::Poll {
+                    question,
+                    options: words,
+                })
+            }
+            "vote" => {
+                let choice = args
+                    .parse()
+                    .map_err(|_| ChatError::Parse("usage: /vote <number>".into()))?;
+                Ok(Command::Vote { choice })
+            }
+            "invitecode" => {
+                let usage = || ChatError::Parse("usage: /invitecode <room> [uses] [ttl]".into());
+                let mut words = args.split_whitespace();
+                let room = words.next().ok_or_else(usage)?.trim_start_matches('#');
+                let uses = match words.next() {
+                    Some(uses) => uses.parse().ok().filter(|&n| n > 0).ok_or_else(usage)?,
+                    None => 1,
+                };
+                let ttl = match words.next() {
+                    Some(ttl) => duration_arg(ttl)?,
+                    None => invite::DEFAULT_TTL,
+                };
+                if room.is_empty() || words.next().is_some() {
+                    return Err(usage());
+                }
+                Ok(Command::InviteCode {
+                    room: room.to_string(),
+                    uses,
+                    ttl,
+                })
+            }
+            "msg" => {
+                let (target, body) = args
+                    .split_once(' ')
+                    .map(|(target, body)| (target, body.trim()))
+                    .filter(|(_, body)| !body.is_empty())
+                    .ok_or_else(|| ChatError::Parse("usage: /msg <user> <message>".into()))?;
+                Ok(Command::Msg {
+                    target: target.to_string(),
+                    body: body.to_string(),
+                })
+            }
+            "remind" => {
+                let usage =
+                    || ChatError::Parse("usage: /remind me|#room <duration> <message>".into());
+                let mut parts = args.splitn(3, ' ');
+                let (Some(target), Some(when), Some(text)) =
+                    (parts.next(), parts.next(), parts.next())
+                else {
+                    return Err(usage());
+                };
+                let target = match target {
+                    "me" => RemindTarget::Me,
+                    room if room.len() > 1 && room.starts_with('#') => {
+                        RemindTarget::Room(room[1..].to_string())
+                    }
+                    _ => return Err(usage()),
+                };
+                let after = duration_arg(when)?;
+                Ok(Command::Remind {
+                    target,
+                    after,
+                    when: when.to_string(),
+                    text: text.trim().to_string(),
+                })
+            }
+            "ignore" => {
+                if args.contains(' ') {
+                    return Err(ChatError::Parse("usage: /ignore [user]".into()));
+                }
+                Ok(Command::Ignore {
+                    target: (!args.is_empty()).then(|| args.to_string()),
+                })
+            }
+            "unignore" => {
+                if args.is_empty() || args.contains(' ') {
+                    return Err(ChatError::Parse("usage: /unignore <user>".into()));
+                }
+                Ok(Command::Unignore {
+                    target: args.to_string(),
+                })
+            }
+            "away" => Ok(Command::Away {
+                message: (!args.is_empty()).then(|| args.to_string()),
+            }),
+            "back" => Ok(Command::Back),
+            "who" => Ok(Command::Who {
+                room: (!args.is_empty()).then(|| args.trim_start_matches('#').to_string()),
+            }),
             "quit" => Ok(Command::Quit),
             "help" => Ok(Command::Help),
-            "list" => Ok(Command::List),
+            "lang" => {
+                if args.contains(' ') {
+                    return Err(ChatError::Parse("usage: /lang [code]".into()));
+                }
+                Ok(Command::Lang {
+                    code: (!args.is_empty()).then(|| args.to_lowercase()),
+                })
+            }
+            "list" => Ok(Command::List {
+                pattern: (!args.is_empty()).then(|| args.trim_start_matches('#').to_string()),
+            }),
+            "edit" => {
+                let usage = || ChatError::Parse("usage: /edit [#room/]<id> <new text>".into());
+                let (target, body) = args.split_once(' ').ok_or_else(usage)?;
+                let (room, id) = parse_message_ref(target).ok_or_else(usage)?;
+                let body = body.trim();
+                if body.is_empty() {
+                    return Err(usage());
+                }
+                Ok(Command::Edit {
+                    room,
+                    id,
+                    body: body.to_string(),
+                })
+            }
+            "delete" => {
+                let (room, id) = parse_message_ref(args)
+                    .ok_or_else(|| ChatError::Parse("usage: /delete [#room/]<id>".into()))?;
+                Ok(Command::Delete { room, id })
+            }
             _ => Err(ChatError::Parse(format!("unknown command: /{cmd}"))),
         }
     }
@@ -75,18 +660,208 @@ impl Command {
     /// Enum dispatch: every variant is handled in one match.
     pub fn execute(self, current_room: RoomId) -> CommandResult {
         match self {
-            Command::Join { room } => CommandResult::JoinRoom { room },
+            Command::Join { room, password } => CommandResult::JoinRoom { room, password },
+            Command::Leave { room } => CommandResult::LeaveRoom { room },
+            Command::Switch { room } => CommandResult::SwitchRoom { room },
             Command::Nick { name } => CommandResult::ChangeNick { new_name: name },
-            Command::Kick { target } => CommandResult::KickUser {
+            Command::Kick { target, reason } => CommandResult::KickUser {
                 target,
                 room_id: current_room,
+                reason,
+            },
+            Command::Mute { target, duration } => CommandResult::MuteUser { target, duration },
+            Command::Op { target } => CommandResult::SetRoomOperator {
+                target,
+                room_id: current_room,
+                on: true,
+            },
+            Command::Deop { target } => CommandResult::SetRoomOperator {
+                target,
+                room_id: current_room,
+                on: false,
+            },
+            Command::Topic { text } => CommandResult::Topic {
+                room_id: current_room,
+                text,
+            },
+            Command::SetPass { password } => CommandResult::SetPassword {
+                room_id: current_room,
+                password,
+            },
+            Command::Set { setting, on } => CommandResult::Set { setting, on },
+            Command::Oper { password } => CommandResult::Oper { password },
+            Command::Drain => CommandResult::Drain,
+            Command::Shutdown => CommandResult::Shutdown,
+            Command::Stats { room } => CommandResult::Stats { room },
+            Command::Ban { target, reason } => CommandResult::Ban { target, reason },
+            Command::Unban { target } => CommandResult::Unban { target },
+            Command::RoomBan { target, reason } => CommandResult::RoomBan {
+                target,
+                room_id: current_room,
+                reason,
+            },
+            Command::RoomUnban { target } => CommandResult::RoomUnban {
+                target,
+                room_id: current_room,
+            },
+            Command::ShadowMute { target } => CommandResult::ShadowMute { target, on: true },
+            Command::Unshadowmute { target } => CommandResult::ShadowMute { target, on: false },
+            Command::Poll { question, options } => CommandResult::OpenPoll { question, options },
+            Command::ClosePoll => CommandResult::ClosePoll,
+            Command::Vote { choice } => CommandResult::Vote { choice },
+            Command::Remind {
+                target,
+                after,
+                when,
+                text,
+            } => CommandResult::Remind {
+                target,
+                after,
+                when,
+                text,
+            },
+            Command::InviteCode { room, uses, ttl } => {
+                CommandResult::InviteCode { room, uses, ttl }
+            }
+            Command::Msg { target, body } => CommandResult::DirectMessage { target, body },
+            Command::Ignore { target } => CommandResult::Ignore { target },
+            Command::Unignore { target } => CommandResult::Unignore { target },
+            Command::Away { message } => CommandResult::Away { message },
+            Command::Back => CommandResult::Back,
+            Command::Who { room } => CommandResult::Who {
+                room_id: current_room,
+                room,
             },
             Command::Quit => CommandResult::Quit,
-            Command::Help => CommandResult::Reply(
-                "Commands: /join <room>, /nick <name>, /kick <user>, /list, /quit, /help"
-                    .to_string(),
-            ),
-            Command::List => CommandResult::Reply("(room listing not yet implemented)".to_string()),
+            Command::Help => CommandResult::Help,
+            Command::Lang { code } => CommandResult::Language { code },
+            Command::List { pattern } => CommandResult::ListRooms { pattern },
+            Command::Edit { room, id, body } => CommandResult::EditMessage {
+                room_id: current_room,
+                room,
+                id,
+                body,
+            },
+            Command::Delete { room, id } => CommandResult::DeleteMessage {
+                room_id: current_room,
+                room,
+                id,
+            },
         }
     }
 }
+
+/// A message as chat lines show it: `12` in the current room, or
+/// `#lobby/12` (the `#` optional) in a named one.
+fn parse_message_ref(input: &str) -> Option<(Option<String>, u64)> {
+    let input = input.trim();
+    match input.rsplit_once('/') {
+        Some((room, id)) => {
+            let room = room.trim_start_matches('#');
+            if room.is_empty() {
+                return None;
+            }
+            Some((Some(room.to_string()), id.parse().ok()?))
+        }
+        None => Some((None, input.parse().ok()?)),
+    }
+}
+
+/// The longest a mute, reminder or invite may run. Past this it's a
+/// typo, or someone seeing whether the clock overflows.
+pub const MAX_DURATION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Parse a short human duration: `30s`, `10m`, `2h`, `1d`.
+/// A bare number is taken as seconds. Zero is rejected — a mute
+/// that expires immediately is almost certainly a typo — and so is
+/// anything longer than `MAX_DURATION`.
+pub fn parse_duration(input: &str) -> Option<Duration> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    let (amount, unit) = input.split_at(split);
+    let amount: u64 = amount.parse().ok()?;
+
+    let secs = match unit {
+        "" | "s" => amount,
+        "m" => amount.checked_mul(60)?,
+        "h" => amount.checked_mul(60 * 60)?,
+        "d" => amount.checked_mul(24 * 60 * 60)?,
+        _ => return None,
+    };
+
+    (secs > 0 && secs <= MAX_DURATION.as_secs()).then(|| Duration::from_secs(secs))
+}
+
+/// `parse_duration` for a command's argument, saying what's allowed
+/// when it isn't.
+fn duration_arg(input: &str) -> Result<Duration, ChatError> {
+    parse_duration(input).ok_or_else(|| {
+        let max = MAX_DURATION.as_secs() / (24 * 60 * 60);
+        ChatError::Parse(format!(
+            "invalid duration: {} (1s up to {max}d)",
+            input.trim()
+        ))
+    })
+}
+
+/// Plugin commands are the opposite case: an open set, unknown until
+/// startup. Here trait objects are the right tool — each plugin brings
+/// its own type, and the registry only needs the shared interface.
+pub trait CommandHandler: Send + Sync {
+    /// The command name, without the leading `/`.
+    fn name(&self) -> &str;
+
+    fn execute(&self, ctx: &CommandContext, args: &str) -> CommandResult;
+}
+
+/// What a plugin command gets to know about who invoked it.
+pub struct CommandContext {
+    pub user_id: UserId,
+    pub username: String,
+    pub room_id: RoomId,
+    pub room: String,
+}
+
+/// Commands registered at runtime, looked up by name.
+pub struct CommandRegistry {
+    handlers: HashMap<String, Box<dyn CommandHandler>>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        Self {
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Register a handler. Built-in names and names already taken by
+    /// another plugin are refused — first registration wins.
+    pub fn register(&mut self, handler: Box<dyn CommandHandler>) -> Result<(), ChatError> {
+        let name = handler.name().to_string();
+        if Command::BUILTIN.contains(&name.as_str()) || self.handlers.contains_key(&name) {
+            return Err(ChatError::Config(format!(
+                "command /{name} is already defined"
+            )));
+        }
+        self.handlers.insert(name, handler);
+        Ok(())
+    }
+
+    /// Remove a handler, e.g. when the script that defined it goes away.
+    pub fn unregister(&mut self, name: &str) {
+        self.handlers.remove(name);
+    }
+
+    /// Run a "/name args" line if a handler for `name` is registered.
+    pub fn dispatch(&self, input: &str, ctx: &CommandContext) -> Option<CommandResult> {
+        let input = input.trim().strip_prefix('/')?;
+        let (name, args) = input
+            .split_once(' ')
+            .map(|(n, a)| (n, a.trim()))
+            .unwrap_or((input, ""));
+        let handler = self.handlers.get(name)?;
+        Some(handler.execute(ctx, args))
+    }
+}
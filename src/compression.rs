@@ -0,0 +1,72 @@
+#[cfg(feature = "compression")]
+use async_compression::tokio::{bufread, write};
+#[cfg(feature = "compression")]
+use tokio::io::BufReader;
+
+use crate::transport::{BoxedReader, BoxedWriter};
+
+/// A compression a connection can agree to with CAP, for clients in
+/// busy rooms that would rather spend CPU than bandwidth.
+///
+/// It wraps the connection's bytes, both ways, starting right after the
+/// CAP: answer — under the framing, so a length-prefixed frame or a
+/// line is what gets compressed, not what gets split. Every write the
+/// server makes is flushed through the compressor, so a client never
+/// waits on a half-full block; the dictionary carries over between
+/// them, which is where chat's repetition pays off.
+///
+/// Needs the `compression` feature. Without it there are no kinds at
+/// all, HELLO offers none and CAP refuses them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    #[cfg(feature = "compression")]
+    Zlib,
+    #[cfg(feature = "compression")]
+    Zstd,
+}
+
+impl Compression {
+    /// Every kind built in, in the order HELLO offers them.
+    pub const ALL: &[Compression] = &[
+        #[cfg(feature = "compression")]
+        Compression::Zlib,
+        #[cfg(feature = "compression")]
+        Compression::Zstd,
+    ];
+
+    /// The name a client asks for it by.
+    pub fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "compression")]
+            Compression::Zlib => "zlib",
+            #[cfg(feature = "compression")]
+            Compression::Zstd => "zstd",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|kind| kind.name() == name)
+    }
+
+    /// What the server writes, compressed on its way out.
+    #[cfg_attr(not(feature = "compression"), allow(unused_variables))]
+    pub fn writer(self, inner: BoxedWriter) -> BoxedWriter {
+        match self {
+            #[cfg(feature = "compression")]
+            Compression::Zlib => Box::new(write::ZlibEncoder::new(inner)),
+            #[cfg(feature = "compression")]
+            Compression::Zstd => Box::new(write::ZstdEncoder::new(inner)),
+        }
+    }
+
+    /// What the client sends, decompressed on its way in.
+    #[cfg_attr(not(feature = "compression"), allow(unused_variables))]
+    pub fn reader(self, inner: BoxedReader) -> BoxedReader {
+        match self {
+            #[cfg(feature = "compression")]
+            Compression::Zlib => Box::new(bufread::ZlibDecoder::new(BufReader::new(inner))),
+            #[cfg(feature = "compression")]
+            Compression::Zstd => Box::new(bufread::ZstdDecoder::new(BufReader::new(inner))),
+        }
+    }
+}
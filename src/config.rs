@@ -1,11 +1,185 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tracing::Level;
+
+use crate::antispam::AntiSpamConfig;
+use crate::dedup::{Dedup, DedupMode};
+use crate::error::ChatError;
+use crate::feed::{FeedConfig, FeedSource};
+use crate::handshake::{Banner, Challenge};
+use crate::history;
+use crate::i18n::MsgId;
+use crate::lines::Decoding;
+use crate::listener::ListenerConfig;
+use crate::logging::LogFormat;
+use crate::message::Oversize;
+use crate::permissions::{PermissionMatrix, Role};
+use crate::ratelimit::{FloodMute, RateLimit};
+use crate::room::{ExpiryAction, RoomExpiry};
+use crate::settings::{self, FileSettings};
+use crate::share::FileLimits;
+use crate::socket::SocketOptions;
+use crate::storage::StorageBackend;
+use crate::summary::SummaryTarget;
+use crate::transport::Transport;
+use crate::trust::{Capability, Threshold, Tier, TrustPolicy};
+use crate::validation::{DuplicateNames, NameRules};
+
 /// Server configuration — too many optional fields for a simple constructor.
 /// Builder pattern: chain method calls, validate at build time.
 pub struct ServerConfig {
     pub addr: String,
     pub port: u16,
+    /// Connections at once, those still signing in included. More are
+    /// told the server is full.
     pub max_users: usize,
+    /// Connections at once from one address, so a single host can't
+    /// take every slot. None for no limit.
+    pub max_per_ip: Option<usize>,
+    /// Rooms users can make with `/join`, DMs aside. Rooms the server
+    /// makes itself — feeds, summaries, ones brought back from the
+    /// archive — don't count against anyone.
     pub max_rooms: usize,
-    pub motd: Option<String>,
+    /// Only operators and admins may make rooms; everyone else joins
+    /// the ones there are.
+    pub restrict_room_creation: bool,
+    /// Shown after sign-in. `{username}`, `{user_count}` and `{uptime}`
+    /// are filled in as it's sent.
+    pub motd: Option<Banner>,
+    /// Shown before the username prompt.
+    pub banner: Option<Banner>,
+    pub plugins: Vec<String>,
+    pub scripts_dir: Option<PathBuf>,
+    /// New connections accepted per second, across all clients.
+    pub accept_rate: RateLimit,
+    /// New connections accepted per second from a single IP.
+    pub handshake_rate: RateLimit,
+    /// Chat messages per second from one user.
+    pub message_rate: RateLimit,
+    /// Slash commands per second from one user, counted separately so
+    /// neither can be spent to starve or hide behind the other.
+    pub command_rate: RateLimit,
+    /// Mute users who keep sending past `message_rate`. Off by default:
+    /// they're only warned.
+    pub flood_mute: Option<FloodMute>,
+    /// How long a new connection has to send its username.
+    pub handshake_timeout: Duration,
+    /// How long a dropped session can be picked up with RESUME, by a
+    /// client that asked for `resume`. None to not offer it.
+    pub resume_grace: Option<Duration>,
+    /// Connections allowed in the handshake at once; more are refused.
+    pub max_pending: usize,
+    /// Optional question a user must answer before joining.
+    pub challenge: Option<Challenge>,
+    /// Language for system messages, e.g. "en" or "es".
+    pub locale: String,
+    /// Operator overrides for system messages, e.g. the join line.
+    pub templates: HashMap<MsgId, String>,
+    /// How to read clients that don't send UTF-8.
+    pub decoding: Decoding,
+    /// Let clients ask for compression with CAP. On by default, where
+    /// the `compression` feature is built in; off, it's never offered.
+    pub compression: bool,
+    /// TCP tuning for each accepted connection.
+    pub socket: SocketOptions,
+    /// What usernames may look like, at sign-in and on `/nick`.
+    pub names: NameRules,
+    pub duplicate_names: DuplicateNames,
+    /// Ports and transports to accept clients on. Empty means plain
+    /// TCP on `port`.
+    pub listeners: Vec<ListenerConfig>,
+    /// Password for `/oper` that grants the admin role.
+    pub admin_password: Option<String>,
+    /// Password for `/oper` that grants the op role. Without either
+    /// password, nobody can become an operator.
+    pub oper_password: Option<String>,
+    /// Where the admin console listens: a Unix socket only the server's
+    /// own user can open. None, the default, runs without one.
+    pub admin_socket: Option<PathBuf>,
+    /// Port for the Prometheus metrics endpoint, on the chat address.
+    /// None, the default, serves no metrics.
+    pub metrics_port: Option<u16>,
+    /// The lowest role allowed each command.
+    pub permissions: PermissionMatrix,
+    /// Refuse, rather than just flag, a new name from a banned address.
+    pub reject_ban_evasion: bool,
+    /// Trust tiers and the capabilities they unlock.
+    pub trust: TrustPolicy,
+    /// Register /roll, /flip and /8ball.
+    pub fun_commands: bool,
+    /// Rooms the server fills from a file or pipe.
+    pub feeds: Vec<FeedConfig>,
+    /// Catch a message identical to the sender's last one. Off by default.
+    pub dedup: Option<Dedup>,
+    /// The built-in spam filter and its escalation. Off by default.
+    pub antispam: Option<AntiSpamConfig>,
+    /// Where to send the daily activity summary. Empty means no summary.
+    pub daily_summary: Vec<SummaryTarget>,
+    /// Rooms created at startup that only admit people with an invite.
+    pub private_rooms: Vec<String>,
+    /// Delete or archive rooms that have been empty for a while. Off
+    /// by default: a room, once made, stays.
+    pub room_expiry: Option<RoomExpiry>,
+    /// LAN mode: rooms whose messages are also sent to a UDP multicast
+    /// group, and the group for each. See `Multicast`.
+    pub multicast: HashMap<String, SocketAddr>,
+    /// Largest EMSG payload accepted, in bytes.
+    pub max_emsg_bytes: usize,
+    /// Sharing files with FILE_START, and how big they may be. None
+    /// turns it off.
+    pub files: Option<FileLimits>,
+    /// Longest chat message, in bytes, and what to do with longer ones.
+    pub max_message_len: usize,
+    pub oversize: Oversize,
+    /// Lines queued for each client before it counts as too slow.
+    pub outbox_size: usize,
+    /// Disconnect a client whose queue overflows. Off, it just misses
+    /// whatever it fell behind on.
+    pub disconnect_slow_clients: bool,
+    /// Keep each pair's `/msg` conversation in a hidden two-member room,
+    /// with history and read markers. Off, a DM is delivered and gone.
+    pub dm_rooms: bool,
+    /// Messages each room keeps for HISTORY requests and replay.
+    pub history_size: usize,
+    /// Recent messages shown to someone joining a room, marked
+    /// `[history]`. Zero turns replay off.
+    pub replay_on_join: usize,
+    /// What keeps accounts, bans, history and read markers across a
+    /// restart. With `Files`, the four paths below.
+    pub storage: StorageBackend,
+    /// Where registered accounts are kept. Without one, accounts last
+    /// until the server stops.
+    pub accounts_file: Option<PathBuf>,
+    /// Where bans are kept. Without one, bans last until the server
+    /// stops.
+    pub bans_file: Option<PathBuf>,
+    /// Where room history is kept, `history_size` messages a room.
+    /// Without one, history starts empty after a restart.
+    pub history_file: Option<PathBuf>,
+    /// Where accounts' read markers are kept, so an account picks up
+    /// where it left off after a restart. Without one, they last until
+    /// the server stops.
+    pub read_markers_file: Option<PathBuf>,
+    /// Where rooms are kept, as TOML: name, topic, privacy, roles and
+    /// invites, read back by `Server::new`. Without one, every room but
+    /// the lobby is gone after a restart.
+    pub rooms_file: Option<PathBuf>,
+    /// Where bot tokens are kept, as digests. Without one, tokens last
+    /// until the server stops.
+    pub tokens_file: Option<PathBuf>,
+    /// How chat lines are stamped, in UTC: `%H`, `%M`, `%S`, `%Y`, `%m`
+    /// and `%d` are replaced, everything else is kept. None sends lines
+    /// unstamped.
+    pub timestamp_format: Option<String>,
+    /// The quietest log events written, and how they're written.
+    pub log_level: Level,
+    pub log_format: LogFormat,
+    /// The file settings were read from, read again on SIGHUP or the
+    /// admin console's `reload config`.
+    pub config_file: Option<PathBuf>,
 }
 
 /// The builder accumulates optional values and produces a validated config.
@@ -13,8 +187,67 @@ pub struct ServerConfigBuilder {
     addr: String,
     port: u16,
     max_users: usize,
+    max_per_ip: Option<usize>,
     max_rooms: usize,
-    motd: Option<String>,
+    restrict_room_creation: bool,
+    motd: Option<Banner>,
+    banner: Option<Banner>,
+    plugins: Vec<String>,
+    scripts_dir: Option<PathBuf>,
+    accept_rate: RateLimit,
+    handshake_rate: RateLimit,
+    message_rate: RateLimit,
+    command_rate: RateLimit,
+    flood_mute: Option<FloodMute>,
+    handshake_timeout: Duration,
+    resume_grace: Option<Duration>,
+    max_pending: usize,
+    challenge: Option<Challenge>,
+    locale: String,
+    templates: HashMap<MsgId, String>,
+    decoding: Decoding,
+    compression: bool,
+    socket: SocketOptions,
+    names: NameRules,
+    duplicate_names: DuplicateNames,
+    listeners: Vec<ListenerConfig>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    admin_password: Option<String>,
+    oper_password: Option<String>,
+    admin_socket: Option<PathBuf>,
+    metrics_port: Option<u16>,
+    permissions: PermissionMatrix,
+    reject_ban_evasion: bool,
+    trust: TrustPolicy,
+    fun_commands: bool,
+    feeds: Vec<FeedConfig>,
+    dedup: Option<Dedup>,
+    antispam: Option<AntiSpamConfig>,
+    daily_summary: Vec<SummaryTarget>,
+    private_rooms: Vec<String>,
+    room_expiry: Option<RoomExpiry>,
+    multicast: HashMap<String, SocketAddr>,
+    max_emsg_bytes: usize,
+    files: Option<FileLimits>,
+    max_message_len: usize,
+    oversize: Oversize,
+    outbox_size: usize,
+    disconnect_slow_clients: bool,
+    dm_rooms: bool,
+    history_size: usize,
+    replay_on_join: usize,
+    storage: StorageBackend,
+    accounts_file: Option<PathBuf>,
+    bans_file: Option<PathBuf>,
+    history_file: Option<PathBuf>,
+    read_markers_file: Option<PathBuf>,
+    rooms_file: Option<PathBuf>,
+    tokens_file: Option<PathBuf>,
+    timestamp_format: Option<String>,
+    log_level: Level,
+    log_format: LogFormat,
+    config_file: Option<PathBuf>,
 }
 
 impl ServerConfig {
@@ -23,9 +256,131 @@ impl ServerConfig {
             addr: "127.0.0.1".to_string(),
             port: 8080,
             max_users: 100,
+            max_per_ip: Some(10),
             max_rooms: 50,
+            restrict_room_creation: false,
             motd: None,
+            banner: None,
+            plugins: Vec::new(),
+            scripts_dir: None,
+            accept_rate: RateLimit::new(50.0, 100),
+            handshake_rate: RateLimit::new(0.5, 10),
+            message_rate: RateLimit::new(2.0, 10),
+            command_rate: RateLimit::new(1.0, 5),
+            flood_mute: None,
+            handshake_timeout: Duration::from_secs(30),
+            resume_grace: Some(Duration::from_secs(120)),
+            max_pending: 64,
+            challenge: None,
+            locale: "en".to_string(),
+            templates: HashMap::new(),
+            decoding: Decoding::Lossy,
+            compression: true,
+            socket: SocketOptions::default(),
+            names: NameRules::default(),
+            duplicate_names: DuplicateNames::Reject,
+            listeners: Vec::new(),
+            tls_cert: None,
+            tls_key: None,
+            admin_password: None,
+            oper_password: None,
+            admin_socket: None,
+            metrics_port: None,
+            permissions: PermissionMatrix::default(),
+            reject_ban_evasion: false,
+            trust: TrustPolicy::default(),
+            fun_commands: true,
+            feeds: Vec::new(),
+            dedup: None,
+            antispam: None,
+            daily_summary: Vec::new(),
+            private_rooms: Vec::new(),
+            room_expiry: None,
+            multicast: HashMap::new(),
+            max_emsg_bytes: 16 * 1024,
+            files: Some(FileLimits::default()),
+            max_message_len: 4 * 1024,
+            oversize: Oversize::Reject,
+            outbox_size: 64,
+            disconnect_slow_clients: true,
+            dm_rooms: true,
+            history_size: history::KEEP,
+            replay_on_join: 20,
+            storage: StorageBackend::Files,
+            accounts_file: None,
+            bans_file: None,
+            history_file: None,
+            read_markers_file: None,
+            rooms_file: None,
+            tokens_file: None,
+            timestamp_format: Some("[%H:%M:%S]".to_string()),
+            log_level: Level::INFO,
+            log_format: LogFormat::Pretty,
+            config_file: None,
+        }
+    }
+
+    /// Longest line read from a client, in bytes. Room for the longest
+    /// message, EMSG payload or file chunk plus its framing; anything
+    /// past it is dropped unread, so one client can't make the server
+    /// buffer megabytes.
+    pub fn max_line(&self) -> usize {
+        let chunk = self.files.map_or(0, |files| files.max_chunk);
+        self.max_message_len.max(self.max_emsg_bytes).max(chunk) + 1024
+    }
+
+    /// Every listener to bind, falling back to plain TCP on `port`.
+    pub fn listeners(&self) -> Vec<ListenerConfig> {
+        if self.listeners.is_empty() {
+            return vec![ListenerConfig {
+                port: self.port,
+                transport: Transport::Plain,
+            }];
+        }
+        self.listeners.clone()
+    }
+
+    /// Settings in `file` that differ from the running ones but are
+    /// only read at startup.
+    pub fn needs_restart(&self, file: &FileSettings) -> Vec<&'static str> {
+        let mut restart = Vec::new();
+        if file.addr.as_ref().is_some_and(|addr| *addr != self.addr) {
+            restart.push("addr");
+        }
+        if file.port.is_some_and(|port| port != self.port) {
+            restart.push("port");
+        }
+        if file.max_users.is_some_and(|max| max != self.max_users) {
+            restart.push("max_users");
+        }
+        restart
+    }
+
+    /// Take the settings from `file` that can change while running, and
+    /// say which they were.
+    pub fn update(&mut self, file: FileSettings) -> Vec<&'static str> {
+        let mut applied = Vec::new();
+        if let Some(motd) = file.motd {
+            self.motd = Some(motd);
+            applied.push("motd");
+        }
+        if let Some(max) = file.max_message_len {
+            self.max_message_len = max;
+            applied.push("max_message_len");
+        }
+        if let Some(rate) = file.message_rate {
+            self.message_rate = rate;
+            applied.push("message_rate");
+        }
+        if let Some(rate) = file.command_rate {
+            self.command_rate = rate;
+            applied.push("command_rate");
         }
+        if let Some(dedup) = file.dedup {
+            self.dedup = dedup;
+            applied.push("dedup");
+        }
+        applied
     }
 }
 
@@ -45,23 +400,566 @@ impl ServerConfigBuilder {
         self
     }
 
+    /// Behind NAT a whole office shares one address; raise this, or
+    /// pass None, if that's who's connecting.
+    pub fn max_per_ip(mut self, max: Option<usize>) -> Self {
+        self.max_per_ip = max;
+        self
+    }
+
     pub fn max_rooms(mut self, max: usize) -> Self {
         self.max_rooms = max;
         self
     }
 
+    /// Leave making rooms to operators. A server with a fixed set of
+    /// rooms wants this; so does one where `/join typo` kept leaving
+    /// rooms behind.
+    pub fn restrict_room_creation(mut self, on: bool) -> Self {
+        self.restrict_room_creation = on;
+        self
+    }
+
+    /// Read settings from a TOML file (see `FileSettings`), over what's
+    /// been set so far, and remember it for reloading. Builder calls
+    /// after this one override the file.
+    pub fn config_file(mut self, path: impl Into<PathBuf>) -> Result<Self, ChatError> {
+        let path = path.into();
+        let file = settings::load(&path)?;
+        if let Some(addr) = file.addr {
+            self.addr = addr;
+        }
+        if let Some(port) = file.port {
+            self.port = port;
+        }
+        if let Some(max) = file.max_users {
+            self.max_users = max;
+        }
+        if file.motd.is_some() {
+            self.motd = file.motd;
+        }
+        if let Some(max) = file.max_message_len {
+            self.max_message_len = max;
+        }
+        if let Some(rate) = file.message_rate {
+            self.message_rate = rate;
+        }
+        if let Some(rate) = file.command_rate {
+            self.command_rate = rate;
+        }
+        if let Some(dedup) = file.dedup {
+            self.dedup = dedup;
+        }
+        self.config_file = Some(path);
+        Ok(self)
+    }
+
     pub fn motd(mut self, motd: impl Into<String>) -> Self {
-        self.motd = Some(motd.into());
+        self.motd = Some(Banner::Text(motd.into()));
+        self
+    }
+
+    /// Like `motd`, but read from `path` each time someone signs in, so
+    /// it can be changed without a restart.
+    pub fn motd_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.motd = Some(Banner::File(path.into()));
+        self
+    }
+
+    pub fn banner(mut self, text: impl Into<String>) -> Self {
+        self.banner = Some(Banner::Text(text.into()));
+        self
+    }
+
+    /// Like `banner`, but read from `path` each time someone connects.
+    pub fn banner_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.banner = Some(Banner::File(path.into()));
+        self
+    }
+
+    /// Enable a plugin by name. Plugins load in the order given.
+    pub fn plugin(mut self, name: impl Into<String>) -> Self {
+        self.plugins.push(name.into());
+        self
+    }
+
+    /// Directory of `.rhai` scripts. Only used with the `scripting` feature.
+    pub fn scripts_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.scripts_dir = Some(dir.into());
+        self
+    }
+
+    pub fn accept_rate(mut self, per_sec: f64, burst: u32) -> Self {
+        self.accept_rate = RateLimit::new(per_sec, burst);
+        self
+    }
+
+    pub fn handshake_rate(mut self, per_sec: f64, burst: u32) -> Self {
+        self.handshake_rate = RateLimit::new(per_sec, burst);
+        self
+    }
+
+    pub fn message_rate(mut self, per_sec: f64, burst: u32) -> Self {
+        self.message_rate = RateLimit::new(per_sec, burst);
+        self
+    }
+
+    pub fn command_rate(mut self, per_sec: f64, burst: u32) -> Self {
+        self.command_rate = RateLimit::new(per_sec, burst);
+        self
+    }
+
+    /// After `strikes` rate-limited messages in a row, mute the sender
+    /// for `duration`. A message that gets through starts the count
+    /// again.
+    pub fn flood_mute(mut self, strikes: u32, duration: Duration) -> Self {
+        self.flood_mute = Some(FloodMute { strikes, duration });
+        self
+    }
+
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Long enough to cover a phone changing networks; short enough
+    /// that a name isn't held for someone who isn't coming back.
+    pub fn resume_grace(mut self, grace: Option<Duration>) -> Self {
+        self.resume_grace = grace;
+        self
+    }
+
+    pub fn max_pending(mut self, max: usize) -> Self {
+        self.max_pending = max;
+        self
+    }
+
+    /// Ask every newcomer `question`; only `answer` (case-insensitive) lets them in.
+    pub fn challenge(mut self, question: impl Into<String>, answer: impl Into<String>) -> Self {
+        self.challenge = Some(Challenge {
+            question: question.into(),
+            answer: answer.into(),
+        });
+        self
+    }
+
+    pub fn locale(mut self, locale: impl Into<String>) -> Self {
+        self.locale = locale.into();
+        self
+    }
+
+    /// Replace the text of a system message. Placeholders such as
+    /// `{user}`, `{room}` and `{reason}` are filled in when it's sent:
+    ///
+    ///   .template(MsgId::Joined, "--> {user} has entered #{room}")
+    pub fn template(mut self, id: MsgId, template: impl Into<String>) -> Self {
+        self.templates.insert(id, template.into());
+        self
+    }
+
+    pub fn decoding(mut self, decoding: Decoding) -> Self {
+        self.decoding = decoding;
+        self
+    }
+
+    /// Force compression off for everyone, whatever clients ask for:
+    /// to spend the CPU on something else, or because a proxy in front
+    /// already compresses.
+    pub fn compression(mut self, enabled: bool) -> Self {
+        self.compression = enabled;
+        self
+    }
+
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.socket.nodelay = nodelay;
+        self
+    }
+
+    /// Probe idle connections at this interval; None turns keepalive off.
+    pub fn keepalive(mut self, interval: Option<Duration>) -> Self {
+        self.socket.keepalive = interval;
+        self
+    }
+
+    /// Disconnect a client that sends nothing for this long, and tell
+    /// their room they timed out. None, the default, lets them idle.
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.socket.read_timeout = timeout;
+        self
+    }
+
+    pub fn write_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.socket.write_timeout = timeout;
+        self
+    }
+
+    /// PING clients that speak the protocol after this long without a
+    /// word from them. None stops the server asking; clients' own PINGs
+    /// are answered either way.
+    pub fn ping_interval(mut self, interval: Option<Duration>) -> Self {
+        self.socket.ping_interval = interval;
+        self
+    }
+
+    /// How long a PING may go unanswered before the client is dropped.
+    pub fn pong_timeout(mut self, timeout: Duration) -> Self {
+        self.socket.pong_timeout = timeout;
+        self
+    }
+
+    /// Longest username, in characters.
+    pub fn max_name_len(mut self, chars: usize) -> Self {
+        self.names.max_len = chars.max(1);
+        self
+    }
+
+    /// Restrict usernames to printable ASCII.
+    pub fn ascii_names(mut self, ascii_only: bool) -> Self {
+        self.names.ascii_only = ascii_only;
+        self
+    }
+
+    /// Refuse a newcomer whose name is in use, or rename them.
+    pub fn duplicate_names(mut self, policy: DuplicateNames) -> Self {
+        self.duplicate_names = policy;
+        self
+    }
+
+    /// Accept clients on `port` over `transport`. Call once per port:
+    ///
+    ///   .listener(8080, Transport::Plain)
+    ///   .listener(8081, Transport::WebSocket)
+    pub fn listener(mut self, port: u16, transport: Transport) -> Self {
+        self.listeners.push(ListenerConfig { port, transport });
+        self
+    }
+
+    /// Serve TLS on `port` instead of plain TCP, with this certificate
+    /// chain (PEM). Shorthand for `.listener(port, Transport::Tls {..})`.
+    ///
+    /// Without `tls_key`, the key is read from the same file — one PEM
+    /// holding both is common.
+    pub fn tls_cert(mut self, path: impl Into<PathBuf>) -> Self {
+        self.tls_cert = Some(path.into());
+        self
+    }
+
+    /// The private key (PEM) for `tls_cert`.
+    pub fn tls_key(mut self, path: impl Into<PathBuf>) -> Self {
+        self.tls_key = Some(path.into());
+        self
+    }
+
+    pub fn admin_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.admin_socket = Some(path.into());
+        self
+    }
+
+    /// Serve `GET /metrics` on this port for Prometheus to scrape.
+    /// Anyone who can reach it can read it, so firewall it off or keep
+    /// the chat address on localhost.
+    pub fn metrics_port(mut self, port: u16) -> Self {
+        self.metrics_port = Some(port);
+        self
+    }
+
+    pub fn admin_password(mut self, password: impl Into<String>) -> Self {
+        self.admin_password = Some(password.into());
+        self
+    }
+
+    pub fn oper_password(mut self, password: impl Into<String>) -> Self {
+        self.oper_password = Some(password.into());
+        self
+    }
+
+    /// Only users with `role` or above may run `/command`, replacing
+    /// the default for it:
+    ///
+    ///   .permission("kick", Role::Op)
+    pub fn permission(mut self, command: &str, role: Role) -> Self {
+        self.permissions.require(command, role);
+        self
+    }
+
+    pub fn reject_ban_evasion(mut self, reject: bool) -> Self {
+        self.reject_ban_evasion = reject;
+        self
+    }
+
+    /// When a name reaches the basic and trusted tiers.
+    pub fn trust_thresholds(mut self, basic: Threshold, trusted: Threshold) -> Self {
+        self.trust.basic = basic;
+        self.trust.trusted = trusted;
+        self
+    }
+
+    /// Only users at `tier` or above may use `capability`:
+    ///
+    ///   .require_trust(Capability::PostLinks, Tier::Basic)
+    pub fn require_trust(mut self, capability: Capability, tier: Tier) -> Self {
+        self.trust.gates.insert(capability, tier);
+        self
+    }
+
+    /// Users at `tier` may send at most `messages` a day. The count
+    /// resets every 24 hours from startup.
+    ///
+    ///   .daily_quota(Tier::New, 100)
+    pub fn daily_quota(mut self, tier: Tier, messages: u64) -> Self {
+        self.trust.quotas.insert(tier, messages);
+        self
+    }
+
+    pub fn fun_commands(mut self, enabled: bool) -> Self {
+        self.fun_commands = enabled;
+        self
+    }
+
+    /// Post each line from `source` into `room` as `bot`:
+    ///
+    ///   .feed("builds", "ci", FeedSource::Pipe("/tmp/build.fifo".into()))
+    pub fn feed(
+        mut self,
+        room: impl Into<String>,
+        bot: impl Into<String>,
+        source: FeedSource,
+    ) -> Self {
+        self.feeds.push(FeedConfig {
+            room: room.into(),
+            bot: bot.into(),
+            source,
+        });
+        self
+    }
+
+    /// Drop or flag a message that repeats the sender's previous one
+    /// within `window`.
+    pub fn dedup(mut self, window: Duration, mode: DedupMode) -> Self {
+        self.dedup = Some(Dedup { window, mode });
+        self
+    }
+
+    /// Block spam — repeats, shouting, link walls, mass mentions — and
+    /// warn, mute, then kick whoever keeps sending it.
+    ///
+    ///   .enable_antispam(AntiSpamConfig::default())
+    pub fn enable_antispam(mut self, config: AntiSpamConfig) -> Self {
+        self.antispam = Some(config);
+        self
+    }
+
+    /// Send a summary of the day's activity to `target` every 24 hours,
+    /// counted from startup. Call again to add more targets.
+    pub fn daily_summary(mut self, target: SummaryTarget) -> Self {
+        self.daily_summary.push(target);
+        self
+    }
+
+    /// Create `room` at startup as invite-only: members get in with a
+    /// code from `/invitecode`.
+    pub fn private_room(mut self, room: impl Into<String>) -> Self {
+        self.private_rooms.push(room.into());
+        self
+    }
+
+    /// Remove rooms once nobody has been in them for `after`:
+    ///
+    ///   .room_expiry(Duration::from_secs(30 * 60), ExpiryAction::Archive)
+    ///
+    /// Rooms are checked every few minutes at most, so one may outlast
+    /// `after` by a little.
+    pub fn room_expiry(mut self, after: Duration, action: ExpiryAction) -> Self {
+        self.room_expiry = Some(RoomExpiry { after, action });
+        self
+    }
+
+    /// Also send what's said in `room` to the multicast `group`, for
+    /// listeners on the local network:
+    ///
+    ///   .multicast_room("lobby", "239.255.42.1:5000".parse().unwrap())
+    pub fn multicast_room(mut self, room: impl Into<String>, group: SocketAddr) -> Self {
+        self.multicast.insert(room.into(), group);
+        self
+    }
+
+    pub fn max_emsg_bytes(mut self, max: usize) -> Self {
+        self.max_emsg_bytes = max;
+        self
+    }
+
+    /// Limits for shared files, or None to turn sharing off.
+    pub fn files(mut self, limits: Option<FileLimits>) -> Self {
+        self.files = limits;
+        self
+    }
+
+    pub fn max_message_len(mut self, max: usize) -> Self {
+        self.max_message_len = max;
+        self
+    }
+
+    /// Reject messages over `max_message_len` (the default), or cut
+    /// them short.
+    pub fn oversize(mut self, policy: Oversize) -> Self {
+        self.oversize = policy;
+        self
+    }
+
+    pub fn outbox_size(mut self, lines: usize) -> Self {
+        self.outbox_size = lines.max(1);
+        self
+    }
+
+    pub fn disconnect_slow_clients(mut self, disconnect: bool) -> Self {
+        self.disconnect_slow_clients = disconnect;
+        self
+    }
+
+    pub fn dm_rooms(mut self, enabled: bool) -> Self {
+        self.dm_rooms = enabled;
+        self
+    }
+
+    pub fn history_size(mut self, messages: usize) -> Self {
+        self.history_size = messages;
+        self
+    }
+
+    /// How many recent messages a joiner sees, up to
+    /// `history::MAX_REPLAY`.
+    pub fn replay_on_join(mut self, messages: usize) -> Self {
+        self.replay_on_join = messages.min(history::MAX_REPLAY);
+        self
+    }
+
+    pub fn storage(mut self, backend: StorageBackend) -> Self {
+        self.storage = backend;
+        self
+    }
+
+    pub fn accounts_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.accounts_file = Some(path.into());
+        self
+    }
+
+    pub fn bans_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.bans_file = Some(path.into());
+        self
+    }
+
+    pub fn history_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.history_file = Some(path.into());
+        self
+    }
+
+    pub fn read_markers_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.read_markers_file = Some(path.into());
+        self
+    }
+
+    pub fn rooms_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.rooms_file = Some(path.into());
+        self
+    }
+
+    pub fn tokens_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.tokens_file = Some(path.into());
+        self
+    }
+
+    /// Stamp chat lines with this format; None turns stamps off.
+    pub fn timestamp_format(mut self, format: Option<&str>) -> Self {
+        self.timestamp_format = format.map(str::to_string);
+        self
+    }
+
+    /// Log events at `level` and above. `RUST_LOG` overrides it.
+    pub fn log_level(mut self, level: Level) -> Self {
+        self.log_level = level;
+        self
+    }
+
+    pub fn log_format(mut self, format: LogFormat) -> Self {
+        self.log_format = format;
         self
     }
 
     pub fn build(self) -> ServerConfig {
+        let mut listeners = self.listeners;
+        if let Some(cert) = self.tls_cert.clone().or_else(|| self.tls_key.clone()) {
+            let key = self.tls_key.unwrap_or_else(|| cert.clone());
+            listeners.insert(
+                0,
+                ListenerConfig {
+                    port: self.port,
+                    transport: Transport::Tls { cert, key },
+                },
+            );
+        }
         ServerConfig {
             addr: self.addr,
             port: self.port,
             max_users: self.max_users,
+            max_per_ip: self.max_per_ip,
             max_rooms: self.max_rooms,
+            restrict_room_creation: self.restrict_room_creation,
             motd: self.motd,
+            banner: self.banner,
+            plugins: self.plugins,
+            scripts_dir: self.scripts_dir,
+            accept_rate: self.accept_rate,
+            handshake_rate: self.handshake_rate,
+            message_rate: self.message_rate,
+            command_rate: self.command_rate,
+            flood_mute: self.flood_mute,
+            handshake_timeout: self.handshake_timeout,
+            resume_grace: self.resume_grace,
+            max_pending: self.max_pending,
+            challenge: self.challenge,
+            locale: self.locale,
+            templates: self.templates,
+            decoding: self.decoding,
+            compression: self.compression,
+            socket: self.socket,
+            names: self.names,
+            duplicate_names: self.duplicate_names,
+            listeners,
+            admin_password: self.admin_password,
+            oper_password: self.oper_password,
+            admin_socket: self.admin_socket,
+            metrics_port: self.metrics_port,
+            permissions: self.permissions,
+            reject_ban_evasion: self.reject_ban_evasion,
+            trust: self.trust,
+            fun_commands: self.fun_commands,
+            feeds: self.feeds,
+            dedup: self.dedup,
+            antispam: self.antispam,
+            daily_summary: self.daily_summary,
+            private_rooms: self.private_rooms,
+            room_expiry: self.room_expiry,
+            multicast: self.multicast,
+            max_emsg_bytes: self.max_emsg_bytes,
+            files: self.files,
+            max_message_len: self.max_message_len,
+            oversize: self.oversize,
+            outbox_size: self.outbox_size,
+            disconnect_slow_clients: self.disconnect_slow_clients,
+            dm_rooms: self.dm_rooms,
+            history_size: self.history_size,
+            replay_on_join: self.replay_on_join,
+            timestamp_format: self.timestamp_format,
+            log_level: self.log_level,
+            log_format: self.log_format,
+            config_file: self.config_file,
+            storage: self.storage,
+            accounts_file: self.accounts_file,
+            bans_file: self.bans_file,
+            history_file: self.history_file,
+            read_markers_file: self.read_markers_file,
+            rooms_file: self.rooms_file,
+            tokens_file: self.tokens_file,
         }
     }
 }
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+
+use crate::filter::FilterContext;
+use crate::server::{AsyncFilter, FilterAction};
+
+/// Once this many senders are remembered, expired entries are swept.
+const PRUNE_AT: usize = 1_000;
+
+/// What to do with a repeat.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedupMode {
+    /// Refuse it; the sender is told why.
+    Drop,
+    /// Let it through, marked as a repeat.
+    Flag,
+}
+
+/// Settings for the duplicate-message filter.
+#[derive(Debug, Clone, Copy)]
+pub struct Dedup {
+    /// How long a message counts as "just sent".
+    pub window: Duration,
+    pub mode: DedupMode,
+}
+
+/// Catches a message identical to the sender's previous one.
+///
+/// Only the previous message is compared, not a history: the point is
+/// the accidental double Enter and the paste-paste-paste, not someone
+/// saying "yes" twice in an hour.
+pub struct DedupFilter {
+    settings: Dedup,
+    last: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl DedupFilter {
+    pub fn new(settings: Dedup) -> Self {
+        Self {
+            settings,
+            last: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl AsyncFilter for DedupFilter {
+    fn apply<'a>(
+        &'a self,
+        ctx: &'a FilterContext<'a>,
+        body: &'a str,
+    ) -> Pin<Box<dyn Future<Output = FilterAction> + Send + 'a>> {
+        Box::pin(async move {
+            let username = ctx.username;
+            let window = self.settings.window;
+            let mut last = self.last.lock().await;
+            if last.len() >= PRUNE_AT {
+                last.retain(|_, (_, at)| at.elapsed() < window);
+            }
+
+            let repeat = last
+                .get(username)
+                .is_some_and(|(previous, at)| previous == body && at.elapsed() < window);
+            // Every message restarts the window, repeats included, so
+            // pasting the same line over and over keeps being caught.
+            last.insert(username.to_string(), (body.to_string(), Instant::now()));
+
+            match (repeat, self.settings.mode) {
+                (false, _) => FilterAction::Allow,
+                (true, DedupMode::Drop) => {
+                    FilterAction::Block("same as your last message".to_string())
+                }
+                (true, DedupMode::Flag) => FilterAction::Modify(format!("{body} [repeat]")),
+            }
+        })
+    }
+}
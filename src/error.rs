@@ -1,5 +1,15 @@
 use thiserror::Error;
 
+use crate::permissions::Role;
+use crate::room::RoomRole;
+
+/// Everything that can go wrong, for the server's own handling and for
+/// telling the client.
+///
+/// Each variant has a numeric code that never changes meaning, so client
+/// programs can match on the number instead of the English. Some
+/// variants describe the server's insides rather than the request, and
+/// those aren't shown to clients — see `client_text`.
 #[derive(Debug, Error)]
 pub enum ChatError {
     #[error("network error: {0}")]
@@ -8,11 +18,193 @@ pub enum ChatError {
     #[error("parse error: {0}")]
     Parse(String),
 
-    #[allow(dead_code)]
-    #[error("unknown room: {0}")]
+    #[error("config error: {0}")]
+    Config(String),
+
+    /// The storage backend couldn't read or keep something.
+    #[error("storage error: {0}")]
+    Storage(String),
+
+    #[error("unknown room: #{0}")]
     UnknownRoom(String),
 
-    #[allow(dead_code)]
     #[error("unknown user: {0}")]
     UnknownUser(String),
+
+    /// Someone who has been here, but isn't now.
+    #[error("{0} is offline")]
+    UserOffline(String),
+
+    #[error("/{command} needs the {required} role (you are {current})")]
+    PermissionDenied {
+        command: String,
+        required: Role,
+        current: Role,
+    },
+
+    /// Like PermissionDenied, but for a role within one room.
+    #[error("/{command} in #{room} needs the room {required} role (you are {current})")]
+    RoomPermissionDenied {
+        command: String,
+        room: String,
+        required: RoomRole,
+        current: RoomRole,
+    },
+
+    /// `what` is the kind of traffic that was limited: "messages",
+    /// "commands".
+    #[error("rate limited: you're sending {what} too fast")]
+    RateLimited { what: &'static str },
+
+    #[allow(dead_code)]
+    #[error("room is full: #{0}")]
+    RoomFull(String),
+
+    #[error("nickname in use: {0}")]
+    NickInUse(String),
+
+    /// Which naming rule was broken. The name itself is left out: it
+    /// may be the line break or control character being refused.
+    #[error("invalid name: {0}")]
+    InvalidName(String),
+
+    #[error("message too long: {len} bytes, the limit is {max}")]
+    MessageTooLong { len: usize, max: usize },
+
+    #[error("authentication failed")]
+    AuthFailed,
+
+    /// A password-protected room, and the password given (if any)
+    /// wasn't its.
+    #[error("#{0} needs its password: /join {0} <password>")]
+    WrongPassword(String),
+
+    #[error("{0} is a registered name: sign in with LOGIN:{0}:<password>")]
+    NameRegistered(String),
+
+    #[error("file too large: {size} bytes, the limit is {max}")]
+    FileTooLarge { size: usize, max: usize },
+
+    /// Shared files together are at their limit. Temporary: space
+    /// comes back as older files expire.
+    #[error("no room for more files right now, try again later")]
+    FileStoreFull,
+
+    /// A FILE_GET token that never existed, or has expired.
+    #[error("no such file: {0}")]
+    UnknownFile(String),
+
+    /// Nothing to `/edit` or `/delete` by that number: it never was,
+    /// it was deleted, or it's older than the room keeps.
+    #[error("no message #{room}/{id}")]
+    UnknownMessage { room: String, id: u64 },
+
+    /// Creating a room would go over `max_rooms`.
+    #[error("too many rooms: the server allows {max}")]
+    TooManyRooms { max: usize },
+}
+
+impl ChatError {
+    /// Stable number for the wire. Client mistakes are in the 100s;
+    /// the server's own trouble is in the 500s.
+    pub fn code(&self) -> u16 {
+        match self {
+            ChatError::Parse(_) => 100,
+            ChatError::UnknownRoom(_) => 101,
+            ChatError::UnknownUser(_) => 102,
+            ChatError::PermissionDenied { .. } => 103,
+            ChatError::RateLimited { .. } => 104,
+            ChatError::RoomFull(_) => 105,
+            ChatError::NickInUse(_) => 106,
+            ChatError::MessageTooLong { .. } => 107,
+            ChatError::AuthFailed => 108,
+            ChatError::UserOffline(_) => 109,
+            ChatError::NameRegistered(_) => 110,
+            ChatError::RoomPermissionDenied { .. } => 111,
+            ChatError::InvalidName(_) => 112,
+            ChatError::WrongPassword(_) => 113,
+            ChatError::FileTooLarge { .. } => 114,
+            ChatError::FileStoreFull => 115,
+            ChatError::UnknownFile(_) => 116,
+            ChatError::UnknownMessage { .. } => 117,
+            ChatError::TooManyRooms { .. } => 118,
+            ChatError::Network(_) => 500,
+            ChatError::Config(_) => 501,
+            ChatError::Storage(_) => 502,
+        }
+    }
+
+    /// Whether the message says anything a client shouldn't see. An I/O
+    /// error can name files and addresses; a config error, the setup.
+    pub fn is_client_safe(&self) -> bool {
+        !matches!(
+            self,
+            ChatError::Network(_) | ChatError::Config(_) | ChatError::Storage(_)
+        )
+    }
+
+    /// The error's details by name, for a translation to put where its
+    /// language wants them (see `Catalog::error_text`). Unnamed fields
+    /// get the name the message gives them.
+    pub fn args(&self) -> Vec<(&'static str, String)> {
+        match self {
+            ChatError::Network(e) => vec![("detail", e.to_string())],
+            ChatError::Parse(detail) | ChatError::Config(detail) | ChatError::Storage(detail) => {
+                vec![("detail", detail.clone())]
+            }
+            ChatError::UnknownRoom(room)
+            | ChatError::RoomFull(room)
+            | ChatError::WrongPassword(room) => vec![("room", room.clone())],
+            ChatError::UnknownUser(user) | ChatError::UserOffline(user) => {
+                vec![("user", user.clone())]
+            }
+            ChatError::NickInUse(name) | ChatError::NameRegistered(name) => {
+                vec![("name", name.clone())]
+            }
+            ChatError::InvalidName(reason) => vec![("reason", reason.clone())],
+            ChatError::UnknownFile(token) => vec![("token", token.clone())],
+            ChatError::PermissionDenied {
+                command,
+                required,
+                current,
+            } => vec![
+                ("command", command.clone()),
+                ("required", required.to_string()),
+                ("current", current.to_string()),
+            ],
+            ChatError::RoomPermissionDenied {
+                command,
+                room,
+                required,
+                current,
+            } => vec![
+                ("command", command.clone()),
+                ("room", room.clone()),
+                ("required", required.to_string()),
+                ("current", current.to_string()),
+            ],
+            ChatError::RateLimited { what } => vec![("what", what.to_string())],
+            ChatError::MessageTooLong { len, max } => {
+                vec![("len", len.to_string()), ("max", max.to_string())]
+            }
+            ChatError::FileTooLarge { size, max } => {
+                vec![("size", size.to_string()), ("max", max.to_string())]
+            }
+            ChatError::UnknownMessage { room, id } => {
+                vec![("room", room.clone()), ("id", id.to_string())]
+            }
+            ChatError::TooManyRooms { max } => vec![("max", max.to_string())],
+            ChatError::AuthFailed | ChatError::FileStoreFull => Vec::new(),
+        }
+    }
+
+    /// What a client is told: the message itself if it's safe, or just
+    /// that something went wrong on our side.
+    pub fn client_text(&self) -> String {
+        if self.is_client_safe() {
+            self.to_string()
+        } else {
+            "internal server error".to_string()
+        }
+    }
 }
@@ -0,0 +1,137 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+use crate::bus::ServerEvent;
+use crate::hooks::DisconnectReason;
+
+type Callbacks<F> = Vec<Box<F>>;
+type ConnectFn = dyn FnMut(&str, SocketAddr) + Send;
+type DisconnectFn = dyn FnMut(&str, Duration, &DisconnectReason) + Send;
+type JoinFn = dyn FnMut(&str, &str) + Send;
+type MessageFn = dyn FnMut(&str, &str, &str) + Send;
+type NickChangeFn = dyn FnMut(&str, &str) + Send;
+
+/// Callbacks for things that happen on the server, for logging, bots
+/// or webhooks.
+///
+/// The FilterRegistry's cousin, but observational: a callback hears
+/// about something after it happened and can't change it. Compare the
+/// hooks on `Server` (`on_join` and friends), which run with the server
+/// lock held and get `&mut Server` to act with. These get plain
+/// strings, on their own task, off the event bus — a slow webhook
+/// holds up nobody but itself.
+///
+/// FnMut, like filters, so a callback can keep count or keep state.
+#[derive(Default)]
+pub struct EventRegistry {
+    connect: Callbacks<ConnectFn>,
+    disconnect: Callbacks<DisconnectFn>,
+    join: Callbacks<JoinFn>,
+    message: Callbacks<MessageFn>,
+    nick_change: Callbacks<NickChangeFn>,
+}
+
+impl EventRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `(username, peer)` for each new user, once they're signed in.
+    pub fn on_connect<F>(&mut self, callback: F)
+    where
+        F: FnMut(&str, SocketAddr) + Send + 'static,
+    {
+        self.connect.push(Box::new(callback));
+    }
+
+    /// `(username, session, reason)` when a user goes, with how long
+    /// they stayed and why they left.
+    pub fn on_disconnect<F>(&mut self, callback: F)
+    where
+        F: FnMut(&str, Duration, &DisconnectReason) + Send + 'static,
+    {
+        self.disconnect.push(Box::new(callback));
+    }
+
+    /// `(username, room)` each time someone joins a room.
+    pub fn on_join<F>(&mut self, callback: F)
+    where
+        F: FnMut(&str, &str) + Send + 'static,
+    {
+        self.join.push(Box::new(callback));
+    }
+
+    /// `(room, from, body)` for each message delivered to a room, after
+    /// filters. Feed posts count; direct messages don't.
+    pub fn on_message<F>(&mut self, callback: F)
+    where
+        F: FnMut(&str, &str, &str) + Send + 'static,
+    {
+        self.message.push(Box::new(callback));
+    }
+
+    /// `(old, new)` when someone changes their name.
+    pub fn on_nick_change<F>(&mut self, callback: F)
+    where
+        F: FnMut(&str, &str) + Send + 'static,
+    {
+        self.nick_change.push(Box::new(callback));
+    }
+
+    /// Hand `event` to every callback registered for its kind, in the
+    /// order they were added. Kinds nobody registered for are ignored.
+    pub fn dispatch(&mut self, event: &ServerEvent) {
+        match event {
+            ServerEvent::UserConnected { username, peer, .. } => {
+                for callback in &mut self.connect {
+                    callback(username, *peer);
+                }
+            }
+            ServerEvent::UserDisconnected {
+                username,
+                session,
+                reason,
+                ..
+            } => {
+                for callback in &mut self.disconnect {
+                    callback(username, *session, reason);
+                }
+            }
+            ServerEvent::UserJoined { username, room, .. } => {
+                for callback in &mut self.join {
+                    callback(username, room);
+                }
+            }
+            ServerEvent::MessageBroadcast {
+                room, from, body, ..
+            } => {
+                for callback in &mut self.message {
+                    callback(room, from, body);
+                }
+            }
+            ServerEvent::NickChanged { old, new, .. } => {
+                for callback in &mut self.nick_change {
+                    callback(old, new);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Drive `registry` from a bus subscription until the server goes.
+pub async fn run(mut registry: EventRegistry, mut events: Receiver<ServerEvent>) {
+    loop {
+        match events.recv().await {
+            Ok(event) => registry.dispatch(&event),
+            Err(RecvError::Lagged(missed)) => {
+                warn!(missed, "event callbacks fell behind and missed events");
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, AsyncSeekExt, BufReader};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::lines::trim_line_ending;
+use crate::server::Server;
+use crate::types::RoomId;
+
+/// How often a tailed file is checked for new lines once caught up.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long to wait before retrying a source that failed (missing file,
+/// permission error). Long enough not to flood the log.
+const RETRY: Duration = Duration::from_secs(5);
+
+/// Where a feed room's lines come from.
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub enum FeedSource {
+    /// Follow a regular file like `tail -f`: start at the end and post
+    /// every line appended after that. If the file is truncated (a log
+    /// rotated in place) reading starts again from the top.
+    Tail(PathBuf),
+    /// Read a named pipe (`mkfifo`). When the last writer closes it the
+    /// pipe is reopened, so `make > build.fifo 2>&1` can run again and
+    /// again.
+    Pipe(PathBuf),
+}
+
+/// A room the server fills itself, posting each line as `bot`.
+#[derive(Debug, Clone)]
+pub struct FeedConfig {
+    pub room: String,
+    pub bot: String,
+    pub source: FeedSource,
+}
+
+/// Run one feed for the life of the server.
+///
+/// Spawned from main next to the scheduler. A feed never gives up: a
+/// file that doesn't exist yet is waited for, and an error is logged
+/// and retried rather than taking the room down with it.
+pub async fn run(server: Arc<Mutex<Server>>, feed: FeedConfig) {
+    let room_id = server.lock().await.feed_room(&feed.room);
+    let poster = Poster {
+        server,
+        room_id,
+        bot: feed.bot,
+    };
+
+    loop {
+        let result = match &feed.source {
+            FeedSource::Tail(path) => poster.tail(path).await,
+            FeedSource::Pipe(path) => poster.pipe(path).await,
+        };
+        match result {
+            // The pipe's writer went away: reopen straight away, which
+            // blocks until the next one arrives.
+            Ok(()) => {}
+            Err(e) => {
+                warn!(room = %feed.room, error = %e, "feed failed");
+                tokio::time::sleep(RETRY).await;
+            }
+        }
+    }
+}
+
+struct Poster {
+    server: Arc<Mutex<Server>>,
+    room_id: RoomId,
+    bot: String,
+}
+
+impl Poster {
+    async fn post(&self, bytes: &[u8]) {
+        // A log is whatever the program wrote; take what's readable.
+        let text = String::from_utf8_lossy(bytes);
+        let line = trim_line_ending(&text);
+        if line.trim().is_empty() {
+            return;
+        }
+        self.server
+            .lock()
+            .await
+            .post_feed(self.room_id, &self.bot, line)
+            .await;
+    }
+
+    async fn tail(&self, path: &Path) -> io::Result<()> {
+        let mut file = File::open(path).await?;
+        let mut pos = file.seek(SeekFrom::End(0)).await?;
+        let mut reader = BufReader::new(file);
+        let mut line = Vec::new();
+
+        loop {
+            let n = reader.read_until(b'\n', &mut line).await?;
+            pos += n as u64;
+
+            if line.ends_with(b"\n") {
+                self.post(&line).await;
+                line.clear();
+                continue;
+            }
+
+            // At the end of the file, possibly halfway through a line
+            // the writer hasn't finished. Keep what we have and wait.
+            if n == 0 {
+                if tokio::fs::metadata(path).await?.len() < pos {
+                    pos = reader.seek(SeekFrom::Start(0)).await?;
+                    line.clear();
+                }
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+    }
+
+    async fn pipe(&self, path: &Path) -> io::Result<()> {
+        let mut reader = BufReader::new(File::open(path).await?);
+        let mut line = Vec::new();
+
+        while reader.read_until(b'\n', &mut line).await? > 0 {
+            self.post(&line).await;
+            line.clear();
+        }
+        Ok(())
+    }
+}
@@ -1,3 +1,21 @@
+use std::time::SystemTime;
+
+use crate::types::UserId;
+
+/// What a filter knows about a message besides its body: who sent it,
+/// where, when, and how much they've said before. Enough for per-room
+/// rules or per-user throttles without the filter keeping its own
+/// bookkeeping.
+#[derive(Debug, Clone, Copy)]
+pub struct FilterContext<'a> {
+    pub sender: UserId,
+    pub username: &'a str,
+    pub room: &'a str,
+    pub at: SystemTime,
+    /// Messages the sender sent before this one.
+    pub message_count: u64,
+}
+
 /// A message filter — a closure that can inspect and optionally modify messages.
 ///
 /// Filters use FnMut because they may maintain state (e.g., counting
@@ -8,11 +26,16 @@
 /// Stored as Box<dyn FnMut> because closures have anonymous types —
 /// you can't name them. Boxing erases the type and lets us store
 /// different closures in a Vec.
+#[derive(Default)]
 pub struct FilterRegistry {
-    filters: Vec<Box<dyn FnMut(&str, &str) -> FilterAction + Send>>,
+    filters: Vec<BoxedFilter>,
 }
 
-/// What a filter decides to do with a message.
+type BoxedFilter = Box<dyn FnMut(&FilterContext<'_>, &str) -> FilterAction + Send>;
+
+/// What a filter decides to do with a message. Sync closures here and
+/// async filters on the server answer the same way.
+#[derive(Debug)]
 pub enum FilterAction {
     /// Let the message through unchanged.
     Allow,
@@ -30,20 +53,20 @@ impl FilterRegistry {
     }
 
     /// Register a filter. Takes any closure that matches the signature.
-    /// The closure receives (username, body) and returns a FilterAction.
+    /// The closure receives (context, body) and returns a FilterAction.
     pub fn add<F>(&mut self, filter: F)
     where
-        F: FnMut(&str, &str) -> FilterAction + Send + 'static,
+        F: FnMut(&FilterContext<'_>, &str) -> FilterAction + Send + 'static,
     {
         self.filters.push(Box::new(filter));
     }
 
     /// Run all filters on a message. Returns the final action.
-    pub fn apply(&mut self, username: &str, body: &str) -> FilterAction {
+    pub fn apply(&mut self, ctx: &FilterContext<'_>, body: &str) -> FilterAction {
         let mut current_body = body.to_string();
 
         for filter in &mut self.filters {
-            match filter(username, &current_body) {
+            match filter(ctx, &current_body) {
                 FilterAction::Allow => {}
                 FilterAction::Modify(new_body) => {
                     current_body = new_body;
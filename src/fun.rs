@@ -0,0 +1,154 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::command::{CommandContext, CommandHandler, CommandResult};
+
+/// Most dice in one roll, and most sides on a die. Enough for any
+/// tabletop game, small enough that the reply fits on a line.
+const MAX_DICE: u32 = 20;
+const MAX_SIDES: u32 = 1000;
+
+const EIGHT_BALL: &[&str] = &[
+    "It is certain.",
+    "Without a doubt.",
+    "You may rely on it.",
+    "Most likely.",
+    "Signs point to yes.",
+    "Reply hazy, try again.",
+    "Ask again later.",
+    "Cannot predict now.",
+    "Don't count on it.",
+    "My sources say no.",
+    "Outlook not so good.",
+    "Very doubtful.",
+];
+
+/// A tiny xorshift generator.
+///
+/// Dice in a chat room don't need cryptographic randomness, so this
+/// avoids a dependency. The state is an atomic because handlers are
+/// shared (`&self`) across every client task.
+struct Dice {
+    state: AtomicU64,
+}
+
+impl Dice {
+    fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        // Xorshift must never be seeded with zero.
+        Self {
+            state: AtomicU64::new(seed | 1),
+        }
+    }
+
+    /// A number in `1..=sides`.
+    fn roll(&self, sides: u32) -> u32 {
+        let mut x = self.state.load(Ordering::Relaxed);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state.store(x, Ordering::Relaxed);
+        (x % sides as u64) as u32 + 1
+    }
+}
+
+/// Parse `NdM` (`2d6`, `d20`). N defaults to 1.
+fn parse_dice(spec: &str) -> Option<(u32, u32)> {
+    let spec = spec.trim().to_ascii_lowercase();
+    let (count, sides) = spec.split_once('d')?;
+    let count = if count.is_empty() {
+        1
+    } else {
+        count.parse().ok()?
+    };
+    let sides = sides.parse().ok()?;
+    ((1..=MAX_DICE).contains(&count) && (2..=MAX_SIDES).contains(&sides)).then_some((count, sides))
+}
+
+/// `/roll [NdM]` — roll dice, 1d6 by default.
+struct Roll {
+    dice: Dice,
+}
+
+impl CommandHandler for Roll {
+    fn name(&self) -> &str {
+        "roll"
+    }
+
+    fn execute(&self, ctx: &CommandContext, args: &str) -> CommandResult {
+        let spec = if args.is_empty() { "1d6" } else { args };
+        let Some((count, sides)) = parse_dice(spec) else {
+            return CommandResult::Reply(format!(
+                "* Usage: /roll NdM, e.g. /roll 2d6 (up to {MAX_DICE} dice of {MAX_SIDES} sides)"
+            ));
+        };
+
+        let rolls: Vec<u32> = (0..count).map(|_| self.dice.roll(sides)).collect();
+        let total: u32 = rolls.iter().sum();
+        let text = if count == 1 {
+            format!("* {} rolls d{sides}: {total}", ctx.username)
+        } else {
+            let parts: Vec<String> = rolls.iter().map(u32::to_string).collect();
+            format!(
+                "* {} rolls {count}d{sides}: {} = {total}",
+                ctx.username,
+                parts.join(" + ")
+            )
+        };
+        CommandResult::Broadcast(text)
+    }
+}
+
+/// `/flip` — heads or tails.
+struct Flip {
+    dice: Dice,
+}
+
+impl CommandHandler for Flip {
+    fn name(&self) -> &str {
+        "flip"
+    }
+
+    fn execute(&self, ctx: &CommandContext, _args: &str) -> CommandResult {
+        let side = if self.dice.roll(2) == 1 {
+            "heads"
+        } else {
+            "tails"
+        };
+        CommandResult::Broadcast(format!("* {} flips a coin: {side}", ctx.username))
+    }
+}
+
+/// `/8ball <question>` — consult the oracle.
+struct EightBall {
+    dice: Dice,
+}
+
+impl CommandHandler for EightBall {
+    fn name(&self) -> &str {
+        "8ball"
+    }
+
+    fn execute(&self, ctx: &CommandContext, args: &str) -> CommandResult {
+        if args.is_empty() {
+            return CommandResult::Reply("* Usage: /8ball <question>".to_string());
+        }
+        let answer = EIGHT_BALL[self.dice.roll(EIGHT_BALL.len() as u32) as usize - 1];
+        CommandResult::Broadcast(format!(
+            "* {} asks: {args} -- the magic 8-ball says: {answer}",
+            ctx.username
+        ))
+    }
+}
+
+/// The fun pack, ready for the command registry.
+pub fn commands() -> Vec<Box<dyn CommandHandler>> {
+    vec![
+        Box::new(Roll { dice: Dice::new() }),
+        Box::new(Flip { dice: Dice::new() }),
+        Box::new(EightBall { dice: Dice::new() }),
+    ]
+}
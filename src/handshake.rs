@@ -0,0 +1,263 @@
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tokio::io::AsyncWriteExt;
+use tracing::warn;
+
+use crate::compression::Compression;
+use crate::error::ChatError;
+use crate::lines::{Decoding, Framing, LineReader};
+use crate::transport::{BoxedWriter, ClientStream};
+
+/// What a HandshakeHook stage returns: boxed, since trait methods
+/// can't name an async block's type.
+pub type HookFuture<'a> = Pin<Box<dyn Future<Output = Result<HookOutcome, ChatError>> + Send + 'a>>;
+
+/// What a hook decided about the connection.
+pub enum HookOutcome {
+    /// Carry on with the handshake.
+    Continue,
+    /// Send this message and close the connection. Use an empty
+    /// message when the client is already gone.
+    Reject(String),
+}
+
+/// Text shown to every connection before it's asked for a name: the
+/// rules, a legal notice, the server's name and version. Unlike the
+/// MOTD, even someone who never logs in sees it.
+#[derive(Debug, Clone)]
+pub enum Banner {
+    Text(String),
+    /// Read on every connection, so it can be edited without a restart.
+    File(PathBuf),
+}
+
+impl Banner {
+    /// The banner's text, or None if the file can't be read — a missing
+    /// banner isn't worth turning people away for.
+    pub async fn text(&self) -> Option<String> {
+        match self {
+            Banner::Text(text) => Some(text.clone()),
+            Banner::File(path) => match tokio::fs::read_to_string(path).await {
+                Ok(text) => Some(text.trim_end().to_string()),
+                Err(e) => {
+                    warn!(path = %path.display(), error = %e, "banner unreadable");
+                    None
+                }
+            },
+        }
+    }
+}
+
+/// The client's socket while the handshake runs.
+///
+/// Until the user is registered there's no writer task and no channel —
+/// hooks talk to the socket directly, one line at a time.
+pub struct HandshakeIo {
+    pub peer: SocketAddr,
+    reader: LineReader,
+    writer: BoxedWriter,
+    framing: Framing,
+}
+
+impl HandshakeIo {
+    pub fn new(stream: ClientStream, decoding: Decoding, max_line: usize) -> Self {
+        Self {
+            peer: stream.peer,
+            reader: LineReader::new(stream.reader, decoding, max_line),
+            writer: stream.writer,
+            framing: Framing::Newline,
+        }
+    }
+
+    pub async fn send(&mut self, text: &str) -> Result<(), ChatError> {
+        let text = format!("{text}\n");
+        self.writer.write_all(&self.framing.encode(&text)).await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    /// Read and write lines this way from now on, both directions.
+    pub fn set_framing(&mut self, framing: Framing) {
+        self.framing = framing;
+        self.reader.set_framing(framing);
+    }
+
+    /// Compress both directions from now on. Whatever was sent before
+    /// this went uncompressed, and so must the client's.
+    pub fn compress(&mut self, kind: Compression) {
+        let writer = std::mem::replace(&mut self.writer, Box::new(tokio::io::sink()));
+        self.writer = kind.writer(writer);
+        self.reader.wrap(|reader| kind.reader(reader));
+    }
+
+    /// Read one trimmed line. `None` means the client hung up.
+    ///
+    /// Telnet clients negotiate as they connect, so this is also where
+    /// our refusals go back out.
+    pub async fn read_line(&mut self) -> Result<Option<String>, ChatError> {
+        let line = self.reader.read_line().await?;
+        let replies = self.reader.take_telnet_replies();
+        if !replies.is_empty() {
+            self.writer.write_all(&replies).await?;
+            self.writer.flush().await?;
+        }
+        Ok(line.map(|line| line.trim().to_string()))
+    }
+
+    /// Send a prompt and wait for the answer.
+    pub async fn ask(&mut self, prompt: &str) -> Result<Option<String>, ChatError> {
+        self.send(prompt).await?;
+        self.read_line().await
+    }
+
+    /// Hand the halves over to the chat loop once the handshake is done.
+    pub fn into_parts(self) -> (LineReader, BoxedWriter) {
+        (self.reader, self.writer)
+    }
+}
+
+/// Hooks around the connect sequence.
+///
+///   connect → pre_prompt → "Enter your username:" → post_username
+///           → pre_join → registered, joins #lobby
+///
+/// Use post_username for checks on the name itself (external auth,
+/// reserved names) and pre_join for extra steps with the person behind
+/// it (terms of service, custom questions). Every stage defaults to
+/// Continue, so a hook implements only the stages it needs.
+pub trait HandshakeHook: Send + Sync {
+    fn pre_prompt<'a>(&'a self, _io: &'a mut HandshakeIo) -> HookFuture<'a> {
+        Box::pin(async { Ok(HookOutcome::Continue) })
+    }
+
+    fn post_username<'a>(&'a self, _io: &'a mut HandshakeIo, _username: &'a str) -> HookFuture<'a> {
+        Box::pin(async { Ok(HookOutcome::Continue) })
+    }
+
+    fn pre_join<'a>(&'a self, _io: &'a mut HandshakeIo, _username: &'a str) -> HookFuture<'a> {
+        Box::pin(async { Ok(HookOutcome::Continue) })
+    }
+}
+
+/// A question every newcomer must answer before chatting.
+#[derive(Debug, Clone)]
+pub struct Challenge {
+    pub question: String,
+    pub answer: String,
+}
+
+/// How many wrong answers before we hang up.
+const CHALLENGE_ATTEMPTS: usize = 3;
+
+/// Lightweight bot deterrence, built on the same hook API embedders use.
+/// It runs at pre_join: the user has a name but can't say anything yet.
+pub struct ChallengeHook {
+    challenge: Challenge,
+    wrong: String,
+    failed: String,
+}
+
+impl ChallengeHook {
+    /// `wrong` and `failed` are the (localized) replies to a wrong answer
+    /// and to running out of attempts.
+    pub fn new(challenge: Challenge, wrong: String, failed: String) -> Self {
+        Self {
+            challenge,
+            wrong,
+            failed,
+        }
+    }
+}
+
+impl HandshakeHook for ChallengeHook {
+    fn pre_join<'a>(&'a self, io: &'a mut HandshakeIo, _username: &'a str) -> HookFuture<'a> {
+        Box::pin(async move {
+            for _ in 0..CHALLENGE_ATTEMPTS {
+                let Some(answer) = io.ask(&self.challenge.question).await? else {
+                    return Ok(HookOutcome::Reject(String::new()));
+                };
+                if answer.eq_ignore_ascii_case(self.challenge.answer.trim()) {
+                    return Ok(HookOutcome::Continue);
+                }
+                io.send(&self.wrong).await?;
+            }
+            Ok(HookOutcome::Reject(self.failed.clone()))
+        })
+    }
+}
+
+/// Which point of the handshake we're at.
+pub enum Stage<'a> {
+    PrePrompt,
+    PostUsername(&'a str),
+    PreJoin(&'a str),
+}
+
+/// Run every hook for one stage, in registration order.
+///
+/// Returns false if a hook rejected the connection; the rejection
+/// message has already been sent.
+pub async fn run_hooks(
+    hooks: &[Arc<dyn HandshakeHook>],
+    io: &mut HandshakeIo,
+    stage: Stage<'_>,
+) -> Result<bool, ChatError> {
+    for hook in hooks {
+        let outcome = match stage {
+            Stage::PrePrompt => hook.pre_prompt(io).await?,
+            Stage::PostUsername(username) => hook.post_username(io, username).await?,
+            Stage::PreJoin(username) => hook.pre_join(io, username).await?,
+        };
+
+        if let HookOutcome::Reject(reason) = outcome {
+            // An empty reason means there's no one left to tell.
+            if !reason.is_empty() {
+                io.send(&reason).await?;
+            }
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// A slot among the connections still in the handshake.
+///
+/// RAII again: the count goes back down when the guard is dropped —
+/// whether the user finished logging in, gave up, timed out, or the
+/// task died. No path can forget to decrement.
+pub struct PendingGuard {
+    count: Arc<AtomicUsize>,
+}
+
+impl PendingGuard {
+    /// Claim a slot, or None if `max` connections are already pending.
+    pub fn try_acquire(count: &Arc<AtomicUsize>, max: usize) -> Option<Self> {
+        count
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .ok()?;
+        Some(Self {
+            count: Arc::clone(count),
+        })
+    }
+
+    /// A slot counted against no listener, for a connection that didn't
+    /// come through one.
+    pub fn detached() -> Self {
+        Self {
+            count: Arc::new(AtomicUsize::new(1)),
+        }
+    }
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::AcqRel);
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Messages kept per room unless configured otherwise. Older ones fall
+/// off the front.
+pub const KEEP: usize = 1_000;
+
+/// Most messages replayed on joining a room. Replay goes through the
+/// client's event queue, `outbox_size` events, and a small queue caps
+/// it further: see `Server::replay_limit`.
+pub const MAX_REPLAY: usize = 50;
+
+/// Most messages one HISTORY request returns.
+pub const MAX_PAGE: usize = 100;
+
+/// Page size when a request doesn't give a limit.
+pub const DEFAULT_PAGE: usize = 50;
+
+/// One message as history remembers it.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Position in the room's history. Starts at 1 and never repeats,
+    /// so a client can ask for "everything before 41" and mean it.
+    pub seq: u64,
+    pub at: SystemTime,
+    pub from: String,
+    pub body: String,
+}
+
+impl Entry {
+    /// Seconds since the Unix epoch, for the wire.
+    pub fn timestamp(&self) -> u64 {
+        self.at
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+/// A room's recent messages, oldest first.
+///
+/// Sequence numbers keep counting after old entries are dropped, so a
+/// page boundary a client holds stays meaningful.
+pub struct History {
+    entries: VecDeque<Entry>,
+    keep: usize,
+    next_seq: u64,
+}
+
+/// One page of history, oldest first.
+pub struct Page<'a> {
+    pub entries: Vec<&'a Entry>,
+    /// Whether anything older than this page is still kept.
+    pub more: bool,
+}
+
+impl History {
+    /// A history holding at most `keep` messages.
+    pub fn new(keep: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            keep,
+            next_seq: 1,
+        }
+    }
+
+    pub fn push(&mut self, from: &str, body: &str) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        // Numbered even when nothing is kept, so read markers still work.
+        if self.keep == 0 {
+            return seq;
+        }
+        if self.entries.len() == self.keep {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Entry {
+            seq,
+            at: SystemTime::now(),
+            from: from.to_string(),
+            body: body.to_string(),
+        });
+        seq
+    }
+
+    /// The number the next message kept will get, without taking it:
+    /// for a message only its sender sees. Taking it would leave a gap
+    /// everyone else could notice; this way the sender alone sees the
+    /// number again, on whatever is said next.
+    pub fn peek(&self) -> u64 {
+        self.next_seq
+    }
+
+    /// Take back messages kept from before a restart, oldest first.
+    /// Numbering carries on after the newest, so read markers and page
+    /// boundaries from last time still mean the same messages.
+    ///
+    /// Storage that can only append records an edit as the message
+    /// again, same number, new body, and a delete as an empty body. The
+    /// last word on each number wins.
+    pub fn restore(&mut self, entries: Vec<Entry>) {
+        let mut latest: BTreeMap<u64, Entry> = BTreeMap::new();
+        for entry in entries {
+            latest.insert(entry.seq, entry);
+        }
+        if let Some(&last) = latest.keys().next_back() {
+            self.next_seq = self.next_seq.max(last + 1);
+        }
+        let kept: Vec<Entry> = latest
+            .into_values()
+            .filter(|entry| !entry.body.is_empty())
+            .collect();
+        let skip = kept.len().saturating_sub(self.keep);
+        self.entries = kept.into_iter().skip(skip).collect();
+    }
+
+    /// Everything kept, oldest first, for `restore` to take back later.
+    pub fn into_entries(self) -> Vec<Entry> {
+        self.entries.into()
+    }
+
+    /// The message numbered `seq`, if it's still kept.
+    pub fn get(&self, seq: u64) -> Option<&Entry> {
+        let at = self.entries.binary_search_by_key(&seq, |e| e.seq).ok()?;
+        self.entries.get(at)
+    }
+
+    /// Change what message `seq` says. False if it isn't kept.
+    pub fn edit(&mut self, seq: u64, body: &str) -> bool {
+        match self.entries.binary_search_by_key(&seq, |e| e.seq) {
+            Ok(at) => {
+                self.entries[at].body = body.to_string();
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Forget message `seq`. Its number isn't reused.
+    pub fn remove(&mut self, seq: u64) -> Option<Entry> {
+        let at = self.entries.binary_search_by_key(&seq, |e| e.seq).ok()?;
+        self.entries.remove(at)
+    }
+
+    /// The newest sequence number handed out, 0 before any message.
+    pub fn latest(&self) -> u64 {
+        self.next_seq - 1
+    }
+
+    /// Every entry still kept that's newer than `seq`, oldest first.
+    pub fn since(&self, seq: u64) -> Vec<&Entry> {
+        let start = self.entries.partition_point(|e| e.seq <= seq);
+        self.entries.range(start..).collect()
+    }
+
+    /// Up to `limit` entries older than `before`, or the newest ones if
+    /// `before` is None.
+    pub fn page(&self, before: Option<u64>, limit: usize) -> Page<'_> {
+        let end = match before {
+            Some(seq) => self.entries.partition_point(|e| e.seq < seq),
+            None => self.entries.len(),
+        };
+        let start = end.saturating_sub(limit.min(MAX_PAGE));
+        Page {
+            entries: self.entries.range(start..end).collect(),
+            more: start > 0,
+        }
+    }
+}
+
+/// The last message each reader was sent in each room, by sequence
+/// number.
+///
+/// A reader is an account, for whoever signed in to one: their markers
+/// follow them across connections and nicks, and are handed to storage
+/// when they leave, to last a restart. A guest has nothing but their
+/// name, and a name is anyone's once they've gone, so a guest's markers
+/// are keyed by it only while they're connected, and then forgotten.
+pub struct ReadMarkers {
+    markers: HashMap<String, HashMap<String, u64>>,
+}
+
+impl ReadMarkers {
+    pub fn new() -> Self {
+        Self {
+            markers: HashMap::new(),
+        }
+    }
+
+    /// Markers kept from before a restart: reader, room, sequence.
+    pub fn from_list(list: Vec<(String, String, u64)>) -> Self {
+        let mut markers = Self::new();
+        for (reader, room, seq) in list {
+            markers.mark(&reader, &room, seq);
+        }
+        markers
+    }
+
+    /// Called for every member on every message, so an existing marker
+    /// is moved in place rather than by allocating its keys again.
+    pub fn mark(&mut self, reader: &str, room: &str, seq: u64) {
+        if let Some(marker) = self
+            .markers
+            .get_mut(reader)
+            .and_then(|rooms| rooms.get_mut(room))
+        {
+            *marker = seq;
+            return;
+        }
+        self.markers
+            .entry(reader.to_string())
+            .or_default()
+            .insert(room.to_string(), seq);
+    }
+
+    /// Drop every marker for a room that's gone, so a new room by the
+    /// same name starts everyone from its first message.
+    pub fn forget_room(&mut self, room: &str) {
+        for rooms in self.markers.values_mut() {
+            rooms.remove(room);
+        }
+        self.markers.retain(|_, rooms| !rooms.is_empty());
+    }
+
+    /// The last sequence number `reader` saw in `room`, if they've
+    /// ever been there.
+    pub fn last_seen(&self, reader: &str, room: &str) -> Option<u64> {
+        self.markers.get(reader)?.get(room).copied()
+    }
+
+    /// Everything `reader` has read, by room.
+    pub fn rooms(&self, reader: &str) -> Option<&HashMap<String, u64>> {
+        self.markers.get(reader)
+    }
+
+    /// Drop `reader`'s markers, handing them back.
+    pub fn take(&mut self, reader: &str) -> HashMap<String, u64> {
+        self.markers.remove(reader).unwrap_or_default()
+    }
+
+    /// Put back markers taken with `take`.
+    pub fn restore(&mut self, reader: &str, rooms: HashMap<String, u64>) {
+        if !rooms.is_empty() {
+            self.markers.insert(reader.to_string(), rooms);
+        }
+    }
+
+    /// A guest changed names: their markers go with them.
+    pub fn rename(&mut self, old: &str, new: &str) {
+        let rooms = self.take(old);
+        self.restore(new, rooms);
+    }
+}
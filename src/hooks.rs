@@ -0,0 +1,82 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::server::Server;
+use crate::types::{RoomId, UserId};
+
+/// Lightweight callbacks for embedders who want to react to what
+/// happens without writing a whole Plugin.
+///
+/// Unlike bus subscribers, hooks run inline, with the server lock held,
+/// and get `&mut Server` — so a join hook can greet the newcomer right
+/// away. The flip side: keep them quick, and don't block.
+pub type JoinHook = Arc<dyn Fn(&mut Server, &JoinInfo) + Send + Sync>;
+pub type MessageHook = Arc<dyn Fn(&mut Server, &MessageInfo) + Send + Sync>;
+pub type DisconnectHook = Arc<dyn Fn(&mut Server, &DisconnectInfo) + Send + Sync>;
+
+/// A user has just joined a room.
+pub struct JoinInfo {
+    pub user_id: UserId,
+    pub username: String,
+    pub room_id: RoomId,
+    pub room: String,
+}
+
+/// A message has just been delivered to a room (after filters).
+pub struct MessageInfo {
+    pub user_id: UserId,
+    pub username: String,
+    pub room_id: RoomId,
+    pub room: String,
+    pub body: String,
+}
+
+/// Why a session ended.
+#[derive(Debug, Clone)]
+pub enum DisconnectReason {
+    /// The user typed /quit.
+    Quit,
+    /// The client closed the socket without saying goodbye.
+    Closed,
+    /// Removed from the server by an operator.
+    Kicked,
+    /// The client's socket wouldn't take a line for too long.
+    Timeout,
+    /// The client sent nothing for longer than the read timeout.
+    Idle,
+    /// The client read so slowly that its outbound queue overflowed.
+    TooSlow,
+    /// The client didn't answer a PING in time.
+    Unresponsive,
+    /// Kicked by the anti-spam filter, for one strike too many.
+    Spam,
+    /// The server is going down.
+    Shutdown,
+    /// The connection failed (I/O error, invalid data).
+    Error(String),
+}
+
+impl std::fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisconnectReason::Quit => write!(f, "quit"),
+            DisconnectReason::Closed => write!(f, "connection closed"),
+            DisconnectReason::Kicked => write!(f, "kicked"),
+            DisconnectReason::Timeout => write!(f, "timed out"),
+            DisconnectReason::Idle => write!(f, "idle"),
+            DisconnectReason::TooSlow => write!(f, "too slow to keep up"),
+            DisconnectReason::Unresponsive => write!(f, "no reply to ping"),
+            DisconnectReason::Spam => write!(f, "kicked for spam"),
+            DisconnectReason::Shutdown => write!(f, "server shutting down"),
+            DisconnectReason::Error(e) => write!(f, "error: {e}"),
+        }
+    }
+}
+
+/// A user's session has ended; they have already left their room.
+pub struct DisconnectInfo {
+    pub user_id: UserId,
+    pub username: String,
+    pub session: Duration,
+    pub reason: DisconnectReason,
+}
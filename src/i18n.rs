@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+
+use crate::error::ChatError;
+
+/// Every piece of text the server says on its own behalf.
+///
+/// Call sites name a message by identifier instead of spelling out
+/// English, so a deployment can swap the words without touching code.
+/// Templates use `{name}` placeholders filled in by `Catalog::render`.
+///
+/// Each message also has a key, its name in the locale bundles: see
+/// `Catalog`.
+macro_rules! messages {
+    ($($id:ident => $key:literal,)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum MsgId {
+            $($id,)*
+        }
+
+        impl MsgId {
+            /// Every message, in the order they're declared.
+            pub const ALL: &[MsgId] = &[$(MsgId::$id,)*];
+
+            /// The message's key in a locale bundle.
+            pub fn key(self) -> &'static str {
+                match self {
+                    $(MsgId::$id => $key,)*
+                }
+            }
+
+            pub fn from_key(key: &str) -> Option<Self> {
+                Self::ALL.iter().copied().find(|id| id.key() == key)
+            }
+        }
+    };
+}
+
+messages! {
+    EnterUsername => "enter_username",
+    HandshakeTimeout => "handshake_timeout",
+    ServerBusy => "server_busy",
+    ServerFull => "server_full",
+    TooManyFromAddress => "too_many_from_address",
+    ChallengeWrong => "challenge_wrong",
+    ChallengeFailed => "challenge_failed",
+    Welcome => "welcome",
+    WelcomeBack => "welcome_back",
+    Joined => "joined",
+    Left => "left",
+    TimedOut => "timed_out",
+    IdleDisconnect => "idle_disconnect",
+    PingTimeout => "ping_timeout",
+    YouJoined => "you_joined",
+    YouLeft => "you_left",
+    Switched => "switched",
+    NotJoined => "not_joined",
+    OnlyRoom => "only_room",
+    NickChanged => "nick_changed",
+    YouAreMuted => "you_are_muted",
+    MutedConfirm => "muted_confirm",
+    StillMuted => "still_muted",
+    Unmuted => "unmuted",
+    UnmutedNotice => "unmuted_notice",
+    MessageBlocked => "message_blocked",
+    MessageTruncated => "message_truncated",
+    Kicked => "kicked",
+    YouWereKicked => "you_were_kicked",
+    NickAnnounce => "nick_announce",
+    RoomOperatorGranted => "room_operator_granted",
+    RoomOperatorRevoked => "room_operator_revoked",
+    OwnerKeepsRole => "owner_keeps_role",
+    Topic => "topic",
+    NoTopic => "no_topic",
+    TopicChanged => "topic_changed",
+    Ignoring => "ignoring",
+    Unignored => "unignored",
+    NotIgnoring => "not_ignoring",
+    IgnoreList => "ignore_list",
+    IgnoringNobody => "ignoring_nobody",
+    NowAway => "now_away",
+    NoLongerAway => "no_longer_away",
+    NotAway => "not_away",
+    AwayReply => "away_reply",
+    AwayDefault => "away_default",
+    WhoList => "who_list",
+    WhoAway => "who_away",
+    SpamWarning => "spam_warning",
+    SpamKicked => "spam_kicked",
+    ShadowMuted => "shadow_muted",
+    ShadowUnmuted => "shadow_unmuted",
+    RoomPasswordSet => "room_password_set",
+    RoomPasswordCleared => "room_password_cleared",
+    NotInRoom => "not_in_room",
+    SettingChanged => "setting_changed",
+    Help => "help",
+    Language => "language",
+    LanguageSet => "language_set",
+    UnknownLanguage => "unknown_language",
+    OperGranted => "oper_granted",
+    DrainStarted => "drain_started",
+    ShuttingDown => "shutting_down",
+    AdminDisconnect => "admin_disconnect",
+    AdminNotice => "admin_notice",
+    Draining => "draining",
+    RoomStats => "room_stats",
+    ServerStats => "server_stats",
+    RoomList => "room_list",
+    RoomListEntry => "room_list_entry",
+    RoomListHere => "room_list_here",
+    NoRoomsMatch => "no_rooms_match",
+    YouAreBanned => "you_are_banned",
+    BanConfirm => "ban_confirm",
+    Unbanned => "unbanned",
+    NotBanned => "not_banned",
+    BannedRefusal => "banned_refusal",
+    EvasionAlert => "evasion_alert",
+    TrustTooLow => "trust_too_low",
+    QuotaReached => "quota_reached",
+    Missed => "missed",
+    MissedMore => "missed_more",
+    InviteCreated => "invite_created",
+    InviteInvalid => "invite_invalid",
+    RoomPrivate => "room_private",
+    RoomBanConfirm => "room_ban_confirm",
+    RoomUnbanned => "room_unbanned",
+    RoomNotBanned => "room_not_banned",
+    RoomBannedRefusal => "room_banned_refusal",
+    LobbyUnbannable => "lobby_unbannable",
+    FileShared => "file_shared",
+    PollOpened => "poll_opened",
+    PollClosed => "poll_closed",
+    PollRunning => "poll_running",
+    NoPoll => "no_poll",
+    PollCloseDenied => "poll_close_denied",
+    VoteCounted => "vote_counted",
+    AlreadyVoted => "already_voted",
+    NoSuchOption => "no_such_option",
+    ReminderSet => "reminder_set",
+    RoomReminderSet => "room_reminder_set",
+    Reminder => "reminder",
+    RoomReminder => "room_reminder",
+    TooManyReminders => "too_many_reminders",
+    Goodbye => "goodbye",
+    Error => "error",
+}
+
+/// The built-in translations, one TOML bundle per language: a key per
+/// message, and an `[errors]` table of error details by code. English
+/// is complete, and the fallback for whatever another bundle leaves
+/// out. Adding a language is adding a file here.
+const BUNDLES: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.toml")),
+    ("es", include_str!("../locales/es.toml")),
+];
+
+/// The language everything falls back to.
+const FALLBACK: &str = "en";
+
+/// One language's translations.
+struct Bundle {
+    messages: HashMap<MsgId, String>,
+    /// Error details, by code, with the error's fields as placeholders
+    /// (see `ChatError::args`). `internal` stands for every error a
+    /// client isn't told the details of.
+    errors: HashMap<String, String>,
+}
+
+impl Bundle {
+    /// The bundles are part of the program, so a mistake in one is a
+    /// bug, caught the first time the server starts.
+    fn parse(locale: &str, text: &str) -> Self {
+        let table: toml::Table = text
+            .parse()
+            .unwrap_or_else(|e| panic!("locale bundle {locale}: {e}"));
+        let mut bundle = Self {
+            messages: HashMap::new(),
+            errors: HashMap::new(),
+        };
+        for (key, value) in table {
+            match (key.as_str(), value) {
+                ("errors", toml::Value::Table(errors)) => {
+                    for (code, text) in errors {
+                        let toml::Value::String(text) = text else {
+                            panic!("locale bundle {locale}: errors.{code} isn't a string");
+                        };
+                        bundle.errors.insert(code, text);
+                    }
+                }
+                (key, toml::Value::String(text)) => {
+                    let id = MsgId::from_key(key)
+                        .unwrap_or_else(|| panic!("locale bundle {locale}: unknown message {key}"));
+                    bundle.messages.insert(id, text);
+                }
+                (key, _) => panic!("locale bundle {locale}: {key} isn't a string"),
+            }
+        }
+        if locale == FALLBACK
+            && let Some(id) = MsgId::ALL
+                .iter()
+                .find(|id| !bundle.messages.contains_key(id))
+        {
+            panic!("locale bundle {locale}: {} is missing", id.key());
+        }
+        bundle
+    }
+}
+
+/// The server's message catalog: a default locale, operator templates,
+/// the built-in translations, and the one rendering helper every
+/// system message goes through.
+pub struct Catalog {
+    locale: String,
+    templates: HashMap<MsgId, String>,
+    bundles: HashMap<&'static str, Bundle>,
+}
+
+impl Catalog {
+    pub fn new(locale: impl Into<String>, templates: HashMap<MsgId, String>) -> Self {
+        let bundles = BUNDLES
+            .iter()
+            .map(|&(locale, text)| (locale, Bundle::parse(locale, text)))
+            .collect();
+        Self {
+            locale: locale.into(),
+            templates,
+            bundles,
+        }
+    }
+
+    /// Render a message in `locale`, or the server default if None.
+    ///
+    /// An operator template wins over every built-in translation — if
+    /// you've customised the join line, that's what everyone sees.
+    pub fn render(&self, locale: Option<&str>, id: MsgId, args: &[(&str, &str)]) -> String {
+        let template = self.templates.get(&id).or_else(|| {
+            self.bundle(locale)
+                .and_then(|bundle| bundle.messages.get(&id))
+                .or_else(|| self.bundles[FALLBACK].messages.get(&id))
+        });
+        fill(template.map_or(id.key(), String::as_str), args)
+    }
+
+    /// What a client is told went wrong, in `locale`: the bundle's
+    /// translation if it has one, else the error's own English.
+    pub fn error_text(&self, locale: Option<&str>, err: &ChatError) -> String {
+        let key = if err.is_client_safe() {
+            err.code().to_string()
+        } else {
+            "internal".to_string()
+        };
+        let Some(template) = self.bundle(locale).and_then(|b| b.errors.get(&key)) else {
+            return err.client_text();
+        };
+        let args = err.args();
+        let args: Vec<(&str, &str)> = args.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        fill(template, &args)
+    }
+
+    /// Whether there's a bundle for `locale`, for `/lang` to offer.
+    pub fn has_locale(&self, locale: &str) -> bool {
+        self.bundles.contains_key(locale)
+    }
+
+    /// Every language there's a bundle for, sorted.
+    pub fn locales(&self) -> Vec<&'static str> {
+        let mut locales: Vec<&'static str> = self.bundles.keys().copied().collect();
+        locales.sort_unstable();
+        locales
+    }
+
+    fn bundle(&self, locale: Option<&str>) -> Option<&Bundle> {
+        self.bundles.get(locale.unwrap_or(&self.locale))
+    }
+}
+
+/// Substitute `{name}` placeholders. Unknown placeholders are left as-is
+/// so a typo in a template is visible rather than silently blank.
+pub fn fill(template: &str, args: &[(&str, &str)]) -> String {
+    let mut out = template.to_string();
+    for (name, value) in args {
+        out = out.replace(&format!("{{{name}}}"), value);
+    }
+    out
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use crate::types::RoomId;
+
+/// How long a code lasts when `/invitecode` doesn't say.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Letters and digits that can't be mistaken for each other when read
+/// out or copied by hand: no 0/O, 1/I/L.
+pub const ALPHABET: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+const CODE_LEN: usize = 8;
+
+/// An outstanding invite.
+pub struct Invite {
+    pub room_id: RoomId,
+    pub uses_left: u32,
+}
+
+/// Invite codes by code.
+///
+/// Each code is removed when its last use is spent, or by the scheduler
+/// when its time is up — whichever comes first.
+pub struct Invites {
+    codes: HashMap<String, Invite>,
+    /// A fresh random key per server. Hashing a counter with it gives
+    /// codes nobody can predict, without a dependency for randomness.
+    keys: RandomState,
+    issued: u64,
+}
+
+impl Invites {
+    pub fn new() -> Self {
+        Self {
+            codes: HashMap::new(),
+            keys: RandomState::new(),
+            issued: 0,
+        }
+    }
+
+    /// Issue a code for `room_id`, good for `uses` joins.
+    pub fn create(&mut self, room_id: RoomId, uses: u32) -> String {
+        let code = loop {
+            let code = self.next_code();
+            if !self.codes.contains_key(&code) {
+                break code;
+            }
+        };
+        self.codes.insert(
+            code.clone(),
+            Invite {
+                room_id,
+                uses_left: uses,
+            },
+        );
+        code
+    }
+
+    /// Spend one use of `code`. Codes are matched case-insensitively,
+    /// since people will type them.
+    pub fn redeem(&mut self, code: &str) -> Option<RoomId> {
+        let code = code.trim().to_ascii_uppercase();
+        let invite = self.codes.get_mut(&code)?;
+        let room_id = invite.room_id;
+        invite.uses_left -= 1;
+        if invite.uses_left == 0 {
+            self.codes.remove(&code);
+        }
+        Some(room_id)
+    }
+
+    pub fn expire(&mut self, code: &str) {
+        self.codes.remove(code);
+    }
+
+    fn next_code(&mut self) -> String {
+        self.issued += 1;
+        random_code(&self.keys, self.issued, CODE_LEN)
+    }
+}
+
+/// `len` characters from ALPHABET, picked by hashing `counter` with
+/// `keys`. A u64 holds a dozen characters' worth of randomness, so
+/// `len` shouldn't be more than that.
+fn random_code(keys: &RandomState, counter: u64, len: usize) -> String {
+    let mut hasher = keys.build_hasher();
+    hasher.write_u64(counter);
+    let mut bits = hasher.finish();
+
+    let mut code = String::with_capacity(len);
+    for _ in 0..len {
+        code.push(ALPHABET[(bits % ALPHABET.len() as u64) as usize] as char);
+        bits /= ALPHABET.len() as u64;
+    }
+    code
+}
@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// What a connection's keepalive timer wants done when it fires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Due {
+    /// Quiet for a whole interval: send `PING:<token>`.
+    Ping(u64),
+    /// Pinged, and nothing came back within the window.
+    Expired,
+}
+
+/// Application-level keepalive for one connection.
+///
+/// TCP keepalive notices a peer that vanished, eventually, but only
+/// between the two kernels; a PING proves the client program itself is
+/// still reading. Anything the client sends counts as a sign of life,
+/// not just a PONG, so a busy client is never pinged at all.
+///
+/// Only clients that speak the protocol are pinged. A person on telnet
+/// would see a stray `PING:` line and be dropped for not answering it,
+/// so pinging starts once a client sends a PING, a PONG or a PROTO
+/// frame of its own.
+pub struct KeepAlive {
+    interval: Option<Duration>,
+    window: Duration,
+    enabled: bool,
+    /// When the timer next fires: a ping, or the end of the window.
+    deadline: Instant,
+    /// A PING is out and nothing has come back yet.
+    waiting: bool,
+    sent: u64,
+}
+
+impl KeepAlive {
+    /// `interval` of None turns server PINGs off.
+    pub fn new(interval: Option<Duration>, window: Duration) -> Self {
+        Self {
+            interval,
+            window,
+            enabled: false,
+            deadline: Instant::now(),
+            waiting: false,
+            sent: 0,
+        }
+    }
+
+    /// The client speaks the protocol, so PINGs won't bother it.
+    pub fn enable(&mut self) {
+        if !self.enabled {
+            self.enabled = true;
+            self.heard();
+        }
+    }
+
+    /// The client sent something: it's alive, start the interval over.
+    pub fn heard(&mut self) {
+        self.waiting = false;
+        if let Some(interval) = self.interval {
+            self.deadline = Instant::now() + interval;
+        }
+    }
+
+    /// Sleep until the timer fires. Never, if pinging is off or the
+    /// client hasn't opted in, so it can sit in a `select!` either way.
+    pub async fn wait(&self) {
+        if self.enabled && self.interval.is_some() {
+            tokio::time::sleep_until(self.deadline).await;
+        } else {
+            std::future::pending::<()>().await;
+        }
+    }
+
+    /// The timer fired: ping, or give up if the last ping went
+    /// unanswered.
+    pub fn fire(&mut self) -> Due {
+        if self.waiting {
+            return Due::Expired;
+        }
+        self.sent += 1;
+        self.waiting = true;
+        self.deadline = Instant::now() + self.window;
+        Due::Ping(self.sent)
+    }
+}
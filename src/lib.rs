@@ -0,0 +1,176 @@
+//! A chat server you can run as it is, or embed in your own program.
+//!
+//! The binary is a few lines on top of this crate: build a
+//! `ServerConfig`, make a `Server`, add whatever filters and commands
+//! you like, and hand it to `run`.
+//!
+//! ```no_run
+//! use rust_chat_server::{
+//!     ChatError, EventRegistry, FilterAction, FilterRegistry, Server, ServerConfig,
+//! };
+//!
+//! # async fn serve() -> Result<(), ChatError> {
+//! let config = ServerConfig::builder().port(9000).build();
+//! let mut server = Server::new(config)?;
+//! let mut filters = FilterRegistry::new();
+//! filters.add(|_ctx, body| {
+//!     if body.contains("spam") {
+//!         FilterAction::Block("no spam, please".into())
+//!     } else {
+//!         FilterAction::Allow
+//!     }
+//! });
+//! server.add_filter_registry(filters);
+//! let mut events = EventRegistry::new();
+//! events.on_join(|user, room| println!("{user} joined #{room}"));
+//! server.add_event_registry(events);
+//! rust_chat_server::run(server).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! `config`, `error`, `filter`, `protocol` and `server` are public, for
+//! everything those few types lead to, and the types an extension needs
+//! — commands, handshake hooks, plugins, event hooks, ids — are
+//! re-exported here. The other modules stay private: they're how the
+//! server works inside, and free to change.
+
+#[cfg(unix)]
+mod admin;
+mod antispam;
+mod auth;
+mod ban;
+mod bot;
+mod bus;
+mod command;
+mod compression;
+pub mod config;
+mod dedup;
+pub mod error;
+mod events;
+mod feed;
+pub mod filter;
+mod fun;
+mod handshake;
+mod history;
+mod hooks;
+mod i18n;
+mod invite;
+mod keepalive;
+mod lines;
+mod listener;
+pub mod logging;
+mod message;
+mod metrics;
+mod multicast;
+mod permissions;
+mod persistence;
+mod plugin;
+mod poll;
+pub mod protocol;
+mod ratelimit;
+mod render;
+mod resume;
+mod room;
+mod scheduler;
+#[cfg(feature = "scripting")]
+mod scripting;
+mod sequence;
+pub mod server;
+mod sessions;
+mod settings;
+mod share;
+mod slab;
+mod socket;
+mod storage;
+mod summary;
+mod telnet;
+mod tokens;
+mod transport;
+mod trust;
+mod types;
+mod validation;
+mod wordlist;
+
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+
+pub use antispam::AntiSpamConfig;
+pub use bot::Bot;
+pub use bus::ServerEvent;
+pub use command::{CommandContext, CommandHandler, CommandResult};
+pub use config::ServerConfig;
+pub use error::ChatError;
+pub use events::EventRegistry;
+pub use filter::{FilterAction, FilterContext, FilterRegistry};
+pub use handshake::{HandshakeHook, HandshakeIo, HookFuture, HookOutcome};
+pub use hooks::{DisconnectInfo, DisconnectReason, JoinInfo, MessageInfo};
+pub use plugin::Plugin;
+pub use protocol::{Frame, WireFormat, parse_frame};
+pub use room::ExpiryAction;
+pub use scheduler::TaskId;
+pub use server::{AsyncFilter, Server};
+pub use types::{RoomId, UserId};
+
+/// Start everything the config asks for around `server` and accept
+/// clients until the listener stops.
+///
+/// Filters, commands and hooks go on the server before this: once it's
+/// running it belongs to the tasks sharing it. Storage, plugins and
+/// scripts are loaded here, so they come after anything added in code.
+pub async fn run(mut server: Server) -> Result<(), ChatError> {
+    if server.config.fun_commands {
+        for command in fun::commands() {
+            server.register_command(command)?;
+        }
+    }
+    server.open_storage()?;
+    server.open_multicast()?;
+    server.load_tokens()?;
+    let bots = server.take_bots();
+    plugin::load_plugins(&mut server)?;
+    #[cfg(feature = "scripting")]
+    scripting::init(&mut server)?;
+
+    #[cfg(unix)]
+    let console = match &server.config.admin_socket {
+        Some(path) => Some(admin::bind(path)?),
+        None => None,
+    };
+
+    let metrics = match server.config.metrics_port {
+        Some(port) => Some(metrics::bind(&server.config.addr, port).await?),
+        None => None,
+    };
+
+    let server = Arc::new(Mutex::new(server));
+
+    #[cfg(unix)]
+    if let Some(console) = console {
+        tokio::spawn(admin::serve(Arc::clone(&server), console));
+    }
+
+    if let Some(metrics) = metrics {
+        tokio::spawn(metrics::serve(Arc::clone(&server), metrics));
+    }
+
+    #[cfg(unix)]
+    if server.lock().await.config.config_file.is_some() {
+        tokio::spawn(settings::reload_on_hangup(Arc::clone(&server)));
+    }
+
+    for (user_id, actions) in bots {
+        tokio::spawn(server::run_bot(Arc::clone(&server), user_id, actions));
+    }
+
+    // Timed work (mute expiry, announcements, ...) runs on its own task.
+    server::spawn_scheduler(&server);
+
+    let feeds = server.lock().await.config.feeds.clone();
+    for feed in feeds {
+        tokio::spawn(feed::run(Arc::clone(&server), feed));
+    }
+
+    listener::serve(server).await
+}
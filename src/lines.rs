@@ -0,0 +1,227 @@
+use std::borrow::Cow;
+use std::io;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, BufReader};
+
+use crate::telnet::TelnetFilter;
+use crate::transport::BoxedReader;
+
+/// What to do with bytes that aren't valid UTF-8.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decoding {
+    /// Treat invalid UTF-8 as a protocol error and drop the client.
+    Strict,
+    /// Replace each invalid sequence with U+FFFD and carry on.
+    Lossy,
+    /// Decode lines that aren't UTF-8 as Latin-1. Every byte is a valid
+    /// Latin-1 character, so nothing is lost — the right choice when
+    /// old clients send ISO-8859-1 rather than garbage.
+    Latin1,
+}
+
+impl Decoding {
+    fn decode(self, bytes: Vec<u8>) -> io::Result<String> {
+        // Valid UTF-8 is always taken as-is; the modes only differ on
+        // what happens when it isn't.
+        let bytes = match String::from_utf8(bytes) {
+            Ok(line) => return Ok(line),
+            Err(e) => e.into_bytes(),
+        };
+        match self {
+            Decoding::Strict => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "stream did not contain valid UTF-8",
+            )),
+            Decoding::Lossy => Ok(String::from_utf8_lossy(&bytes).into_owned()),
+            Decoding::Latin1 => Ok(bytes.iter().map(|&b| b as char).collect()),
+        }
+    }
+}
+
+/// How one line is told from the next on the wire.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// Each line ends at a `\n`. What telnet and people typing send,
+    /// and where every connection starts.
+    #[default]
+    Newline,
+    /// Each line is a 4-byte big-endian length, then exactly that many
+    /// bytes. Nothing inside needs escaping, newlines included, and the
+    /// reader never has to search for where a line ends. Client
+    /// programs ask for it with CAP:binary.
+    LengthPrefixed,
+}
+
+impl Framing {
+    /// Ready text for the wire. `text` is what one event rendered to:
+    /// one or more lines, the last ending in `\n`. Length-prefixed, it
+    /// all goes as one frame, less that last newline, so a frame is
+    /// always exactly one thing the server had to say.
+    pub fn encode(self, text: &str) -> Cow<'_, [u8]> {
+        match self {
+            Framing::Newline => Cow::Borrowed(text.as_bytes()),
+            Framing::LengthPrefixed => {
+                let payload = text.strip_suffix('\n').unwrap_or(text).as_bytes();
+                let mut frame = Vec::with_capacity(4 + payload.len());
+                frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+                frame.extend_from_slice(payload);
+                Cow::Owned(frame)
+            }
+        }
+    }
+}
+
+/// Drop the line terminator, whichever one the client uses.
+///
+/// Unix clients end lines with `\n`, Windows telnet and PuTTY with
+/// `\r\n`. Without this a Windows user's name is "alice\r" — which
+/// prints fine and then never matches anything.
+pub fn trim_line_ending(line: &str) -> &str {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+/// The one place bytes from a client become lines of text.
+///
+/// Both the handshake and the chat loop read through this, so whatever
+/// cleanup the wire needs (telnet negotiation, line endings, decoding)
+/// happens
+/// exactly once, before any code sees a username or a message.
+pub struct LineReader {
+    inner: BufReader<BoxedReader>,
+    telnet: TelnetFilter,
+    decoding: Decoding,
+    framing: Framing,
+    buf: Vec<u8>,
+    /// Bytes kept per line; the rest of a longer one is skipped.
+    max_line: usize,
+}
+
+impl LineReader {
+    pub fn new(read_half: BoxedReader, decoding: Decoding, max_line: usize) -> Self {
+        Self {
+            inner: BufReader::new(read_half),
+            telnet: TelnetFilter::new(),
+            decoding,
+            framing: Framing::Newline,
+            buf: Vec::new(),
+            max_line,
+        }
+    }
+
+    /// Switch how lines are read from here on. Anything already
+    /// buffered is read the new way too, so a client must wait for the
+    /// server to agree before it sends its first frame.
+    pub fn set_framing(&mut self, framing: Framing) {
+        self.framing = framing;
+    }
+
+    /// Read through `wrap` from here on — a decompressor, say. Bytes
+    /// already buffered go through it too, ahead of the rest.
+    pub fn wrap(&mut self, wrap: impl FnOnce(BoxedReader) -> BoxedReader) {
+        let buffered = self.inner.buffer().to_vec();
+        let old = std::mem::replace(
+            &mut self.inner,
+            BufReader::new(Box::new(tokio::io::empty())),
+        );
+        let rest = io::Cursor::new(buffered).chain(old.into_inner());
+        self.inner = BufReader::new(wrap(Box::new(rest)));
+    }
+
+    /// Read one line, without its line ending. `None` means the client
+    /// hung up. A line longer than `max_line` comes back cut to that
+    /// length.
+    pub async fn read_line(&mut self) -> io::Result<Option<String>> {
+        self.buf.clear();
+        if self.framing == Framing::LengthPrefixed {
+            return self.read_frame().await;
+        }
+        if read_until_capped(&mut self.inner, &mut self.buf, self.max_line).await? == 0 {
+            return Ok(None);
+        }
+
+        self.telnet.strip(&mut self.buf);
+
+        let mut line = self.decoding.decode(std::mem::take(&mut self.buf))?;
+        line.truncate(trim_line_ending(&line).len());
+        Ok(Some(line))
+    }
+
+    /// One length-prefixed frame, taken exactly as sent: no telnet, no
+    /// line endings to trim.
+    ///
+    /// The length is checked before anything is read. A frame can't be
+    /// cut short the way a long line is: skipping the rest would mean
+    /// trusting four bytes that may well be garbage, and reading
+    /// gigabytes to find out. Too long is taken as a broken client, and
+    /// dropped.
+    async fn read_frame(&mut self) -> io::Result<Option<String>> {
+        let mut len = [0; 4];
+        match self.inner.read_exact(&mut len).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if len > self.max_line {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "frame of {len} bytes is over the {}-byte limit",
+                    self.max_line
+                ),
+            ));
+        }
+        self.buf.resize(len, 0);
+        self.inner.read_exact(&mut self.buf).await?;
+        self.decoding
+            .decode(std::mem::take(&mut self.buf))
+            .map(Some)
+    }
+
+    /// Telnet answers owed to the client. Only the handshake sends them:
+    /// clients negotiate when they connect, and after that the writer
+    /// task owns the socket.
+    pub fn take_telnet_replies(&mut self) -> Vec<u8> {
+        self.telnet.take_replies()
+    }
+}
+
+/// Like `read_until(b'\n')`, but keeps at most `max` bytes. The rest of
+/// the line is still consumed, so the next read starts on the next
+/// line. Returns how many bytes were consumed, kept or not.
+async fn read_until_capped<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    buf: &mut Vec<u8>,
+    max: usize,
+) -> io::Result<usize> {
+    let mut consumed = 0;
+    loop {
+        let available = reader.fill_buf().await?;
+        if available.is_empty() {
+            break;
+        }
+        let (chunk, done) = match available.iter().position(|&b| b == b'\n') {
+            Some(at) => (&available[..=at], true),
+            None => (available, false),
+        };
+        let room = max.saturating_sub(buf.len());
+        buf.extend_from_slice(&chunk[..chunk.len().min(room)]);
+        let used = chunk.len();
+        reader.consume(used);
+        consumed += used;
+        if done {
+            break;
+        }
+    }
+    // A cut can land inside a character: drop the partial one rather
+    // than have Strict decoding reject the whole line.
+    if buf.len() == max
+        && let Err(e) = std::str::from_utf8(buf)
+        && e.error_len().is_none()
+    {
+        buf.truncate(e.valid_up_to());
+    }
+    Ok(consumed)
+}
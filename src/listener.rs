@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+use tracing::{Instrument, info, warn};
+
+use crate::error::ChatError;
+use crate::handshake::PendingGuard;
+use crate::i18n::MsgId;
+use crate::ratelimit::{RateLimiter, TokenBucket};
+use crate::server::{self, Server};
+use crate::socket::{self, SocketOptions};
+use crate::transport::{ClientStream, Transport};
+
+/// One port the server accepts clients on.
+#[derive(Debug, Clone)]
+pub struct ListenerConfig {
+    pub port: u16,
+    pub transport: Transport,
+}
+
+/// Admission control shared by every listener.
+///
+/// The limits protect the server, not a port — a flood split across
+/// telnet and WebSocket is still a flood. A std Mutex is enough: the
+/// checks are a few arithmetic operations and never await.
+struct Gate {
+    accept_limit: std::sync::Mutex<TokenBucket>,
+    per_ip_limit: std::sync::Mutex<RateLimiter<IpAddr>>,
+    pending: Arc<AtomicUsize>,
+    max_pending: usize,
+    open: Arc<std::sync::Mutex<Open>>,
+    max_users: usize,
+    max_per_ip: Option<usize>,
+    full: String,
+    too_many: String,
+    socket: SocketOptions,
+    handshake_timeout: Duration,
+    busy: String,
+    draining: Arc<AtomicBool>,
+    draining_text: String,
+}
+
+impl Gate {
+    /// Shed floods before a task (and a username prompt) exists.
+    fn admit(&self, ip: IpAddr) -> bool {
+        let accepted = self.accept_limit.lock().unwrap().try_take();
+        accepted && self.per_ip_limit.lock().unwrap().check(ip)
+    }
+
+    /// Count a new connection from `ip`, or say why there's no room
+    /// for it.
+    fn claim(&self, ip: IpAddr) -> Result<ConnectionSlot, &str> {
+        let mut open = self.open.lock().unwrap();
+        if open.total >= self.max_users {
+            return Err(&self.full);
+        }
+        let from_ip = open.per_ip.get(&ip).copied().unwrap_or(0);
+        if self.max_per_ip.is_some_and(|max| from_ip >= max) {
+            return Err(&self.too_many);
+        }
+        open.total += 1;
+        open.per_ip.insert(ip, from_ip + 1);
+        Ok(ConnectionSlot {
+            open: Arc::clone(&self.open),
+            ip,
+        })
+    }
+}
+
+/// Connections open right now: in all, and from each address.
+#[derive(Default)]
+struct Open {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+/// One open connection, counted from accept until its task ends —
+/// signing in, chatting, or anything in between. RAII like
+/// `PendingGuard`: dropping it gives the slot back.
+struct ConnectionSlot {
+    open: Arc<std::sync::Mutex<Open>>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut open = self.open.lock().unwrap();
+        open.total -= 1;
+        if let Some(count) = open.per_ip.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                open.per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// How one listener turns an accepted socket into a ClientStream.
+enum Upgrade {
+    Plain,
+    #[cfg(feature = "tls")]
+    Tls(tokio_rustls::TlsAcceptor),
+    #[cfg(feature = "websocket")]
+    WebSocket,
+}
+
+impl Upgrade {
+    /// Prepare a transport at startup. TLS loads its certificate here;
+    /// a transport compiled out of this build is a config error.
+    fn new(transport: &Transport) -> Result<Self, ChatError> {
+        match transport {
+            Transport::Plain => Ok(Upgrade::Plain),
+            #[cfg(feature = "tls")]
+            Transport::Tls { cert, key } => {
+                Ok(Upgrade::Tls(crate::transport::tls::acceptor(cert, key)?))
+            }
+            #[cfg(feature = "websocket")]
+            Transport::WebSocket => Ok(Upgrade::WebSocket),
+            #[allow(unreachable_patterns)]
+            other => Err(ChatError::Config(format!(
+                "this build has no {other} support; enable the cargo feature"
+            ))),
+        }
+    }
+
+    async fn apply(&self, stream: TcpStream) -> Result<ClientStream, ChatError> {
+        let peer = stream.peer_addr()?;
+        match self {
+            Upgrade::Plain => Ok(ClientStream::new(stream, peer)),
+            #[cfg(feature = "tls")]
+            Upgrade::Tls(acceptor) => Ok(ClientStream::new(acceptor.accept(stream).await?, peer)),
+            #[cfg(feature = "websocket")]
+            Upgrade::WebSocket => Ok(ClientStream::new(
+                crate::transport::websocket::accept(stream).await?,
+                peer,
+            )),
+        }
+    }
+
+    /// Only a plain socket can be told "busy" in plain text; anything
+    /// else would be garbage to a TLS or WebSocket client.
+    fn speaks_plain_text(&self) -> bool {
+        matches!(self, Upgrade::Plain)
+    }
+}
+
+/// Bind every configured listener and accept clients on all of them.
+///
+/// All listeners are bound before any accepts, so a port that's already
+/// taken stops startup instead of leaving a half-running server.
+pub async fn serve(server: Arc<Mutex<Server>>) -> Result<(), ChatError> {
+    let (addr, listeners, gate, drained) = {
+        let srv = server.lock().await;
+        let (draining, drained) = srv.drain_handles();
+        let gate = Gate {
+            accept_limit: std::sync::Mutex::new(TokenBucket::new(srv.config.accept_rate)),
+            per_ip_limit: std::sync::Mutex::new(RateLimiter::new(srv.config.handshake_rate)),
+            pending: Arc::new(AtomicUsize::new(0)),
+            max_pending: srv.config.max_pending,
+            open: Arc::default(),
+            max_users: srv.config.max_users,
+            max_per_ip: srv.config.max_per_ip,
+            full: format!(
+                "{}\n",
+                srv.text(
+                    MsgId::ServerFull,
+                    &[("max", &srv.config.max_users.to_string())]
+                )
+            ),
+            too_many: format!(
+                "{}\n",
+                srv.text(
+                    MsgId::TooManyFromAddress,
+                    &[("max", &srv.config.max_per_ip.unwrap_or(0).to_string())]
+                )
+            ),
+            socket: srv.config.socket,
+            handshake_timeout: srv.config.handshake_timeout,
+            busy: format!("{}\n", srv.text(MsgId::ServerBusy, &[])),
+            draining,
+            draining_text: format!("{}\n", srv.text(MsgId::Draining, &[])),
+        };
+        (
+            srv.config.addr.clone(),
+            srv.config.listeners(),
+            gate,
+            drained,
+        )
+    };
+    let gate = Arc::new(gate);
+
+    let mut bound = Vec::new();
+    for config in listeners {
+        let upgrade = Upgrade::new(&config.transport)?;
+        let listener = TcpListener::bind((addr.as_str(), config.port)).await?;
+        info!(%addr, port = config.port, transport = %config.transport, "listening");
+        bound.push((listener, upgrade));
+    }
+
+    let mut tasks = JoinSet::new();
+    for (listener, upgrade) in bound {
+        tasks.spawn(accept_loop(
+            listener,
+            upgrade,
+            Arc::clone(&gate),
+            Arc::clone(&server),
+        ));
+    }
+
+    // Listeners only return on a fatal accept error; one failing takes
+    // the server down rather than silently serving fewer ports. A
+    // finished drain is the clean way out: returning drops the JoinSet,
+    // which stops every listener, and main exits. SIGINT or SIGTERM
+    // starts one, the same as `/shutdown`.
+    let signal = shutdown_signal();
+    tokio::pin!(signal);
+    let mut signalled = false;
+    loop {
+        tokio::select! {
+            _ = &mut signal, if !signalled => {
+                signalled = true;
+                server.lock().await.shut_down();
+            }
+            result = tasks.join_next() => match result {
+                Some(result) => {
+                    result.map_err(|e| ChatError::Config(format!("listener task failed: {e}")))??;
+                }
+                None => return Ok(()),
+            },
+            _ = drained.notified() => return Ok(()),
+        }
+    }
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM where there is such a thing. A
+/// handler that can't be installed just never fires.
+async fn shutdown_signal() {
+    let interrupt = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
+
+/// Turn a connection away with a one-line explanation, where the
+/// transport lets us send one.
+fn refuse(mut stream: TcpStream, upgrade: &Upgrade, text: &str) {
+    if upgrade.speaks_plain_text() {
+        let text = text.to_string();
+        tokio::spawn(async move {
+            let _ = stream.write_all(text.as_bytes()).await;
+        });
+    }
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    upgrade: Upgrade,
+    gate: Arc<Gate>,
+    server: Arc<Mutex<Server>>,
+) -> Result<(), ChatError> {
+    let upgrade = Arc::new(upgrade);
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+
+        // Dropping the stream closes the socket; a well-behaved client
+        // just retries a little later.
+        if !gate.admit(peer.ip()) {
+            drop(stream);
+            continue;
+        }
+
+        // Draining for maintenance: say so politely and hang up.
+        if gate.draining.load(Ordering::Relaxed) {
+            refuse(stream, &upgrade, &gate.draining_text);
+            continue;
+        }
+
+        // Full, or this address has its share: say so and hang up.
+        let slot = match gate.claim(peer.ip()) {
+            Ok(slot) => slot,
+            Err(text) => {
+                refuse(stream, &upgrade, text);
+                continue;
+            }
+        };
+
+        // Too many half-open logins: refuse rather than queue, so the
+        // server stays responsive for the users already chatting.
+        let Some(pending) = PendingGuard::try_acquire(&gate.pending, gate.max_pending) else {
+            refuse(stream, &upgrade, &gate.busy);
+            continue;
+        };
+
+        if let Err(e) = socket::apply(&stream, &gate.socket) {
+            warn!(%peer, error = %e, "socket setup failed");
+            continue;
+        }
+
+        let server = Arc::clone(&server);
+        let upgrade = Arc::clone(&upgrade);
+        let timeout = gate.handshake_timeout;
+        // Everything logged on the client's behalf says which one. The
+        // user id isn't known until after the handshake.
+        let span = tracing::info_span!("conn", %peer, user = tracing::field::Empty);
+
+        // tokio::spawn requires the future to be Send.
+        // Our handle_client is Send because all data held across
+        // .await points is Send.
+        let client = async move {
+            let _slot = slot;
+            // The TLS or WebSocket handshake happens here, on the
+            // client's own task, while it holds its pending slot — and
+            // under the same deadline as the username prompt.
+            let result = match tokio::time::timeout(timeout, upgrade.apply(stream)).await {
+                Ok(Ok(client)) => server::handle_client(server, client, pending).await,
+                Ok(Err(e)) => Err(e),
+                Err(_) => Ok(()),
+            };
+            if let Err(e) = result {
+                warn!(error = %e, "client error");
+            }
+        };
+        tokio::spawn(client.instrument(span));
+    }
+}
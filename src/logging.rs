@@ -0,0 +1,32 @@
+use std::io::IsTerminal;
+
+use tracing::Level;
+use tracing_subscriber::EnvFilter;
+
+/// How log lines are written to stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// One readable line per event, for a person at a terminal.
+    #[default]
+    Pretty,
+    /// One JSON object per event, for a log collector.
+    Json,
+}
+
+/// Install the global subscriber. Call once, before anything logs.
+///
+/// `RUST_LOG`, when set, wins over `level`, so one module can be
+/// turned up (`RUST_LOG=rust_chat_server::scripting=debug`) without
+/// touching the config.
+pub fn init(level: Level, format: LogFormat) {
+    let filter = EnvFilter::builder()
+        .with_default_directive(level.into())
+        .from_env_lossy();
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(std::io::stdout().is_terminal());
+    match format {
+        LogFormat::Pretty => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+}
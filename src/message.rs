@@ -1,52 +1,34 @@
 use std::borrow::Cow;
-use std::fmt;
 
 use crate::error::ChatError;
 
-/// A chat message with a username and body.
-///
-/// Uses Cow<str> so it can borrow from an input buffer (zero-copy)
-/// or own its data when needed.
-#[derive(Debug, Clone)]
-pub struct Message<'a> {
-    pub username: Cow<'a, str>,
-    pub body: Cow<'a, str>,
+/// What happens to a chat message longer than the limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Oversize {
+    /// Refuse it with an error; the sender can split it and try again.
+    Reject,
+    /// Cut it to the limit and send what fits.
+    Truncate,
 }
 
-impl<'a> Message<'a> {
-    pub fn new(username: Cow<'a, str>, body: Cow<'a, str>) -> Self {
-        Self { username, body }
+/// `body` as it may be sent: unchanged if it fits in `max` bytes,
+/// otherwise refused or cut short according to `policy`. A cut never
+/// splits a character.
+pub fn fit(body: &str, max: usize, policy: Oversize) -> Result<Cow<'_, str>, ChatError> {
+    if body.len() <= max {
+        return Ok(Cow::Borrowed(body));
     }
-
-    /// Parse from the old "username:body" format (backwards compat).
-    pub fn parse(line: &'a str) -> Result<Self, ChatError> {
-        let (username, body) = line
-            .split_once(':')
-            .ok_or_else(|| ChatError::Parse("missing ':' delimiter".into()))?;
-
-        let username = username.trim();
-        if username.is_empty() {
-            return Err(ChatError::Parse("empty username".into()));
+    match policy {
+        Oversize::Reject => Err(ChatError::MessageTooLong {
+            len: body.len(),
+            max,
+        }),
+        Oversize::Truncate => {
+            let mut end = max;
+            while !body.is_char_boundary(end) {
+                end -= 1;
+            }
+            Ok(Cow::Owned(body[..end].to_string()))
         }
-
-        Ok(Message {
-            username: Cow::Borrowed(username),
-            body: Cow::Borrowed(body),
-        })
-    }
-
-    /// Convert to an owned Message with 'static lifetime.
-    /// The 'static + Clone escape hatch for crossing scope boundaries.
-    pub fn into_owned(self) -> Message<'static> {
-        Message {
-            username: Cow::Owned(self.username.into_owned()),
-            body: Cow::Owned(self.body.into_owned()),
-        }
-    }
-}
-
-impl fmt::Display for Message<'_> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "<{}> {}", self.username, self.body)
     }
 }
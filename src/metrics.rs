@@ -0,0 +1,292 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::{Display, Write as _};
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::error::ChatError;
+use crate::server::Server;
+use crate::types::UserId;
+
+/// How long a scraper gets to send its request before it's dropped.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How far back activity is remembered. The longest window we report
+/// is a day, so anything older can go.
+const RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Activity in one room, kept as timestamped samples.
+///
+/// Rolling windows ("last hour", "last day") need to forget old events,
+/// which a plain counter can't do. A VecDeque of (when, what) does: new
+/// samples go on the back, expired ones fall off the front, and a
+/// window is just a scan over the tail.
+pub struct RoomActivity {
+    messages: VecDeque<(Instant, UserId)>,
+    /// Member count after each change.
+    occupancy: VecDeque<(Instant, usize)>,
+    total_messages: u64,
+}
+
+/// What happened in a room during one window.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WindowStats {
+    pub messages: usize,
+    pub speakers: usize,
+    pub peak_members: usize,
+}
+
+impl RoomActivity {
+    pub fn new() -> Self {
+        Self {
+            messages: VecDeque::new(),
+            occupancy: VecDeque::new(),
+            total_messages: 0,
+        }
+    }
+
+    pub fn record_message(&mut self, speaker: UserId) {
+        let now = Instant::now();
+        self.messages.push_back((now, speaker));
+        self.total_messages += 1;
+        self.prune(now);
+    }
+
+    /// Note the room's size after someone joined or left.
+    pub fn record_members(&mut self, count: usize) {
+        let now = Instant::now();
+        self.occupancy.push_back((now, count));
+        self.prune(now);
+    }
+
+    pub fn total_messages(&self) -> u64 {
+        self.total_messages
+    }
+
+    /// Summarise the last `span` of activity.
+    pub fn window(&self, span: Duration) -> WindowStats {
+        let now = Instant::now();
+        let since = now.checked_sub(span).unwrap_or(now);
+
+        let recent = self.messages.iter().filter(|(at, _)| *at >= since);
+        let messages = recent.clone().count();
+        let speakers = recent.map(|(_, id)| *id).collect::<HashSet<_>>().len();
+
+        // The room's size when the window opened counts too: a room
+        // that's held 40 people all day without anyone moving still
+        // peaked at 40.
+        let mut peak_members = 0;
+        for &(at, count) in &self.occupancy {
+            if at < since {
+                peak_members = count;
+            } else {
+                peak_members = peak_members.max(count);
+            }
+        }
+
+        WindowStats {
+            messages,
+            speakers,
+            peak_members,
+        }
+    }
+
+    fn prune(&mut self, now: Instant) {
+        let Some(cutoff) = now.checked_sub(RETENTION) else {
+            return;
+        };
+        while self.messages.front().is_some_and(|(at, _)| *at < cutoff) {
+            self.messages.pop_front();
+        }
+        // Keep the newest expired sample: it's the room's size at the
+        // start of the oldest window.
+        while self.occupancy.get(1).is_some_and(|(at, _)| *at < cutoff) {
+            self.occupancy.pop_front();
+        }
+    }
+}
+
+/// A room's activity, ready to show an operator.
+#[derive(Debug, Clone)]
+pub struct RoomStats {
+    pub room: String,
+    pub members_now: usize,
+    pub last_hour: WindowStats,
+    pub last_day: WindowStats,
+    pub total_messages: u64,
+}
+
+/// The whole server at a glance: what `/stats` shows with no room
+/// named, and what a STATS frame answers a client program with.
+#[derive(Debug, Clone, Copy)]
+pub struct ServerStats {
+    pub uptime: Duration,
+    pub users: usize,
+    pub rooms: usize,
+    /// Since the server started, as the metrics endpoint counts them.
+    pub messages: u64,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+}
+
+/// Server-wide counts that no single room sees, kept since the last
+/// daily summary. Unlike RoomActivity these are plain counters: the
+/// summary reads them once and starts them over.
+#[derive(Debug, Default)]
+pub struct DailyCounters {
+    /// Names connecting for the first time.
+    pub new_users: u64,
+    /// Messages by username, across every room.
+    pub talkers: HashMap<String, u64>,
+    /// Messages a filter refused.
+    pub filter_blocks: u64,
+    /// Most users online at once.
+    pub peak_online: usize,
+}
+
+impl DailyCounters {
+    pub fn record_message(&mut self, username: &str) {
+        *self.talkers.entry(username.to_string()).or_default() += 1;
+    }
+
+    pub fn record_online(&mut self, online: usize) {
+        self.peak_online = self.peak_online.max(online);
+    }
+
+    /// Hand over the counts so far and start a new day. The new day's
+    /// peak starts at whoever is still online.
+    pub fn take(&mut self, online: usize) -> DailyCounters {
+        std::mem::replace(
+            self,
+            DailyCounters {
+                peak_online: online,
+                ..DailyCounters::default()
+            },
+        )
+    }
+
+    /// The `n` busiest talkers, busiest first.
+    pub fn top_talkers(&self, n: usize) -> Vec<(String, u64)> {
+        let mut talkers: Vec<_> = self
+            .talkers
+            .iter()
+            .map(|(name, count)| (name.clone(), *count))
+            .collect();
+        talkers.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        talkers.truncate(n);
+        talkers
+    }
+}
+
+/// Running totals since the server started, for the metrics endpoint.
+///
+/// Unlike DailyCounters these never reset: Prometheus wants counters
+/// that only go up, and works out rates itself with `rate()`. They're
+/// atomics rather than plain fields so the writer tasks can count the
+/// bytes they send without waiting for the server lock.
+#[derive(Debug, Default)]
+pub struct Counters {
+    /// Messages delivered to a room.
+    pub messages: AtomicU64,
+    /// Bytes written to clients, greeting included.
+    pub bytes_sent: AtomicU64,
+    /// Bytes of the lines read from signed-in clients.
+    pub bytes_received: AtomicU64,
+    /// Messages a filter refused.
+    pub filter_blocks: AtomicU64,
+    /// Clients that got as far as signing in.
+    pub connections: AtomicU64,
+}
+
+/// Prometheus' text exposition format, built up a metric at a time:
+/// a `# HELP` line, a `# TYPE` line, then the sample.
+#[derive(Default)]
+pub struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    pub fn counter(&mut self, name: &str, help: &str, value: impl Display) {
+        self.metric(name, "counter", help, value);
+    }
+
+    pub fn gauge(&mut self, name: &str, help: &str, value: impl Display) {
+        self.metric(name, "gauge", help, value);
+    }
+
+    fn metric(&mut self, name: &str, kind: &str, help: &str, value: impl Display) {
+        let _ = writeln!(self.text, "# HELP {name} {help}");
+        let _ = writeln!(self.text, "# TYPE {name} {kind}");
+        let _ = writeln!(self.text, "{name} {value}");
+    }
+
+    pub fn finish(self) -> String {
+        self.text
+    }
+}
+
+/// Open the metrics port. Done before the server starts, like the
+/// admin console, so a port already in use stops it at once.
+pub async fn bind(addr: &str, port: u16) -> Result<TcpListener, ChatError> {
+    let listener = TcpListener::bind((addr, port)).await?;
+    info!(%addr, port, "metrics listening");
+    Ok(listener)
+}
+
+/// Answer scrapes until the server stops.
+pub async fn serve(server: Arc<Mutex<Server>>, listener: TcpListener) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(scrape(Arc::clone(&server), stream));
+            }
+            Err(e) => warn!(error = %e, "metrics accept failed"),
+        }
+    }
+}
+
+/// One HTTP request, one response, then the connection closes.
+///
+/// Just enough HTTP for Prometheus, curl and a browser: read the
+/// request line, skip the headers, answer `GET /metrics`. Pulling in
+/// an HTTP server for one read-only page would be most of the build.
+async fn scrape(server: Arc<Mutex<Server>>, stream: TcpStream) {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    let request = async {
+        let request_line = lines.next_line().await.ok().flatten()?;
+        while let Ok(Some(header)) = lines.next_line().await {
+            if header.is_empty() {
+                break;
+            }
+        }
+        Some(request_line)
+    };
+    let Ok(Some(request_line)) = tokio::time::timeout(REQUEST_TIMEOUT, request).await else {
+        return;
+    };
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", server.lock().await.metrics()),
+        (Some("GET"), _) => ("404 Not Found", "not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "only GET\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    );
+    let _ = write.write_all(response.as_bytes()).await;
+}
+
+pub const MINUTE: Duration = Duration::from_secs(60);
+pub const HOUR: Duration = Duration::from_secs(60 * 60);
+pub const DAY: Duration = RETENTION;
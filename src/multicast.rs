@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+
+use tracing::debug;
+
+use crate::error::ChatError;
+
+/// Hops a datagram may take. One keeps it on the local network, which
+/// is the whole idea: LAN mode, not a way to publish a room to the world.
+const TTL: u32 = 1;
+
+/// Copies of room messages, sent as UDP datagrams to a multicast group
+/// per room.
+///
+/// For listeners that only want to show what's said — a wall display, a
+/// log on another machine — without signing in or keeping a connection
+/// open. Each datagram is one chat line as a plain client would see it,
+/// uncoloured. Send-only: nothing that arrives on a group is read.
+///
+/// Anyone on the network can join a group, so whatever room is sent
+/// there is public, whatever its settings say.
+pub struct Multicast {
+    v4: Option<UdpSocket>,
+    v6: Option<UdpSocket>,
+    groups: HashMap<String, SocketAddr>,
+}
+
+impl Multicast {
+    /// Check every group is a multicast address, and open a socket for
+    /// each address family in use.
+    pub fn open(groups: &HashMap<String, SocketAddr>) -> Result<Self, ChatError> {
+        if let Some((room, group)) = groups.iter().find(|(_, g)| !g.ip().is_multicast()) {
+            return Err(ChatError::Config(format!(
+                "multicast group for #{room}: {group} is not a multicast address"
+            )));
+        }
+        let v4 = if groups.values().any(SocketAddr::is_ipv4) {
+            let socket = sender(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)))?;
+            socket.set_multicast_ttl_v4(TTL)?;
+            Some(socket)
+        } else {
+            None
+        };
+        // IPv6 multicast stays on the link by default.
+        let v6 = if groups.values().any(SocketAddr::is_ipv6) {
+            Some(sender(SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)))?)
+        } else {
+            None
+        };
+        Ok(Self {
+            v4,
+            v6,
+            groups: groups.clone(),
+        })
+    }
+
+    /// Send `line` to `room`'s group, if it has one.
+    ///
+    /// Never waits: this runs with the server lock held, and a datagram
+    /// the socket can't take right now is dropped, as UDP would have
+    /// been free to do anyway.
+    pub fn send(&self, room: &str, line: &str) {
+        let Some(group) = self.groups.get(room) else {
+            return;
+        };
+        let socket = match group {
+            SocketAddr::V4(_) => &self.v4,
+            SocketAddr::V6(_) => &self.v6,
+        };
+        if let Some(socket) = socket
+            && let Err(e) = socket.send_to(line.as_bytes(), group)
+        {
+            debug!(%room, %group, error = %e, "multicast datagram dropped");
+        }
+    }
+}
+
+fn sender(addr: SocketAddr) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(addr)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::fmt;
+
+/// What a user is allowed to do, lowest to highest.
+///
+/// Guest and User are earned: a name is a guest until it reaches the
+/// basic trust tier. Op and Admin are granted by `/oper` with the
+/// matching password.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Guest,
+    User,
+    Op,
+    Admin,
+}
+
+impl Role {
+    /// The inverse of `Display`.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "guest" => Some(Role::Guest),
+            "user" => Some(Role::User),
+            "op" => Some(Role::Op),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Guest => write!(f, "guest"),
+            Role::User => write!(f, "user"),
+            Role::Op => write!(f, "op"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// The lowest role allowed each command, by name without the `/`.
+///
+/// One table for built-in and plugin commands alike, so the server
+/// checks a command before it knows or cares which kind it is. A
+/// command that isn't listed is open to everyone.
+#[derive(Debug, Clone)]
+pub struct PermissionMatrix {
+    required: HashMap<String, Role>,
+}
+
+impl Default for PermissionMatrix {
+    /// What used to be hard-coded: operator commands for operators,
+    /// and shutting the server down for admins only.
+    fn default() -> Self {
+        let mut matrix = Self {
+            required: HashMap::new(),
+        };
+        matrix.require("stats", Role::Op);
+        matrix.require("ban", Role::Op);
+        matrix.require("unban", Role::Op);
+        matrix.require("shadowmute", Role::Op);
+        matrix.require("unshadowmute", Role::Op);
+        matrix.require("drain", Role::Admin);
+        matrix.require("shutdown", Role::Admin);
+        matrix
+    }
+}
+
+impl PermissionMatrix {
+    pub fn require(&mut self, command: &str, role: Role) {
+        self.required.insert(command.to_string(), role);
+    }
+
+    /// The role `command` needs, or Guest if it isn't restricted.
+    pub fn required(&self, command: &str) -> Role {
+        self.required.get(command).copied().unwrap_or(Role::Guest)
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::auth::Credentials;
+use crate::error::ChatError;
+use crate::room::{Holder, Room, RoomRole};
+
+/// The part of a room worth keeping across a restart: what it is, who
+/// runs it and who's kept out. Members, history and polls belong to the running
+/// server and start empty.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomRecord {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    #[serde(default)]
+    pub private: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "password")]
+    pub password: Option<Credentials>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub invited: Vec<String>,
+    /// Tables last: TOML wants them after a room's plain values.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub roles: BTreeMap<String, RoomRole>,
+    /// Banned name to the reason given.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub bans: BTreeMap<String, String>,
+}
+
+impl RoomRecord {
+    pub fn of(room: &Room) -> Self {
+        let mut invited: Vec<_> = room.invited.iter().cloned().collect();
+        invited.sort();
+        Self {
+            name: room.name.clone(),
+            topic: room.topic.clone(),
+            private: room.private,
+            password: room.password.clone(),
+            invited,
+            roles: room
+                .roles()
+                .map(|(name, role)| (name.to_string(), role))
+                .collect(),
+            bans: room
+                .banned
+                .iter()
+                .map(|(name, reason)| (name.clone(), reason.clone()))
+                .collect(),
+        }
+    }
+
+    /// Put a saved room's settings back on a freshly made one. A room
+    /// the config makes private stays private.
+    pub fn apply(self, room: &mut Room) {
+        room.topic = self.topic;
+        room.private |= self.private;
+        room.password = self.password;
+        for (name, role) in self.roles {
+            room.set_role(&Holder::Account(name), role);
+        }
+        room.invited.extend(self.invited);
+        room.banned.extend(self.bans);
+    }
+}
+
+/// The rooms file: a TOML array of tables, one per room.
+///
+/// ```toml
+/// [[rooms]]
+/// name = "rust"
+/// topic = "All things Rust"
+/// invited = ["bob"]
+///
+/// [rooms.roles]
+/// alice = "owner"
+///
+/// [rooms.bans]
+/// mallory = "spam"
+/// ```
+#[derive(Serialize, Deserialize)]
+struct RoomsFile {
+    #[serde(default)]
+    rooms: Vec<RoomRecord>,
+}
+
+/// A room password as it's kept everywhere else: `Credentials::encode`.
+mod password {
+    use serde::{Deserialize, Deserializer, Serializer, de};
+
+    use crate::auth::Credentials;
+
+    pub fn serialize<S: Serializer>(
+        password: &Option<Credentials>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match password {
+            Some(credentials) => serializer.serialize_str(&credentials.encode()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Credentials>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        Credentials::decode(&text)
+            .map(Some)
+            .ok_or_else(|| de::Error::custom("bad password hash"))
+    }
+}
+
+/// Read the rooms saved in `path`. A missing file means none yet.
+pub fn load_rooms(path: &Path) -> Result<Vec<RoomRecord>, ChatError> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let file: RoomsFile =
+        toml::from_str(&text).map_err(|e| ChatError::Config(format!("{}: {e}", path.display())))?;
+    Ok(file.rooms)
+}
+
+/// Write every room out, replacing what was there. Rooms change
+/// rarely and there are few of them, so rewriting is simplest.
+pub async fn save_rooms(path: &Path, rooms: &[RoomRecord]) -> Result<(), ChatError> {
+    let file = RoomsFile {
+        rooms: rooms.to_vec(),
+    };
+    let text = toml::to_string(&file).map_err(|e| ChatError::Storage(e.to_string()))?;
+    replace(path, text).await
+}
+
+/// Write `contents` over `path` in one go: into a file beside it
+/// first, then renamed into place, so a crash or a reader mid-write
+/// sees the old file or the new, never half of one.
+pub async fn replace(path: &Path, contents: impl AsRef<[u8]>) -> Result<(), ChatError> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let mut file = tokio::fs::File::create(&temp).await?;
+    file.write_all(contents.as_ref()).await?;
+    file.sync_all().await?;
+    tokio::fs::rename(&temp, path).await?;
+    Ok(())
+}
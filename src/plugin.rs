@@ -0,0 +1,100 @@
+use std::sync::Arc;
+
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+
+use crate::bus::ServerEvent;
+use crate::command::CommandHandler;
+use crate::error::ChatError;
+use crate::server::{AsyncFilter, Server};
+
+/// An extension that lives outside the core server.
+///
+/// Every method has a default, so a plugin only implements the parts it
+/// cares about: a moderation bot might only need `on_event`, a game only
+/// `commands`.
+pub trait Plugin: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Called once at startup, before any client connects.
+    /// This is the place to schedule tasks or create rooms.
+    fn init(&mut self, _server: &mut Server) {}
+
+    /// Called for every event published on the bus.
+    /// Runs on the plugin's own task, outside the server lock.
+    fn on_event(&self, _event: &ServerEvent) {}
+
+    /// Slash commands this plugin adds.
+    fn commands(&self) -> Vec<Box<dyn CommandHandler>> {
+        Vec::new()
+    }
+
+    /// Message filters this plugin adds, run after the built-in ones.
+    fn filters(&self) -> Vec<Box<dyn AsyncFilter>> {
+        Vec::new()
+    }
+}
+
+/// Plugins compiled into this binary, looked up by the names in config.
+fn builtin(name: &str) -> Option<Box<dyn Plugin>> {
+    match name {
+        "logger" => Some(Box::new(LoggerPlugin)),
+        _ => None,
+    }
+}
+
+/// Load every plugin named in `server.config.plugins`.
+pub fn load_plugins(server: &mut Server) -> Result<(), ChatError> {
+    for name in server.config.plugins.clone() {
+        let plugin =
+            builtin(&name).ok_or_else(|| ChatError::Config(format!("unknown plugin: {name}")))?;
+        install(server, plugin)?;
+    }
+    Ok(())
+}
+
+/// Put one plugin to work, built in or an embedder's own.
+///
+/// Registration happens here, in one place: commands go into the
+/// server's registry, filters onto its filter chain, and each plugin
+/// gets a bus subscription driven by its own task.
+pub fn install(server: &mut Server, mut plugin: Box<dyn Plugin>) -> Result<(), ChatError> {
+    plugin.init(server);
+    for command in plugin.commands() {
+        server.register_command(command)?;
+    }
+    for filter in plugin.filters() {
+        server.add_filter(filter);
+    }
+
+    let name = plugin.name().to_string();
+    let plugin: Arc<dyn Plugin> = Arc::from(plugin);
+    let mut events = server.subscribe();
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => plugin.on_event(&event),
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(plugin = plugin.name(), missed, "plugin missed events");
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+
+    info!(plugin = %name, "loaded plugin");
+    Ok(())
+}
+
+/// Logs every server event — handy when developing a plugin.
+struct LoggerPlugin;
+
+impl Plugin for LoggerPlugin {
+    fn name(&self) -> &str {
+        "logger"
+    }
+
+    fn on_event(&self, event: &ServerEvent) {
+        info!(?event, "event");
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::scheduler::TaskId;
+use crate::types::UserId;
+
+/// How long a poll stays open if nobody closes it.
+pub const POLL_TTL: Duration = Duration::from_secs(30 * 60);
+
+pub const MIN_OPTIONS: usize = 2;
+pub const MAX_OPTIONS: usize = 10;
+
+/// A poll running in one room. Each user gets one vote, and can't
+/// change it — a poll that flips while people watch isn't much use.
+/// Votes are kept by account, or by name for a guest, so reconnecting
+/// doesn't make a new voter.
+pub struct Poll {
+    /// The scheduled auto-close, cancelled if someone closes it first.
+    pub expiry: Option<TaskId>,
+    pub creator: UserId,
+    pub creator_name: String,
+    pub question: String,
+    pub options: Vec<String>,
+    votes: HashMap<String, usize>,
+}
+
+pub enum Vote {
+    Counted,
+    AlreadyVoted,
+    NoSuchOption,
+}
+
+impl Poll {
+    pub fn new(
+        creator: UserId,
+        creator_name: String,
+        question: String,
+        options: Vec<String>,
+    ) -> Self {
+        Self {
+            expiry: None,
+            creator,
+            creator_name,
+            question,
+            options,
+            votes: HashMap::new(),
+        }
+    }
+
+    /// Record `voter`'s vote for option `choice`, counting from 1.
+    pub fn vote(&mut self, voter: &str, choice: usize) -> Vote {
+        if choice == 0 || choice > self.options.len() {
+            return Vote::NoSuchOption;
+        }
+        if self.votes.contains_key(voter) {
+            return Vote::AlreadyVoted;
+        }
+        self.votes.insert(voter.to_string(), choice - 1);
+        Vote::Counted
+    }
+
+    /// Carry a guest's vote over a `/nick`, so it can't be cast twice.
+    pub fn rename(&mut self, old: &str, new: &str) {
+        if let Some(choice) = self.votes.remove(old) {
+            self.votes.insert(new.to_string(), choice);
+        }
+    }
+
+    /// The options as a numbered list, one per line.
+    pub fn ballot(&self) -> String {
+        self.options
+            .iter()
+            .enumerate()
+            .map(|(i, option)| format!("*   {}) {option}", i + 1))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// The options with their vote counts, one per line.
+    pub fn tally(&self) -> String {
+        let mut counts = vec![0; self.options.len()];
+        for &choice in self.votes.values() {
+            counts[choice] += 1;
+        }
+        self.options
+            .iter()
+            .zip(counts)
+            .map(|(option, n)| {
+                let noun = if n == 1 { "vote" } else { "votes" };
+                format!("*   {option}: {n} {noun}")
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Split on whitespace, keeping "double-quoted phrases" together.
+/// `"Lunch where?" pizza "sushi bar"` → [Lunch where?, pizza, sushi bar]
+pub fn split_quoted(input: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+
+    for c in input.chars() {
+        match c {
+            '"' => {
+                if quoted || !current.is_empty() {
+                    words.push(std::mem::take(&mut current));
+                }
+                quoted = !quoted;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    words.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
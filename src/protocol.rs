@@ -1,6 +1,12 @@
 use std::borrow::Cow;
+use std::fmt::{self, Write as _};
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use crate::compression::Compression;
 use crate::error::ChatError;
+use crate::history::{DEFAULT_PAGE, Page};
+use crate::lines::{Framing, trim_line_ending};
+use crate::metrics::ServerStats;
 
 /// Wire protocol format:
 ///
@@ -11,6 +17,90 @@ use crate::error::ChatError;
 ///   JOIN:room_name        — join a room
 ///   NICK:new_name         — change username
 ///   QUIT:                 — disconnect
+/// Sent by the server when a user joins a room they've been in before:
+///   READ:room:last=<seq>:latest=<seq>
+///                         — where they left off, and where the room is
+/// Sent when someone writes @name, as well as the message itself:
+///   NOTIFY:room:id:from:body
+///                         — message `id` in `room` mentions you. From
+///                           a room you're not in, only after
+///                           /set mentions on
+/// Sent once signed in, with `resume` on:
+///   SESSION:token         — what to answer the username prompt with
+///                           to come back, if the connection drops
+///
+/// At the username prompt, instead of a bare name:
+///   LOGIN:user:password   — sign in to a registered account
+///   REGISTER:user:password
+///                         — create an account and sign in to it
+///   AUTH:token            — sign in with a token from the admin
+///                           console, as its user and with its role
+///                           (see Tokens)
+///   RESUME:token          — pick a dropped session back up, with the
+///                           token SESSION: gave it (see Resumes)
+///
+///   JOINCODE:code         — join the room an invite code is for
+///   EMSG:user:payload     — an end-to-end encrypted message for `user`;
+///                           the server routes the payload untouched and
+///                           delivers it as EMSG:sender:payload
+///   PRIV:user:body        — a direct message, as /msg sends, without
+///                           the slash-command parsing; the body may
+///                           hold colons
+///   EDIT:room:id:body     — change what message `id` in `room` says, as
+///                           /edit does; the body may hold colons
+///   DELETE:room:id        — take message `id` in `room` back, as
+///                           /delete does
+///   HISTORY:room:before=<seq>:limit=<n>
+///                         — page back through a room's history; both
+///                           fields are optional, in either order
+///   STATS:                — the server's figures, as /stats shows them
+///                           and for the same roles. Answered with
+///                           STATS:uptime=<secs>:users=<n>:rooms=<n>:
+///                           messages=<n>:bytes_in=<n>:bytes_out=<n>
+///   PROTO:json            — from now on, send this client JSON objects
+///                           instead of text lines (see JsonFrame);
+///                           PROTO:line switches back
+///   PING:token            — are you there? Answered with PONG:token.
+///                           Either side may ask; the server starts
+///                           asking once a client has spoken the
+///                           protocol (see KeepAlive)
+///   PONG:token            — yes
+///   ACK:seq               — with `seq` on: I have every line up to
+///                           `seq`; send again what came after it
+///                           (see Sequencer)
+///   FILE_START:name:size  — share a file (or a long paste) with the
+///                           current room: `size` bytes, in as many
+///   FILE_CHUNK:data         chunks as it takes, then FILE_END. The
+///   FILE_END:               server keeps the chunks as they are, so
+///                           encode binary files (base64, say). The room
+///                           is told a token to fetch it with
+///   FILE_GET:token        — fetch a shared file. It comes back as the
+///                           same FILE_START, FILE_CHUNK..., FILE_END
+///                           lines it was sent as
+///
+/// Before the username, as many times as needed:
+///   HELLO:                — what can this server do? Answered with
+///                           CAPS:name,name, every capability CAP: may
+///                           ask for
+///   CAP:name,name         — ask for optional behaviour. Answered with
+///                           CAP: and the names the server agreed to,
+///                           in the same order; unknown ones are left
+///                           out. Known: `seq`, which puts SEQ:<n>: in
+///                           front of every line the server sends (a
+///                           "seq" field, in JSON); `binary`, which
+///                           swaps the `\n` after each line for a
+///                           4-byte big-endian length before it, both
+///                           ways, starting right after the CAP: answer
+///                           (see Framing). Bodies may then hold `\n`;
+///                           each server frame is one event's output;
+///                           `json`, which is PROTO:json from the first
+///                           line after sign-in (see Caps); `zlib` or
+///                           `zstd`, one of them, which compresses
+///                           everything both ways from right after the
+///                           CAP: answer, under any framing (see
+///                           Compression); `resume`, which sends
+///                           SESSION: once signed in, if the server
+///                           keeps dropped sessions
 ///
 /// Frame is the parsed representation. It borrows from the input buffer
 /// when possible (zero-copy) and owns data only when transformation is
@@ -27,9 +117,96 @@ pub enum Frame<'a> {
     Nick {
         name: Cow<'a, str>,
     },
+    JoinCode {
+        code: Cow<'a, str>,
+    },
+    Login {
+        username: Cow<'a, str>,
+        password: Cow<'a, str>,
+    },
+    Register {
+        username: Cow<'a, str>,
+        password: Cow<'a, str>,
+    },
+    Auth {
+        token: Cow<'a, str>,
+    },
+    Resume {
+        token: Cow<'a, str>,
+    },
+    EMsg {
+        to: Cow<'a, str>,
+        payload: Cow<'a, str>,
+    },
+    Priv {
+        to: Cow<'a, str>,
+        body: Cow<'a, str>,
+    },
+    Edit {
+        room: Cow<'a, str>,
+        id: u64,
+        body: Cow<'a, str>,
+    },
+    Delete {
+        room: Cow<'a, str>,
+        id: u64,
+    },
+    History {
+        room: Cow<'a, str>,
+        /// Only messages older than this sequence number; None for the
+        /// newest.
+        before: Option<u64>,
+        limit: usize,
+    },
+    Proto {
+        format: WireFormat,
+    },
+    Ping {
+        token: Cow<'a, str>,
+    },
+    Pong {
+        token: Cow<'a, str>,
+    },
+    Hello,
+    Cap {
+        caps: Vec<Cow<'a, str>>,
+    },
+    Ack {
+        seq: u64,
+    },
+    FileStart {
+        name: Cow<'a, str>,
+        size: usize,
+    },
+    FileChunk {
+        data: Cow<'a, str>,
+    },
+    FileEnd,
+    FileGet {
+        token: Cow<'a, str>,
+    },
+    Stats,
     Quit,
 }
 
+/// What the server writes to a client. Lines are the default, and what
+/// a person on telnet wants; a client program can ask for JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WireFormat {
+    #[default]
+    Line,
+    Json,
+}
+
+impl fmt::Display for WireFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WireFormat::Line => write!(f, "line"),
+            WireFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
 /// Parse a single line into a Frame.
 ///
 /// The lifetime annotation `'a` ties the Frame to the input buffer.
@@ -77,6 +254,197 @@ pub fn parse_frame<'a>(line: &'a str) -> Result<Frame<'a>, ChatError> {
                 name: Cow::Borrowed(name),
             })
         }
+        "JOINCODE" => {
+            let code = payload.trim();
+            if code.is_empty() {
+                return Err(ChatError::Parse("JOINCODE requires a code".into()));
+            }
+            Ok(Frame::JoinCode {
+                code: Cow::Borrowed(code),
+            })
+        }
+        "LOGIN" | "REGISTER" => {
+            // Split at the first ':' only: passwords may contain one.
+            let (username, password) = payload
+                .split_once(':')
+                .ok_or_else(|| ChatError::Parse(format!("{cmd} requires user:password")))?;
+            let username = username.trim();
+            if username.is_empty() || password.is_empty() {
+                return Err(ChatError::Parse(format!("{cmd} requires user:password")));
+            }
+            let (username, password) = (Cow::Borrowed(username), Cow::Borrowed(password));
+            Ok(if cmd == "LOGIN" {
+                Frame::Login { username, password }
+            } else {
+                Frame::Register { username, password }
+            })
+        }
+        "AUTH" => {
+            let token = payload.trim();
+            if token.is_empty() {
+                return Err(ChatError::Parse("AUTH requires a token".into()));
+            }
+            Ok(Frame::Auth {
+                token: Cow::Borrowed(token),
+            })
+        }
+        "RESUME" => {
+            let token = payload.trim();
+            if token.is_empty() {
+                return Err(ChatError::Parse("RESUME requires a token".into()));
+            }
+            Ok(Frame::Resume {
+                token: Cow::Borrowed(token),
+            })
+        }
+        "EMSG" => {
+            let (to, payload) = payload
+                .split_once(':')
+                .ok_or_else(|| ChatError::Parse("EMSG requires user:payload".into()))?;
+            let to = to.trim();
+            if to.is_empty() || payload.is_empty() {
+                return Err(ChatError::Parse("EMSG requires user:payload".into()));
+            }
+            Ok(Frame::EMsg {
+                to: Cow::Borrowed(to),
+                payload: Cow::Borrowed(payload),
+            })
+        }
+        "PRIV" => {
+            let usage = || ChatError::Parse("PRIV requires user:body".into());
+            let (to, body) = payload.split_once(':').ok_or_else(usage)?;
+            let (to, body) = (to.trim(), body.trim());
+            if to.is_empty() || body.is_empty() {
+                return Err(usage());
+            }
+            Ok(Frame::Priv {
+                to: Cow::Borrowed(to),
+                body: Cow::Borrowed(body),
+            })
+        }
+        "EDIT" => {
+            let usage = || ChatError::Parse("EDIT requires room:id:body".into());
+            let mut fields = payload.splitn(3, ':');
+            let (Some(room), Some(id), Some(body)) = (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(usage());
+            };
+            let (room, body) = (room.trim(), body.trim());
+            let id = id.trim().parse().map_err(|_| usage())?;
+            if room.is_empty() || body.is_empty() {
+                return Err(usage());
+            }
+            Ok(Frame::Edit {
+                room: Cow::Borrowed(room),
+                id,
+                body: Cow::Borrowed(body),
+            })
+        }
+        "DELETE" => {
+            let usage = || ChatError::Parse("DELETE requires room:id".into());
+            let (room, id) = payload.split_once(':').ok_or_else(usage)?;
+            let room = room.trim();
+            let id = id.trim().parse().map_err(|_| usage())?;
+            if room.is_empty() {
+                return Err(usage());
+            }
+            Ok(Frame::Delete {
+                room: Cow::Borrowed(room),
+                id,
+            })
+        }
+        "HISTORY" => {
+            let mut fields = payload.split(':');
+            let room = fields.next().unwrap_or("").trim();
+            if room.is_empty() {
+                return Err(ChatError::Parse("HISTORY requires a room name".into()));
+            }
+            let mut before = None;
+            let mut limit = DEFAULT_PAGE;
+            for field in fields {
+                let number = |value: &str| {
+                    value
+                        .parse()
+                        .map_err(|_| ChatError::Parse(format!("HISTORY: bad number in {field}")))
+                };
+                match field.split_once('=') {
+                    Some(("before", value)) => before = Some(number(value)?),
+                    Some(("limit", value)) => limit = number(value)? as usize,
+                    _ => return Err(ChatError::Parse(format!("HISTORY: unknown field {field}"))),
+                }
+            }
+            Ok(Frame::History {
+                room: Cow::Borrowed(room),
+                before,
+                limit,
+            })
+        }
+        "PROTO" => {
+            let format = match payload.trim() {
+                "line" => WireFormat::Line,
+                "json" => WireFormat::Json,
+                other => {
+                    return Err(ChatError::Parse(format!(
+                        "PROTO: unknown format {other}, expected line or json"
+                    )));
+                }
+            };
+            Ok(Frame::Proto { format })
+        }
+        // The token is whatever the asker likes, even nothing; it only
+        // has to come back unchanged.
+        "PING" => Ok(Frame::Ping {
+            token: Cow::Borrowed(payload.trim()),
+        }),
+        "PONG" => Ok(Frame::Pong {
+            token: Cow::Borrowed(payload.trim()),
+        }),
+        "HELLO" => Ok(Frame::Hello),
+        "CAP" => Ok(Frame::Cap {
+            caps: payload
+                .split(',')
+                .map(str::trim)
+                .filter(|cap| !cap.is_empty())
+                .map(Cow::Borrowed)
+                .collect(),
+        }),
+        "ACK" => {
+            let seq = payload
+                .trim()
+                .parse()
+                .map_err(|_| ChatError::Parse("ACK requires a sequence number".into()))?;
+            Ok(Frame::Ack { seq })
+        }
+        "FILE_START" => {
+            // The size is after the last ':', so a name may have one.
+            let usage = || ChatError::Parse("FILE_START requires name:size".into());
+            let (name, size) = payload.rsplit_once(':').ok_or_else(usage)?;
+            let name = name.trim();
+            if name.is_empty() {
+                return Err(usage());
+            }
+            let size = size.trim().parse().map_err(|_| usage())?;
+            Ok(Frame::FileStart {
+                name: Cow::Borrowed(name),
+                size,
+            })
+        }
+        // Not trimmed after the ':': in a pasted line, indentation
+        // is part of the text.
+        "FILE_CHUNK" => Ok(Frame::FileChunk {
+            data: Cow::Borrowed(payload),
+        }),
+        "FILE_END" => Ok(Frame::FileEnd),
+        "FILE_GET" => {
+            let token = payload.trim();
+            if token.is_empty() {
+                return Err(ChatError::Parse("FILE_GET requires a token".into()));
+            }
+            Ok(Frame::FileGet {
+                token: Cow::Borrowed(token),
+            })
+        }
+        "STATS" => Ok(Frame::Stats),
         "QUIT" => Ok(Frame::Quit),
         _ => Err(ChatError::Parse(format!("unknown command: {cmd}"))),
     }
@@ -100,15 +468,187 @@ impl<'a> Frame<'a> {
             Frame::Nick { name } => Frame::Nick {
                 name: Cow::Owned(name.into_owned()),
             },
+            Frame::JoinCode { code } => Frame::JoinCode {
+                code: Cow::Owned(code.into_owned()),
+            },
+            Frame::Login { username, password } => Frame::Login {
+                username: Cow::Owned(username.into_owned()),
+                password: Cow::Owned(password.into_owned()),
+            },
+            Frame::Register { username, password } => Frame::Register {
+                username: Cow::Owned(username.into_owned()),
+                password: Cow::Owned(password.into_owned()),
+            },
+            Frame::Auth { token } => Frame::Auth {
+                token: Cow::Owned(token.into_owned()),
+            },
+            Frame::Resume { token } => Frame::Resume {
+                token: Cow::Owned(token.into_owned()),
+            },
+            Frame::EMsg { to, payload } => Frame::EMsg {
+                to: Cow::Owned(to.into_owned()),
+                payload: Cow::Owned(payload.into_owned()),
+            },
+            Frame::Priv { to, body } => Frame::Priv {
+                to: Cow::Owned(to.into_owned()),
+                body: Cow::Owned(body.into_owned()),
+            },
+            Frame::Edit { room, id, body } => Frame::Edit {
+                room: Cow::Owned(room.into_owned()),
+                id,
+                body: Cow::Owned(body.into_owned()),
+            },
+            Frame::Delete { room, id } => Frame::Delete {
+                room: Cow::Owned(room.into_owned()),
+                id,
+            },
+            Frame::History {
+                room,
+                before,
+                limit,
+            } => Frame::History {
+                room: Cow::Owned(room.into_owned()),
+                before,
+                limit,
+            },
+            Frame::Proto { format } => Frame::Proto { format },
+            Frame::Ping { token } => Frame::Ping {
+                token: Cow::Owned(token.into_owned()),
+            },
+            Frame::Pong { token } => Frame::Pong {
+                token: Cow::Owned(token.into_owned()),
+            },
+            Frame::Cap { caps } => Frame::Cap {
+                caps: caps
+                    .into_iter()
+                    .map(|cap| Cow::Owned(cap.into_owned()))
+                    .collect(),
+            },
+            Frame::Hello => Frame::Hello,
+            Frame::Ack { seq } => Frame::Ack { seq },
+            Frame::FileStart { name, size } => Frame::FileStart {
+                name: Cow::Owned(name.into_owned()),
+                size,
+            },
+            Frame::FileChunk { data } => Frame::FileChunk {
+                data: Cow::Owned(data.into_owned()),
+            },
+            Frame::FileEnd => Frame::FileEnd,
+            Frame::FileGet { token } => Frame::FileGet {
+                token: Cow::Owned(token.into_owned()),
+            },
+            Frame::Stats => Frame::Stats,
             Frame::Quit => Frame::Quit,
         }
     }
 }
 
+/// Encode a page of history as a batch: a header saying how many lines
+/// follow, then one line per message, oldest first.
+///
+///   HISTORY:lobby:count=2:more=1
+///   HIST:41:1760000000:alice:hello
+///   HIST:42:1760000003:bob:hi alice
+///
+/// `more=1` means older messages exist: ask again with `before` set to
+/// the first sequence number in this batch.
+pub fn encode_history(room: &str, page: &Page<'_>) -> String {
+    let mut lines = vec![format!(
+        "HISTORY:{room}:count={}:more={}",
+        page.entries.len(),
+        u8::from(page.more)
+    )];
+    for entry in &page.entries {
+        lines.push(format!(
+            "HIST:{}:{}:{}:{}",
+            entry.seq,
+            entry.timestamp(),
+            entry.from,
+            entry.body
+        ));
+    }
+    lines.join("\n")
+}
+
+/// One event for a client in JSON mode, on a line of its own:
+///
+///   {"type":"message","sender":"alice","room":"lobby","id":12,"timestamp":1760000000,"body":"hi"}
+///
+/// `sender` and `room` are null where they don't apply: a system notice
+/// has neither. A direct message adds `to`; a room message, its `id`.
+/// An "edit" carries the new body for an `id`, a "delete" an empty one,
+/// with `sender` who deleted it. The timestamp is seconds
+/// since the Unix epoch, as in HISTORY batches.
+pub struct JsonFrame<'a> {
+    pub kind: &'a str,
+    pub sender: Option<&'a str>,
+    pub to: Option<&'a str>,
+    pub room: Option<&'a str>,
+    /// A room message's number, for edits and deletes to refer to.
+    pub id: Option<u64>,
+    pub at: SystemTime,
+    pub body: &'a str,
+}
+
+impl JsonFrame<'_> {
+    pub fn encode(&self) -> String {
+        let mut out = String::from("{\"type\":");
+        json_string(&mut out, self.kind);
+        out.push_str(",\"sender\":");
+        json_option(&mut out, self.sender);
+        if let Some(to) = self.to {
+            out.push_str(",\"to\":");
+            json_string(&mut out, to);
+        }
+        out.push_str(",\"room\":");
+        json_option(&mut out, self.room);
+        if let Some(id) = self.id {
+            let _ = write!(out, ",\"id\":{id}");
+        }
+        let timestamp = self
+            .at
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let _ = write!(out, ",\"timestamp\":{timestamp},\"body\":");
+        json_string(&mut out, self.body);
+        out.push('}');
+        out
+    }
+}
+
+fn json_option(out: &mut String, value: Option<&str>) {
+    match value {
+        Some(value) => json_string(out, value),
+        None => out.push_str("null"),
+    }
+}
+
+/// Append `text` as a quoted JSON string. Control characters are
+/// escaped, so a body can never break the one-object-per-line framing.
+fn json_string(out: &mut String, text: &str) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
 /// Custom iterator that parses frames from a buffer of accumulated bytes.
 ///
 /// Yields one Frame per complete line (\n-terminated) in the buffer.
 /// Incomplete lines (no trailing \n) are left for the next read.
+/// A \r before the \n is dropped, so Windows clients parse the same.
 pub struct FrameIter<'a> {
     buf: &'a str,
     pos: usize,
@@ -127,7 +667,7 @@ impl<'a> Iterator for FrameIter<'a> {
         let remaining = &self.buf[self.pos..];
         let newline = remaining.find('\n')?;
 
-        let line = &remaining[..newline];
+        let line = trim_line_ending(&remaining[..newline]);
         self.pos += newline + 1; // skip past the \n
 
         if line.trim().is_empty() {
@@ -146,3 +686,120 @@ impl FrameIter<'_> {
         self.pos
     }
 }
+
+/// Encode a read marker: the last message a user saw in `room`, and the
+/// newest one there now. Anything between is theirs to backfill.
+pub fn encode_read(room: &str, last: u64, latest: u64) -> String {
+    format!("READ:{room}:last={last}:latest={latest}")
+}
+
+/// Encode the answer to a STATS frame. Uptime is in whole seconds, for
+/// programs; people get `/stats`.
+pub fn encode_stats(stats: &ServerStats) -> String {
+    format!(
+        "STATS:uptime={}:users={}:rooms={}:messages={}:bytes_in={}:bytes_out={}",
+        stats.uptime.as_secs(),
+        stats.users,
+        stats.rooms,
+        stats.messages,
+        stats.bytes_received,
+        stats.bytes_sent,
+    )
+}
+
+pub fn encode_session(token: &str) -> String {
+    format!("SESSION:{token}")
+}
+
+pub fn encode_ping(token: &str) -> String {
+    format!("PING:{token}")
+}
+
+pub fn encode_pong(token: &str) -> String {
+    format!("PONG:{token}")
+}
+
+pub fn encode_cap(caps: &[&str]) -> String {
+    format!("CAP:{}", caps.join(","))
+}
+
+/// The answer to HELLO: everything a client may ask for, as
+/// `Caps::offered` lists it.
+pub fn encode_caps(offered: &[&str]) -> String {
+    format!("CAPS:{}", offered.join(","))
+}
+
+/// What a connection agreed to at the username prompt. All off until
+/// the client asks; the connection decides how to write and what to
+/// accept from these.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Caps {
+    /// SEQ:<n>: in front of every line, and ACK to have them resent.
+    pub seq: bool,
+    /// Length-prefixed frames instead of lines, both ways.
+    pub binary: bool,
+    /// JSON objects from the start, rather than after PROTO:json.
+    pub json: bool,
+    /// Every byte compressed, both ways. One kind per connection.
+    pub compression: Option<Compression>,
+    /// A SESSION token, to come back with if the connection drops.
+    pub resume: bool,
+}
+
+impl Caps {
+    /// Every capability the server always has, in the order CAPS lists
+    /// them.
+    pub const SUPPORTED: &[&str] = &["seq", "binary", "json"];
+
+    /// What this server offers: SUPPORTED, then each compression built
+    /// in, unless `compression` is off server-wide, then `resume` if
+    /// dropped sessions are kept.
+    pub fn offered(compression: bool, resume: bool) -> Vec<&'static str> {
+        let mut names = Self::SUPPORTED.to_vec();
+        if compression {
+            names.extend(Compression::ALL.iter().map(|kind| kind.name()));
+        }
+        if resume {
+            names.push("resume");
+        }
+        names
+    }
+
+    /// Turn on `name`, one of those offered. False if it can't be: a
+    /// name nobody knows, or a second compression.
+    pub fn enable(&mut self, name: &str) -> bool {
+        match name {
+            "seq" => self.seq = true,
+            "binary" => self.binary = true,
+            "json" => self.json = true,
+            "resume" => self.resume = true,
+            name => match Compression::from_name(name) {
+                Some(kind) if self.compression.is_none() => self.compression = Some(kind),
+                _ => return false,
+            },
+        }
+        true
+    }
+
+    pub fn framing(self) -> Framing {
+        if self.binary {
+            Framing::LengthPrefixed
+        } else {
+            Framing::Newline
+        }
+    }
+}
+
+/// Encode a shared file for someone fetching it, as the lines it was
+/// uploaded with.
+pub fn encode_file(name: &str, size: usize, chunks: &[String]) -> String {
+    let mut lines = vec![format!("FILE_START:{name}:{size}")];
+    lines.extend(chunks.iter().map(|chunk| format!("FILE_CHUNK:{chunk}")));
+    lines.push("FILE_END:".to_string());
+    lines.join("\n")
+}
+
+/// Encode an encrypted message for its recipient, naming the sender.
+pub fn encode_emsg(from: &str, payload: &str) -> String {
+    format!("EMSG:{from}:{payload}")
+}
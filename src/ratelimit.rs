@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// Once a keyed limiter tracks this many keys, idle ones are swept out.
+const PRUNE_AT: usize = 10_000;
+
+/// A rate: `per_sec` sustained, with room for `burst` at once.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    pub per_sec: f64,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub fn new(per_sec: f64, burst: u32) -> Self {
+        Self { per_sec, burst }
+    }
+}
+
+/// Escalation for someone who keeps hitting the message limit: after
+/// `strikes` refused messages in a row, mute them for `duration`.
+#[derive(Debug, Clone, Copy)]
+pub struct FloodMute {
+    pub strikes: u32,
+    pub duration: Duration,
+}
+
+/// Token bucket: holds up to `burst` tokens, refills at `per_sec`.
+/// Each action takes one token; an empty bucket means "slow down".
+///
+/// Refill is computed lazily from the elapsed time on each call, so
+/// there's no background timer — a bucket is just two numbers and a
+/// timestamp.
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            last: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_sec).min(self.limit.burst as f64);
+        self.last = now;
+    }
+
+    /// Take a token if one is available.
+    pub fn try_take(&mut self) -> bool {
+        self.refill();
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// A full bucket carries no information — it can be forgotten.
+    fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.limit.burst as f64
+    }
+}
+
+/// One token bucket per key (IP address, user, ...).
+pub struct RateLimiter<K> {
+    limit: RateLimit,
+    buckets: HashMap<K, TokenBucket>,
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Change the rate for every key, buckets already in use included.
+    /// Nobody keeps more tokens than the new burst allows.
+    pub fn set_limit(&mut self, limit: RateLimit) {
+        self.limit = limit;
+        for bucket in self.buckets.values_mut() {
+            bucket.limit = limit;
+            bucket.tokens = bucket.tokens.min(limit.burst as f64);
+        }
+    }
+
+    /// Take a token from `key`'s bucket.
+    pub fn check(&mut self, key: K) -> bool {
+        if self.buckets.len() >= PRUNE_AT {
+            self.buckets.retain(|_, bucket| !bucket.is_full());
+        }
+
+        let limit = self.limit;
+        self.buckets
+            .entry(key)
+            .or_insert_with(|| TokenBucket::new(limit))
+            .try_take()
+    }
+}
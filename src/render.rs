@@ -0,0 +1,324 @@
+use std::fmt::Write as _;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::protocol::JsonFrame;
+use crate::server::Event;
+
+const RESET: &str = "\x1b[0m";
+const NAME: &str = "\x1b[1;36m"; // bold cyan
+const SYSTEM: &str = "\x1b[33m"; // yellow
+const MENTION: &str = "\x1b[1;35m"; // bold magenta
+
+/// Strip control characters from text on its way to a terminal.
+///
+/// Anything a user typed can reach someone else's screen, and a raw ESC
+/// byte lets them move the cursor, clear the screen or recolour the rest
+/// of the session. Newlines stay: multi-line system messages use them.
+/// Chat bodies only have one when it came in a length-prefixed frame,
+/// and `chat_body` decides what happens to those.
+pub fn sanitize(text: &str) -> String {
+    text.chars()
+        .filter(|&c| c == '\n' || !c.is_control())
+        .collect()
+}
+
+/// Turn an event into the line one client sees.
+///
+/// Order matters: sanitize first, colour second. Colouring adds the only
+/// escape sequences that may appear in the output, so with `color` off
+/// the line is guaranteed to be plain text.
+///
+/// Chat lines start with the time they were sent when `stamps` gives a
+/// format — for replayed history, the time it was first said — then the
+/// room, since a user can be in several at once, and the message's
+/// number in it, for `/edit` and `/delete`: `#lobby/12`.
+///
+/// `multiline` is for clients reading length-prefixed frames, where a
+/// chat line can carry its newlines intact. Everyone else gets them as
+/// spaces, so one message is still one line.
+pub fn line(event: &Event, color: bool, multiline: bool, stamps: Option<&str>) -> String {
+    let stamp = |at: &SystemTime| match stamps {
+        Some(format) => format!("{} ", timestamp(*at, format)),
+        None => String::new(),
+    };
+    match event {
+        Event::Message {
+            room,
+            id,
+            from,
+            body,
+            at,
+        } => {
+            let (stamp, room) = (stamp(at), sanitize(room));
+            let (from, body) = (sanitize(from), chat_body(body, multiline));
+            if color {
+                format!(
+                    "{stamp}#{room}/{id} <{NAME}{from}{RESET}> {}\n",
+                    highlight_mentions(&body)
+                )
+            } else {
+                format!("{stamp}#{room}/{id} <{from}> {body}\n")
+            }
+        }
+        // Marked so a client can tell it from live chat.
+        Event::Replay {
+            room,
+            id,
+            from,
+            body,
+            at,
+        } => {
+            let (stamp, room) = (stamp(at), sanitize(room));
+            let (from, body) = (sanitize(from), chat_body(body, multiline));
+            if color {
+                format!(
+                    "{SYSTEM}[history]{RESET} {stamp}#{room}/{id} <{NAME}{from}{RESET}> {body}\n"
+                )
+            } else {
+                format!("[history] {stamp}#{room}/{id} <{from}> {body}\n")
+            }
+        }
+        // The message again, as it reads now. The time is the edit's.
+        Event::Edited {
+            room,
+            id,
+            from,
+            body,
+            at,
+        } => {
+            let (stamp, room) = (stamp(at), sanitize(room));
+            let (from, body) = (sanitize(from), chat_body(body, multiline));
+            if color {
+                format!(
+                    "{stamp}#{room}/{id} <{NAME}{from}{RESET}> {body} {SYSTEM}(edited){RESET}\n"
+                )
+            } else {
+                format!("{stamp}#{room}/{id} <{from}> {body} (edited)\n")
+            }
+        }
+        Event::Deleted { room, id, by, .. } => {
+            let (room, by) = (sanitize(room), sanitize(by));
+            if color {
+                format!("{SYSTEM}* #{room}/{id} was deleted by {by}{RESET}\n")
+            } else {
+                format!("* #{room}/{id} was deleted by {by}\n")
+            }
+        }
+        Event::Direct { from, to, body, at } => {
+            let (stamp, from, to, body) = (
+                stamp(at),
+                sanitize(from),
+                sanitize(to),
+                chat_body(body, multiline),
+            );
+            if color {
+                format!("{stamp}[{NAME}{from}{RESET} -> {NAME}{to}{RESET}] {body}\n")
+            } else {
+                format!("{stamp}[{from} -> {to}] {body}\n")
+            }
+        }
+        // A frame for the client program to beep or highlight with,
+        // alongside the message itself if they're in the room.
+        Event::Mention {
+            room,
+            id,
+            from,
+            body,
+            ..
+        } => {
+            let (room, from) = (sanitize(room), sanitize(from));
+            format!("NOTIFY:{room}:{id}:{from}:{}\n", chat_body(body, multiline))
+        }
+        // The writer acts on these instead of rendering them.
+        Event::Close(_) | Event::Resend(_) => String::new(),
+        // Protocol replies are for the client program, not a person:
+        // never coloured.
+        Event::Frames(text) => format!("{}\n", sanitize(text)),
+        Event::System(text) | Event::Presence(text) => {
+            let text = sanitize(text);
+            if color {
+                format!("{SYSTEM}{text}{RESET}\n")
+            } else {
+                format!("{text}\n")
+            }
+        }
+    }
+}
+
+/// A chat body made safe for a terminal, its newlines kept only when
+/// the client can tell them from the end of the line.
+fn chat_body(body: &str, multiline: bool) -> String {
+    let body = sanitize(body);
+    if multiline {
+        body
+    } else {
+        body.replace('\n', " ")
+    }
+}
+
+/// Format a moment in UTC. `%H`, `%M`, `%S`, `%Y`, `%m` and `%d` are
+/// replaced and `%%` is a percent sign; anything else is copied as is.
+pub fn timestamp(at: SystemTime, format: &str) -> String {
+    let secs = at
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, of_day) = (secs / 86_400, secs % 86_400);
+    let (year, month, day) = civil_date(days);
+    let mut out = String::with_capacity(format.len() + 8);
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let _ = match chars.next() {
+            Some('H') => write!(out, "{:02}", of_day / 3600),
+            Some('M') => write!(out, "{:02}", of_day % 3600 / 60),
+            Some('S') => write!(out, "{:02}", of_day % 60),
+            Some('Y') => write!(out, "{year:04}"),
+            Some('m') => write!(out, "{month:02}"),
+            Some('d') => write!(out, "{day:02}"),
+            Some('%') => write!(out, "%"),
+            Some(other) => write!(out, "%{other}"),
+            None => write!(out, "%"),
+        };
+    }
+    out
+}
+
+/// Year, month and day for a count of days since 1970-01-01, by Howard
+/// Hinnant's `civil_from_days`: shift the year to start in March, so
+/// the leap day falls at the end, then count 400-year eras.
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Turn an event into one JSON object for a client that asked for
+/// `PROTO:json`. No sanitizing or colour: escaping takes care of control
+/// characters, and presentation is the client program's business.
+pub fn json(event: &Event) -> String {
+    let frame = match event {
+        Event::Message {
+            room,
+            id,
+            from,
+            body,
+            at,
+        } => JsonFrame {
+            kind: "message",
+            sender: Some(from),
+            to: None,
+            room: Some(room),
+            id: Some(*id),
+            at: *at,
+            body,
+        },
+        Event::Replay {
+            room,
+            id,
+            from,
+            body,
+            at,
+        } => JsonFrame {
+            kind: "history",
+            sender: Some(from),
+            to: None,
+            room: Some(room),
+            id: Some(*id),
+            at: *at,
+            body,
+        },
+        Event::Edited {
+            room,
+            id,
+            from,
+            body,
+            at,
+        } => JsonFrame {
+            kind: "edit",
+            sender: Some(from),
+            to: None,
+            room: Some(room),
+            id: Some(*id),
+            at: *at,
+            body,
+        },
+        // The sender here is who deleted it.
+        Event::Deleted { room, id, by, at } => JsonFrame {
+            kind: "delete",
+            sender: Some(by),
+            to: None,
+            room: Some(room),
+            id: Some(*id),
+            at: *at,
+            body: "",
+        },
+        Event::Mention {
+            room,
+            id,
+            from,
+            body,
+            at,
+            ..
+        } => JsonFrame {
+            kind: "notify",
+            sender: Some(from),
+            to: None,
+            room: Some(room),
+            id: Some(*id),
+            at: *at,
+            body,
+        },
+        Event::Direct { from, to, body, at } => JsonFrame {
+            kind: "direct",
+            sender: Some(from),
+            to: Some(to),
+            room: None,
+            id: None,
+            at: *at,
+            body,
+        },
+        Event::Close(_) | Event::Resend(_) => return String::new(),
+        Event::Frames(text) => notice("frame", text),
+        Event::System(text) => notice("system", text),
+        Event::Presence(text) => notice("presence", text),
+    };
+    format!("{}\n", frame.encode())
+}
+
+/// The server speaking for itself: no sender, no room, sent now.
+fn notice<'a>(kind: &'a str, body: &'a str) -> JsonFrame<'a> {
+    JsonFrame {
+        kind,
+        sender: None,
+        to: None,
+        room: None,
+        id: None,
+        at: SystemTime::now(),
+        body,
+    }
+}
+
+/// Colour every `@name` word.
+fn highlight_mentions(body: &str) -> String {
+    body.split(' ')
+        .map(|word| {
+            if word.len() > 1 && word.starts_with('@') {
+                format!("{MENTION}{word}{RESET}")
+            } else {
+                word.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,98 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::error::ChatError;
+use crate::permissions::Role;
+use crate::room::RoomRole;
+use crate::tokens;
+use crate::types::UserId;
+
+/// Who a dropped client was and where, kept for it to come back to.
+#[derive(Debug, Clone)]
+pub struct Parked {
+    pub username: String,
+    pub account: Option<String>,
+    pub role: Role,
+    pub by_token: bool,
+    pub locale: Option<String>,
+    pub ignored: HashSet<String>,
+    /// A guest's read markers, which would otherwise go with the
+    /// connection. An account's stay put.
+    pub read: HashMap<String, u64>,
+    /// A guest's room roles, by room name, likewise.
+    pub room_roles: Vec<(String, RoomRole)>,
+    /// The rooms they were in, by name, oldest first. Ids don't last:
+    /// a room can go and another take its slot.
+    pub rooms: Vec<String>,
+    /// The one they were talking in.
+    pub active: String,
+}
+
+/// Sessions a client can pick up again with `RESUME:token`.
+///
+/// A phone that walks out of wifi range drops its connection without
+/// meaning to leave. A client that asked for `resume` at the username
+/// prompt is given a token once it's signed in; if its connection dies
+/// (rather than it quitting, or being kicked), what it was is parked
+/// here under that token for the grace period. Coming back with the
+/// token puts it back: same name, same rooms, and what was said while
+/// it was gone replayed from each room's history.
+///
+/// The name is held for it meanwhile, so nobody else can take it in the
+/// gap. A token works once: the resumed session gets a new one.
+pub struct Resumes {
+    /// Tokens of live sessions, for parking them when they drop.
+    issued: HashMap<UserId, String>,
+    /// By token, with when each stops being resumable.
+    parked: HashMap<String, (Instant, Parked)>,
+}
+
+impl Resumes {
+    pub fn new() -> Self {
+        Self {
+            issued: HashMap::new(),
+            parked: HashMap::new(),
+        }
+    }
+
+    /// A token for `user_id`'s session, to hand to the client.
+    pub fn issue(&mut self, user_id: UserId) -> Result<String, ChatError> {
+        let token = tokens::random_token()?;
+        self.issued.insert(user_id, token.clone());
+        Ok(token)
+    }
+
+    /// `user_id` is gone. Their token, if they had one, to park them
+    /// under.
+    pub fn close(&mut self, user_id: UserId) -> Option<String> {
+        self.issued.remove(&user_id)
+    }
+
+    /// Keep `parked` under `token` for `grace`.
+    pub fn park(&mut self, token: String, parked: Parked, grace: Duration) {
+        let now = Instant::now();
+        self.parked.retain(|_, (until, _)| *until > now);
+        self.parked.insert(token, (now + grace, parked));
+    }
+
+    /// The session parked under `token`, if it's still in time. Taking
+    /// it is what makes the token single-use.
+    pub fn take(&mut self, token: &str) -> Option<Parked> {
+        let (until, parked) = self.parked.remove(token)?;
+        (until > Instant::now()).then_some(parked)
+    }
+
+    /// Whether a parked session is holding `name`.
+    pub fn holds(&self, name: &str) -> bool {
+        let now = Instant::now();
+        self.parked
+            .values()
+            .any(|(until, parked)| *until > now && parked.username == name)
+    }
+
+    /// Let go of whatever's parked under `name`. For when the name's
+    /// owner signs in afresh: the account is theirs, parked or not.
+    pub fn forget(&mut self, name: &str) {
+        self.parked.retain(|_, (_, parked)| parked.username != name);
+    }
+}
@@ -1,21 +1,230 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
 
-use crate::types::{RoomId, UserId};
+use crate::auth::Credentials;
+use crate::history::History;
+use crate::metrics::{DAY, HOUR, RoomActivity, RoomStats};
+use crate::poll::Poll;
+use crate::server::AsyncFilter;
+use crate::types::UserId;
+
+/// Rooms whose names start with this hold direct conversations.
+const DM_PREFIX: &str = "dm/";
+
+/// The room two users' direct messages live in. The names are sorted,
+/// so it's the same room whichever of them writes first.
+pub fn dm_room_name(a: &str, b: &str) -> String {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    format!("{DM_PREFIX}{first}/{second}")
+}
+
+pub fn is_dm_name(name: &str) -> bool {
+    name.starts_with(DM_PREFIX)
+}
+
+/// Whether a room name matches a `/list` pattern, ignoring case. `*`
+/// stands for any run of characters; a pattern without one matches
+/// anywhere in the name, so `/list rust` finds #rust-beginners too.
+pub fn name_matches(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (pattern.to_lowercase(), name.to_lowercase());
+    if !pattern.contains('*') {
+        return name.contains(&pattern);
+    }
+    let pieces: Vec<&str> = pattern.split('*').collect();
+    let (first, last) = (pieces[0], pieces[pieces.len() - 1]);
+    if !name.starts_with(first) || name.len() < first.len() + last.len() {
+        return false;
+    }
+    // The first and last pieces are pinned to the ends; the ones in
+    // between only need to appear in order.
+    let mut rest = &name[first.len()..name.len() - last.len()];
+    for piece in &pieces[1..pieces.len() - 1] {
+        match rest.find(piece) {
+            Some(at) => rest = &rest[at + piece.len()..],
+            None => return false,
+        }
+    }
+    name.ends_with(last)
+}
+
+/// What becomes of a room nobody has been in for a while.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpiryAction {
+    /// Gone for good: its settings, its stored history and everyone's
+    /// read markers for it. The name is free for anyone to `/join`.
+    Delete,
+    /// Put away: it stops taking up a slot, but the next `/join` brings
+    /// it back as it was, owner, topic, password and history included.
+    Archive,
+}
+
+/// When rooms made with `/join` go away once everyone has left.
+///
+/// The lobby, the configured private rooms, feed and multicast rooms,
+/// and DM rooms are never expired: the server or the config depends on
+/// them being there.
+#[derive(Debug, Clone, Copy)]
+pub struct RoomExpiry {
+    /// How long a room must have been empty.
+    pub after: Duration,
+    pub action: ExpiryAction,
+}
+
+/// Standing within one room, lowest to highest.
+///
+/// Separate from the server-wide `Role`: whoever creates a room owns
+/// it, and may hand operator status to others with `/op`. Server
+/// operators count as owners everywhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RoomRole {
+    Member,
+    Operator,
+    Owner,
+}
+
+impl fmt::Display for RoomRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoomRole::Member => write!(f, "member"),
+            RoomRole::Operator => write!(f, "operator"),
+            RoomRole::Owner => write!(f, "owner"),
+        }
+    }
+}
+
+/// Who holds a room role. An account's role lasts, in the rooms file
+/// too. A guest's lasts only as long as the guest: once they've gone,
+/// their name is anyone's.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Holder {
+    Account(String),
+    Guest(String),
+}
 
 /// Thread-safe room using tokio's async Mutex.
 pub struct Room {
-    pub id: RoomId,
     pub name: String,
+    /// Set by operators with `/topic`, shown to everyone who joins.
+    pub topic: Option<String>,
+    /// Set by `/setpass`, or by whoever created the room with
+    /// `/join <room> <password>`. Hashed, like an account's.
+    pub password: Option<Credentials>,
     pub members: Arc<Mutex<Vec<UserId>>>,
+    /// Never held across an await, so a std Mutex will do.
+    pub activity: std::sync::Mutex<RoomActivity>,
+    /// At most one poll per room at a time.
+    pub poll: Option<Poll>,
+    /// Recent messages with sequence numbers, for HISTORY requests.
+    pub history: History,
+    /// Private rooms are entered with an invite code, not `/join`.
+    pub private: bool,
+    /// Names that have redeemed an invite, and may come and go freely.
+    pub invited: HashSet<String>,
+    /// Names kept out by `/roomban`, with the reason given.
+    pub banned: HashMap<String, String>,
+    /// Left out of room listings: a DM room, for one.
+    pub hidden: bool,
+    /// When the expiry sweep first found the room with nobody in it.
+    /// None while it has members, and until the sweep has looked.
+    pub empty_since: Option<Instant>,
+    /// Owner and operators by account; everyone else is a member.
+    /// Keyed by account so a role survives reconnecting.
+    roles: HashMap<String, RoomRole>,
+    /// Guests' roles, by name, for as long as each guest is here.
+    guest_roles: HashMap<String, RoomRole>,
+    /// Filters for this room only, by name, run after the server's own
+    /// in the order they were added.
+    filters: Vec<(String, Box<dyn AsyncFilter>)>,
 }
 
 impl Room {
-    pub fn new(id: RoomId, name: String) -> Self {
+    pub fn new(name: String, history_size: usize) -> Self {
         Self {
-            id,
             name,
+            topic: None,
+            password: None,
             members: Arc::new(Mutex::new(Vec::new())),
+            activity: std::sync::Mutex::new(RoomActivity::new()),
+            poll: None,
+            history: History::new(history_size),
+            private: false,
+            invited: HashSet::new(),
+            banned: HashMap::new(),
+            hidden: false,
+            empty_since: None,
+            roles: HashMap::new(),
+            guest_roles: HashMap::new(),
+            filters: Vec::new(),
+        }
+    }
+
+    pub fn role_of(&self, holder: &Holder) -> RoomRole {
+        let role = match holder {
+            Holder::Account(name) => self.roles.get(name),
+            Holder::Guest(name) => self.guest_roles.get(name),
+        };
+        role.copied().unwrap_or(RoomRole::Member)
+    }
+
+    /// Give `holder` a role here. Member is the default, so setting it
+    /// just forgets the entry.
+    pub fn set_role(&mut self, holder: &Holder, role: RoomRole) {
+        let (name, roles) = match holder {
+            Holder::Account(name) => (name, &mut self.roles),
+            Holder::Guest(name) => (name, &mut self.guest_roles),
+        };
+        if role == RoomRole::Member {
+            roles.remove(name);
+        } else {
+            roles.insert(name.clone(), role);
+        }
+    }
+
+    /// Forget a guest's role as they leave.
+    pub fn forget_guest(&mut self, name: &str) {
+        self.guest_roles.remove(name);
+    }
+
+    /// Accounts above Member, for saving. Guests' roles aren't kept.
+    pub fn roles(&self) -> impl Iterator<Item = (&str, RoomRole)> {
+        self.roles.iter().map(|(name, &role)| (name.as_str(), role))
+    }
+
+    /// Add a filter called `name`, after any already here. False if
+    /// the room already has one by that name.
+    pub fn add_filter(&mut self, name: &str, filter: Box<dyn AsyncFilter>) -> bool {
+        if self.filters.iter().any(|(existing, _)| existing == name) {
+            return false;
+        }
+        self.filters.push((name.to_string(), filter));
+        true
+    }
+
+    /// False if there was no filter called `name`.
+    pub fn remove_filter(&mut self, name: &str) -> bool {
+        let before = self.filters.len();
+        self.filters.retain(|(existing, _)| existing != name);
+        self.filters.len() < before
+    }
+
+    pub fn filter_names(&self) -> Vec<String> {
+        self.filters.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    pub fn filters(&self) -> impl Iterator<Item = &dyn AsyncFilter> {
+        self.filters.iter().map(|(_, filter)| filter.as_ref())
+    }
+
+    /// Carry a guest's role over a `/nick`. An account's needn't move.
+    pub fn rename_guest(&mut self, old: &str, new: &str) {
+        if let Some(role) = self.guest_roles.remove(old) {
+            self.guest_roles.insert(new.to_string(), role);
         }
     }
 
@@ -23,14 +232,38 @@ impl Room {
         let mut members = self.members.lock().await;
         if !members.contains(&user_id) {
             members.push(user_id);
+            self.activity.lock().unwrap().record_members(members.len());
         }
     }
 
     pub async fn remove_member(&self, user_id: UserId) {
-        self.members.lock().await.retain(|&id| id != user_id);
+        let mut members = self.members.lock().await;
+        members.retain(|&id| id != user_id);
+        self.activity.lock().unwrap().record_members(members.len());
     }
 
     pub async fn member_ids(&self) -> Vec<UserId> {
         self.members.lock().await.clone()
     }
+
+    /// Visit every member without copying the list, for the hot path
+    /// of a broadcast. The lock is held throughout, so `visit` mustn't
+    /// wait on anything.
+    pub async fn for_each_member(&self, mut visit: impl FnMut(UserId)) {
+        for &member_id in self.members.lock().await.iter() {
+            visit(member_id);
+        }
+    }
+
+    pub async fn stats(&self) -> RoomStats {
+        let members_now = self.members.lock().await.len();
+        let activity = self.activity.lock().unwrap();
+        RoomStats {
+            room: self.name.clone(),
+            members_now,
+            last_hour: activity.window(HOUR),
+            last_day: activity.window(DAY),
+            total_messages: activity.total_messages(),
+        }
+    }
 }
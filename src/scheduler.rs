@@ -0,0 +1,169 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+use tokio::time::MissedTickBehavior;
+
+use crate::server::Server;
+
+/// How often the scheduler wakes up to look for due tasks.
+/// Everything it runs is human-scale (mutes, announcements, purges),
+/// so a quarter-second of slack is invisible.
+const TICK: Duration = Duration::from_millis(250);
+
+type TaskFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A scheduled task.
+///
+/// Same trick as AsyncFilter: the closure returns a boxed future so we
+/// can store different async tasks in one collection. The task gets its
+/// own handle to the shared server and locks it when it needs to — the
+/// scheduler never holds the lock while a task runs.
+pub type Task = Box<dyn FnMut(Arc<Mutex<Server>>) -> TaskFuture + Send>;
+
+/// Handle returned by `schedule`, used to cancel a pending task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TaskId(u64);
+
+struct Entry {
+    due: Instant,
+    id: TaskId,
+    every: Option<Duration>,
+    task: Task,
+}
+
+// BinaryHeap is a max-heap; reverse the ordering so the earliest
+// deadline sits on top.
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .due
+            .cmp(&self.due)
+            .then_with(|| other.id.0.cmp(&self.id.0))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Entry {}
+
+/// One-shot and recurring tasks, ordered by deadline.
+pub struct Scheduler {
+    queue: BinaryHeap<Entry>,
+    next_id: u64,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self {
+            queue: BinaryHeap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Run `task` once, `after` from now. None, and the task dropped, if
+    /// that's further off than the clock can count.
+    pub fn once<F, Fut>(&mut self, after: Duration, task: F) -> Option<TaskId>
+    where
+        F: FnOnce(Arc<Mutex<Server>>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        // Adapt FnOnce to the FnMut the queue stores: the Option is
+        // emptied on the first (and only) call.
+        let mut task = Some(task);
+        self.push(
+            after,
+            None,
+            Box::new(move |server| match task.take() {
+                Some(task) => Box::pin(task(server)),
+                None => Box::pin(async {}),
+            }),
+        )
+    }
+
+    /// Run `task` every `period`, first firing one period from now.
+    /// None, as for `once`, if that can't be counted.
+    pub fn every<F, Fut>(&mut self, period: Duration, mut task: F) -> Option<TaskId>
+    where
+        F: FnMut(Arc<Mutex<Server>>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.push(
+            period,
+            Some(period),
+            Box::new(move |server| Box::pin(task(server))),
+        )
+    }
+
+    /// Drop a pending task. Cancelling a task that already ran is a no-op.
+    pub fn cancel(&mut self, id: TaskId) {
+        self.queue.retain(|entry| entry.id != id);
+    }
+
+    fn push(&mut self, after: Duration, every: Option<Duration>, task: Task) -> Option<TaskId> {
+        let due = Instant::now().checked_add(after)?;
+        let id = TaskId(self.next_id);
+        self.next_id += 1;
+        self.queue.push(Entry {
+            due,
+            id,
+            every,
+            task,
+        });
+        Some(id)
+    }
+
+    /// Pop everything due at `now` and turn it into futures ready to spawn.
+    /// Recurring tasks go straight back into the queue.
+    fn poll(&mut self, now: Instant, server: &Arc<Mutex<Server>>) -> Vec<TaskFuture> {
+        let mut ready = Vec::new();
+
+        while self.queue.peek().is_some_and(|entry| entry.due <= now) {
+            let Some(mut entry) = self.queue.pop() else {
+                break;
+            };
+            ready.push((entry.task)(Arc::clone(server)));
+
+            if let Some(period) = entry.every {
+                entry.due += period;
+                self.queue.push(entry);
+            }
+        }
+
+        ready
+    }
+}
+
+/// Drive the scheduler for the lifetime of the server.
+///
+/// Each tick takes the lock just long enough to collect due tasks, then
+/// spawns them — a task that sleeps or locks the server itself can't
+/// stall the others.
+pub async fn run(server: Arc<Mutex<Server>>) {
+    let mut tick = tokio::time::interval(TICK);
+    tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tick.tick().await;
+
+        let ready = server.lock().await.scheduler.poll(Instant::now(), &server);
+
+        for task in ready {
+            tokio::spawn(task);
+        }
+    }
+}
@@ -0,0 +1,310 @@
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use rhai::{AST, Dynamic, Engine, Map, Scope};
+use tracing::{info, warn};
+
+use crate::command::{CommandContext, CommandHandler, CommandResult};
+use crate::error::ChatError;
+use crate::filter::FilterContext;
+use crate::server::{AsyncFilter, FilterAction, Server};
+
+/// How often the scripts directory is checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Scripts register by naming convention, so a script is just functions:
+///
+///   fn cmd_roll(user, room, args) { ... }   // adds /roll, returns the reply
+///   fn filter_caps(user, body) { ... }      // runs on every message
+///
+/// A filter returns `()` to allow, a string to replace the body, or
+/// `block("reason")` to drop the message.
+const COMMAND_PREFIX: &str = "cmd_";
+const FILTER_PREFIX: &str = "filter_";
+
+/// Hard limits so a buggy script can't hang or exhaust the server.
+/// Rhai has no file or network access unless we register it, so these
+/// plus the functions below are the whole API surface.
+fn sandboxed_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(100_000);
+    engine.set_max_call_levels(32);
+    engine.set_max_string_size(64 * 1024);
+    engine.set_max_array_size(10_000);
+    engine.set_max_map_size(10_000);
+
+    engine.register_fn("block", |reason: &str| {
+        let mut map = Map::new();
+        map.insert("block".into(), reason.into());
+        map
+    });
+    engine.register_fn("log", |text: &str| info!(target: "script", "{text}"));
+
+    engine
+}
+
+struct Script {
+    file: PathBuf,
+    ast: AST,
+}
+
+/// Cheap fingerprint of the directory: file count and newest mtime.
+type Stamp = (usize, Option<SystemTime>);
+
+#[derive(Default)]
+struct Loaded {
+    stamp: Option<Stamp>,
+    scripts: Vec<Script>,
+}
+
+/// Compiled scripts plus the engine that runs them.
+///
+/// Shared (Arc) between the command handlers and the filter; the RwLock
+/// lets many messages run filters at once while a reload swaps the set.
+pub struct ScriptHost {
+    dir: PathBuf,
+    engine: Engine,
+    loaded: RwLock<Loaded>,
+    /// Command names currently in the server's registry on our behalf.
+    registered: Mutex<Vec<String>>,
+}
+
+impl ScriptHost {
+    fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            engine: sandboxed_engine(),
+            loaded: RwLock::new(Loaded::default()),
+            registered: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn stamp(&self) -> Stamp {
+        let files = script_files(&self.dir);
+        let newest = files
+            .iter()
+            .filter_map(|f| fs::metadata(f).and_then(|m| m.modified()).ok())
+            .max();
+        (files.len(), newest)
+    }
+
+    /// Recompile every script if anything in the directory changed.
+    /// Returns true if the set was reloaded. A script that fails to
+    /// compile is reported and skipped; the others still load.
+    fn reload_if_changed(&self) -> bool {
+        let stamp = self.stamp();
+        if self.loaded.read().unwrap().stamp == Some(stamp) {
+            return false;
+        }
+
+        let mut scripts = Vec::new();
+        for file in script_files(&self.dir) {
+            let compiled = fs::read_to_string(&file)
+                .map_err(|e| e.to_string())
+                .and_then(|src| self.engine.compile(src).map_err(|e| e.to_string()));
+            match compiled {
+                Ok(ast) => scripts.push(Script { file, ast }),
+                Err(e) => warn!(file = %file.display(), error = %e, "script failed to compile"),
+            }
+        }
+
+        *self.loaded.write().unwrap() = Loaded {
+            stamp: Some(stamp),
+            scripts,
+        };
+        true
+    }
+
+    /// Names of all commands the current scripts define.
+    fn command_names(&self) -> Vec<String> {
+        self.function_names(COMMAND_PREFIX)
+            .into_iter()
+            .map(|(_, name)| name)
+            .collect()
+    }
+
+    /// (script index, name without prefix) for every function with `prefix`.
+    fn function_names(&self, prefix: &str) -> Vec<(usize, String)> {
+        let loaded = self.loaded.read().unwrap();
+        loaded
+            .scripts
+            .iter()
+            .enumerate()
+            .flat_map(|(index, script)| {
+                script
+                    .ast
+                    .iter_functions()
+                    .filter_map(|f| f.name.strip_prefix(prefix))
+                    .map(move |name| (index, name.to_string()))
+            })
+            .collect()
+    }
+
+    fn call(&self, index: usize, function: &str, args: impl rhai::FuncArgs) -> Option<Dynamic> {
+        let loaded = self.loaded.read().unwrap();
+        let script = loaded.scripts.get(index)?;
+        match self
+            .engine
+            .call_fn::<Dynamic>(&mut Scope::new(), &script.ast, function, args)
+        {
+            Ok(value) => Some(value),
+            Err(e) => {
+                warn!(file = %script.file.display(), function, error = %e, "script failed");
+                None
+            }
+        }
+    }
+
+    fn run_command(&self, name: &str, ctx: &CommandContext, args: &str) -> CommandResult {
+        let function = format!("{COMMAND_PREFIX}{name}");
+        let index = self
+            .function_names(COMMAND_PREFIX)
+            .into_iter()
+            .find(|(_, n)| n == name)
+            .map(|(index, _)| index);
+
+        let reply = index
+            .and_then(|index| {
+                self.call(
+                    index,
+                    &function,
+                    (ctx.username.clone(), ctx.room.clone(), args.to_string()),
+                )
+            })
+            .map(|value| value.to_string())
+            .unwrap_or_else(|| format!("* /{name} failed"));
+        CommandResult::Reply(reply)
+    }
+
+    fn run_filters(&self, username: &str, body: &str) -> FilterAction {
+        let mut current = body.to_string();
+
+        for (index, name) in self.function_names(FILTER_PREFIX) {
+            let function = format!("{FILTER_PREFIX}{name}");
+            let Some(value) = self.call(index, &function, (username.to_string(), current.clone()))
+            else {
+                continue;
+            };
+
+            if value.is_string() {
+                current = value.to_string();
+            } else if let Some(map) = value.try_cast::<Map>()
+                && let Some(reason) = map.get("block")
+            {
+                return FilterAction::Block(reason.to_string());
+            }
+        }
+
+        if current != body {
+            FilterAction::Modify(current)
+        } else {
+            FilterAction::Allow
+        }
+    }
+}
+
+fn script_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|ext| ext == "rhai"))
+        .collect();
+    files.sort();
+    files
+}
+
+/// A slash command backed by a script function.
+struct ScriptCommand {
+    name: String,
+    host: Arc<ScriptHost>,
+}
+
+impl CommandHandler for ScriptCommand {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn execute(&self, ctx: &CommandContext, args: &str) -> CommandResult {
+        self.host.run_command(&self.name, ctx, args)
+    }
+}
+
+/// One filter in the chain that runs every script filter in turn.
+struct ScriptFilter {
+    host: Arc<ScriptHost>,
+}
+
+impl AsyncFilter for ScriptFilter {
+    fn apply<'a>(
+        &'a self,
+        ctx: &'a FilterContext<'a>,
+        body: &'a str,
+    ) -> Pin<Box<dyn Future<Output = FilterAction> + Send + 'a>> {
+        Box::pin(async move { self.host.run_filters(ctx.username, body) })
+    }
+}
+
+/// Swap the registered script commands for the current set.
+fn register_commands(server: &mut Server, host: &Arc<ScriptHost>) {
+    let mut registered = host.registered.lock().unwrap();
+    for name in registered.drain(..) {
+        server.unregister_command(&name);
+    }
+
+    for name in host.command_names() {
+        let handler = Box::new(ScriptCommand {
+            name: name.clone(),
+            host: Arc::clone(host),
+        });
+        match server.register_command(handler) {
+            Ok(()) => registered.push(name),
+            Err(e) => warn!(error = %e, "script command not registered"),
+        }
+    }
+}
+
+/// Load scripts from `config.scripts_dir` and keep them fresh.
+///
+/// Reloading piggybacks on the scheduler: every couple of seconds we
+/// fingerprint the directory and, if it changed, recompile and swap the
+/// command set — no restart, no admin command needed.
+pub fn init(server: &mut Server) -> Result<(), ChatError> {
+    let Some(dir) = server.config.scripts_dir.clone() else {
+        return Ok(());
+    };
+    if !dir.is_dir() {
+        return Err(ChatError::Config(format!(
+            "scripts dir not found: {}",
+            dir.display()
+        )));
+    }
+
+    let host = Arc::new(ScriptHost::new(dir));
+    host.reload_if_changed();
+    register_commands(server, &host);
+    server.add_filter(Box::new(ScriptFilter {
+        host: Arc::clone(&host),
+    }));
+    info!(
+        commands = host.registered.lock().unwrap().len(),
+        "loaded scripts"
+    );
+
+    server.schedule_every(RELOAD_INTERVAL, move |server| {
+        let host = Arc::clone(&host);
+        async move {
+            if host.reload_if_changed() {
+                register_commands(&mut *server.lock().await, &host);
+                info!("reloaded scripts");
+            }
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use std::collections::VecDeque;
+
+/// How many numbered lines a connection keeps for resending.
+const RESEND_WINDOW: usize = 256;
+
+/// Numbers every line a client is sent, for clients that asked with
+/// `CAP:seq`.
+///
+/// Over TCP a line either arrives or the connection dies, so numbers
+/// aren't about the network losing lines: they're about the server
+/// dropping them. A client that lags behind its queue loses events
+/// before they're ever written (see `disconnect_slow_clients`), and
+/// those still use up their numbers, so the jump from 41 to 45 says
+/// what went missing. A client that notices a gap, or that was in a
+/// tunnel and isn't sure what it saw, sends `ACK:<seq>` for the last
+/// line it trusts and gets everything after it again, as far back as
+/// the window reaches. Lost lines were never written, so they can't be
+/// resent; for those there's HISTORY.
+pub struct Sequencer {
+    /// The number the next line gets. Starts at 1, so `ACK:0` means
+    /// "nothing yet".
+    next: u64,
+    /// Recent lines, oldest first, already numbered and with their
+    /// line endings.
+    sent: VecDeque<(u64, String)>,
+}
+
+impl Default for Sequencer {
+    fn default() -> Self {
+        Self {
+            next: 1,
+            sent: VecDeque::new(),
+        }
+    }
+}
+
+impl Sequencer {
+    /// Number each line of `text` (an event can render as several) and
+    /// remember them. `SEQ:<n>:` goes in front of a text line, a `seq`
+    /// field at the start of a JSON object.
+    pub fn number(&mut self, text: &str, json: bool) -> String {
+        let mut out = String::with_capacity(text.len() + 16);
+        for line in text.lines() {
+            let seq = self.next;
+            self.next += 1;
+            let numbered = match line.strip_prefix('{') {
+                Some(rest) if json => format!("{{\"seq\":{seq},{rest}\n"),
+                _ => format!("SEQ:{seq}:{line}\n"),
+            };
+            out.push_str(&numbered);
+            if self.sent.len() == RESEND_WINDOW {
+                self.sent.pop_front();
+            }
+            self.sent.push_back((seq, numbered));
+        }
+        out
+    }
+
+    /// `count` lines were dropped unwritten: use up their numbers.
+    pub fn skip(&mut self, count: u64) {
+        self.next += count;
+    }
+
+    /// The client has everything up to `acked`: forget those, and
+    /// return what's still held after it, to send again.
+    pub fn resend(&mut self, acked: u64) -> String {
+        while self.sent.front().is_some_and(|(seq, _)| *seq <= acked) {
+            self.sent.pop_front();
+        }
+        self.sent.iter().map(|(_, line)| line.as_str()).collect()
+    }
+}
@@ -1,22 +1,143 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::TcpStream;
-use tokio::sync::{broadcast, Mutex};
+use tokio::io::{AsyncWriteExt, DuplexStream};
+use tokio::sync::{Mutex, Notify, broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tracing::{Instrument, debug, info, warn};
 
-use crate::command::{Command, CommandResult};
+use crate::antispam::{self, Penalty, Strike};
+use crate::auth::{self, Accounts, Credentials};
+use crate::ban::{Ban, BanList, BanMatch};
+use crate::bot::{Bot, BotAction};
+use crate::bus::{EventBus, ServerEvent};
+use crate::command::{
+    Command, CommandContext, CommandHandler, CommandRegistry, CommandResult, RemindTarget, Setting,
+};
 use crate::config::ServerConfig;
+use crate::dedup::DedupFilter;
 use crate::error::ChatError;
-use crate::room::Room;
+use crate::events::{self, EventRegistry};
+pub use crate::filter::FilterAction;
+use crate::filter::{FilterContext, FilterRegistry};
+use crate::handshake::{self, ChallengeHook, HandshakeHook, HandshakeIo, PendingGuard, Stage};
+use crate::history::{self, Entry, ReadMarkers};
+use crate::hooks::{
+    DisconnectHook, DisconnectInfo, DisconnectReason, JoinHook, JoinInfo, MessageHook, MessageInfo,
+};
+use crate::i18n::{self, Catalog, MsgId};
+use crate::invite::Invites;
+use crate::keepalive::{Due, KeepAlive};
+use crate::lines::Framing;
+use crate::message;
+use crate::metrics::{Counters, DAY, DailyCounters, Exposition, MINUTE, RoomStats, ServerStats};
+use crate::multicast::Multicast;
+use crate::permissions::Role;
+use crate::persistence::{self, RoomRecord};
+use crate::plugin::{self, Plugin};
+use crate::poll::{POLL_TTL, Poll, Vote};
+use crate::protocol::{self, Caps, Frame, WireFormat};
+use crate::ratelimit::RateLimiter;
+use crate::render;
+use crate::resume::{Parked, Resumes};
+use crate::room::{self, ExpiryAction, Holder, Room, RoomRole};
+use crate::scheduler::{self, Scheduler, TaskId};
+use crate::sequence::Sequencer;
+use crate::sessions::SessionLog;
+use crate::settings;
+use crate::share::{FileLimits, SharedFiles, Upload};
+use crate::slab::Slab;
+use crate::storage::{self, MemoryStorage, Storage};
+use crate::summary::{DailyReport, SummaryTarget};
+use crate::tokens::{Grant, Tokens};
+use crate::transport::{BoxedWriter, ClientStream};
+use crate::trust::{self, Capability, Tier, TrustLedger};
 use crate::types::{RoomId, UserId};
+use crate::validation::DuplicateNames;
+
+/// How long a shutdown waits for clients to hang up before exiting
+/// anyway.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// Reminders one user may have waiting at once.
+const MAX_REMINDERS: usize = 10;
 
 /// A broadcast event.
 #[derive(Debug, Clone)]
 pub enum Event {
-    Message { from: String, body: String },
+    /// A chat line, sent to every member of the room. Shared rather
+    /// than owned: each member's copy is a reference count, not three
+    /// fresh strings.
+    Message {
+        room: Arc<str>,
+        /// Its number in the room's history, for `/edit` and `/delete`.
+        id: u64,
+        from: Arc<str>,
+        body: Arc<str>,
+        at: SystemTime,
+    },
     System(String),
+    /// Join/leave/nick chatter — system text a user can opt out of.
+    Presence(String),
+    /// Protocol frames answering a request, such as a HISTORY batch.
+    Frames(String),
+    /// A message from before this user joined, replayed from history.
+    Replay {
+        room: String,
+        id: u64,
+        from: String,
+        body: String,
+        at: SystemTime,
+    },
+    /// Message `id` in `room` now says `body`. `at` is when it changed.
+    Edited {
+        room: String,
+        id: u64,
+        from: String,
+        body: String,
+        at: SystemTime,
+    },
+    /// Message `id` in `room` was taken back, by its sender or an
+    /// operator.
+    Deleted {
+        room: String,
+        id: u64,
+        by: String,
+        at: SystemTime,
+    },
+    /// Message `id` in `room` names this user with `@`. Shares the
+    /// message's strings.
+    Mention {
+        room: Arc<str>,
+        id: u64,
+        from: Arc<str>,
+        body: Arc<str>,
+        at: SystemTime,
+        /// Whether they're in `room`. From anywhere else it's only
+        /// shown with `/set mentions on`.
+        here: bool,
+    },
+    /// A `/msg`, seen by its sender and its recipient only.
+    Direct {
+        from: String,
+        to: String,
+        body: String,
+        at: SystemTime,
+    },
+    /// Disconnect this client, for this reason. Sent after any last
+    /// words.
+    Close(DisconnectReason),
+    /// `ACK:<seq>` from a client numbering its lines: send again what
+    /// came after `seq`. Goes through the queue like everything else,
+    /// so the resent lines can't overtake or interleave with new ones.
+    Resend(u64),
 }
 
 /// An async message filter.
@@ -30,24 +151,18 @@ pub enum Event {
 /// Pin: the future won't move in memory (required because async state
 /// machines contain self-references). Box: heap-allocate to erase the
 /// concrete type. Send: can be used across .await points in tokio::spawn.
+///
+/// Filters see the message's `FilterContext` as well as its body.
 pub trait AsyncFilter: Send + Sync {
     fn apply<'a>(
         &'a self,
-        username: &'a str,
+        ctx: &'a FilterContext<'a>,
         body: &'a str,
     ) -> Pin<Box<dyn Future<Output = FilterAction> + Send + 'a>>;
 }
 
-#[derive(Debug)]
-pub enum FilterAction {
-    Allow,
-    #[allow(dead_code)]
-    Modify(String),
-    #[allow(dead_code)]
-    Block(String),
-}
-
 /// A simple counting filter — demonstrates implementing AsyncFilter.
+#[derive(Default)]
 pub struct CountingFilter {
     count: Mutex<u64>,
 }
@@ -63,327 +178,4785 @@ impl CountingFilter {
 impl AsyncFilter for CountingFilter {
     fn apply<'a>(
         &'a self,
-        _username: &'a str,
+        _ctx: &'a FilterContext<'a>,
         _body: &'a str,
     ) -> Pin<Box<dyn Future<Output = FilterAction> + Send + 'a>> {
         Box::pin(async move {
             let mut count = self.count.lock().await;
             *count += 1;
-            println!("  [filter] message #{} processed", *count);
+            debug!("message #{} processed", *count);
             FilterAction::Allow
         })
     }
 }
 
+/// A FilterRegistry in the async chain. Its closures are FnMut, so
+/// they take turns behind a lock; none of them awaits anything, so the
+/// lock is never held across an await.
+impl AsyncFilter for std::sync::Mutex<FilterRegistry> {
+    fn apply<'a>(
+        &'a self,
+        ctx: &'a FilterContext<'a>,
+        body: &'a str,
+    ) -> Pin<Box<dyn Future<Output = FilterAction> + Send + 'a>> {
+        let action = self
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .apply(ctx, body);
+        Box::pin(async move { action })
+    }
+}
+
 /// Per-client handle: a broadcast sender for delivering events.
 struct ClientHandle {
     username: String,
+    peer: SocketAddr,
     tx: broadcast::Sender<Event>,
+    mute: Option<Mute>,
+    /// Overrides the server locale for this user's system messages.
+    locale: Option<String>,
+    /// User, or whatever `/oper` granted. See `Server::role`.
+    role: Role,
+    /// The account signed in to. None for a guest.
+    account: Option<String>,
+    /// Signed in with an AUTH token, whose role stands as issued.
+    by_token: bool,
+    /// Set by `/shadowmute`: what they say is shown to them as sent,
+    /// and to nobody else.
+    shadow_muted: bool,
+    /// Every room this user is in, oldest first.
+    rooms: Vec<RoomId>,
+    /// Where their messages go: one of `rooms`, picked by `/join` or
+    /// `/switch`.
+    active: RoomId,
+    /// Names whose messages and DMs this user isn't sent. Follows a
+    /// `/nick`, so changing names doesn't get around it.
+    ignored: HashSet<String>,
+    /// Set by `/away`: what anyone who writes to them is told. Cleared
+    /// by `/back` or by their next message.
+    away: Option<String>,
+    /// Coming back with RESUME: rooms rejoined now replay what was
+    /// missed, rather than the usual last few, up to this many each.
+    resuming: Option<usize>,
+}
+
+impl ClientHandle {
+    /// Whose read markers and poll votes are theirs: the account's, or
+    /// a guest's own.
+    fn reader(&self) -> &str {
+        self.account.as_deref().unwrap_or(&self.username)
+    }
+
+    /// Who a room role would belong to: the account, if they've one.
+    fn holder(&self) -> Holder {
+        match &self.account {
+            Some(account) => Holder::Account(account.clone()),
+            None => Holder::Guest(self.username.clone()),
+        }
+    }
+}
+
+/// Per-connection preferences.
+///
+/// The reader loop changes them and the writer task reads them, so
+/// they live behind an Arc and use atomics — no lock on the hot path
+/// of delivering every line.
+#[derive(Default)]
+struct Settings {
+    quiet: AtomicBool,
+    color: AtomicBool,
+    /// Set by `PROTO:json`.
+    json: AtomicBool,
+    mentions: AtomicBool,
+}
+
+impl Settings {
+    fn set(&self, setting: Setting, on: bool) {
+        match setting {
+            Setting::Quiet => self.quiet.store(on, Ordering::Relaxed),
+            Setting::Color => self.color.store(on, Ordering::Relaxed),
+            Setting::Mentions => self.mentions.store(on, Ordering::Relaxed),
+        }
+    }
+}
+
+/// One connected user, as the admin console lists them.
+pub struct UserInfo {
+    pub user_id: UserId,
+    pub username: String,
+    pub peer: SocketAddr,
+    pub role: Role,
+    pub rooms: Vec<String>,
+}
+
+/// An active mute: when it lifts and who to tell when it does.
+struct Mute {
+    until: Instant,
+    by: UserId,
 }
 
 pub struct Server {
-    rooms: Vec<Room>,
-    clients: Vec<Option<ClientHandle>>,
+    rooms: Slab<RoomId, Room>,
+    clients: Slab<UserId, ClientHandle>,
+    /// Where everyone lands on connecting. The first room made.
+    lobby: RoomId,
     filters: Vec<Box<dyn AsyncFilter>>,
+    /// The duplicate-message filter, if `config.dedup` is on. Kept
+    /// apart from `filters` so a config reload can swap it; it runs
+    /// first.
+    dedup: Option<DedupFilter>,
+    commands: CommandRegistry,
+    handshake_hooks: Vec<Arc<dyn HandshakeHook>>,
+    join_hooks: Vec<JoinHook>,
+    message_hooks: Vec<MessageHook>,
+    disconnect_hooks: Vec<DisconnectHook>,
+    /// LAN mode's sockets, once `open_multicast` has run.
+    multicast: Option<Multicast>,
+    /// Bots added before the server runs, waiting for `run` to start
+    /// the tasks that carry out what they ask.
+    bots: Vec<(UserId, mpsc::UnboundedReceiver<BotAction>)>,
     pub config: ServerConfig,
-    next_user_id: u64,
+    pub scheduler: Scheduler,
+    catalog: Catalog,
+    bus: EventBus,
+    /// Set by `/drain`. Shared with the listeners, which check it on
+    /// every accept without taking the server lock.
+    draining: Arc<AtomicBool>,
+    /// Signalled once draining and the last user has gone.
+    drained: Arc<Notify>,
+    /// When the server started, for `{uptime}` in the MOTD.
+    started: Instant,
+    sessions: SessionLog,
+    /// Dropped sessions a client may come back to.
+    resumes: Resumes,
+    bans: BanList,
+    trust: TrustLedger,
+    /// Per-user buckets. Two of them, so a burst of `/list` can't be
+    /// paid for with message allowance, or the other way round.
+    message_limits: RateLimiter<UserId>,
+    command_limits: RateLimiter<UserId>,
+    /// Rate-limited messages in a row, per user, for `flood_mute`.
+    flood_strikes: HashMap<UserId, u32>,
+    /// Each user's reminders still to come, and when. A reminder goes
+    /// with whoever set it: these are cancelled as they leave.
+    reminders: HashMap<UserId, Vec<(TaskId, Instant)>>,
+    /// What the anti-spam filter caught, for the server to punish, if
+    /// `config.antispam` is on.
+    spam_strikes: Option<mpsc::UnboundedReceiver<Strike>>,
+    /// Server-wide counts for the daily summary.
+    daily: DailyCounters,
+    /// Totals since startup, for the metrics endpoint.
+    counters: Arc<Counters>,
+    /// Where each user left off in each room, kept across reconnects.
+    read_markers: ReadMarkers,
+    invites: Invites,
+    /// Files shared with FILE_START, until they expire.
+    files: SharedFiles,
+    accounts: Accounts,
+    /// For AUTH frames, issued from the admin console.
+    tokens: Tokens,
+    /// Keeps accounts, bans and history across restarts.
+    storage: Box<dyn Storage>,
+    /// History loaded at startup for rooms that don't exist yet. Each
+    /// room takes its own when it's created.
+    saved_history: HashMap<String, Vec<Entry>>,
+    /// Rooms put away by `config.room_expiry`, by name, until someone
+    /// joins one again. Their history waits in `saved_history`.
+    archived: HashMap<String, RoomRecord>,
 }
 
 impl Server {
-    pub fn new(config: ServerConfig) -> Self {
+    /// A server as `config` describes it, with the rooms saved last
+    /// time back in place. Fails only if the rooms file can't be read.
+    pub fn new(config: ServerConfig) -> Result<Self, ChatError> {
+        let catalog = Catalog::new(config.locale.clone(), config.templates.clone());
+        let trust = TrustLedger::new(config.trust.clone());
+        let message_limits = RateLimiter::new(config.message_rate);
+        let command_limits = RateLimiter::new(config.command_rate);
+        let mut rooms = Slab::with_capacity(config.max_rooms);
+        let lobby = rooms.insert(Room::new("lobby".to_string(), config.history_size));
         let mut server = Self {
-            rooms: Vec::new(),
-            clients: Vec::new(),
+            rooms,
+            clients: Slab::with_capacity(config.max_users),
+            lobby,
             filters: Vec::new(),
+            dedup: config.dedup.map(DedupFilter::new),
+            commands: CommandRegistry::new(),
+            handshake_hooks: Vec::new(),
+            join_hooks: Vec::new(),
+            message_hooks: Vec::new(),
+            disconnect_hooks: Vec::new(),
+            multicast: None,
+            bots: Vec::new(),
             config,
-            next_user_id: 0,
+            scheduler: Scheduler::new(),
+            catalog,
+            bus: EventBus::new(256),
+            draining: Arc::new(AtomicBool::new(false)),
+            started: Instant::now(),
+            drained: Arc::new(Notify::new()),
+            sessions: SessionLog::new(),
+            resumes: Resumes::new(),
+            bans: BanList::new(),
+            trust,
+            message_limits,
+            command_limits,
+            flood_strikes: HashMap::new(),
+            reminders: HashMap::new(),
+            spam_strikes: None,
+            daily: DailyCounters::default(),
+            counters: Arc::default(),
+            read_markers: ReadMarkers::new(),
+            invites: Invites::new(),
+            files: SharedFiles::new(),
+            accounts: Accounts::new(),
+            tokens: Tokens::new(),
+            storage: Box::new(MemoryStorage),
+            saved_history: HashMap::new(),
+            archived: HashMap::new(),
         };
-        server.create_room("lobby".to_string());
-        server
+        for name in server.config.private_rooms.clone() {
+            let room_id = server.find_or_create_room(&name);
+            server.rooms[room_id].private = true;
+        }
+        server.load_rooms()?;
+        if let Some(challenge) = server.config.challenge.clone() {
+            let wrong = server.text(MsgId::ChallengeWrong, &[]);
+            let failed = server.text(MsgId::ChallengeFailed, &[]);
+            server.add_handshake_hook(Box::new(ChallengeHook::new(challenge, wrong, failed)));
+        }
+        if let Some(config) = server.config.antispam {
+            let (strikes, caught) = mpsc::unbounded_channel();
+            let mut registry = FilterRegistry::new();
+            registry.add(antispam::filter(config, strikes));
+            server.add_filter_registry(registry);
+            server.spam_strikes = Some(caught);
+        }
+        server.schedule_housekeeping();
+        Ok(server)
     }
 
-    pub fn add_filter(&mut self, filter: Box<dyn AsyncFilter>) {
-        self.filters.push(filter);
+    /// The recurring jobs the config asks for. They run once something
+    /// drives the scheduler: `run`, or `spawn_scheduler`.
+    fn schedule_housekeeping(&mut self) {
+        if !self.config.daily_summary.is_empty() {
+            self.schedule_every(DAY, |server| async move {
+                server.lock().await.daily_summary().await;
+            });
+        }
+        if self.has_quotas() {
+            self.schedule_every(DAY, |server| async move {
+                server.lock().await.reset_quotas();
+            });
+        }
+        if let Some(expiry) = self.config.room_expiry {
+            // Often enough that a room outlasts its time by a quarter at
+            // most, and never so often that the sweep is busy work.
+            let every = (expiry.after / 4).clamp(Duration::from_secs(1), Duration::from_secs(60));
+            self.schedule_every(every, |server| async move {
+                server.lock().await.expire_rooms().await;
+            });
+        }
     }
 
-    pub fn bind_addr(&self) -> String {
-        format!("{}:{}", self.config.addr, self.config.port)
+    /// Open the configured storage backend and load what it kept:
+    /// accounts, bans, read markers and each room's history.
+    pub fn open_storage(&mut self) -> Result<(), ChatError> {
+        self.storage = storage::open(&self.config)?;
+        self.load_accounts_and_bans()?;
+        self.read_markers = ReadMarkers::from_list(self.storage.load_read_markers()?);
+        self.saved_history = self.storage.load_history(self.config.history_size)?;
+        // The lobby and the configured rooms exist already.
+        let names: Vec<String> = self.rooms.iter().map(|(_, r)| r.name.clone()).collect();
+        for name in names {
+            self.restore_history(&name);
+        }
+        Ok(())
     }
 
-    fn create_room(&mut self, name: String) -> RoomId {
-        let id = RoomId::new(self.rooms.len() as u64);
-        self.rooms.push(Room::new(id, name));
-        id
+    /// Open the sockets for the rooms `config.multicast` names. Nothing
+    /// to do if it names none.
+    pub fn open_multicast(&mut self) -> Result<(), ChatError> {
+        if !self.config.multicast.is_empty() {
+            self.multicast = Some(Multicast::open(&self.config.multicast)?);
+        }
+        Ok(())
     }
 
-    fn find_room_by_name(&self, name: &str) -> Option<RoomId> {
-        self.rooms.iter().find(|r| r.name == name).map(|r| r.id)
+    fn load_accounts_and_bans(&mut self) -> Result<(), ChatError> {
+        self.accounts = Accounts::from_list(self.storage.load_accounts()?);
+        self.bans = BanList::from_list(self.storage.load_bans()?);
+        Ok(())
     }
 
-    fn find_or_create_room(&mut self, name: &str) -> RoomId {
-        self.find_room_by_name(name)
-            .unwrap_or_else(|| self.create_room(name.to_string()))
+    fn restore_history(&mut self, name: &str) {
+        let Some(entries) = self.saved_history.remove(name) else {
+            return;
+        };
+        if let Some(room_id) = self.find_room_by_name(name) {
+            self.rooms[room_id].history.restore(entries);
+        }
     }
 
-    fn register_client(&mut self, username: String) -> (UserId, broadcast::Receiver<Event>) {
-        let id = UserId::new(self.next_user_id);
-        self.next_user_id += 1;
+    /// Add a message to a room's history, and hand it to storage.
+    /// Returns its sequence number.
+    async fn record(&mut self, room_id: RoomId, from: &str, body: &str) -> u64 {
+        let seq = self.rooms[room_id].history.push(from, body);
+        self.store(room_id, seq, from, body).await;
+        seq
+    }
 
-        let (tx, rx) = broadcast::channel::<Event>(64);
-        let handle = ClientHandle { username, tx };
+    /// Hand storage a message already in a room's history as `seq`.
+    /// It has been delivered by now, so a failure is only logged.
+    async fn store(&mut self, room_id: RoomId, seq: u64, from: &str, body: &str) {
+        let entry = Entry {
+            seq,
+            at: SystemTime::now(),
+            from: from.to_string(),
+            body: body.to_string(),
+        };
+        self.store_entry(room_id, &entry).await;
+    }
 
-        if id.index() < self.clients.len() {
-            self.clients[id.index()] = Some(handle);
-        } else {
-            self.clients.push(Some(handle));
+    /// Like `store`, for an entry made already: an edited or deleted
+    /// message keeps its number and the time it was first said.
+    async fn store_entry(&mut self, room_id: RoomId, entry: &Entry) {
+        if self.config.history_size == 0 {
+            return;
+        }
+        let Some(room) = self.rooms.get(room_id) else {
+            return;
+        };
+        if let Err(e) = self.storage.append_message(&room.name, entry).await {
+            warn!(room = %room.name, error = %e, "couldn't store a message");
         }
-
-        (id, rx)
     }
 
-    fn unregister_client(&mut self, user_id: UserId) {
-        if let Some(slot) = self.clients.get_mut(user_id.index()) {
-            *slot = None;
+    /// Store a new account, then add it. Stored first, so an account
+    /// that's been granted is never lost to a restart.
+    async fn register_account(
+        &mut self,
+        name: &str,
+        credentials: Credentials,
+    ) -> Result<(), ChatError> {
+        if self.accounts.is_registered(name) {
+            return Err(ChatError::NameRegistered(name.to_string()));
         }
+        self.storage.add_account(name, &credentials).await?;
+        self.accounts.register(name, credentials);
+        Ok(())
     }
 
-    async fn join_room(&mut self, user_id: UserId, room_id: RoomId) {
-        let Some(room) = self.rooms.get(room_id.index()) else {
-            return;
+    /// Bring back the rooms saved last time, if a rooms file is
+    /// configured. One that won't parse is moved aside to
+    /// `<file>.corrupt` and the server starts without it.
+    fn load_rooms(&mut self) -> Result<(), ChatError> {
+        let Some(path) = self.config.rooms_file.clone() else {
+            return Ok(());
+        };
+        // With archiving on, a saved room stays put away until someone
+        // joins it, just as it would have without the restart.
+        let archiving = self
+            .config
+            .room_expiry
+            .is_some_and(|expiry| expiry.action == ExpiryAction::Archive);
+        let records = match persistence::load_rooms(&path) {
+            Ok(records) => records,
+            // Kept aside rather than saved over, for someone to mend.
+            Err(e @ ChatError::Config(_)) => {
+                let mut aside = path.clone().into_os_string();
+                aside.push(".corrupt");
+                warn!(error = %e, "rooms file unreadable, starting without saved rooms");
+                std::fs::rename(&path, &aside)?;
+                Vec::new()
+            }
+            Err(e) => return Err(e),
         };
+        for record in records {
+            match self.find_room_by_name(&record.name) {
+                Some(room_id) => record.apply(&mut self.rooms[room_id]),
+                None if archiving => {
+                    self.archived.insert(record.name.clone(), record);
+                }
+                None => {
+                    let room_id = self.create_room(record.name.clone());
+                    record.apply(&mut self.rooms[room_id]);
+                }
+            }
+        }
+        Ok(())
+    }
 
-        room.add_member(user_id).await;
+    /// Bring back the tokens issued last time, if a tokens file is
+    /// configured.
+    pub fn load_tokens(&mut self) -> Result<(), ChatError> {
+        if let Some(path) = &self.config.tokens_file {
+            self.tokens = Tokens::load(path)?;
+        }
+        Ok(())
+    }
 
-        let username = self.client_name(user_id);
-        let room_name = room.name.clone();
-        let members = room.member_ids().await;
+    /// A token that signs in as `username` with `role`, for the admin
+    /// console to hand over. Saved before it's handed over, so a token
+    /// that's been given out works after a restart.
+    pub async fn issue_token(&mut self, username: &str, role: Role) -> Result<String, ChatError> {
+        self.config.names.check(username)?;
+        let token = self.tokens.issue(username, role)?;
+        if let Err(e) = self.save_tokens().await {
+            self.tokens.revoke(username);
+            return Err(e);
+        }
+        info!(user = %username, %role, "token issued");
+        Ok(token)
+    }
 
-        let event = Event::System(format!("* {username} joined #{room_name}"));
-        self.send_to_members(&members, user_id, &event);
+    /// Take back every token for `username`. Returns how many.
+    pub async fn revoke_tokens(&mut self, username: &str) -> Result<usize, ChatError> {
+        let revoked = self.tokens.revoke(username);
+        if revoked > 0 {
+            self.save_tokens().await?;
+            info!(user = %username, revoked, "tokens revoked");
+        }
+        Ok(revoked)
     }
 
-    async fn leave_room(&mut self, user_id: UserId, room_id: RoomId) {
-        let Some(room) = self.rooms.get(room_id.index()) else {
-            return;
-        };
+    /// Every token's user and role, for the console.
+    pub fn token_grants(&self) -> Vec<Grant> {
+        self.tokens.list().into_iter().cloned().collect()
+    }
 
-        let username = self.client_name(user_id);
-        let room_name = room.name.clone();
-        let members = room.member_ids().await;
+    async fn save_tokens(&mut self) -> Result<(), ChatError> {
+        match &self.config.tokens_file {
+            Some(path) => self.tokens.save(path).await,
+            None => Ok(()),
+        }
+    }
 
-        let event = Event::System(format!("* {username} left #{room_name}"));
-        self.send_to_members(&members, user_id, &event);
+    /// Rewrite the rooms file after a room changed. The change stands
+    /// either way; `by` hears if it won't survive a restart.
+    async fn save_rooms(&mut self, by: UserId) {
+        if let Err(e) = self.write_rooms().await {
+            self.report(by, &e);
+        }
+    }
 
-        room.remove_member(user_id).await;
+    /// Every room worth keeping, archived ones too, to the rooms file,
+    /// if one is configured.
+    async fn write_rooms(&mut self) -> Result<(), ChatError> {
+        let Some(path) = &self.config.rooms_file else {
+            return Ok(());
+        };
+        // A DM room comes back just as it was on the next /msg.
+        let mut rooms: Vec<RoomRecord> = self
+            .rooms
+            .iter()
+            .filter(|(_, room)| !room.hidden)
+            .map(|(_, room)| RoomRecord::of(room))
+            .chain(self.archived.values().cloned())
+            .collect();
+        rooms.sort_by(|a, b| a.name.cmp(&b.name));
+        persistence::save_rooms(path, &rooms).await
     }
 
-    async fn broadcast_message(
+    pub fn add_filter(&mut self, filter: Box<dyn AsyncFilter>) {
+        self.filters.push(filter);
+    }
+
+    /// Add a registry of plain closures as one filter. Its closures run
+    /// in the order they were added, at this point in the chain.
+    pub fn add_filter_registry(&mut self, registry: FilterRegistry) {
+        self.add_filter(Box::new(std::sync::Mutex::new(registry)));
+    }
+
+    /// Add a filter to one room, run after the server-wide ones. The
+    /// name is for taking it off again with `remove_room_filter`.
+    pub fn add_room_filter(
         &mut self,
-        room_id: RoomId,
-        sender_id: UserId,
-        username: &str,
-        body: &str,
-    ) {
-        // Run async filters.
-        let mut final_body = body.to_string();
-        for filter in &self.filters {
-            match filter.apply(username, &final_body).await {
-                FilterAction::Allow => {}
-                FilterAction::Modify(new) => final_body = new,
-                FilterAction::Block(reason) => {
-                    if let Some(Some(client)) = self.clients.get(sender_id.index()) {
-                        let _ = client
-                            .tx
-                            .send(Event::System(format!("* Message blocked: {reason}")));
-                    }
+        room: &str,
+        name: &str,
+        filter: Box<dyn AsyncFilter>,
+    ) -> Result<bool, ChatError> {
+        let room_id = self
+            .find_room_by_name(room)
+            .ok_or_else(|| ChatError::UnknownRoom(room.to_string()))?;
+        Ok(self.rooms[room_id].add_filter(name, filter))
+    }
+
+    /// Whether the room had a filter by that name.
+    pub fn remove_room_filter(&mut self, room: &str, name: &str) -> Result<bool, ChatError> {
+        let room_id = self
+            .find_room_by_name(room)
+            .ok_or_else(|| ChatError::UnknownRoom(room.to_string()))?;
+        Ok(self.rooms[room_id].remove_filter(name))
+    }
+
+    pub fn room_filters(&self, room: &str) -> Result<Vec<String>, ChatError> {
+        let room_id = self
+            .find_room_by_name(room)
+            .ok_or_else(|| ChatError::UnknownRoom(room.to_string()))?;
+        Ok(self.rooms[room_id].filter_names())
+    }
+
+    /// Add a hook to the connect sequence. Hooks run in the order added.
+    pub fn add_handshake_hook(&mut self, hook: Box<dyn HandshakeHook>) {
+        self.handshake_hooks.push(Arc::from(hook));
+    }
+
+    /// Call `callback` every time a user joins a room.
+    pub fn on_join<F>(&mut self, callback: F)
+    where
+        F: Fn(&mut Server, &JoinInfo) + Send + Sync + 'static,
+    {
+        self.join_hooks.push(Arc::new(callback));
+    }
+
+    /// Call `callback` every time a message is delivered to a room.
+    pub fn on_message<F>(&mut self, callback: F)
+    where
+        F: Fn(&mut Server, &MessageInfo) + Send + Sync + 'static,
+    {
+        self.message_hooks.push(Arc::new(callback));
+    }
+
+    /// Call `callback` when a session ends, with how long it lasted and why.
+    pub fn on_disconnect<F>(&mut self, callback: F)
+    where
+        F: Fn(&mut Server, &DisconnectInfo) + Send + Sync + 'static,
+    {
+        self.disconnect_hooks.push(Arc::new(callback));
+    }
+
+    /// Add a plugin of your own, alongside any named in the config. Like
+    /// `add_event_registry`, this needs the tokio runtime going.
+    pub fn add_plugin(&mut self, plugin: Box<dyn Plugin>) -> Result<(), ChatError> {
+        plugin::install(self, plugin)
+    }
+
+    /// Start calling `registry`'s callbacks as things happen. They run
+    /// on a task of their own, so this needs the tokio runtime going.
+    pub fn add_event_registry(&mut self, registry: EventRegistry) {
+        tokio::spawn(events::run(registry, self.subscribe()));
+    }
+
+    /// Sign in a bot called `name` and put it in the lobby.
+    ///
+    /// The name is held to the same rules as anyone's, and a taken one
+    /// gets a number on the end the same way. Registered names are
+    /// refused: nobody signs in to them.
+    pub async fn add_bot(&mut self, name: &str) -> Result<Bot, ChatError> {
+        self.config.names.check(name)?;
+        if self.accounts.is_registered(name) {
+            return Err(ChatError::NickInUse(name.to_string()));
+        }
+        let name = self.claim_name(name.to_string())?;
+        // No address to speak of. Unspecified can't match a ban.
+        let peer = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0));
+        let (user_id, events) = self.register_client(name.clone(), peer, None);
+        self.publish(ServerEvent::UserConnected {
+            user_id,
+            username: name.clone(),
+            peer,
+        });
+        let lobby = self.lobby;
+        self.join_room(user_id, lobby).await;
+        let (actions, queue) = mpsc::unbounded_channel();
+        self.bots.push((user_id, queue));
+        Ok(Bot::new(user_id, name, events, actions))
+    }
+
+    /// The action queues of bots added so far, for `run` to drive.
+    pub fn take_bots(&mut self) -> Vec<(UserId, mpsc::UnboundedReceiver<BotAction>)> {
+        std::mem::take(&mut self.bots)
+    }
+
+    /// One thing a bot asked for, checked the way it would be if the
+    /// bot were a person typing it.
+    async fn bot_action(&mut self, user_id: UserId, action: BotAction) {
+        match action {
+            BotAction::Say { room, body } => {
+                let room_id = match room {
+                    Some(name) => match self.find_room_by_name(&name) {
+                        Some(room_id) => room_id,
+                        None => {
+                            self.report(user_id, &ChatError::UnknownRoom(name));
+                            return;
+                        }
+                    },
+                    None => self.active_room(user_id),
+                };
+                if !self.joined_rooms(user_id).contains(&room_id) {
+                    let room = self.room_name(room_id);
+                    self.notify(user_id, MsgId::NotJoined, &[("room", &room)]);
                     return;
                 }
+                if !self.allow_message(user_id) {
+                    return;
+                }
+                let name = self.client_name(user_id);
+                self.broadcast_message(room_id, user_id, &name, &body).await;
             }
+            BotAction::Join { room } => self.bot_join(user_id, &room).await,
+            BotAction::Leave { room } => self.leave(user_id, Some(&room)).await,
+            BotAction::Msg { to, body } => self.direct_message(user_id, &to, &body).await,
         }
+    }
 
-        let Some(room) = self.rooms.get(room_id.index()) else {
+    /// `/join` for a bot. Password-protected rooms are out: a bot has
+    /// no way to be told the password but code, and no business
+    /// holding one.
+    async fn bot_join(&mut self, user_id: UserId, room: &str) {
+        if room.is_empty() || room.contains(char::is_whitespace) || room::is_dm_name(room) {
+            self.report(user_id, &ChatError::UnknownRoom(room.to_string()));
             return;
-        };
+        }
+        let existing = self
+            .find_room_by_name(room)
+            .or_else(|| self.unarchive(room));
+        if let Some(room_id) = existing {
+            if self.joined_rooms(user_id).contains(&room_id) {
+                self.switch_room(user_id, room_id);
+                return;
+            }
+            if self.room_password(user_id, room_id).is_some() {
+                self.report(user_id, &ChatError::WrongPassword(room.to_string()));
+                return;
+            }
+        } else if !self.may_create_room(user_id) {
+            return;
+        }
+        let room_id = self.find_or_create_room(room);
+        if existing.is_none() {
+            let holder = self.holder(user_id);
+            self.rooms[room_id].set_role(&holder, RoomRole::Owner);
+            self.save_rooms(user_id).await;
+        }
+        if !self.may_enter(user_id, room_id) {
+            return;
+        }
+        self.notify(user_id, MsgId::YouJoined, &[("room", room)]);
+        self.join_room(user_id, room_id).await;
+    }
 
-        let members = room.member_ids().await;
-        let event = Event::Message {
-            from: username.to_string(),
-            body: final_body,
-        };
+    /// Render a system message in the server's locale.
+    pub fn text(&self, id: MsgId, args: &[(&str, &str)]) -> String {
+        self.catalog.render(None, id, args)
+    }
 
-        for &member_id in &members {
-            if let Some(Some(client)) = self.clients.get(member_id.index()) {
-                let _ = client.tx.send(event.clone());
+    /// Render a system message in `user_id`'s locale.
+    fn text_for(&self, user_id: UserId, id: MsgId, args: &[(&str, &str)]) -> String {
+        let locale = self.clients.get(user_id).and_then(|c| c.locale.as_deref());
+        self.catalog.render(locale, id, args)
+    }
+
+    /// Send a catalog message to one user, in their language.
+    fn notify(&self, user_id: UserId, id: MsgId, args: &[(&str, &str)]) {
+        self.send_system(user_id, self.text_for(user_id, id, args));
+    }
+
+    /// Send a catalog message to room members, each in their own language.
+    fn notify_members(
+        &self,
+        members: &[UserId],
+        exclude: UserId,
+        id: MsgId,
+        args: &[(&str, &str)],
+    ) {
+        for &member_id in members {
+            if member_id != exclude {
+                self.notify(member_id, id, args);
             }
         }
     }
 
-    fn send_to_members(&self, members: &[UserId], exclude: UserId, event: &Event) {
+    /// Like notify_members, but delivered as presence chatter that users
+    /// with `/set quiet on` never see.
+    fn announce_presence(
+        &self,
+        members: &[UserId],
+        exclude: UserId,
+        id: MsgId,
+        args: &[(&str, &str)],
+    ) {
         for &member_id in members {
-            if member_id != exclude {
-                if let Some(Some(client)) = self.clients.get(member_id.index()) {
-                    let _ = client.tx.send(event.clone());
-                }
+            if member_id == exclude {
+                continue;
+            }
+            if let Some(client) = self.clients.get(member_id) {
+                let text = self.text_for(member_id, id, args);
+                let _ = client.tx.send(Event::Presence(text));
             }
         }
     }
 
-    fn client_name(&self, user_id: UserId) -> String {
-        self.clients
-            .get(user_id.index())
-            .and_then(|c| c.as_ref())
-            .map(|c| c.username.clone())
-            .unwrap_or_else(|| "unknown".to_string())
+    /// `/lang`: switch to `code` if there's a translation for it, or
+    /// say which language is in use and which ones there are.
+    fn choose_language(&mut self, user_id: UserId, code: Option<String>) {
+        let available = self.catalog.locales().join(", ");
+        match code {
+            Some(code) if self.catalog.has_locale(&code) => {
+                self.set_locale(user_id, code.clone());
+                self.notify(user_id, MsgId::LanguageSet, &[("language", &code)]);
+            }
+            Some(code) => self.notify(
+                user_id,
+                MsgId::UnknownLanguage,
+                &[("language", &code), ("available", &available)],
+            ),
+            None => {
+                let current = self.clients.get(user_id).and_then(|c| c.locale.clone());
+                let current = current.unwrap_or_else(|| self.config.locale.clone());
+                self.notify(
+                    user_id,
+                    MsgId::Language,
+                    &[("language", &current), ("available", &available)],
+                );
+            }
+        }
     }
 
-    fn set_client_name(&mut self, user_id: UserId, name: String) {
-        if let Some(Some(client)) = self.clients.get_mut(user_id.index()) {
-            client.username = name;
+    /// Choose the language for one user's system messages.
+    pub fn set_locale(&mut self, user_id: UserId, locale: impl Into<String>) {
+        if let Some(client) = self.clients.get_mut(user_id) {
+            client.locale = Some(locale.into());
         }
     }
-}
 
-/// Handle a single client as a tokio task.
-pub async fn handle_client(
-    server: Arc<Mutex<Server>>,
-    stream: TcpStream,
-) -> Result<(), ChatError> {
-    let peer = stream.peer_addr()?;
-    let (reader, mut writer) = stream.into_split();
-    let mut reader = BufReader::new(reader);
+    /// Send a system line to one user. Handy from hooks and plugins.
+    pub fn send_system(&self, user_id: UserId, text: impl Into<String>) {
+        if let Some(client) = self.clients.get(user_id) {
+            let _ = client.tx.send(Event::System(text.into()));
+        }
+    }
 
-    writer
-        .write_all(b"Enter your username:\n")
-        .await?;
+    /// Send a protocol frame, for client programs rather than people.
+    fn send_frame(&self, user_id: UserId, frame: String) {
+        if let Some(client) = self.clients.get(user_id) {
+            let _ = client.tx.send(Event::Frames(frame));
+        }
+    }
 
-    let mut username = String::new();
-    reader.read_line(&mut username).await?;
-    let username = username.trim().to_string();
-    if username.is_empty() {
-        return Ok(());
+    /// Have the user's writer resend what it numbered after `acked`.
+    fn resend(&self, user_id: UserId, acked: u64) {
+        if let Some(client) = self.clients.get(user_id) {
+            let _ = client.tx.send(Event::Resend(acked));
+        }
     }
 
-    // Register and join lobby.
-    let (user_id, mut rx, motd) = {
-        let mut srv = server.lock().await;
-        let (uid, rx) = srv.register_client(username.clone());
-        let motd = srv.config.motd.clone();
-        srv.join_room(uid, RoomId::new(0)).await;
-        (uid, rx, motd)
-    };
+    /// Send a system line to everyone in a room.
+    pub async fn send_room_system(&mut self, room_id: RoomId, text: impl Into<String>) {
+        let Some(room) = self.rooms.get(room_id) else {
+            return;
+        };
+        let text = text.into();
+        for member_id in room.member_ids().await {
+            self.send_system(member_id, text.clone());
+        }
+    }
 
-    println!("[{user_id}] {username} connected from {peer}");
+    pub fn register_command(&mut self, handler: Box<dyn CommandHandler>) -> Result<(), ChatError> {
+        self.commands.register(handler)
+    }
 
-    if let Some(motd) = motd {
-        writer.write_all(format!("{motd}\n").as_bytes()).await?;
+    pub fn unregister_command(&mut self, name: &str) {
+        self.commands.unregister(name);
     }
-    writer
-        .write_all(format!("Welcome, {username}! You're in #lobby.\nType a message or /help for commands.\n").as_bytes())
-        .await?;
 
-    // Spawn a writer task — reads from the broadcast receiver.
-    let mut write_clone = writer;
-    let writer_task = tokio::spawn(async move {
-        while let Ok(event) = rx.recv().await {
-            let line = match event {
-                Event::Message { from, body } => format!("<{from}> {body}\n"),
-                Event::System(text) => format!("{text}\n"),
-            };
-            if write_clone.write_all(line.as_bytes()).await.is_err() {
-                break;
-            }
-        }
-    });
+    /// Run `task` once, `after` from now. The task receives the shared
+    /// server handle, so it can lock it and act like any other client.
+    /// None if `after` is too far off to count, and the task is dropped.
+    pub fn schedule<F, Fut>(&mut self, after: Duration, task: F) -> Option<TaskId>
+    where
+        F: FnOnce(Arc<Mutex<Server>>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.scheduler.once(after, task)
+    }
 
-    // Reader loop.
-    let mut current_room = RoomId::new(0);
-    let mut current_name = username;
-    let mut line = String::new();
+    /// Run `task` repeatedly, every `period`. None as for `schedule`.
+    pub fn schedule_every<F, Fut>(&mut self, period: Duration, task: F) -> Option<TaskId>
+    where
+        F: FnMut(Arc<Mutex<Server>>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.scheduler.every(period, task)
+    }
 
-    loop {
-        line.clear();
-        let bytes = reader.read_line(&mut line).await?;
-        if bytes == 0 {
-            break; // client disconnected
-        }
+    pub fn cancel_task(&mut self, id: TaskId) {
+        self.scheduler.cancel(id);
+    }
 
-        let trimmed = line.trim();
-        if trimmed.is_empty() {
-            continue;
-        }
+    /// Subscribe to everything that happens on the server from here on.
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<ServerEvent> {
+        self.bus.subscribe()
+    }
 
-        if trimmed.starts_with('/') {
-            match Command::parse(trimmed) {
-                Ok(cmd) => {
-                    let mut srv = server.lock().await;
-                    match cmd.execute(current_room) {
-                        CommandResult::JoinRoom { room } => {
-                            let room_id = srv.find_or_create_room(&room);
-                            srv.leave_room(user_id, current_room).await;
-                            srv.join_room(user_id, room_id).await;
-                            current_room = room_id;
-                            // Send via channel (writer task handles output).
-                            if let Some(Some(client)) = srv.clients.get(user_id.index()) {
-                                let _ = client.tx.send(Event::System(
-                                    format!("* You joined #{room}"),
-                                ));
-                            }
-                        }
-                        CommandResult::ChangeNick { new_name } => {
-                            let old = current_name.clone();
-                            current_name = new_name.clone();
-                            srv.set_client_name(user_id, new_name.clone());
-                            if let Some(Some(client)) = srv.clients.get(user_id.index()) {
-                                let _ = client.tx.send(Event::System(
-                                    format!("* You are now {new_name} (was {old})"),
-                                ));
-                            }
-                        }
-                        CommandResult::KickUser { .. } => {
-                            if let Some(Some(client)) = srv.clients.get(user_id.index()) {
-                                let _ = client.tx.send(Event::System(
-                                    "* /kick not yet implemented in async mode".to_string(),
-                                ));
-                            }
-                        }
-                        CommandResult::Quit => {
-                            if let Some(Some(client)) = srv.clients.get(user_id.index()) {
-                                let _ = client.tx.send(Event::System("* Goodbye!".to_string()));
-                            }
-                            break;
-                        }
-                        CommandResult::Reply(text) => {
-                            if let Some(Some(client)) = srv.clients.get(user_id.index()) {
-                                let _ = client.tx.send(Event::System(text));
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    let srv = server.lock().await;
-                    if let Some(Some(client)) = srv.clients.get(user_id.index()) {
-                        let _ = client.tx.send(Event::System(format!("ERROR: {e}")));
-                    }
-                }
-            }
-            continue;
+    fn publish(&self, event: ServerEvent) {
+        self.bus.publish(event);
+    }
+
+    /// A room made for the first time, or brought back from the archive
+    /// as it was. A slot freed by an expired room is reused under a new
+    /// generation, so an id kept for the old room finds nothing rather
+    /// than this one.
+    fn create_room(&mut self, name: String) -> RoomId {
+        let mut room = Room::new(name.clone(), self.config.history_size);
+        if let Some(record) = self.archived.remove(&name) {
+            record.apply(&mut room);
         }
+        let id = self.rooms.insert(room);
+        self.restore_history(&name);
+        self.publish(ServerEvent::RoomCreated { room_id: id, name });
+        id
+    }
 
-        // Plain text — broadcast.
-        let mut srv = server.lock().await;
-        srv.broadcast_message(current_room, user_id, &current_name, trimmed)
-            .await;
+    /// The room a feed posts into, created at startup if need be.
+    pub fn feed_room(&mut self, name: &str) -> RoomId {
+        self.find_or_create_room(name)
     }
 
-    // Cleanup.
-    println!("[{user_id}] {current_name} disconnected");
-    {
-        let mut srv = server.lock().await;
-        srv.leave_room(user_id, current_room).await;
-        srv.unregister_client(user_id);
+    /// Post one line from a feed. There's no client behind it, so the
+    /// user-facing checks (mutes, trust, filters) don't apply — the
+    /// operator chose the source.
+    pub async fn post_feed(&mut self, room_id: RoomId, bot: &str, body: &str) {
+        let Some(room) = self.rooms.get(room_id) else {
+            return;
+        };
+        let room_name = room.name.clone();
+        let seq = self.rooms[room_id].history.push(bot, body);
+        let event = Event::Message {
+            room: room_name.as_str().into(),
+            id: seq,
+            from: bot.into(),
+            body: body.into(),
+            at: SystemTime::now(),
+        };
+        self.deliver(room_id, bot, &event, seq).await;
+        self.store(room_id, seq, bot, body).await;
+        self.publish(ServerEvent::MessageBroadcast {
+            room_id,
+            room: room_name,
+            from: bot.to_string(),
+            body: body.to_string(),
+        });
     }
 
-    writer_task.abort();
+    fn find_room_by_name(&self, name: &str) -> Option<RoomId> {
+        self.rooms
+            .iter()
+            .find(|(_, r)| r.name == name)
+            .map(|(id, _)| id)
+    }
 
-    Ok(())
+    /// Bring `name` back if it was archived, so a `/join` finds it as
+    /// it was rather than making a new room with a new owner.
+    fn unarchive(&mut self, name: &str) -> Option<RoomId> {
+        if !self.archived.contains_key(name) {
+            return None;
+        }
+        let room_id = self.create_room(name.to_string());
+        info!(room = %name, "archived room restored");
+        Some(room_id)
+    }
+
+    /// Rooms whose removal would pull the rug from under something: the
+    /// lobby, rooms the config names, and DM rooms, which have no
+    /// members to ever make them look occupied.
+    fn expires(&self, room: &Room) -> bool {
+        let config = &self.config;
+        room.name != "lobby"
+            && !room.hidden
+            && !config.private_rooms.contains(&room.name)
+            && !config.multicast.contains_key(&room.name)
+            && !config.feeds.iter().any(|feed| feed.room == room.name)
+    }
+
+    /// Look for rooms that have been empty for `config.room_expiry`,
+    /// and delete or archive them. Run every so often by `run`.
+    ///
+    /// A room's emptiness is timed from the first sweep that finds it
+    /// so, not from when its last member left: close enough, and it
+    /// saves every departure from checking.
+    pub async fn expire_rooms(&mut self) {
+        let Some(expiry) = self.config.room_expiry else {
+            return;
+        };
+        let now = Instant::now();
+        let ids: Vec<RoomId> = self
+            .rooms
+            .iter()
+            .filter(|(_, room)| self.expires(room))
+            .map(|(id, _)| id)
+            .collect();
+        let mut expired = Vec::new();
+        for room_id in ids {
+            let empty = self.rooms[room_id].members.lock().await.is_empty();
+            let room = &mut self.rooms[room_id];
+            if !empty {
+                room.empty_since = None;
+            } else if now - *room.empty_since.get_or_insert(now) >= expiry.after {
+                expired.push(room_id);
+            }
+        }
+        if expired.is_empty() {
+            return;
+        }
+        for room_id in expired {
+            self.remove_room(room_id, expiry.action).await;
+        }
+        if let Err(e) = self.write_rooms().await {
+            warn!(error = %e, "couldn't save rooms after expiring some");
+        }
+    }
+
+    /// Take an empty room out of the server. The caller saves the rooms
+    /// file afterwards.
+    async fn remove_room(&mut self, room_id: RoomId, action: ExpiryAction) {
+        let Some(mut room) = self.rooms.remove(room_id) else {
+            return;
+        };
+        if let Some(expiry) = room.poll.take().and_then(|poll| poll.expiry) {
+            self.cancel_task(expiry);
+        }
+        let name = room.name.clone();
+        match action {
+            ExpiryAction::Archive => {
+                self.archived.insert(name.clone(), RoomRecord::of(&room));
+                self.saved_history
+                    .insert(name.clone(), room.history.into_entries());
+            }
+            ExpiryAction::Delete => {
+                self.saved_history.remove(&name);
+                self.read_markers.forget_room(&name);
+                if let Err(e) = self.storage.delete_history(&name).await {
+                    warn!(room = %name, error = %e, "couldn't delete a room's history");
+                }
+            }
+        }
+        let archived = action == ExpiryAction::Archive;
+        info!(room = %name, archived, "empty room expired");
+        self.publish(ServerEvent::RoomRemoved {
+            room_id,
+            name,
+            archived,
+        });
+    }
+
+    fn find_or_create_room(&mut self, name: &str) -> RoomId {
+        self.find_room_by_name(name)
+            .unwrap_or_else(|| self.create_room(name.to_string()))
+    }
+
+    fn register_client(
+        &mut self,
+        username: String,
+        peer: SocketAddr,
+        account: Option<String>,
+    ) -> (UserId, broadcast::Receiver<Event>) {
+        if self.trust.seen(&username) {
+            self.daily.new_users += 1;
+        }
+
+        let (tx, rx) = broadcast::channel::<Event>(self.config.outbox_size);
+        let handle = ClientHandle {
+            username,
+            peer,
+            tx,
+            mute: None,
+            locale: None,
+            role: Role::User,
+            account,
+            by_token: false,
+            shadow_muted: false,
+            rooms: Vec::new(),
+            active: self.lobby,
+            ignored: HashSet::new(),
+            away: None,
+            resuming: None,
+        };
+
+        let id = self.clients.insert(handle);
+        self.sessions
+            .start(id, peer.ip(), &self.clients[id].username);
+        let online = self.clients.len();
+        self.daily.record_online(online);
+        self.counters.connections.fetch_add(1, Ordering::Relaxed);
+
+        (id, rx)
+    }
+
+    /// Everything a session's end sets off: out of every room, off the
+    /// server, and everyone who watches for it told.
+    async fn disconnect(
+        &mut self,
+        user_id: UserId,
+        username: String,
+        session: Duration,
+        reason: DisconnectReason,
+    ) {
+        let left = if matches!(
+            reason,
+            DisconnectReason::Idle | DisconnectReason::Unresponsive
+        ) {
+            MsgId::TimedOut
+        } else {
+            MsgId::Left
+        };
+        self.park(user_id, &reason);
+        // Saved before anyone hears they've gone.
+        self.put_away_read_markers(user_id).await;
+        for room_id in self.joined_rooms(user_id) {
+            self.depart(user_id, room_id, left).await;
+        }
+        self.forget_guest_roles(user_id);
+        self.unregister_client(user_id);
+
+        let info = DisconnectInfo {
+            user_id,
+            username,
+            session,
+            reason,
+        };
+        for hook in self.disconnect_hooks.clone() {
+            hook(self, &info);
+        }
+        self.publish(ServerEvent::UserDisconnected {
+            user_id,
+            username: info.username,
+            session: info.session,
+            reason: info.reason,
+        });
+        self.finish_drain_if_empty();
+    }
+
+    /// An account's read markers go to storage as they leave, to be
+    /// there after a restart; a guest's are forgotten with them.
+    async fn put_away_read_markers(&mut self, user_id: UserId) {
+        let Some(client) = self.clients.get(user_id) else {
+            return;
+        };
+        let Some(account) = &client.account else {
+            self.read_markers.take(&client.username);
+            return;
+        };
+        let Some(rooms) = self.read_markers.rooms(account) else {
+            return;
+        };
+        if let Err(e) = self.storage.save_read_markers(account, rooms).await {
+            warn!(error = %e, %account, "read markers not saved");
+        }
+    }
+
+    /// A guest's room roles go with them: whoever takes the name next
+    /// starts out a member like anyone else.
+    fn forget_guest_roles(&mut self, user_id: UserId) {
+        let Some(client) = self.clients.get(user_id) else {
+            return;
+        };
+        if client.account.is_none() {
+            for (_, room) in self.rooms.iter_mut() {
+                room.forget_guest(&client.username);
+            }
+        }
+    }
+
+    /// Keep a dropped session for its client to resume, if it asked to
+    /// be able to. Leaving on purpose, or being put out, isn't a drop.
+    fn park(&mut self, user_id: UserId, reason: &DisconnectReason) {
+        let token = self.resumes.close(user_id);
+        let dropped = !matches!(
+            reason,
+            DisconnectReason::Quit
+                | DisconnectReason::Kicked
+                | DisconnectReason::Spam
+                | DisconnectReason::Shutdown
+        );
+        let (true, Some(token), Some(grace), Some(client)) = (
+            dropped,
+            token,
+            self.config.resume_grace,
+            self.clients.get(user_id),
+        ) else {
+            return;
+        };
+        let parked = Parked {
+            username: client.username.clone(),
+            account: client.account.clone(),
+            role: client.role,
+            by_token: client.by_token,
+            locale: client.locale.clone(),
+            ignored: client.ignored.clone(),
+            read: match client.account {
+                Some(_) => HashMap::new(),
+                None => self
+                    .read_markers
+                    .rooms(&client.username)
+                    .cloned()
+                    .unwrap_or_default(),
+            },
+            room_roles: match client.account {
+                Some(_) => Vec::new(),
+                None => {
+                    let holder = client.holder();
+                    self.rooms
+                        .iter()
+                        .map(|(_, room)| (room.name.clone(), room.role_of(&holder)))
+                        .filter(|&(_, role)| role != RoomRole::Member)
+                        .collect()
+                }
+            },
+            rooms: client.rooms.iter().map(|&id| self.room_name(id)).collect(),
+            active: self.room_name(client.active),
+        };
+        info!(user = %parked.username, "session parked");
+        self.resumes.park(token, parked, grace);
+    }
+
+    /// Put a resumed session back as it was: its standing, its
+    /// settings, and its rooms, each with what it missed meanwhile.
+    /// Rooms that have gone since, or that won't have it back, are
+    /// skipped; with none left, it starts in the lobby.
+    async fn resume(&mut self, user_id: UserId, parked: Parked) {
+        let limit = self.replay_limit(parked.rooms.len());
+        let Some(client) = self.clients.get_mut(user_id) else {
+            return;
+        };
+        client.role = parked.role;
+        client.by_token = parked.by_token;
+        client.locale = parked.locale;
+        client.ignored = parked.ignored;
+        client.resuming = Some(limit);
+        self.read_markers.restore(&parked.username, parked.read);
+        let holder = Holder::Guest(parked.username.clone());
+        for (name, role) in parked.room_roles {
+            if let Some(room_id) = self.find_room_by_name(&name) {
+                self.rooms[room_id].set_role(&holder, role);
+            }
+        }
+        for name in &parked.rooms {
+            if let Some(room_id) = self.find_room_by_name(name)
+                && self.may_enter(user_id, room_id)
+            {
+                self.join_room(user_id, room_id).await;
+            }
+        }
+        if self.joined_rooms(user_id).is_empty() {
+            let lobby = self.lobby;
+            self.join_room(user_id, lobby).await;
+        }
+        let active = self.find_room_by_name(&parked.active);
+        if let Some(client) = self.clients.get_mut(user_id) {
+            client.resuming = None;
+            if let Some(room_id) = active.filter(|id| client.rooms.contains(id)) {
+                client.active = room_id;
+            }
+        }
+    }
+
+    /// Handles for the listeners: the draining flag, and the signal that
+    /// the server has emptied out and can stop.
+    pub fn drain_handles(&self) -> (Arc<AtomicBool>, Arc<Notify>) {
+        (Arc::clone(&self.draining), Arc::clone(&self.drained))
+    }
+
+    /// Try to make `user_id` an operator.
+    /// `/oper <password>`: the admin password grants Admin, the oper
+    /// password Op. Returns the role granted, if either matched.
+    fn oper(&mut self, user_id: UserId, password: &str) -> Option<Role> {
+        let matches = |expected: &Option<String>| expected.as_deref() == Some(password);
+        let role = if matches(&self.config.admin_password) {
+            Role::Admin
+        } else if matches(&self.config.oper_password) {
+            Role::Op
+        } else {
+            return None;
+        };
+        if let Some(client) = self.clients.get_mut(user_id) {
+            client.role = role;
+        }
+        Some(role)
+    }
+
+    /// A user's role right now. Op and Admin stick once granted; below
+    /// that, a name counts as a guest until it reaches the basic trust
+    /// tier.
+    fn role(&self, user_id: UserId) -> Role {
+        let Some(client) = self.clients.get(user_id) else {
+            return Role::Guest;
+        };
+        if client.role >= Role::Op || client.by_token {
+            return client.role;
+        }
+        if self.trust.tier(&client.username) == Tier::New {
+            Role::Guest
+        } else {
+            Role::User
+        }
+    }
+
+    fn is_oper(&self, user_id: UserId) -> bool {
+        self.role(user_id) >= Role::Op
+    }
+
+    /// May `user_id` run `/command`? The one check every command goes
+    /// through, built-in or plugin, before it's parsed or executed.
+    fn authorize(&self, user_id: UserId, command: &str) -> bool {
+        let required = self.config.permissions.required(command);
+        let current = self.role(user_id);
+        if current >= required {
+            return true;
+        }
+        let err = ChatError::PermissionDenied {
+            command: command.to_string(),
+            required,
+            current,
+        };
+        self.report(user_id, &err);
+        false
+    }
+
+    /// `user_id`'s standing in one room. Server operators own them all.
+    fn room_role(&self, user_id: UserId, room_id: RoomId) -> RoomRole {
+        if self.is_oper(user_id) {
+            return RoomRole::Owner;
+        }
+        match (self.clients.get(user_id), self.rooms.get(room_id)) {
+            (Some(client), Some(room)) => room.role_of(&client.holder()),
+            _ => RoomRole::Member,
+        }
+    }
+
+    /// Like `authorize`, for commands that act on one room.
+    fn authorize_in_room(
+        &self,
+        user_id: UserId,
+        room_id: RoomId,
+        command: &str,
+        required: RoomRole,
+    ) -> bool {
+        let current = self.room_role(user_id, room_id);
+        if current >= required {
+            return true;
+        }
+        let err = ChatError::RoomPermissionDenied {
+            command: command.to_string(),
+            room: self.room_name(room_id),
+            required,
+            current,
+        };
+        self.report(user_id, &err);
+        false
+    }
+
+    /// Tell a client what went wrong.
+    fn report(&self, user_id: UserId, err: &ChatError) {
+        self.send_system(user_id, self.error_line(err, Some(user_id)));
+    }
+
+    /// How an error reads to a client. Every error reply goes through
+    /// here, so each one carries its code, and details that aren't safe
+    /// to show stay in the server log. `user_id` is None before the
+    /// client has signed in.
+    fn error_line(&self, err: &ChatError, user_id: Option<UserId>) -> String {
+        if !err.is_client_safe() {
+            match user_id {
+                Some(user_id) => warn!(user = %user_id, "{err}"),
+                None => warn!("{err}"),
+            }
+        }
+        let locale = user_id
+            .and_then(|user_id| self.clients.get(user_id))
+            .and_then(|c| c.locale.as_deref());
+        let (code, detail) = (err.code().to_string(), self.catalog.error_text(locale, err));
+        let args = [("code", code.as_str()), ("error", detail.as_str())];
+        self.catalog.render(locale, MsgId::Error, &args)
+    }
+
+    /// Stop taking new users. Everyone already here keeps chatting.
+    fn start_draining(&mut self) {
+        self.draining.store(true, Ordering::Relaxed);
+        info!("draining: refusing new connections");
+    }
+
+    /// Stop the server: refuse new connections, say goodbye to everyone
+    /// and hang up on them. The listeners return once the last client
+    /// task has cleaned up, or after `SHUTDOWN_GRACE` if one is stuck.
+    pub fn shut_down(&mut self) {
+        info!(users = self.clients.len(), "shutting down");
+        self.start_draining();
+        let text = self.text(MsgId::ShuttingDown, &[]);
+        for (_, client) in self.clients.iter() {
+            let _ = client.tx.send(Event::System(text.clone()));
+            let _ = client.tx.send(Event::Close(DisconnectReason::Shutdown));
+        }
+        self.finish_drain_if_empty();
+        self.schedule(SHUTDOWN_GRACE, |server| async move {
+            let srv = server.lock().await;
+            warn!(users = srv.clients.len(), "shutdown grace period over");
+            srv.drained.notify_one();
+        });
+    }
+
+    /// Tell one client why, and disconnect them.
+    fn close(&self, user_id: UserId, reason: DisconnectReason, id: MsgId, args: &[(&str, &str)]) {
+        self.notify(user_id, id, args);
+        if let Some(client) = self.clients.get(user_id) {
+            let _ = client.tx.send(Event::Close(reason));
+        }
+    }
+
+    /// Once draining, the last one out turns off the lights.
+    fn finish_drain_if_empty(&self) {
+        if self.draining.load(Ordering::Relaxed) && self.clients.is_empty() {
+            info!("drained: last user left, shutting down");
+            self.drained.notify_one();
+        }
+    }
+
+    /// Send a catalog message to every operator online.
+    fn notify_opers(&self, id: MsgId, args: &[(&str, &str)]) {
+        for (user_id, client) in self.clients.iter() {
+            if client.role >= Role::Op {
+                self.notify(user_id, id, args);
+            }
+        }
+    }
+
+    /// May `user_id` make a new room? Operators only, if the server says
+    /// so; never past `max_rooms`; and only at the trust tier that
+    /// unlocks it. Refusals are reported.
+    fn may_create_room(&self, user_id: UserId) -> bool {
+        if self.config.restrict_room_creation && !self.is_oper(user_id) {
+            let denied = ChatError::PermissionDenied {
+                command: "join".to_string(),
+                required: Role::Op,
+                current: self.role(user_id),
+            };
+            self.report(user_id, &denied);
+            return false;
+        }
+        let rooms = self
+            .rooms
+            .iter()
+            .filter(|(_, room)| !room::is_dm_name(&room.name))
+            .count();
+        if rooms >= self.config.max_rooms {
+            let max = self.config.max_rooms;
+            self.report(user_id, &ChatError::TooManyRooms { max });
+            return false;
+        }
+        self.permitted(user_id, Capability::CreateRooms)
+    }
+
+    /// May `user_id` use `capability`? If not, tell them why.
+    /// Operators are never held back.
+    fn permitted(&self, user_id: UserId, capability: Capability) -> bool {
+        let Some(required) = self.trust.required(capability) else {
+            return true;
+        };
+        if self.is_oper(user_id) {
+            return true;
+        }
+        let current = self.trust.tier(&self.client_name(user_id));
+        if current >= required {
+            return true;
+        }
+        let (required, current) = (required.to_string(), current.to_string());
+        self.notify(
+            user_id,
+            MsgId::TrustTooLow,
+            &[
+                ("action", capability.describe()),
+                ("tier", &required),
+                ("current", &current),
+            ],
+        );
+        false
+    }
+
+    /// Check a newcomer against the ban list before they're registered.
+    /// Returns the refusal to send them, or None to let them in.
+    fn screen(&self, username: &str, ip: IpAddr) -> Option<String> {
+        match self.bans.check(username, ip) {
+            BanMatch::Clear => None,
+            BanMatch::Name(ban) => {
+                Some(self.text(MsgId::BannedRefusal, &[("reason", &ban.reason)]))
+            }
+            BanMatch::Address(ban) => {
+                let names = self.sessions.names_from(ip).join(", ");
+                let ip = ip.to_string();
+                self.notify_opers(
+                    MsgId::EvasionAlert,
+                    &[
+                        ("user", username),
+                        ("ip", &ip),
+                        ("banned", &ban.username),
+                        ("names", &names),
+                    ],
+                );
+                self.config
+                    .reject_ban_evasion
+                    .then(|| self.text(MsgId::BannedRefusal, &[("reason", &ban.reason)]))
+            }
+        }
+    }
+
+    /// Ban `target` by name and address, and disconnect them.
+    async fn ban(&mut self, by: UserId, target: &str, reason: Option<String>) {
+        let Some(target_id) = self.find_client_by_name(target) else {
+            self.report(by, &ChatError::UnknownUser(target.to_string()));
+            return;
+        };
+        let Some(client) = self.clients.get(target_id) else {
+            return;
+        };
+        let ip = client.peer.ip();
+        let tx = client.tx.clone();
+        let reason = reason.unwrap_or_else(|| "no reason given".to_string());
+        let by_name = self.client_name(by);
+
+        self.bans.add(Ban {
+            username: target.to_string(),
+            ip,
+            reason: reason.clone(),
+            by: by_name.clone(),
+            at: SystemTime::now(),
+        });
+        // The ban holds either way; the operator should know it won't
+        // survive a restart.
+        if let Err(e) = self.storage.save_bans(self.bans.bans()).await {
+            self.report(by, &e);
+        }
+
+        self.notify(
+            target_id,
+            MsgId::YouAreBanned,
+            &[("by", &by_name), ("reason", &reason)],
+        );
+        let _ = tx.send(Event::Close(DisconnectReason::Kicked));
+        self.notify(
+            by,
+            MsgId::BanConfirm,
+            &[("user", target), ("reason", &reason)],
+        );
+    }
+
+    /// Whether what `user_id` says reaches only them.
+    fn shadow_muted(&self, user_id: UserId) -> bool {
+        self.clients.get(user_id).is_some_and(|c| c.shadow_muted)
+    }
+
+    /// `/shadowmute` and `/unshadowmute`. The target is never told; the
+    /// other operators are, so they don't wonder why someone's gone
+    /// quiet.
+    fn shadow_mute(&mut self, by: UserId, target: &str, on: bool) {
+        let Some(target_id) = self.find_client_by_name(target) else {
+            self.report(by, &ChatError::UnknownUser(target.to_string()));
+            return;
+        };
+        self.clients[target_id].shadow_muted = on;
+        let by_name = self.client_name(by);
+        info!(user = %target, by = %by_name, on, "shadow mute");
+        let id = if on {
+            MsgId::ShadowMuted
+        } else {
+            MsgId::ShadowUnmuted
+        };
+        let args = [("user", target), ("by", by_name.as_str())];
+        self.notify_opers(id, &args);
+        // Someone the permissions let in without being an operator.
+        if self.clients.get(by).is_some_and(|c| c.role < Role::Op) {
+            self.notify(by, id, &args);
+        }
+    }
+
+    async fn unban(&mut self, by: UserId, target: &str) {
+        if !self.bans.remove(target) {
+            self.notify(by, MsgId::NotBanned, &[("user", target)]);
+            return;
+        }
+        self.notify(by, MsgId::Unbanned, &[("user", target)]);
+        // Lifted, but only until a restart reads the old list.
+        if let Err(e) = self.storage.save_bans(self.bans.bans()).await {
+            self.report(by, &e);
+        }
+    }
+
+    fn unregister_client(&mut self, user_id: UserId) {
+        self.sessions.end(user_id);
+        self.flood_strikes.remove(&user_id);
+        for (task, _) in self.reminders.remove(&user_id).unwrap_or_default() {
+            self.scheduler.cancel(task);
+        }
+        self.clients.remove(user_id);
+    }
+
+    async fn join_room(&mut self, user_id: UserId, room_id: RoomId) {
+        let Some(room) = self.rooms.get(room_id) else {
+            return;
+        };
+
+        room.add_member(user_id).await;
+        if let Some(client) = self.clients.get_mut(user_id) {
+            if !client.rooms.contains(&room_id) {
+                client.rooms.push(room_id);
+            }
+            client.active = room_id;
+        }
+
+        let username = self.client_name(user_id);
+        let room_name = room.name.clone();
+        let topic = room.topic.clone();
+        let members = room.member_ids().await;
+
+        info!(user = %username, room = %room_name, "joined");
+        let args = [("user", username.as_str()), ("room", room_name.as_str())];
+        self.announce_presence(&members, user_id, MsgId::Joined, &args);
+        if let Some(topic) = topic {
+            self.notify(
+                user_id,
+                MsgId::Topic,
+                &[("room", &room_name), ("topic", &topic)],
+            );
+        }
+        self.replay_history(user_id, room_id);
+        self.offer_backfill(user_id, room_id);
+
+        self.publish(ServerEvent::UserJoined {
+            user_id,
+            username: username.clone(),
+            room: room_name.clone(),
+        });
+
+        // Cloning the Vec of Arcs lets each hook borrow `self` mutably.
+        let info = JoinInfo {
+            user_id,
+            username,
+            room_id,
+            room: room_name,
+        };
+        for hook in self.join_hooks.clone() {
+            hook(self, &info);
+        }
+    }
+
+    /// Send a room's message, numbered `seq` in its history, to every
+    /// member, and mark it read for each. One pass over the members
+    /// under the room's lock, without copying the list.
+    ///
+    /// Members ignoring `from` aren't sent it, but it's still marked
+    /// read for them: there's nothing there they want to catch up on.
+    async fn deliver(&mut self, room_id: RoomId, from: &str, event: &Event, seq: u64) {
+        let Some(room) = self.rooms.get(room_id) else {
+            return;
+        };
+        let (clients, read_markers) = (&self.clients, &mut self.read_markers);
+        room.for_each_member(|member_id| {
+            if let Some(client) = clients.get(member_id) {
+                if !client.ignored.contains(from) {
+                    let _ = client.tx.send(event.clone());
+                }
+                read_markers.mark(client.reader(), &room.name, seq);
+            }
+        })
+        .await;
+        if let Some(multicast) = &self.multicast {
+            multicast.send(&room.name, &render::line(event, false, false, None));
+        }
+    }
+
+    /// `/ignore`: stop sending `user_id` what `target` says, or with
+    /// no target, tell them who they're ignoring.
+    fn ignore(&mut self, user_id: UserId, target: Option<String>) {
+        let Some(target) = target else {
+            let Some(client) = self.clients.get(user_id) else {
+                return;
+            };
+            let mut names: Vec<&str> = client.ignored.iter().map(String::as_str).collect();
+            if names.is_empty() {
+                self.notify(user_id, MsgId::IgnoringNobody, &[]);
+            } else {
+                names.sort_unstable();
+                let users = names.join(", ");
+                self.notify(user_id, MsgId::IgnoreList, &[("users", &users)]);
+            }
+            return;
+        };
+        if target == self.client_name(user_id) {
+            self.report(
+                user_id,
+                &ChatError::Parse("you can't ignore yourself".into()),
+            );
+            return;
+        }
+        // Only someone here now: a typo shouldn't quietly ignore nobody.
+        if self.find_client_by_name(&target).is_none() {
+            self.report(user_id, &self.absent(&target));
+            return;
+        }
+        if let Some(client) = self.clients.get_mut(user_id) {
+            client.ignored.insert(target.clone());
+        }
+        self.notify(user_id, MsgId::Ignoring, &[("user", &target)]);
+    }
+
+    fn unignore(&mut self, user_id: UserId, target: &str) {
+        let removed = self
+            .clients
+            .get_mut(user_id)
+            .is_some_and(|client| client.ignored.remove(target));
+        let id = if removed {
+            MsgId::Unignored
+        } else {
+            MsgId::NotIgnoring
+        };
+        self.notify(user_id, id, &[("user", target)]);
+    }
+
+    /// `/away`: mark `user_id` away until they're back.
+    fn set_away(&mut self, user_id: UserId, message: Option<String>) {
+        let message = message.unwrap_or_else(|| self.text_for(user_id, MsgId::AwayDefault, &[]));
+        self.notify(user_id, MsgId::NowAway, &[("message", &message)]);
+        if let Some(client) = self.clients.get_mut(user_id) {
+            client.away = Some(message);
+        }
+    }
+
+    /// `/back`, or speaking: no longer away. Only `/back` is told off
+    /// for it when they weren't; a message just goes through.
+    fn come_back(&mut self, user_id: UserId, asked: bool) {
+        let was_away = self
+            .clients
+            .get_mut(user_id)
+            .is_some_and(|client| client.away.take().is_some());
+        if was_away {
+            self.notify(user_id, MsgId::NoLongerAway, &[]);
+        } else if asked {
+            self.notify(user_id, MsgId::NotAway, &[]);
+        }
+    }
+
+    /// `/who`: everyone in a room, with the away ones tagged. Rooms the
+    /// user couldn't `/list` stay hidden unless they're in them.
+    async fn who(&mut self, user_id: UserId, current_room: RoomId, room: Option<&str>) {
+        let room_id = match room {
+            None => current_room,
+            Some(name) => match self.find_room_by_name(name) {
+                Some(room_id) => room_id,
+                None => return self.report(user_id, &ChatError::UnknownRoom(name.to_string())),
+            },
+        };
+        let Some(room) = self.rooms.get(room_id) else {
+            return;
+        };
+        let members = room.member_ids().await;
+        if (room.private || room.hidden) && !members.contains(&user_id) {
+            let name = room.name.clone();
+            return self.report(user_id, &ChatError::UnknownRoom(name));
+        }
+        let mut names: Vec<String> = members
+            .iter()
+            .filter_map(|&member_id| self.clients.get(member_id))
+            .map(|client| match &client.away {
+                Some(message) => self.text_for(
+                    user_id,
+                    MsgId::WhoAway,
+                    &[("user", &client.username), ("message", message)],
+                ),
+                None => client.username.clone(),
+            })
+            .collect();
+        names.sort_unstable();
+        let (room, users) = (self.room_name(room_id), names.join(", "));
+        self.notify(
+            user_id,
+            MsgId::WhoList,
+            &[("room", &room), ("users", &users)],
+        );
+    }
+
+    /// Show someone arriving in a room what was said just before.
+    fn replay_history(&self, user_id: UserId, room_id: RoomId) {
+        let (Some(client), Some(room)) = (self.clients.get(user_id), self.rooms.get(room_id))
+        else {
+            return;
+        };
+        let missed = client
+            .resuming
+            .zip(self.read_markers.last_seen(client.reader(), &room.name));
+        let entries = match missed {
+            Some((limit, last)) => {
+                let mut entries = room.history.since(last);
+                let skipped = entries.len().saturating_sub(limit);
+                entries.drain(..skipped);
+                if skipped > 0 {
+                    let before = entries.first().map_or(room.history.latest() + 1, |e| e.seq);
+                    let text = self.text_for(
+                        user_id,
+                        MsgId::MissedMore,
+                        &[
+                            ("count", &skipped.to_string()),
+                            ("room", &room.name),
+                            ("before", &before.to_string()),
+                        ],
+                    );
+                    let _ = client.tx.send(Event::System(text));
+                }
+                entries
+            }
+            None => {
+                let limit = self.config.replay_on_join.min(self.replay_limit(1));
+                room.history.page(None, limit).entries
+            }
+        };
+        for entry in entries {
+            let _ = client.tx.send(Event::Replay {
+                room: room.name.clone(),
+                id: entry.seq,
+                from: entry.from.clone(),
+                body: entry.body.clone(),
+                at: entry.at,
+            });
+        }
+    }
+
+    /// How many messages to replay into a client's queue per room, when
+    /// joining `rooms` at once. Replay is queued before anything drains
+    /// it, so it must fit alongside a few notices per room: any more
+    /// and the client lags, losing messages or dropped as too slow.
+    fn replay_limit(&self, rooms: usize) -> usize {
+        const NOTICES: usize = 4;
+        let rooms = rooms.max(1);
+        let share = self.config.outbox_size.saturating_sub(NOTICES * rooms) / rooms;
+        history::MAX_REPLAY.min(share)
+    }
+
+    /// Back in a room they've been in before: tell the client where
+    /// they left off, and the person how much they missed.
+    fn offer_backfill(&mut self, user_id: UserId, room_id: RoomId) {
+        let Some(reader) = self.clients.get(user_id).map(|c| c.reader().to_string()) else {
+            return;
+        };
+        let room = &self.rooms[room_id];
+        let (name, latest) = (room.name.clone(), room.history.latest());
+        let Some(last) = self.read_markers.last_seen(&reader, &name) else {
+            // First visit: they start from here.
+            self.read_markers.mark(&reader, &name, latest);
+            return;
+        };
+
+        if let Some(client) = self.clients.get(user_id) {
+            let _ = client
+                .tx
+                .send(Event::Frames(protocol::encode_read(&name, last, latest)));
+        }
+        // Resuming, they've just been sent it all.
+        let resuming = self
+            .clients
+            .get(user_id)
+            .is_some_and(|c| c.resuming.is_some());
+        if latest > last && !resuming {
+            let count = (latest - last).to_string();
+            self.notify(
+                user_id,
+                MsgId::Missed,
+                &[("count", &count), ("room", &name)],
+            );
+        }
+        self.read_markers.mark(&reader, &name, latest);
+    }
+
+    async fn leave_room(&mut self, user_id: UserId, room_id: RoomId) {
+        self.depart(user_id, room_id, MsgId::Left).await;
+    }
+
+    /// The room `user_id` is talking in.
+    fn active_room(&self, user_id: UserId) -> RoomId {
+        self.clients
+            .get(user_id)
+            .map_or(self.lobby, |client| client.active)
+    }
+
+    fn joined_rooms(&self, user_id: UserId) -> Vec<RoomId> {
+        self.clients
+            .get(user_id)
+            .map(|client| client.rooms.clone())
+            .unwrap_or_default()
+    }
+
+    /// Drop a room from a user's list once they're out of it. If they
+    /// were talking there, they now talk in the room they joined most
+    /// recently.
+    fn forget_room(&mut self, user_id: UserId, room_id: RoomId) {
+        let Some(client) = self.clients.get_mut(user_id) else {
+            return;
+        };
+        client.rooms.retain(|&id| id != room_id);
+        if client.active == room_id
+            && let Some(&last) = client.rooms.last()
+        {
+            client.active = last;
+        }
+    }
+
+    /// `/leave`: out of one room, staying in the rest. Nobody leaves
+    /// their last room; they `/join` somewhere else instead.
+    async fn leave(&mut self, user_id: UserId, room: Option<&str>) {
+        let room_id = match room {
+            Some(name) => match self.find_room_by_name(name) {
+                Some(room_id) => room_id,
+                None => {
+                    self.report(user_id, &ChatError::UnknownRoom(name.to_string()));
+                    return;
+                }
+            },
+            None => self.active_room(user_id),
+        };
+        let joined = self.joined_rooms(user_id);
+        let name = self.room_name(room_id);
+        if !joined.contains(&room_id) {
+            self.notify(user_id, MsgId::NotJoined, &[("room", &name)]);
+            return;
+        }
+        if joined.len() == 1 {
+            self.notify(user_id, MsgId::OnlyRoom, &[("room", &name)]);
+            return;
+        }
+        let was_active = self.active_room(user_id) == room_id;
+        self.leave_room(user_id, room_id).await;
+        self.notify(user_id, MsgId::YouLeft, &[("room", &name)]);
+        if was_active {
+            let active = self.room_name(self.active_room(user_id));
+            self.notify(user_id, MsgId::Switched, &[("room", &active)]);
+        }
+    }
+
+    /// `/switch`: talk in another room already joined.
+    fn switch_room(&mut self, user_id: UserId, room_id: RoomId) {
+        let name = self.room_name(room_id);
+        let Some(client) = self.clients.get_mut(user_id) else {
+            return;
+        };
+        if !client.rooms.contains(&room_id) {
+            self.notify(user_id, MsgId::NotJoined, &[("room", &name)]);
+            return;
+        }
+        client.active = room_id;
+        self.notify(user_id, MsgId::Switched, &[("room", &name)]);
+    }
+
+    /// Take `user_id` out of a room, telling the others with `id`.
+    async fn depart(&mut self, user_id: UserId, room_id: RoomId, id: MsgId) {
+        let Some(room) = self.rooms.get(room_id) else {
+            return;
+        };
+
+        let username = self.client_name(user_id);
+        let room_name = room.name.clone();
+        let members = room.member_ids().await;
+
+        info!(user = %username, room = %room_name, "left");
+        let args = [("user", username.as_str()), ("room", room_name.as_str())];
+        self.announce_presence(&members, user_id, id, &args);
+
+        room.remove_member(user_id).await;
+        self.forget_room(user_id, room_id);
+
+        self.publish(ServerEvent::UserLeft {
+            user_id,
+            username,
+            room: room_name,
+        });
+    }
+
+    /// Remove `target` from a room on `by`'s say-so, telling the room why.
+    async fn kick(&mut self, by: UserId, target: &str, room_id: RoomId, reason: Option<String>) {
+        if !self.authorize_in_room(by, room_id, "kick", RoomRole::Operator) {
+            return;
+        }
+        let Some(target_id) = self.find_client_by_name(target) else {
+            self.report(by, &ChatError::UnknownUser(target.to_string()));
+            return;
+        };
+        let Some(room) = self.rooms.get(room_id) else {
+            return;
+        };
+        let room_name = room.name.clone();
+        let members = room.member_ids().await;
+        if !members.contains(&target_id) {
+            self.notify(
+                by,
+                MsgId::NotInRoom,
+                &[("user", target), ("room", &room_name)],
+            );
+            return;
+        }
+
+        let was_active = self.active_room(target_id) == room_id;
+        room.remove_member(target_id).await;
+        self.forget_room(target_id, room_id);
+
+        let by_name = self.client_name(by);
+        let reason = reason.unwrap_or_else(|| "no reason given".to_string());
+        let args = [
+            ("user", target),
+            ("room", room_name.as_str()),
+            ("by", by_name.as_str()),
+            ("reason", reason.as_str()),
+        ];
+        self.notify_members(&members, target_id, MsgId::Kicked, &args);
+        self.notify(target_id, MsgId::YouWereKicked, &args);
+        if was_active {
+            self.send_to_lobby(target_id, room_id).await;
+        }
+
+        self.publish(ServerEvent::UserKicked {
+            user_id: target_id,
+            username: target.to_string(),
+            room: room_name,
+            by: by_name,
+        });
+    }
+
+    /// `/roomban`: keep `target` out of the room from now on, by name,
+    /// and kick them if they're in it. Everyone has to be able to
+    /// reach the lobby, so `/ban` is the tool there.
+    async fn room_ban(
+        &mut self,
+        by: UserId,
+        target: &str,
+        room_id: RoomId,
+        reason: Option<String>,
+    ) {
+        if !self.authorize_in_room(by, room_id, "roomban", RoomRole::Operator) {
+            return;
+        }
+        let room_name = self.room_name(room_id);
+        if room_id == self.lobby {
+            self.notify(by, MsgId::LobbyUnbannable, &[("room", &room_name)]);
+            return;
+        }
+        let reason = reason.unwrap_or_else(|| "no reason given".to_string());
+        self.rooms[room_id]
+            .banned
+            .insert(target.to_string(), reason.clone());
+        self.save_rooms(by).await;
+        info!(user = %target, room = %room_name, by = %self.client_name(by), "room ban");
+        self.notify(
+            by,
+            MsgId::RoomBanConfirm,
+            &[("user", target), ("room", &room_name), ("reason", &reason)],
+        );
+        if let Some(target_id) = self.find_client_by_name(target)
+            && self.joined_rooms(target_id).contains(&room_id)
+        {
+            self.kick(by, target, room_id, Some(reason)).await;
+        }
+    }
+
+    async fn room_unban(&mut self, by: UserId, target: &str, room_id: RoomId) {
+        if !self.authorize_in_room(by, room_id, "roomunban", RoomRole::Operator) {
+            return;
+        }
+        let room_name = self.room_name(room_id);
+        let id = if self.rooms[room_id].banned.remove(target).is_some() {
+            self.save_rooms(by).await;
+            MsgId::RoomUnbanned
+        } else {
+            MsgId::RoomNotBanned
+        };
+        self.notify(by, id, &[("user", target), ("room", &room_name)]);
+    }
+
+    /// After a kick from the room they were talking in: back to the
+    /// lobby, joining it again if they'd left, and told so. Kicked from
+    /// the lobby itself, they talk in the room they joined last, if
+    /// there is one.
+    async fn send_to_lobby(&mut self, user_id: UserId, kicked_from: RoomId) {
+        let lobby = self.lobby;
+        if kicked_from == lobby {
+            let active = self.active_room(user_id);
+            if active != lobby {
+                let room = self.room_name(active);
+                self.notify(user_id, MsgId::Switched, &[("room", &room)]);
+            }
+            return;
+        }
+        if self.joined_rooms(user_id).contains(&lobby) {
+            self.switch_room(user_id, lobby);
+        } else {
+            let room = self.room_name(lobby);
+            self.notify(user_id, MsgId::YouJoined, &[("room", &room)]);
+            self.join_room(user_id, lobby).await;
+        }
+    }
+
+    /// `/op` and `/deop`. Operators may make more operators; only the
+    /// owner may take it away again, and nobody can demote the owner.
+    async fn set_room_operator(&mut self, by: UserId, target: &str, room_id: RoomId, on: bool) {
+        let (command, required) = if on {
+            ("op", RoomRole::Operator)
+        } else {
+            ("deop", RoomRole::Owner)
+        };
+        if !self.authorize_in_room(by, room_id, command, required) {
+            return;
+        }
+        let Some(target_id) = self.find_client_by_name(target) else {
+            self.report(by, &self.absent(target));
+            return;
+        };
+        let Some(room) = self.rooms.get(room_id) else {
+            return;
+        };
+        let room_name = room.name.clone();
+        let holder = self.holder(target_id);
+        if room.role_of(&holder) == RoomRole::Owner {
+            self.notify(
+                by,
+                MsgId::OwnerKeepsRole,
+                &[("user", target), ("room", &room_name)],
+            );
+            return;
+        }
+        let role = if on {
+            RoomRole::Operator
+        } else {
+            RoomRole::Member
+        };
+        let members = room.member_ids().await;
+        self.rooms[room_id].set_role(&holder, role);
+        self.save_rooms(by).await;
+
+        let by_name = self.client_name(by);
+        let id = if on {
+            MsgId::RoomOperatorGranted
+        } else {
+            MsgId::RoomOperatorRevoked
+        };
+        let args = [
+            ("user", target),
+            ("room", room_name.as_str()),
+            ("by", by_name.as_str()),
+        ];
+        for &member_id in &members {
+            self.notify(member_id, id, &args);
+        }
+        if !members.contains(&target_id) {
+            self.notify(target_id, id, &args);
+        }
+    }
+
+    /// `/topic`: anyone may read a room's topic, operators may set it.
+    async fn topic(&mut self, user_id: UserId, room_id: RoomId, text: Option<String>) {
+        let Some(room) = self.rooms.get(room_id) else {
+            return;
+        };
+        let room_name = room.name.clone();
+        let Some(text) = text else {
+            match &room.topic {
+                Some(topic) => self.notify(
+                    user_id,
+                    MsgId::Topic,
+                    &[("room", &room_name), ("topic", topic)],
+                ),
+                None => self.notify(user_id, MsgId::NoTopic, &[("room", &room_name)]),
+            }
+            return;
+        };
+        if !self.authorize_in_room(user_id, room_id, "topic", RoomRole::Operator) {
+            return;
+        }
+        let members = room.member_ids().await;
+        self.rooms[room_id].topic = Some(text.clone());
+        self.save_rooms(user_id).await;
+
+        let by_name = self.client_name(user_id);
+        let args = [
+            ("by", by_name.as_str()),
+            ("room", room_name.as_str()),
+            ("topic", text.as_str()),
+        ];
+        for &member_id in &members {
+            self.notify(member_id, MsgId::TopicChanged, &args);
+        }
+        if !members.contains(&user_id) {
+            self.notify(user_id, MsgId::TopicChanged, &args);
+        }
+    }
+
+    fn find_client_by_name(&self, name: &str) -> Option<UserId> {
+        self.clients
+            .iter()
+            .find(|(_, c)| c.username == name)
+            .map(|(user_id, _)| user_id)
+    }
+
+    /// Mute `user_id` for `duration`. False if they've gone, or the
+    /// duration runs past what the clock can count.
+    fn mute(&mut self, user_id: UserId, by: UserId, duration: Duration) -> bool {
+        let Some(until) = Instant::now().checked_add(duration) else {
+            return false;
+        };
+        let Some(client) = self.clients.get_mut(user_id) else {
+            return false;
+        };
+        client.mute = Some(Mute { until, by });
+        let username = client.username.clone();
+
+        let secs = duration.as_secs().to_string();
+        self.notify(user_id, MsgId::YouAreMuted, &[("secs", &secs)]);
+        self.publish(ServerEvent::UserMuted {
+            user_id,
+            username,
+            duration,
+        });
+        true
+    }
+
+    /// `/mute`: silence `target` for `duration`, and lift it after.
+    fn mute_user(&mut self, by: UserId, target: &str, duration: Duration) {
+        let Some(target_id) = self.find_client_by_name(target) else {
+            self.report(by, &ChatError::UnknownUser(target.to_string()));
+            return;
+        };
+        if !self.mute(target_id, by, duration) {
+            let secs = duration.as_secs();
+            self.report(by, &too_far_off(&format!("{secs}s")));
+            return;
+        }
+        let secs = duration.as_secs().to_string();
+        self.notify(
+            by,
+            MsgId::MutedConfirm,
+            &[("user", target), ("secs", &secs)],
+        );
+        self.schedule(duration, move |server| async move {
+            server.lock().await.expire_mute(target_id);
+        });
+    }
+
+    /// Time left on a user's mute, if any.
+    fn mute_remaining(&self, user_id: UserId) -> Option<Duration> {
+        let client = self.clients.get(user_id)?;
+        let mute = client.mute.as_ref()?;
+        let remaining = mute.until.saturating_duration_since(Instant::now());
+        (!remaining.is_zero()).then_some(remaining)
+    }
+
+    /// Spend a message token. Without one the sender is warned, and with
+    /// `flood_mute` set, enough refusals in a row get them muted.
+    fn allow_message(&mut self, user_id: UserId) -> bool {
+        if self.message_limits.check(user_id) {
+            self.flood_strikes.remove(&user_id);
+            return true;
+        }
+        self.report(user_id, &ChatError::RateLimited { what: "messages" });
+
+        let Some(flood) = self.config.flood_mute else {
+            return false;
+        };
+        let strikes = self.flood_strikes.entry(user_id).or_insert(0);
+        *strikes += 1;
+        if *strikes >= flood.strikes {
+            self.flood_strikes.remove(&user_id);
+            self.mute_automatically(user_id, flood.duration);
+        }
+        false
+    }
+
+    /// A mute the server gives out on its own, for flooding or spam.
+    fn mute_automatically(&mut self, user_id: UserId, duration: Duration) {
+        // Muted by themselves, in effect: there's no one else to tell
+        // when it lifts.
+        if !self.mute(user_id, user_id, duration) {
+            return;
+        }
+        self.schedule(duration, move |server| async move {
+            server.lock().await.expire_mute(user_id);
+        });
+    }
+
+    /// Carry out what the anti-spam filter decided about the messages
+    /// it just blocked. Operators are never punished, though their
+    /// spam is still blocked.
+    fn punish_spam(&mut self) {
+        while let Some(Strike {
+            user_id,
+            spam,
+            penalty,
+        }) = self.spam_strikes.as_mut().and_then(|c| c.try_recv().ok())
+        {
+            if self.is_oper(user_id) {
+                continue;
+            }
+            let reason = spam.reason();
+            match penalty {
+                Penalty::Warn => self.notify(user_id, MsgId::SpamWarning, &[("reason", reason)]),
+                Penalty::Mute(duration) => self.mute_automatically(user_id, duration),
+                Penalty::Kick => {
+                    info!(user = %self.client_name(user_id), reason, "kicked for spam");
+                    self.close(
+                        user_id,
+                        DisconnectReason::Spam,
+                        MsgId::SpamKicked,
+                        &[("reason", reason)],
+                    );
+                }
+            }
+        }
+    }
+
+    /// Lift an expired mute and tell both parties.
+    ///
+    /// Called from a delayed task. A later `/mute` pushes `until` forward,
+    /// so a timer left over from an earlier, shorter mute fires as a no-op.
+    fn expire_mute(&mut self, user_id: UserId) {
+        let Some(client) = self.clients.get_mut(user_id) else {
+            return;
+        };
+        let Some(mute) = client.mute.as_ref() else {
+            return;
+        };
+        if mute.until > Instant::now() {
+            return;
+        }
+
+        let by = mute.by;
+        client.mute = None;
+        let username = client.username.clone();
+
+        self.notify(user_id, MsgId::Unmuted, &[]);
+        if by != user_id {
+            self.notify(by, MsgId::UnmutedNotice, &[("user", &username)]);
+        }
+
+        self.publish(ServerEvent::UserUnmuted { user_id, username });
+    }
+
+    /// Hold a chat message to `max_message_len`. None if it was refused;
+    /// either way the sender hears about it.
+    fn fit_message<'a>(&self, user_id: UserId, body: &'a str) -> Option<Cow<'a, str>> {
+        let max = self.config.max_message_len;
+        match message::fit(body, max, self.config.oversize) {
+            Ok(body) => {
+                if matches!(body, Cow::Owned(_)) {
+                    let max = max.to_string();
+                    self.notify(user_id, MsgId::MessageTruncated, &[("max", &max)]);
+                }
+                Some(body)
+            }
+            Err(e) => {
+                self.report(user_id, &e);
+                None
+            }
+        }
+    }
+
+    /// Everything a chat message must get past before it's said: the
+    /// length limit, mutes, quotas, link trust and the filters. What it
+    /// should say, after filters have had their way, or None if it was
+    /// refused — the sender has been told why.
+    ///
+    /// An edit goes through here too, or `/edit` would be a way round
+    /// the lot.
+    async fn screen_message(
+        &mut self,
+        room_id: RoomId,
+        sender_id: UserId,
+        username: &str,
+        body: &str,
+    ) -> Option<String> {
+        let body = self.fit_message(sender_id, body)?;
+        let body = body.as_ref();
+        if let Some(remaining) = self.mute_remaining(sender_id) {
+            let secs = remaining.as_secs().max(1).to_string();
+            self.notify(sender_id, MsgId::StillMuted, &[("secs", &secs)]);
+            return None;
+        }
+
+        if let Some(quota) = self.trust.quota_reached(username)
+            && !self.is_oper(sender_id)
+        {
+            let quota = quota.to_string();
+            self.notify(sender_id, MsgId::QuotaReached, &[("quota", &quota)]);
+            return None;
+        }
+
+        if trust::has_link(body) && !self.permitted(sender_id, Capability::PostLinks) {
+            return None;
+        }
+
+        // Run async filters: the server's, then the room's own.
+        let room_name = self.room_name(room_id);
+        let ctx = FilterContext {
+            sender: sender_id,
+            username,
+            room: &room_name,
+            at: SystemTime::now(),
+            message_count: self.trust.messages(username),
+        };
+        let mut final_body = body.to_string();
+        let room_filters = self.rooms.get(room_id).into_iter().flat_map(Room::filters);
+        let dedup = self.dedup.iter().map(|dedup| dedup as &dyn AsyncFilter);
+        let chain = dedup
+            .chain(self.filters.iter().map(Box::as_ref))
+            .chain(room_filters);
+        let mut blocked = None;
+        for filter in chain {
+            match filter.apply(&ctx, &final_body).await {
+                FilterAction::Allow => {}
+                FilterAction::Modify(new) => final_body = new,
+                FilterAction::Block(reason) => {
+                    blocked = Some(reason);
+                    break;
+                }
+            }
+        }
+        if let Some(reason) = blocked {
+            self.daily.filter_blocks += 1;
+            self.counters.filter_blocks.fetch_add(1, Ordering::Relaxed);
+            self.notify(sender_id, MsgId::MessageBlocked, &[("reason", &reason)]);
+            self.punish_spam();
+            return None;
+        }
+        Some(final_body)
+    }
+
+    async fn broadcast_message(
+        &mut self,
+        room_id: RoomId,
+        sender_id: UserId,
+        username: &str,
+        body: &str,
+    ) {
+        self.come_back(sender_id, false);
+        let Some(final_body) = self
+            .screen_message(room_id, sender_id, username, body)
+            .await
+        else {
+            return;
+        };
+        let Some(room) = self.rooms.get_mut(room_id) else {
+            return;
+        };
+        if let Some(client) = self.clients.get(sender_id)
+            && client.shadow_muted
+        {
+            // Just as it would have looked, but sent to them alone: not
+            // kept, not counted, and nothing for anyone else to notice.
+            let event = Event::Message {
+                room: room.name.as_str().into(),
+                id: room.history.peek(),
+                from: username.into(),
+                body: final_body.as_str().into(),
+                at: SystemTime::now(),
+            };
+            let _ = client.tx.send(event);
+            debug!(user = %username, room = %room.name, "shadow-muted message dropped");
+            return;
+        }
+
+        room.activity.lock().unwrap().record_message(sender_id);
+
+        let room_name = room.name.clone();
+        let seq = self.rooms[room_id].history.push(username, &final_body);
+        let event = Event::Message {
+            room: room_name.as_str().into(),
+            id: seq,
+            from: username.into(),
+            body: final_body.as_str().into(),
+            at: SystemTime::now(),
+        };
+        self.deliver(room_id, username, &event, seq).await;
+        self.notify_mentions(room_id, &event);
+
+        debug!(user = %username, room = %room_name, bytes = final_body.len(), "message");
+        self.trust.record_message(username);
+        self.daily.record_message(username);
+        self.counters.messages.fetch_add(1, Ordering::Relaxed);
+        self.store(room_id, seq, username, &final_body).await;
+        self.publish(ServerEvent::MessageBroadcast {
+            room_id,
+            room: room_name.clone(),
+            from: username.to_string(),
+            body: final_body.clone(),
+        });
+
+        let info = MessageInfo {
+            user_id: sender_id,
+            username: username.to_string(),
+            room_id,
+            room: room_name,
+            body: final_body,
+        };
+        for hook in self.message_hooks.clone() {
+            hook(self, &info);
+        }
+    }
+
+    /// Tell everyone `@named` in `message`, in the room or not: whether
+    /// one from elsewhere is shown is up to each of them. Not the
+    /// sender, and not anyone ignoring them.
+    fn notify_mentions(&self, room_id: RoomId, message: &Event) {
+        let Event::Message {
+            room,
+            id,
+            from,
+            body,
+            at,
+        } = message
+        else {
+            return;
+        };
+        let mut told = HashSet::new();
+        for name in mentions(body) {
+            let Some(user_id) = self.find_client_by_name(name) else {
+                continue;
+            };
+            let client = &self.clients[user_id];
+            if !told.insert(user_id)
+                || *client.username == **from
+                || client.ignored.contains(&**from)
+            {
+                continue;
+            }
+            let _ = client.tx.send(Event::Mention {
+                room: Arc::clone(room),
+                id: *id,
+                from: Arc::clone(from),
+                body: Arc::clone(body),
+                at: *at,
+                here: client.rooms.contains(&room_id),
+            });
+        }
+    }
+
+    /// The message `/edit` or `/delete` means, if it's there and this
+    /// user may change it: their own, or any in a room they operate.
+    fn changeable_message(
+        &self,
+        user_id: UserId,
+        current_room: RoomId,
+        room: Option<&str>,
+        id: u64,
+        command: &str,
+    ) -> Option<(RoomId, Entry)> {
+        let room_id = match room {
+            None => current_room,
+            Some(name) => match self.find_room_by_name(name) {
+                Some(room_id) => room_id,
+                None => {
+                    self.report(user_id, &ChatError::UnknownRoom(name.to_string()));
+                    return None;
+                }
+            },
+        };
+        let room = self.rooms.get(room_id)?;
+        let Some(entry) = room.history.get(id) else {
+            let err = ChatError::UnknownMessage {
+                room: room.name.clone(),
+                id,
+            };
+            self.report(user_id, &err);
+            return None;
+        };
+        let own = self
+            .clients
+            .get(user_id)
+            .is_some_and(|client| client.username == entry.from);
+        if !own && !self.authorize_in_room(user_id, room_id, command, RoomRole::Operator) {
+            return None;
+        }
+        Some((room_id, entry.clone()))
+    }
+
+    /// `/edit` or EDIT: say something else in message `id` instead.
+    /// The new text is screened as a new message would be.
+    async fn edit_message(
+        &mut self,
+        user_id: UserId,
+        current_room: RoomId,
+        room: Option<&str>,
+        id: u64,
+        body: &str,
+    ) {
+        let Some((room_id, mut entry)) =
+            self.changeable_message(user_id, current_room, room, id, "edit")
+        else {
+            return;
+        };
+        let username = self.client_name(user_id);
+        let Some(body) = self.screen_message(room_id, user_id, &username, body).await else {
+            return;
+        };
+        if self.shadow_muted(user_id) {
+            // The edit as they'd see it, shown to them alone.
+            let event = Event::Edited {
+                room: self.rooms[room_id].name.clone(),
+                id,
+                from: entry.from,
+                body,
+                at: SystemTime::now(),
+            };
+            if let Some(client) = self.clients.get(user_id) {
+                let _ = client.tx.send(event);
+            }
+            return;
+        }
+        let room = &mut self.rooms[room_id];
+        room.history.edit(id, &body);
+        let (room_name, latest) = (room.name.clone(), room.history.latest());
+        entry.body = body;
+
+        let event = Event::Edited {
+            room: room_name.clone(),
+            id,
+            from: entry.from.clone(),
+            body: entry.body.clone(),
+            at: SystemTime::now(),
+        };
+        self.deliver(room_id, &entry.from, &event, latest).await;
+        debug!(user = %username, room = %room_name, id, "message edited");
+        self.store_entry(room_id, &entry).await;
+    }
+
+    /// `/delete` or DELETE: take message `id` back. What's already been
+    /// read can't be unread, but it's gone from history and clients are
+    /// told to strike it.
+    async fn delete_message(
+        &mut self,
+        user_id: UserId,
+        current_room: RoomId,
+        room: Option<&str>,
+        id: u64,
+    ) {
+        let Some((room_id, mut entry)) =
+            self.changeable_message(user_id, current_room, room, id, "delete")
+        else {
+            return;
+        };
+        let room = &mut self.rooms[room_id];
+        room.history.remove(id);
+        let (room_name, latest) = (room.name.clone(), room.history.latest());
+
+        let by = self.client_name(user_id);
+        let event = Event::Deleted {
+            room: room_name.clone(),
+            id,
+            by: by.clone(),
+            at: SystemTime::now(),
+        };
+        self.deliver(room_id, &entry.from, &event, latest).await;
+        debug!(user = %by, room = %room_name, id, "message deleted");
+        entry.body.clear();
+        self.store_entry(room_id, &entry).await;
+    }
+
+    /// `/invitecode`: only someone already in the room (or an operator)
+    /// can hand out a way in.
+    async fn create_invite(&mut self, user_id: UserId, room: &str, uses: u32, ttl: Duration) {
+        let Some(room_id) = self.find_room_by_name(room) else {
+            self.report(user_id, &ChatError::UnknownRoom(room.to_string()));
+            return;
+        };
+        let member = self.rooms[room_id].member_ids().await.contains(&user_id);
+        if !member && !self.is_oper(user_id) {
+            let name = self.client_name(user_id);
+            self.notify(
+                user_id,
+                MsgId::NotInRoom,
+                &[("user", &name), ("room", room)],
+            );
+            return;
+        }
+
+        let code = self.invites.create(room_id, uses);
+        let expiring = code.clone();
+        self.schedule(ttl, move |server| async move {
+            server.lock().await.invites.expire(&expiring);
+        });
+        let (uses, ttl) = (uses.to_string(), ttl.as_secs().to_string());
+        self.notify(
+            user_id,
+            MsgId::InviteCreated,
+            &[
+                ("room", room),
+                ("code", &code),
+                ("uses", &uses),
+                ("ttl", &ttl),
+            ],
+        );
+    }
+
+    /// JOINCODE: spend a use of the code and let this user into its room
+    /// from now on. Returns the room to move them to.
+    fn redeem_invite(&mut self, user_id: UserId, code: &str) -> Option<RoomId> {
+        let Some(room_id) = self.invites.redeem(code) else {
+            self.notify(user_id, MsgId::InviteInvalid, &[]);
+            return None;
+        };
+        let name = self.client_name(user_id);
+        // The room may have expired since the code was made.
+        if self.rooms.get(room_id).is_none() {
+            self.notify(user_id, MsgId::InviteInvalid, &[]);
+            return None;
+        }
+        // A code doesn't get round a ban.
+        if self.banned_from(user_id, room_id) {
+            return None;
+        }
+        self.rooms[room_id].invited.insert(name);
+        Some(room_id)
+    }
+
+    /// The password `user_id` must give to `/join` `room_id`, if any.
+    /// Those who could let themselves in anyway — server operators,
+    /// the room's owner and operators, anyone invited — needn't.
+    fn room_password(&self, user_id: UserId, room_id: RoomId) -> Option<Credentials> {
+        let room = self.rooms.get(room_id)?;
+        let password = room.password.as_ref()?;
+        let name = self.client_name(user_id);
+        if self.is_oper(user_id)
+            || room.role_of(&self.holder(user_id)) != RoomRole::Member
+            || room.invited.contains(&name)
+        {
+            return None;
+        }
+        Some(password.clone())
+    }
+
+    /// `/setpass`, once the password is hashed: put it on the room, or
+    /// take it off with None. Members already inside stay.
+    async fn set_room_password(
+        &mut self,
+        by: UserId,
+        room_id: RoomId,
+        password: Option<Credentials>,
+    ) {
+        let Some(room) = self.rooms.get(room_id) else {
+            return;
+        };
+        let room_name = room.name.clone();
+        let members = room.member_ids().await;
+        let id = if password.is_some() {
+            MsgId::RoomPasswordSet
+        } else {
+            MsgId::RoomPasswordCleared
+        };
+        self.rooms[room_id].password = password;
+        self.save_rooms(by).await;
+
+        let by_name = self.client_name(by);
+        let args = [("by", by_name.as_str()), ("room", room_name.as_str())];
+        for &member_id in &members {
+            self.notify(member_id, id, &args);
+        }
+    }
+
+    /// May `user_id` walk into `room_id` with `/join`? Nobody the room
+    /// has banned, and private rooms need an invite; operators can go
+    /// anywhere.
+    fn may_enter(&self, user_id: UserId, room_id: RoomId) -> bool {
+        let Some(room) = self.rooms.get(room_id) else {
+            return false;
+        };
+        if self.banned_from(user_id, room_id) {
+            return false;
+        }
+        if !room.private
+            || self.is_oper(user_id)
+            || room.invited.contains(&self.client_name(user_id))
+        {
+            return true;
+        }
+        self.notify(user_id, MsgId::RoomPrivate, &[("room", &room.name)]);
+        false
+    }
+
+    /// Whether `/roomban` keeps `user_id` out of `room_id`, telling
+    /// them why if so. Server operators aren't kept out of anywhere.
+    fn banned_from(&self, user_id: UserId, room_id: RoomId) -> bool {
+        let Some(room) = self.rooms.get(room_id) else {
+            return false;
+        };
+        let Some(reason) = room.banned.get(&self.client_name(user_id)) else {
+            return false;
+        };
+        if self.is_oper(user_id) {
+            return false;
+        }
+        self.notify(
+            user_id,
+            MsgId::RoomBannedRefusal,
+            &[("room", &room.name), ("reason", reason)],
+        );
+        true
+    }
+
+    /// `/msg`: deliver to the recipient, and echo to the sender so the
+    /// conversation reads the same on both screens.
+    ///
+    /// With `dm_rooms` on, the pair's hidden room keeps the message too.
+    /// Neither of them is ever a member of it — they stay in whatever
+    /// room they're in — but it gives the conversation a history and
+    /// read markers like any other room.
+    async fn direct_message(&mut self, from_id: UserId, target: &str, body: &str) {
+        self.come_back(from_id, false);
+        let Some(body) = self.fit_message(from_id, body) else {
+            return;
+        };
+        let body = body.as_ref();
+        if let Some(remaining) = self.mute_remaining(from_id) {
+            let secs = remaining.as_secs().max(1).to_string();
+            self.notify(from_id, MsgId::StillMuted, &[("secs", &secs)]);
+            return;
+        }
+        if !self.permitted(from_id, Capability::SendDms) {
+            return;
+        }
+        let Some(to_id) = self.find_client_by_name(target) else {
+            self.report(from_id, &self.absent(target));
+            return;
+        };
+
+        let from = self.client_name(from_id);
+        let event = Event::Direct {
+            from: from.clone(),
+            to: target.to_string(),
+            body: body.to_string(),
+            at: SystemTime::now(),
+        };
+        // Someone ignoring the sender isn't told; the sender isn't
+        // told they're ignored. Shadow-muted, they get the echo alone.
+        let shadowed = self.shadow_muted(from_id);
+        for user_id in [from_id, to_id] {
+            if let Some(client) = self.clients.get(user_id)
+                && !client.ignored.contains(&from)
+            {
+                let _ = client.tx.send(event.clone());
+            }
+            // Writing to yourself: once is enough.
+            if from_id == to_id || shadowed {
+                break;
+            }
+        }
+        if let Some(message) = self.clients.get(to_id).and_then(|c| c.away.as_deref()) {
+            self.notify(
+                from_id,
+                MsgId::AwayReply,
+                &[("user", target), ("message", message)],
+            );
+        }
+
+        if self.config.dm_rooms && !shadowed {
+            let room_id = self.dm_room(&from, target);
+            let seq = self.record(room_id, &from, body).await;
+            let name = self.rooms[room_id].name.clone();
+            for user_id in [from_id, to_id] {
+                if let Some(client) = self.clients.get(user_id) {
+                    self.read_markers.mark(client.reader(), &name, seq);
+                }
+            }
+        }
+    }
+
+    /// Why a private message to `name` can't be delivered: they've gone,
+    /// or there was never anyone by that name.
+    fn absent(&self, name: &str) -> ChatError {
+        if self.trust.knows(name) {
+            ChatError::UserOffline(name.to_string())
+        } else {
+            ChatError::UnknownUser(name.to_string())
+        }
+    }
+
+    /// EMSG: route an end-to-end encrypted payload to one user.
+    ///
+    /// The server can't read the payload and doesn't try: no filters,
+    /// no history, nothing that would have to understand it. What it
+    /// can still enforce is who may send (mutes, the DM trust gate)
+    /// and how much.
+    fn route_encrypted(&mut self, from_id: UserId, to: &str, payload: &str) {
+        if let Some(remaining) = self.mute_remaining(from_id) {
+            let secs = remaining.as_secs().max(1).to_string();
+            self.notify(from_id, MsgId::StillMuted, &[("secs", &secs)]);
+            return;
+        }
+        if !self.permitted(from_id, Capability::SendDms) {
+            return;
+        }
+        if payload.len() > self.config.max_emsg_bytes {
+            let err = ChatError::MessageTooLong {
+                len: payload.len(),
+                max: self.config.max_emsg_bytes,
+            };
+            self.report(from_id, &err);
+            return;
+        }
+        let Some(to_id) = self.find_client_by_name(to) else {
+            self.report(from_id, &self.absent(to));
+            return;
+        };
+
+        if self.shadow_muted(from_id) {
+            // There's no echo to fake: it just never arrives.
+            return;
+        }
+        let frame = protocol::encode_emsg(&self.client_name(from_id), payload);
+        if let Some(client) = self.clients.get(to_id) {
+            let _ = client.tx.send(Event::Frames(frame));
+        }
+    }
+
+    /// FILE_END: keep the upload and tell the sender's room how to
+    /// fetch it. The file expires after the configured time, fetched
+    /// or not.
+    async fn share_file(&mut self, user_id: UserId, upload: Upload) {
+        let Some(limits) = self.config.files else {
+            return;
+        };
+        if let Some(remaining) = self.mute_remaining(user_id) {
+            let secs = remaining.as_secs().max(1).to_string();
+            self.notify(user_id, MsgId::StillMuted, &[("secs", &secs)]);
+            return;
+        }
+        let room_id = self.active_room(user_id);
+        let user = self.client_name(user_id);
+        let file = match upload.finish(room_id) {
+            Ok(file) => file,
+            Err(e) => return self.report(user_id, &e),
+        };
+        let (name, size) = (file.name.clone(), file.size.to_string());
+        let token = match self.files.insert(file, &limits) {
+            Ok(token) => token,
+            Err(e) => return self.report(user_id, &e),
+        };
+        let expiring = token.clone();
+        self.schedule(limits.ttl, move |server| async move {
+            server.lock().await.files.expire(&expiring);
+        });
+
+        info!(user = %user, room = %self.room_name(room_id), %name, %size, "file shared");
+        let ttl = limits.ttl.as_secs().to_string();
+        let args = [
+            ("user", user.as_str()),
+            ("name", &name),
+            ("size", &size),
+            ("token", &token),
+            ("ttl", &ttl),
+        ];
+        // Shadow-muted, the share is real but only they hear of it.
+        if self.shadow_muted(user_id) {
+            self.notify(user_id, MsgId::FileShared, &args);
+            return;
+        }
+        for member_id in self.rooms[room_id].member_ids().await {
+            self.notify(member_id, MsgId::FileShared, &args);
+        }
+    }
+
+    /// FILE_GET: send a shared file back, to members of the room it
+    /// was shared in. To anyone else it doesn't exist.
+    async fn fetch_file(&mut self, user_id: UserId, token: &str) {
+        let unknown = || ChatError::UnknownFile(token.to_string());
+        let Some(file) = self.files.get(token) else {
+            return self.report(user_id, &unknown());
+        };
+        let member = match self.rooms.get(file.room_id) {
+            Some(room) => room.member_ids().await.contains(&user_id),
+            None => false,
+        };
+        if !member {
+            return self.report(user_id, &unknown());
+        }
+        let frames = protocol::encode_file(&file.name, file.size, &file.chunks);
+        self.send_frame(user_id, frames);
+    }
+
+    /// The hidden room for `a` and `b`'s conversation, created on first
+    /// use. Private, with just the two of them invited.
+    fn dm_room(&mut self, a: &str, b: &str) -> RoomId {
+        let name = room::dm_room_name(a, b);
+        if let Some(room_id) = self.find_room_by_name(&name) {
+            return room_id;
+        }
+        let room_id = self.create_room(name);
+        let room = &mut self.rooms[room_id];
+        room.private = true;
+        room.hidden = true;
+        room.invited.extend([a.to_string(), b.to_string()]);
+        room_id
+    }
+
+    /// Answer a HISTORY request with a batch of frames. Only members of
+    /// a room can read its history.
+    async fn send_history(
+        &mut self,
+        user_id: UserId,
+        room: &str,
+        before: Option<u64>,
+        limit: usize,
+    ) {
+        let Some(room_id) = self.find_room_by_name(room) else {
+            self.report(user_id, &ChatError::UnknownRoom(room.to_string()));
+            return;
+        };
+        let room_ref = &self.rooms[room_id];
+        // A DM room has no members, only its two participants.
+        let participant = room_ref.hidden && room_ref.invited.contains(&self.client_name(user_id));
+        if !participant && !room_ref.member_ids().await.contains(&user_id) {
+            let name = self.client_name(user_id);
+            self.notify(
+                user_id,
+                MsgId::NotInRoom,
+                &[("user", &name), ("room", room)],
+            );
+            return;
+        }
+        let page = room_ref.history.page(before, limit);
+        let batch = protocol::encode_history(room, &page);
+        if let Some(client) = self.clients.get(user_id) {
+            let _ = client.tx.send(Event::Frames(batch));
+        }
+    }
+
+    /// Start a poll in `room_id`, closing itself after POLL_TTL.
+    async fn open_poll(
+        &mut self,
+        user_id: UserId,
+        room_id: RoomId,
+        question: String,
+        options: Vec<String>,
+    ) {
+        let creator_name = self.client_name(user_id);
+        let Some(room) = self.rooms.get_mut(room_id) else {
+            return;
+        };
+        if let Some(poll) = &room.poll {
+            let question = poll.question.clone();
+            self.notify(user_id, MsgId::PollRunning, &[("question", &question)]);
+            return;
+        }
+
+        let mut poll = Poll::new(user_id, creator_name.clone(), question, options);
+        let args = [
+            ("user", creator_name.as_str()),
+            ("question", poll.question.as_str()),
+            ("ballot", &poll.ballot()),
+        ];
+        let text = self.text(MsgId::PollOpened, &args);
+
+        poll.expiry = self.schedule(POLL_TTL, move |server| async move {
+            server.lock().await.close_poll(room_id).await;
+        });
+        self.rooms[room_id].poll = Some(poll);
+        self.send_room_system(room_id, text).await;
+    }
+
+    fn vote(&mut self, user_id: UserId, room_id: RoomId, choice: usize) {
+        let Some(voter) = self.clients.get(user_id).map(|c| c.reader().to_string()) else {
+            return;
+        };
+        let Some(poll) = self
+            .rooms
+            .get_mut(room_id)
+            .and_then(|room| room.poll.as_mut())
+        else {
+            let room = self.room_name(room_id);
+            self.notify(user_id, MsgId::NoPoll, &[("room", &room)]);
+            return;
+        };
+
+        match poll.vote(&voter, choice) {
+            Vote::Counted => {
+                let option = poll.options[choice - 1].clone();
+                self.notify(user_id, MsgId::VoteCounted, &[("option", &option)]);
+            }
+            Vote::AlreadyVoted => self.notify(user_id, MsgId::AlreadyVoted, &[]),
+            Vote::NoSuchOption => {
+                let count = poll.options.len().to_string();
+                self.notify(user_id, MsgId::NoSuchOption, &[("count", &count)]);
+            }
+        }
+    }
+
+    /// `/poll close`: only the poll's creator or an operator may.
+    async fn request_close_poll(&mut self, user_id: UserId, room_id: RoomId) {
+        let Some(poll) = self.rooms.get(room_id).and_then(|room| room.poll.as_ref()) else {
+            let room = self.room_name(room_id);
+            self.notify(user_id, MsgId::NoPoll, &[("room", &room)]);
+            return;
+        };
+        if poll.creator != user_id && !self.is_oper(user_id) {
+            let creator = poll.creator_name.clone();
+            self.notify(user_id, MsgId::PollCloseDenied, &[("user", &creator)]);
+            return;
+        }
+        self.close_poll(room_id).await;
+    }
+
+    /// End the room's poll, if any, and show everyone the results.
+    async fn close_poll(&mut self, room_id: RoomId) {
+        let Some(poll) = self
+            .rooms
+            .get_mut(room_id)
+            .and_then(|room| room.poll.take())
+        else {
+            return;
+        };
+        if let Some(expiry) = poll.expiry {
+            self.cancel_task(expiry);
+        }
+        let text = self.text(
+            MsgId::PollClosed,
+            &[("question", &poll.question), ("tally", &poll.tally())],
+        );
+        self.send_room_system(room_id, text).await;
+    }
+
+    /// Schedule a `/remind`.
+    ///
+    /// A personal reminder is addressed by user id, not room, so it finds
+    /// the user wherever they've wandered off to. A room reminder
+    /// resolves the name now, so a typo is caught while the user is
+    /// still looking, and only a member can leave one. Either kind is
+    /// dropped if its user disconnects first, and nobody has more than
+    /// `MAX_REMINDERS` waiting.
+    fn remind(
+        &mut self,
+        user_id: UserId,
+        target: RemindTarget,
+        after: Duration,
+        when: &str,
+        text: String,
+    ) {
+        let now = Instant::now();
+        let pending = self.reminders.entry(user_id).or_default();
+        pending.retain(|&(_, due)| due > now);
+        if pending.len() >= MAX_REMINDERS {
+            let max = MAX_REMINDERS.to_string();
+            self.notify(user_id, MsgId::TooManyReminders, &[("max", &max)]);
+            return;
+        }
+        let scheduled = match target {
+            RemindTarget::Me => {
+                let scheduled = self.schedule(after, move |server| async move {
+                    server
+                        .lock()
+                        .await
+                        .notify(user_id, MsgId::Reminder, &[("text", &text)]);
+                });
+                if scheduled.is_none() {
+                    self.report(user_id, &too_far_off(when));
+                    return;
+                }
+                self.notify(user_id, MsgId::ReminderSet, &[("when", when)]);
+                scheduled
+            }
+            RemindTarget::Room(name) => {
+                let Some(room_id) = self.find_room_by_name(&name) else {
+                    self.report(user_id, &ChatError::UnknownRoom(name.to_string()));
+                    return;
+                };
+                // Only those who could say it there now may say it later.
+                if !self.joined_rooms(user_id).contains(&room_id) && !self.is_oper(user_id) {
+                    self.notify(user_id, MsgId::NotJoined, &[("room", &name)]);
+                    return;
+                }
+                let from = self.client_name(user_id);
+                let scheduled = self.schedule(after, move |server| async move {
+                    let mut server = server.lock().await;
+                    let line =
+                        server.text(MsgId::RoomReminder, &[("user", &from), ("text", &text)]);
+                    server.send_room_system(room_id, line).await;
+                });
+                if scheduled.is_none() {
+                    self.report(user_id, &too_far_off(when));
+                    return;
+                }
+                self.notify(
+                    user_id,
+                    MsgId::RoomReminderSet,
+                    &[("room", &name), ("when", when)],
+                );
+                scheduled
+            }
+        };
+        if let Some(task) = scheduled {
+            let pending = self.reminders.entry(user_id).or_default();
+            pending.push((task, now + after));
+        }
+    }
+
+    /// Everyone connected.
+    pub fn users(&self) -> Vec<UserInfo> {
+        self.clients
+            .iter()
+            .map(|(user_id, client)| UserInfo {
+                user_id,
+                username: client.username.clone(),
+                peer: client.peer,
+                role: self.role(user_id),
+                rooms: client.rooms.iter().map(|&r| self.room_name(r)).collect(),
+            })
+            .collect()
+    }
+
+    /// Hang up on `name` from outside the chat, telling them why.
+    /// False if nobody online has that name.
+    pub fn disconnect_user(&self, name: &str, reason: &str) -> bool {
+        let Some(user_id) = self.find_client_by_name(name) else {
+            return false;
+        };
+        self.close(
+            user_id,
+            DisconnectReason::Kicked,
+            MsgId::AdminDisconnect,
+            &[("reason", reason)],
+        );
+        true
+    }
+
+    /// A notice to everyone connected, whatever room they're in.
+    /// Returns how many it went to.
+    pub fn notice_all(&self, text: &str) -> usize {
+        for (user_id, _) in self.clients.iter() {
+            self.notify(user_id, MsgId::AdminNotice, &[("text", text)]);
+        }
+        self.clients.len()
+    }
+
+    /// Read the accounts and bans back from storage, for edits made by
+    /// hand while the server runs.
+    pub fn reload_storage(&mut self) -> Result<(), ChatError> {
+        self.load_accounts_and_bans()
+    }
+
+    /// Read the config file again and apply what can change while
+    /// running. Connections stay up; those already open keep the line
+    /// length limit they started with. Returns the settings applied,
+    /// and those that only a restart will change.
+    pub fn reload_config(&mut self) -> Result<(Vec<&'static str>, Vec<&'static str>), ChatError> {
+        let path = self
+            .config
+            .config_file
+            .clone()
+            .ok_or_else(|| ChatError::Config("started without a config file".into()))?;
+        let file = settings::load(&path)?;
+        let restart = self.config.needs_restart(&file);
+        let applied = self.config.update(file);
+
+        self.message_limits.set_limit(self.config.message_rate);
+        self.command_limits.set_limit(self.config.command_rate);
+        if applied.contains(&"dedup") {
+            self.dedup = self.config.dedup.map(DedupFilter::new);
+        }
+        info!(path = %path.display(), ?applied, ?restart, "config reloaded");
+        Ok((applied, restart))
+    }
+
+    /// `room_stats` for every room `/list` would show.
+    pub async fn all_room_stats(&mut self) -> Vec<RoomStats> {
+        let mut all = Vec::new();
+        for (_, room) in self.rooms.iter().filter(|(_, room)| !room.hidden) {
+            all.push(room.stats().await);
+        }
+        all
+    }
+
+    /// Activity figures for a room, by name. This is what `/stats`
+    /// shows; embedders can poll it for their own dashboards.
+    pub async fn room_stats(&mut self, name: &str) -> Option<RoomStats> {
+        let room_id = self.find_room_by_name(name)?;
+        Some(self.rooms[room_id].stats().await)
+    }
+
+    /// Server health in Prometheus' text format, for the metrics
+    /// endpoint. Messages per second is over the last minute, for a
+    /// quick look; for graphs, `rate(chat_messages_total[5m])` is the
+    /// better measure.
+    pub fn metrics(&self) -> String {
+        let recent: usize = self
+            .rooms
+            .iter()
+            .map(|(_, room)| room.activity.lock().unwrap().window(MINUTE).messages)
+            .sum();
+        let load = |counter: &std::sync::atomic::AtomicU64| counter.load(Ordering::Relaxed);
+
+        let mut out = Exposition::default();
+        out.gauge(
+            "chat_users_connected",
+            "Users signed in now.",
+            self.clients.len(),
+        );
+        out.gauge(
+            "chat_rooms",
+            "Rooms that exist now, hidden ones included.",
+            self.rooms.len(),
+        );
+        out.gauge(
+            "chat_messages_per_second",
+            "Messages per second over the last minute.",
+            recent as f64 / MINUTE.as_secs_f64(),
+        );
+        out.counter(
+            "chat_messages_total",
+            "Messages delivered to rooms.",
+            load(&self.counters.messages),
+        );
+        out.counter(
+            "chat_bytes_sent_total",
+            "Bytes written to clients.",
+            load(&self.counters.bytes_sent),
+        );
+        out.counter(
+            "chat_bytes_received_total",
+            "Bytes read from signed-in clients.",
+            load(&self.counters.bytes_received),
+        );
+        out.counter(
+            "chat_filter_blocks_total",
+            "Messages refused by a filter.",
+            load(&self.counters.filter_blocks),
+        );
+        out.counter(
+            "chat_connections_total",
+            "Clients that signed in.",
+            load(&self.counters.connections),
+        );
+        out.gauge(
+            "chat_uptime_seconds",
+            "Seconds since the server started.",
+            self.started.elapsed().as_secs(),
+        );
+        out.finish()
+    }
+
+    /// The daily quota reset, run by the scheduler.
+    pub fn reset_quotas(&mut self) {
+        self.trust.reset_daily();
+    }
+
+    pub fn has_quotas(&self) -> bool {
+        self.trust.has_quotas()
+    }
+
+    /// Send out the day's summary and start counting a new day.
+    pub async fn daily_summary(&mut self) {
+        let online = self.clients.len();
+        let counters = self.daily.take(online);
+        let mut rooms: Vec<(String, usize)> = self
+            .rooms
+            .iter()
+            .map(|(_, room)| {
+                let messages = room.activity.lock().unwrap().window(DAY).messages;
+                (room.name.clone(), messages)
+            })
+            .filter(|(_, messages)| *messages > 0)
+            .collect();
+        rooms.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let text = DailyReport { counters, rooms }.render();
+
+        for target in self.config.daily_summary.clone() {
+            match target {
+                SummaryTarget::Room(name) => {
+                    let room_id = self.find_or_create_room(&name);
+                    self.send_room_system(room_id, text.clone()).await;
+                }
+                SummaryTarget::File(path) => {
+                    if let Err(e) = append_line(&path, &text).await {
+                        warn!(path = %path.display(), error = %e, "daily summary not written");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Figures for the whole server, for `/stats` and STATS frames.
+    pub fn server_stats(&self) -> ServerStats {
+        let load = |counter: &std::sync::atomic::AtomicU64| counter.load(Ordering::Relaxed);
+        ServerStats {
+            uptime: self.started.elapsed(),
+            users: self.clients.len(),
+            rooms: self.rooms.len(),
+            messages: load(&self.counters.messages),
+            bytes_received: load(&self.counters.bytes_received),
+            bytes_sent: load(&self.counters.bytes_sent),
+        }
+    }
+
+    /// Render the server's stats for `user_id`.
+    fn notify_server_stats(&self, user_id: UserId) {
+        let stats = self.server_stats();
+        let numbers = [
+            ("uptime", uptime(stats.uptime)),
+            ("users", stats.users.to_string()),
+            ("rooms", stats.rooms.to_string()),
+            ("messages", stats.messages.to_string()),
+            ("received", stats.bytes_received.to_string()),
+            ("sent", stats.bytes_sent.to_string()),
+        ];
+        let args: Vec<(&str, &str)> = numbers
+            .iter()
+            .map(|(name, value)| (*name, value.as_str()))
+            .collect();
+        self.notify(user_id, MsgId::ServerStats, &args);
+    }
+
+    /// Render a room's stats for `user_id`.
+    async fn notify_stats(&mut self, user_id: UserId, name: &str) {
+        let Some(stats) = self.room_stats(name).await else {
+            self.report(user_id, &ChatError::UnknownRoom(name.to_string()));
+            return;
+        };
+        let numbers = [
+            ("members", stats.members_now.to_string()),
+            ("total", stats.total_messages.to_string()),
+            ("msgs_hour", stats.last_hour.messages.to_string()),
+            ("speakers_hour", stats.last_hour.speakers.to_string()),
+            ("peak_hour", stats.last_hour.peak_members.to_string()),
+            ("msgs_day", stats.last_day.messages.to_string()),
+            ("speakers_day", stats.last_day.speakers.to_string()),
+            ("peak_day", stats.last_day.peak_members.to_string()),
+        ];
+        let mut args = vec![("room", stats.room.as_str())];
+        args.extend(numbers.iter().map(|(name, value)| (*name, value.as_str())));
+        self.notify(user_id, MsgId::RoomStats, &args);
+    }
+
+    /// `/list`: every room a user could find, with how many are in it,
+    /// and which one they're in. DM rooms stay out of it.
+    async fn list_rooms(&mut self, user_id: UserId, current_room: RoomId, pattern: Option<&str>) {
+        let matching: Vec<(RoomId, String)> = self
+            .rooms
+            .iter()
+            .filter(|(_, room)| !room.hidden)
+            .filter(|(_, room)| pattern.is_none_or(|p| room::name_matches(p, &room.name)))
+            .map(|(id, room)| (id, room.name.clone()))
+            .collect();
+        if matching.is_empty() {
+            let pattern = pattern.unwrap_or("*");
+            self.notify(user_id, MsgId::NoRoomsMatch, &[("pattern", pattern)]);
+            return;
+        }
+
+        let mut lines = vec![self.text_for(user_id, MsgId::RoomList, &[])];
+        for (room_id, name) in matching {
+            let members = self.rooms[room_id].member_ids().await.len().to_string();
+            let id = if room_id == current_room {
+                MsgId::RoomListHere
+            } else {
+                MsgId::RoomListEntry
+            };
+            lines.push(self.text_for(user_id, id, &[("room", &name), ("members", &members)]));
+        }
+        self.send_system(user_id, lines.join("\n"));
+    }
+
+    fn room_name(&self, room_id: RoomId) -> String {
+        self.rooms
+            .get(room_id)
+            .map(|r| r.name.clone())
+            .unwrap_or_else(|| "unknown".to_string())
+    }
+
+    /// Who `user_id`'s room roles belong to. See `ClientHandle::holder`.
+    fn holder(&self, user_id: UserId) -> Holder {
+        self.clients
+            .get(user_id)
+            .map(ClientHandle::holder)
+            .unwrap_or_else(|| Holder::Guest("unknown".to_string()))
+    }
+
+    fn client_name(&self, user_id: UserId) -> String {
+        self.clients
+            .get(user_id)
+            .map(|c| c.username.clone())
+            .unwrap_or_else(|| "unknown".to_string())
+    }
+
+    /// The name a newcomer gets: the one they asked for if nobody online
+    /// has it, otherwise whatever `duplicate_names` says.
+    fn claim_name(&self, name: String) -> Result<String, ChatError> {
+        if !self.name_taken(&name) {
+            return Ok(name);
+        }
+        if self.config.duplicate_names == DuplicateNames::Reject {
+            return Err(ChatError::NickInUse(name));
+        }
+        // Someone else's registered name is no more free than a taken one.
+        let mut n = 2;
+        loop {
+            let candidate = format!("{name}{n}");
+            if !self.name_taken(&candidate) && !self.is_reserved(&candidate) {
+                return Ok(candidate);
+            }
+            n += 1;
+        }
+    }
+
+    /// Someone online has `name`, or a dropped session is holding it for
+    /// its client to come back to.
+    fn name_taken(&self, name: &str) -> bool {
+        self.find_client_by_name(name).is_some() || self.resumes.holds(name)
+    }
+
+    /// Names with an account, or a token, aren't for guests to take.
+    fn is_reserved(&self, name: &str) -> bool {
+        self.accounts.is_registered(name) || self.tokens.holds(name)
+    }
+
+    /// Registered names belong to whoever signed in to them.
+    fn may_use_name(&self, user_id: UserId, name: &str) -> bool {
+        !self.is_reserved(name)
+            || self
+                .clients
+                .get(user_id)
+                .is_some_and(|c| c.account.as_deref() == Some(name))
+    }
+
+    /// `/nick` and NICK frames. Returns false, having told the user why,
+    /// if the name can't be had.
+    async fn change_nick(&mut self, user_id: UserId, new_name: String) -> bool {
+        if let Err(e) = self.config.names.check(&new_name) {
+            self.report(user_id, &e);
+            return false;
+        }
+        if self
+            .find_client_by_name(&new_name)
+            .is_some_and(|id| id != user_id)
+            || self.resumes.holds(&new_name)
+        {
+            self.report(user_id, &ChatError::NickInUse(new_name));
+            return false;
+        }
+        if !self.may_use_name(user_id, &new_name) {
+            self.report(user_id, &ChatError::NameRegistered(new_name));
+            return false;
+        }
+        let old = self.client_name(user_id);
+        self.set_client_name(user_id, new_name.clone()).await;
+        self.notify(
+            user_id,
+            MsgId::NickChanged,
+            &[("new", &new_name), ("old", &old)],
+        );
+        true
+    }
+
+    /// Rename a user and let the room they're in know who they are now.
+    async fn set_client_name(&mut self, user_id: UserId, name: String) {
+        let Some(client) = self.clients.get_mut(user_id) else {
+            return;
+        };
+        let old = std::mem::replace(&mut client.username, name.clone());
+        if client.account.is_none() {
+            self.read_markers.rename(&old, &name);
+            for (_, room) in self.rooms.iter_mut() {
+                room.rename_guest(&old, &name);
+                if let Some(poll) = &mut room.poll {
+                    poll.rename(&old, &name);
+                }
+            }
+        }
+        for (_, client) in self.clients.iter_mut() {
+            if client.ignored.remove(&old) {
+                client.ignored.insert(name.clone());
+            }
+        }
+        self.sessions.rename(user_id, &name);
+        self.trust.seen(&name);
+
+        // Once per person, however many of their rooms they share.
+        let mut members = Vec::new();
+        for room_id in self.joined_rooms(user_id) {
+            if let Some(room) = self.rooms.get(room_id) {
+                for member_id in room.member_ids().await {
+                    if !members.contains(&member_id) {
+                        members.push(member_id);
+                    }
+                }
+            }
+        }
+        let args = [("user", name.as_str()), ("old", old.as_str())];
+        self.announce_presence(&members, user_id, MsgId::NickAnnounce, &args);
+
+        self.publish(ServerEvent::NickChanged {
+            user_id,
+            old,
+            new: name,
+        });
+    }
+}
+
+/// Turn the answer to the username prompt into a name, the account
+/// signed in to if any, and the role a token grants. LOGIN: and
+/// REGISTER: frames are checked against the accounts, AUTH: against the
+/// tokens; a bare name is a guest, who may not take a registered one.
+/// New names must pass `NameRules`; a LOGIN doesn't, so tightening the
+/// rules never locks an existing account out.
+///
+/// Hashing is slow by design, so it runs on the blocking pool and never
+/// under the server lock. A token needs no such care: see `Tokens`.
+///
+/// A REGISTER isn't kept yet: the account is created once the name is
+/// past the ban screen, so a banned name can't register itself.
+async fn sign_in(server: &Arc<Mutex<Server>>, answer: String) -> Result<SignedIn, ChatError> {
+    match protocol::parse_frame(&answer) {
+        Ok(Frame::Auth { token }) => {
+            let srv = server.lock().await;
+            let grant = srv.tokens.check(&token).ok_or(ChatError::AuthFailed)?;
+            let username = grant.username.clone();
+            Ok(SignedIn {
+                username: username.clone(),
+                account: Some(username),
+                token_role: Some(grant.role),
+                resumed: None,
+                registering: None,
+            })
+        }
+        Ok(Frame::Resume { token }) => {
+            let parked = server
+                .lock()
+                .await
+                .resumes
+                .take(&token)
+                .ok_or(ChatError::AuthFailed)?;
+            Ok(SignedIn {
+                username: parked.username.clone(),
+                account: parked.account.clone(),
+                token_role: None,
+                resumed: Some(parked),
+                registering: None,
+            })
+        }
+        Ok(Frame::Login { username, password }) => {
+            let credentials = server.lock().await.accounts.credentials(&username);
+            let verified = match credentials {
+                Some(credentials) => verify_password(credentials, password.into_owned()).await,
+                None => false,
+            };
+            if !verified {
+                return Err(ChatError::AuthFailed);
+            }
+            Ok(SignedIn::account(username.into_owned()))
+        }
+        Ok(Frame::Register { username, password }) => {
+            let srv = server.lock().await;
+            srv.config.names.check(&username)?;
+            if srv.accounts.is_registered(&username) {
+                return Err(ChatError::NameRegistered(username.into_owned()));
+            }
+            drop(srv);
+            if password.chars().count() < auth::MIN_PASSWORD {
+                return Err(ChatError::Parse(format!(
+                    "passwords need at least {} characters",
+                    auth::MIN_PASSWORD
+                )));
+            }
+            let credentials = hash_password(password.into_owned()).await?;
+            Ok(SignedIn {
+                registering: Some(credentials),
+                ..SignedIn::account(username.into_owned())
+            })
+        }
+        _ => {
+            let srv = server.lock().await;
+            srv.config.names.check(&answer)?;
+            if srv.is_reserved(&answer) {
+                return Err(ChatError::NameRegistered(answer));
+            }
+            Ok(SignedIn {
+                username: answer,
+                account: None,
+                token_role: None,
+                resumed: None,
+                registering: None,
+            })
+        }
+    }
+}
+
+/// Who a client signed in as, and how.
+struct SignedIn {
+    username: String,
+    /// The account signed in to. None for a guest.
+    account: Option<String>,
+    /// An AUTH token's role, which stands as issued.
+    token_role: Option<Role>,
+    /// What RESUME brought back.
+    resumed: Option<Parked>,
+    /// A REGISTER's new account, to create once past the ban screen.
+    registering: Option<Credentials>,
+}
+
+impl SignedIn {
+    fn account(username: String) -> Self {
+        Self {
+            username: username.clone(),
+            account: Some(username),
+            token_role: None,
+            resumed: None,
+            registering: None,
+        }
+    }
+}
+
+/// Hash a password on the blocking pool, where slow work belongs.
+async fn hash_password(password: String) -> Result<Credentials, ChatError> {
+    tokio::task::spawn_blocking(move || Credentials::new(&password))
+        .await
+        .map_err(|e| ChatError::Config(format!("password hashing: {e}")))?
+}
+
+/// Check a password on the blocking pool. A failed task is a wrong
+/// password: nobody gets in by accident.
+async fn verify_password(credentials: Credentials, password: String) -> bool {
+    tokio::task::spawn_blocking(move || credentials.verify(&password))
+        .await
+        .unwrap_or(false)
+}
+
+/// The names `@mentioned` in a message, as they'd be typed in a
+/// sentence: `@bob,` and `@bob:` are both bob.
+fn mentions(body: &str) -> impl Iterator<Item = &str> {
+    body.split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .map(|name| name.trim_end_matches([',', ':', ';', '.', '!', '?']))
+        .filter(|name| !name.is_empty())
+}
+
+/// For a duration the scheduler can't count to.
+fn too_far_off(when: &str) -> ChatError {
+    ChatError::Parse(format!("{when} is too far off to schedule"))
+}
+
+/// How long the server has been up, roughly: "3d 4h", "2h 15m", "40s".
+fn uptime(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    let (days, hours, mins) = (secs / 86_400, secs / 3_600 % 24, secs / 60 % 60);
+    match (days, hours, mins) {
+        (0, 0, 0) => format!("{secs}s"),
+        (0, 0, _) => format!("{mins}m"),
+        (0, _, _) => format!("{hours}h {mins}m"),
+        _ => format!("{days}d {hours}h"),
+    }
+}
+
+/// Run a client session over an in-memory pipe instead of a socket,
+/// and hand back the client's end: write lines to it, read what the
+/// server says. Everything past the accept — handshake, commands,
+/// broadcast — is the code a TCP client gets, so a test can drive it
+/// with `cargo test` and no ports, and a program embedding the server
+/// can talk to it in-process.
+///
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use rust_chat_server::{Server, ServerConfig, server};
+/// # use tokio::io::AsyncWriteExt;
+/// # use tokio::sync::Mutex;
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let config = ServerConfig::builder().build();
+/// let server = Arc::new(Mutex::new(Server::new(config)?));
+/// let mut alice = server::connect_local(&server, "127.0.0.1:50000".parse()?);
+/// alice.write_all(b"alice\n").await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Connection limits belong to the listener, so they don't apply here.
+/// Bans do: `peer` is the address they, and the logs, see. Nor does
+/// anything timed happen unless the scheduler runs: see
+/// `spawn_scheduler`.
+pub fn connect_local(server: &Arc<Mutex<Server>>, peer: SocketAddr) -> DuplexStream {
+    let (stream, client) = ClientStream::pipe(peer);
+    let server = Arc::clone(server);
+    let span = tracing::info_span!("conn", %peer, user = tracing::field::Empty);
+    let session = async move {
+        if let Err(e) = handle_client(server, stream, PendingGuard::detached()).await {
+            warn!(error = %e, "client error");
+        }
+    };
+    tokio::spawn(session.instrument(span));
+    client
+}
+
+/// Drive `server`'s scheduler — mute expiry, reminders, room expiry,
+/// resume timeouts — on a task of its own. `run` does this; a server
+/// only `connect_local` talks to needs it done by hand. Abort the
+/// handle to stop it.
+pub fn spawn_scheduler(server: &Arc<Mutex<Server>>) -> JoinHandle<()> {
+    tokio::spawn(scheduler::run(Arc::clone(server)))
+}
+
+/// Handle a single client as a tokio task.
+pub async fn handle_client(
+    server: Arc<Mutex<Server>>,
+    stream: ClientStream,
+    pending: PendingGuard,
+) -> Result<(), ChatError> {
+    // Hooks are cloned out so the lock isn't held while they talk to
+    // the client — a slow human must not stall the whole server.
+    let (
+        banner,
+        hooks,
+        handshake_timeout,
+        decoding,
+        max_line,
+        socket,
+        offered,
+        files,
+        prompt,
+        timed_out,
+    ) = {
+        let srv = server.lock().await;
+        (
+            srv.config.banner.clone(),
+            srv.handshake_hooks.clone(),
+            srv.config.handshake_timeout,
+            srv.config.decoding,
+            srv.config.max_line(),
+            srv.config.socket,
+            Caps::offered(srv.config.compression, srv.config.resume_grace.is_some()),
+            srv.config.files,
+            srv.text(MsgId::EnterUsername, &[]),
+            srv.text(MsgId::HandshakeTimeout, &[]),
+        )
+    };
+
+    let mut io = HandshakeIo::new(stream, decoding, max_line);
+    let peer = io.peer;
+
+    if let Some(banner) = banner
+        && let Some(text) = banner.text().await
+    {
+        io.send(&text).await?;
+    }
+
+    if !handshake::run_hooks(&hooks, &mut io, Stage::PrePrompt).await? {
+        return Ok(());
+    }
+
+    // A connection that never answers would otherwise hold its task
+    // (and socket) forever. Client programs may ask for capabilities
+    // first (HELLO: for the list, CAP: to pick), each line answered
+    // before the next is read.
+    let mut caps = Caps::default();
+    let negotiate = async {
+        let mut answer = io.ask(&prompt).await?;
+        let negotiating = |a: &String| a.starts_with("CAP:") || a.starts_with("HELLO:");
+        while let Some(line) = answer.as_ref().filter(|a| negotiating(a)) {
+            match protocol::parse_frame(line) {
+                Ok(Frame::Hello) => io.send(&protocol::encode_caps(&offered)).await?,
+                Ok(Frame::Cap { caps: asked }) => {
+                    let compressed = caps.compression;
+                    let mut accepted = Vec::new();
+                    for cap in asked {
+                        if let Some(&known) = offered.iter().find(|&&known| known == cap)
+                            && !accepted.contains(&known)
+                            && caps.enable(known)
+                        {
+                            accepted.push(known);
+                        }
+                    }
+                    // The answer still goes as a line: the client can't
+                    // know it's been agreed to until it reads it.
+                    io.send(&protocol::encode_cap(&accepted)).await?;
+                    io.set_framing(caps.framing());
+                    // Once compressed, a connection stays so.
+                    if let (None, Some(kind)) = (compressed, caps.compression) {
+                        io.compress(kind);
+                    }
+                }
+                _ => io.send(&protocol::encode_cap(&[])).await?,
+            }
+            answer = io.read_line().await?;
+        }
+        Ok::<_, ChatError>(answer)
+    };
+    let answer = tokio::time::timeout(handshake_timeout, negotiate).await;
+    let Ok(answer) = answer else {
+        io.send(&timed_out).await?;
+        return Ok(());
+    };
+    let Some(answer) = answer? else {
+        return Ok(());
+    };
+    if answer.is_empty() {
+        return Ok(());
+    }
+    let SignedIn {
+        username,
+        account,
+        token_role,
+        resumed,
+        registering,
+    } = match sign_in(&server, answer).await {
+        Ok(signed_in) => signed_in,
+        Err(e) => {
+            let line = server.lock().await.error_line(&e, None);
+            io.send(&line).await?;
+            return Ok(());
+        }
+    };
+
+    if !handshake::run_hooks(&hooks, &mut io, Stage::PostUsername(&username)).await? {
+        return Ok(());
+    }
+
+    // Banned names never get further; a new name from a banned address
+    // is flagged to operators (and refused, if configured).
+    let refusal = server.lock().await.screen(&username, peer.ip());
+    if let Some(refusal) = refusal {
+        io.send(&refusal).await?;
+        return Ok(());
+    }
+    if let Some(credentials) = registering {
+        let mut srv = server.lock().await;
+        if let Err(e) = srv.register_account(&username, credentials).await {
+            let line = srv.error_line(&e, None);
+            drop(srv);
+            io.send(&line).await?;
+            return Ok(());
+        }
+    }
+
+    if !handshake::run_hooks(&hooks, &mut io, Stage::PreJoin(&username)).await? {
+        return Ok(());
+    }
+
+    // Handshake done — free the pending slot for the next newcomer.
+    drop(pending);
+
+    let (mut reader, mut writer) = io.into_parts();
+
+    // Register and join lobby. The name is claimed under the same lock
+    // as the registration, so two newcomers can't both be "alice".
+    let mut srv = server.lock().await;
+    // The name's owner is back afresh: whatever dropped is let go.
+    if account.is_some() && resumed.is_none() {
+        srv.resumes.forget(&username);
+    }
+    let username = match srv.claim_name(username) {
+        Ok(username) => username,
+        Err(e) => {
+            let line = srv.error_line(&e, None);
+            drop(srv);
+            writer
+                .write_all(&caps.framing().encode(&format!("{line}\n")))
+                .await?;
+            // Pushes it out of a compressor, too.
+            writer.flush().await?;
+            return Ok(());
+        }
+    };
+    let (user_id, rx, motd, user_count, uptime, welcome, stamps, drop_if_slow, counters) = {
+        let (uid, rx) = srv.register_client(username.clone(), peer, account);
+        if let Some(role) = token_role {
+            let client = &mut srv.clients[uid];
+            client.role = role;
+            client.by_token = true;
+        }
+        tracing::Span::current().record("user", tracing::field::display(uid));
+        srv.publish(ServerEvent::UserConnected {
+            user_id: uid,
+            username: username.clone(),
+            peer,
+        });
+        let motd = srv.config.motd.clone();
+        let user_count = srv.clients.len().to_string();
+        let uptime = uptime(srv.started.elapsed());
+        let stamps = srv.config.timestamp_format.clone();
+        let drop_if_slow = srv.config.disconnect_slow_clients;
+        let counters = Arc::clone(&srv.counters);
+        if caps.resume {
+            match srv.resumes.issue(uid) {
+                Ok(token) => srv.send_frame(uid, protocol::encode_session(&token)),
+                Err(e) => warn!(error = %e, "no session token"),
+            }
+        }
+        let welcome = match resumed {
+            Some(parked) => {
+                srv.resume(uid, parked).await;
+                let room = srv.room_name(srv.active_room(uid));
+                srv.text_for(
+                    uid,
+                    MsgId::WelcomeBack,
+                    &[("user", &username), ("room", &room)],
+                )
+            }
+            None => {
+                let lobby = srv.lobby;
+                srv.join_room(uid, lobby).await;
+                srv.text_for(
+                    uid,
+                    MsgId::Welcome,
+                    &[("user", &username), ("room", "lobby")],
+                )
+            }
+        };
+        (
+            uid,
+            rx,
+            motd,
+            user_count,
+            uptime,
+            welcome,
+            stamps,
+            drop_if_slow,
+            counters,
+        )
+    };
+    drop(srv);
+
+    info!(%username, "connected");
+
+    // Numbering starts with the first line after the handshake.
+    let (framing, numbered) = (caps.framing(), caps.seq);
+    let mut sequencer = numbered.then(Sequencer::default);
+    let mut greeting = String::new();
+    if let Some(motd) = motd
+        && let Some(text) = motd.text().await
+    {
+        let args = [
+            ("username", username.as_str()),
+            ("user_count", user_count.as_str()),
+            ("uptime", uptime.as_str()),
+        ];
+        greeting.push_str(&format!("{}\n", i18n::fill(&text, &args)));
+    }
+    greeting.push_str(&format!("{welcome}\n"));
+    if caps.json {
+        greeting = greeting
+            .lines()
+            .map(|line| render::json(&Event::System(line.to_string())))
+            .collect();
+    }
+    if let Some(sequencer) = &mut sequencer {
+        greeting = sequencer.number(&greeting, caps.json);
+    }
+    let greeting = framing.encode(&greeting);
+    writer.write_all(&greeting).await?;
+    writer.flush().await?;
+    counters
+        .bytes_sent
+        .fetch_add(greeting.len() as u64, Ordering::Relaxed);
+
+    let received = Arc::clone(&counters);
+
+    // Spawn a writer task — reads from the broadcast receiver.
+    // Delivery is where per-user preferences apply: the server sends
+    // everyone the same events, and each writer decides what to show.
+    //
+    // The task ends with a reason only when the client can't be written
+    // to any more; the reader loop below watches for that.
+    let settings = Arc::new(Settings::default());
+    settings.json.store(caps.json, Ordering::Relaxed);
+    let outbox = Outbox {
+        writer,
+        rx,
+        settings: Arc::clone(&settings),
+        sequencer,
+        framing,
+        stamps,
+        write_timeout: socket.write_timeout,
+        counters,
+        drop_if_slow,
+    };
+    let mut writer_task = tokio::spawn(outbox.run().in_current_span());
+
+    // Reader loop. Every way out of it says why, so cleanup below
+    // runs exactly once whatever happened.
+    let connected_at = Instant::now();
+    let mut session = Session {
+        server: Arc::clone(&server),
+        user_id,
+        name: username,
+        numbered,
+        files,
+        settings,
+        keepalive: KeepAlive::new(socket.ping_interval, socket.pong_timeout),
+        upload: None,
+    };
+
+    let reason = loop {
+        let read = async {
+            match socket.read_timeout {
+                Some(limit) => tokio::time::timeout(limit, reader.read_line()).await.ok(),
+                None => Some(reader.read_line().await),
+            }
+        };
+        let line = tokio::select! {
+            read = read => match read {
+                Some(Ok(Some(line))) => line,
+                Some(Ok(None)) => break DisconnectReason::Closed,
+                Some(Err(e)) => break DisconnectReason::Error(e.to_string()),
+                None => {
+                    // Say why before hanging up: the writer delivers the
+                    // notice, then stops at the Close.
+                    let secs = socket.read_timeout.unwrap_or_default().as_secs().to_string();
+                    server.lock().await.close(
+                        user_id,
+                        DisconnectReason::Idle,
+                        MsgId::IdleDisconnect,
+                        &[("secs", &secs)],
+                    );
+                    let _ = (&mut writer_task).await;
+                    break DisconnectReason::Idle;
+                }
+            },
+            // The writer gave up on this client, so should we.
+            written = &mut writer_task => {
+                break written.unwrap_or_else(|e| DisconnectReason::Error(e.to_string()));
+            }
+            _ = session.keepalive.wait() => match session.keepalive.fire() {
+                Due::Ping(token) => {
+                    let ping = protocol::encode_ping(&token.to_string());
+                    server.lock().await.send_frame(user_id, ping);
+                    continue;
+                }
+                // Likely half-open, so unlike an idle disconnect, don't
+                // wait on the writer to deliver the notice.
+                Due::Expired => {
+                    let secs = socket.pong_timeout.as_secs().to_string();
+                    server.lock().await.close(
+                        user_id,
+                        DisconnectReason::Unresponsive,
+                        MsgId::PingTimeout,
+                        &[("secs", &secs)],
+                    );
+                    break DisconnectReason::Unresponsive;
+                }
+            },
+        };
+        session.keepalive.heard();
+        received
+            .bytes_received
+            .fetch_add(line.len() as u64, Ordering::Relaxed);
+
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if let Some(reason) = session.handle(trimmed).await {
+            break reason;
+        }
+    };
+
+    // Cleanup.
+    let current_name = session.name;
+    info!(username = %current_name, %reason, "disconnected");
+    server
+        .lock()
+        .await
+        .disconnect(user_id, current_name, connected_at.elapsed(), reason)
+        .await;
+
+    writer_task.abort();
+
+    Ok(())
+}
+
+/// The writer task's end of a connection: every event for this client
+/// goes out through here, rendered the way the client asked.
+struct Outbox {
+    writer: BoxedWriter,
+    rx: broadcast::Receiver<Event>,
+    /// Shared with the reader, where /set changes them.
+    settings: Arc<Settings>,
+    sequencer: Option<Sequencer>,
+    framing: Framing,
+    stamps: Option<String>,
+    write_timeout: Option<Duration>,
+    counters: Arc<Counters>,
+    drop_if_slow: bool,
+}
+
+impl Outbox {
+    /// Write until the client can't be written to any more, and say why.
+    async fn run(mut self) -> DisconnectReason {
+        loop {
+            let event = match self.rx.recv().await {
+                Ok(event) => event,
+                // Too slow to keep up: the queue overflowed and the
+                // oldest lines are gone. Either skip them and carry on,
+                // or stop spending a queue on this client.
+                Err(broadcast::error::RecvError::Lagged(_)) if self.drop_if_slow => {
+                    return DisconnectReason::TooSlow;
+                }
+                // Each lost event is counted as one line, so the gap
+                // shows in the numbering. An event that would have
+                // rendered as several lines comes up short; the gap is
+                // there either way.
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    if let Some(sequencer) = &mut self.sequencer {
+                        sequencer.skip(missed);
+                    }
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => return DisconnectReason::Closed,
+            };
+            if let Event::Close(reason) = event {
+                let _ = self.writer.flush().await;
+                return reason;
+            }
+            if matches!(event, Event::Presence(_)) && self.settings.quiet.load(Ordering::Relaxed) {
+                continue;
+            }
+            if matches!(event, Event::Mention { here: false, .. })
+                && !self.settings.mentions.load(Ordering::Relaxed)
+            {
+                continue;
+            }
+            let json = self.settings.json.load(Ordering::Relaxed);
+            let line = match event {
+                Event::Resend(acked) => match &mut self.sequencer {
+                    Some(sequencer) => sequencer.resend(acked),
+                    None => continue,
+                },
+                event => {
+                    let line = if json {
+                        render::json(&event)
+                    } else {
+                        let color = self.settings.color.load(Ordering::Relaxed);
+                        let multiline = self.framing == Framing::LengthPrefixed;
+                        render::line(&event, color, multiline, self.stamps.as_deref())
+                    };
+                    match &mut self.sequencer {
+                        Some(sequencer) => sequencer.number(&line, json),
+                        None => line,
+                    }
+                }
+            };
+            if line.is_empty() {
+                continue;
+            }
+
+            // A client that stops reading fills its socket buffer and
+            // would block this write forever. The flush matters for TLS,
+            // which buffers inside the encryption layer.
+            let bytes = self.framing.encode(&line);
+            let write = async {
+                self.writer.write_all(&bytes).await?;
+                self.writer.flush().await
+            };
+            let written = match self.write_timeout {
+                Some(limit) => match tokio::time::timeout(limit, write).await {
+                    Ok(written) => written,
+                    Err(_) => return DisconnectReason::Timeout,
+                },
+                None => write.await,
+            };
+            if let Err(e) = written {
+                return DisconnectReason::Error(e.to_string());
+            }
+            self.counters
+                .bytes_sent
+                .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Frames a signed-in client may send. Any other line is chat, colon
+/// or no colon: "note: back at 3" is someone talking.
+const SESSION_FRAMES: &[&str] = &[
+    "PING",
+    "PONG",
+    "ACK",
+    "STATS",
+    "HISTORY",
+    "EMSG",
+    "PRIV",
+    "EDIT",
+    "DELETE",
+    "NICK",
+    "PROTO",
+    "JOINCODE",
+    "FILE_START",
+    "FILE_CHUNK",
+    "FILE_END",
+    "FILE_GET",
+];
+
+/// Frames that don't cost a command's allowance: the answer to our own
+/// PING, chat the message allowance already covers, and the pieces of
+/// a file, which the size limit covers. Every other frame asks the
+/// server to do something, so it's held to what a command is.
+const UNMETERED_FRAMES: &[&str] = &["PONG", "EMSG", "FILE_CHUNK"];
+
+/// A signed-in connection, as the reader loop sees it: what it keeps
+/// between lines, and what each kind of line does. Every handler takes
+/// the server lock itself, for as long as it needs it.
+struct Session {
+    server: Arc<Mutex<Server>>,
+    user_id: UserId,
+    /// Kept up with /nick, for the log and plugin commands.
+    name: String,
+    /// The client asked for CAP:seq, so ACK: means something.
+    numbered: bool,
+    files: Option<FileLimits>,
+    /// Shared with the writer task, which applies them.
+    settings: Arc<Settings>,
+    keepalive: KeepAlive,
+    /// A file on its way in. Any mistake drops it: start again.
+    upload: Option<Upload>,
+}
+
+impl Session {
+    /// Act on one line from the client. A reason means the session is
+    /// over.
+    async fn handle(&mut self, line: &str) -> Option<DisconnectReason> {
+        if line.starts_with('/') {
+            return self.command(line).await;
+        }
+        let Some((name, _)) = line
+            .split_once(':')
+            .filter(|(name, _)| SESSION_FRAMES.contains(name))
+        else {
+            self.chat(line).await;
+            return None;
+        };
+        if !UNMETERED_FRAMES.contains(&name) {
+            let mut srv = self.server.lock().await;
+            if !self.allow_command(&mut srv) {
+                return None;
+            }
+        }
+        match protocol::parse_frame(line) {
+            Ok(frame) => self.frame(frame).await,
+            Err(e) => {
+                if line.starts_with("FILE_") {
+                    self.upload = None;
+                }
+                self.server.lock().await.report(self.user_id, &e);
+            }
+        }
+        None
+    }
+
+    /// Protocol requests from client programs, rather than people.
+    async fn frame(&mut self, frame: Frame<'_>) {
+        match frame {
+            Frame::Ping { token } => self.ping(&token).await,
+            Frame::Pong { .. } => self.keepalive.enable(),
+            Frame::Ack { seq } => self.ack(seq).await,
+            Frame::Stats => self.stats().await,
+            Frame::History {
+                room,
+                before,
+                limit,
+            } => {
+                let mut srv = self.server.lock().await;
+                srv.send_history(self.user_id, &room, before, limit).await;
+            }
+            Frame::EMsg { to, payload } => {
+                let mut srv = self.server.lock().await;
+                if srv.allow_message(self.user_id) {
+                    srv.route_encrypted(self.user_id, &to, &payload);
+                }
+            }
+            Frame::Priv { to, body } => self.private_message(&to, &body).await,
+            Frame::Edit { room, id, body } => self.edit(&room, id, &body).await,
+            Frame::Delete { room, id } => self.delete(&room, id).await,
+            Frame::Nick { name } => self.nick(&name).await,
+            Frame::Proto { format } => self.proto(format).await,
+            Frame::JoinCode { code } => self.join_code(&code).await,
+            Frame::FileStart { .. }
+            | Frame::FileChunk { .. }
+            | Frame::FileEnd
+            | Frame::FileGet { .. } => self.file(frame).await,
+            // Sign-in frames: too late for those now.
+            _ => {}
+        }
+    }
+
+    /// Spend a command's worth of allowance, or say there's none left.
+    fn allow_command(&self, srv: &mut Server) -> bool {
+        if srv.command_limits.check(self.user_id) {
+            return true;
+        }
+        srv.report(self.user_id, &ChatError::RateLimited { what: "commands" });
+        false
+    }
+
+    async fn ping(&mut self, token: &str) {
+        self.keepalive.enable();
+        let pong = protocol::encode_pong(token);
+        self.server.lock().await.send_frame(self.user_id, pong);
+    }
+
+    async fn ack(&self, seq: u64) {
+        let srv = self.server.lock().await;
+        if self.numbered {
+            srv.resend(self.user_id, seq);
+        } else {
+            let e = ChatError::Parse("ACK needs CAP:seq at sign-in".into());
+            srv.report(self.user_id, &e);
+        }
+    }
+
+    async fn stats(&self) {
+        let srv = self.server.lock().await;
+        if srv.authorize(self.user_id, "stats") {
+            srv.send_frame(self.user_id, protocol::encode_stats(&srv.server_stats()));
+        }
+    }
+
+    /// Held to what /msg is: whatever the permissions say about "msg".
+    async fn private_message(&self, to: &str, body: &str) {
+        let mut srv = self.server.lock().await;
+        if srv.authorize(self.user_id, "msg") {
+            srv.direct_message(self.user_id, to, body).await;
+        }
+    }
+
+    /// As /edit, with the room always named.
+    async fn edit(&self, room: &str, id: u64, body: &str) {
+        let mut srv = self.server.lock().await;
+        if srv.authorize(self.user_id, "edit") {
+            let current_room = srv.active_room(self.user_id);
+            srv.edit_message(self.user_id, current_room, Some(room), id, body)
+                .await;
+        }
+    }
+
+    /// As /delete, with the room always named.
+    async fn delete(&self, room: &str, id: u64) {
+        let mut srv = self.server.lock().await;
+        if srv.authorize(self.user_id, "delete") {
+            let current_room = srv.active_room(self.user_id);
+            srv.delete_message(self.user_id, current_room, Some(room), id)
+                .await;
+        }
+    }
+
+    async fn nick(&mut self, name: &str) {
+        let mut srv = self.server.lock().await;
+        if srv.change_nick(self.user_id, name.to_string()).await {
+            self.name = name.to_string();
+        }
+    }
+
+    async fn proto(&mut self, format: WireFormat) {
+        self.keepalive.enable();
+        self.settings
+            .json
+            .store(format == WireFormat::Json, Ordering::Relaxed);
+        let format = format.to_string();
+        self.server.lock().await.notify(
+            self.user_id,
+            MsgId::SettingChanged,
+            &[("setting", "proto"), ("value", &format)],
+        );
+    }
+
+    async fn join_code(&self, code: &str) {
+        let mut srv = self.server.lock().await;
+        if let Some(room_id) = srv.redeem_invite(self.user_id, code) {
+            srv.save_rooms(self.user_id).await;
+            let room = srv.room_name(room_id);
+            srv.notify(self.user_id, MsgId::YouJoined, &[("room", &room)]);
+            srv.join_room(self.user_id, room_id).await;
+        }
+    }
+
+    /// FILE_START, FILE_CHUNK and FILE_END bring a file in a piece at a
+    /// time; FILE_GET fetches one someone shared.
+    async fn file(&mut self, frame: Frame<'_>) {
+        let Some(limits) = self.files else {
+            let off = ChatError::Parse("file sharing is off on this server".into());
+            self.server.lock().await.report(self.user_id, &off);
+            return;
+        };
+        let result = match frame {
+            Frame::FileStart { name, size } => {
+                Upload::start(&name, size, limits).map(|started| self.upload = Some(started))
+            }
+            Frame::FileChunk { data } => match &mut self.upload {
+                Some(upload) => upload.push(&data),
+                None => Err(ChatError::Parse("FILE_CHUNK before FILE_START".into())),
+            },
+            Frame::FileEnd => match self.upload.take() {
+                Some(finished) => {
+                    let mut srv = self.server.lock().await;
+                    if srv.allow_message(self.user_id) {
+                        srv.share_file(self.user_id, finished).await;
+                    }
+                    Ok(())
+                }
+                None => Err(ChatError::Parse("FILE_END before FILE_START".into())),
+            },
+            Frame::FileGet { token } => {
+                let mut srv = self.server.lock().await;
+                srv.fetch_file(self.user_id, &token).await;
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            self.upload = None;
+            self.server.lock().await.report(self.user_id, &e);
+        }
+    }
+
+    /// Plain text: said in the room the user is talking in.
+    async fn chat(&self, body: &str) {
+        let mut srv = self.server.lock().await;
+        let current_room = srv.active_room(self.user_id);
+        // Kicked from the lobby, with nowhere else to talk.
+        if !srv.joined_rooms(self.user_id).contains(&current_room) {
+            let room = srv.room_name(current_room);
+            srv.notify(self.user_id, MsgId::NotJoined, &[("room", &room)]);
+            return;
+        }
+        if !srv.allow_message(self.user_id) {
+            return;
+        }
+        srv.broadcast_message(current_room, self.user_id, &self.name, body)
+            .await;
+    }
+
+    /// A slash command, built in or a plugin's.
+    async fn command(&mut self, line: &str) -> Option<DisconnectReason> {
+        let user_id = self.user_id;
+        let mut srv = self.server.lock().await;
+        let current_room = srv.active_room(user_id);
+
+        // /quit always gets through: refusing to let someone leave
+        // is no way to slow them down.
+        if line != "/quit" && !self.allow_command(&mut srv) {
+            return None;
+        }
+        if !srv.authorize(user_id, Command::name(line)) {
+            return None;
+        }
+
+        // Built-in commands first; anything the parser doesn't know
+        // gets a chance in the plugin registry before it's an error.
+        let result = match Command::parse(line) {
+            Ok(cmd) => cmd.execute(current_room),
+            Err(e) => {
+                let ctx = CommandContext {
+                    user_id,
+                    username: self.name.clone(),
+                    room_id: current_room,
+                    room: srv.room_name(current_room),
+                };
+                match srv.commands.dispatch(line, &ctx) {
+                    Some(result) => result,
+                    None => {
+                        srv.report(user_id, &e);
+                        return None;
+                    }
+                }
+            }
+        };
+
+        match result {
+            // These let go of the lock while they hash.
+            CommandResult::JoinRoom { room, password } => {
+                drop(srv);
+                self.join(room, password).await;
+            }
+            CommandResult::SetPassword { room_id, password } => {
+                drop(srv);
+                self.set_password(room_id, password).await;
+            }
+            CommandResult::LeaveRoom { room } => {
+                srv.leave(user_id, room.as_deref()).await;
+            }
+            CommandResult::SwitchRoom { room } => match srv.find_room_by_name(&room) {
+                Some(room_id) => srv.switch_room(user_id, room_id),
+                None => srv.report(user_id, &ChatError::UnknownRoom(room)),
+            },
+            CommandResult::ChangeNick { new_name } => {
+                if srv.change_nick(user_id, new_name.clone()).await {
+                    self.name = new_name;
+                }
+            }
+            CommandResult::KickUser {
+                target,
+                room_id,
+                reason,
+            } => {
+                srv.kick(user_id, &target, room_id, reason).await;
+            }
+            CommandResult::RoomBan {
+                target,
+                room_id,
+                reason,
+            } => {
+                srv.room_ban(user_id, &target, room_id, reason).await;
+            }
+            CommandResult::RoomUnban { target, room_id } => {
+                srv.room_unban(user_id, &target, room_id).await;
+            }
+            CommandResult::SetRoomOperator {
+                target,
+                room_id,
+                on,
+            } => {
+                srv.set_room_operator(user_id, &target, room_id, on).await;
+            }
+            CommandResult::Topic { room_id, text } => {
+                srv.topic(user_id, room_id, text).await;
+            }
+            CommandResult::MuteUser { target, duration } => {
+                srv.mute_user(user_id, &target, duration);
+            }
+            CommandResult::Oper { password } => match srv.oper(user_id, &password) {
+                Some(role) => {
+                    let role = role.to_string();
+                    srv.notify(user_id, MsgId::OperGranted, &[("role", &role)]);
+                }
+                None => srv.report(user_id, &ChatError::AuthFailed),
+            },
+            CommandResult::Drain => {
+                srv.start_draining();
+                srv.notify(user_id, MsgId::DrainStarted, &[]);
+            }
+            CommandResult::Shutdown => srv.shut_down(),
+            CommandResult::ListRooms { pattern } => {
+                srv.list_rooms(user_id, current_room, pattern.as_deref())
+                    .await;
+            }
+            CommandResult::Stats { room: None } => srv.notify_server_stats(user_id),
+            CommandResult::Stats { room: Some(room) } => {
+                srv.notify_stats(user_id, &room).await;
+            }
+            CommandResult::Ban { target, reason } => {
+                srv.ban(user_id, &target, reason).await;
+            }
+            CommandResult::Unban { target } => {
+                srv.unban(user_id, &target).await;
+            }
+            CommandResult::ShadowMute { target, on } => {
+                srv.shadow_mute(user_id, &target, on);
+            }
+            CommandResult::OpenPoll { question, options } => {
+                srv.open_poll(user_id, current_room, question, options)
+                    .await;
+            }
+            CommandResult::ClosePoll => {
+                srv.request_close_poll(user_id, current_room).await;
+            }
+            CommandResult::Vote { choice } => {
+                srv.vote(user_id, current_room, choice);
+            }
+            CommandResult::Remind {
+                target,
+                after,
+                when,
+                text,
+            } => {
+                srv.remind(user_id, target, after, &when, text);
+            }
+            CommandResult::InviteCode { room, uses, ttl } => {
+                srv.create_invite(user_id, &room, uses, ttl).await;
+            }
+            CommandResult::DirectMessage { target, body } => {
+                srv.direct_message(user_id, &target, &body).await;
+            }
+            CommandResult::Ignore { target } => srv.ignore(user_id, target),
+            CommandResult::Unignore { target } => srv.unignore(user_id, &target),
+            CommandResult::Away { message } => srv.set_away(user_id, message),
+            CommandResult::Back => srv.come_back(user_id, true),
+            CommandResult::Who { room_id, room } => {
+                srv.who(user_id, room_id, room.as_deref()).await;
+            }
+            CommandResult::EditMessage {
+                room_id,
+                room,
+                id,
+                body,
+            } => {
+                srv.edit_message(user_id, room_id, room.as_deref(), id, &body)
+                    .await;
+            }
+            CommandResult::DeleteMessage { room_id, room, id } => {
+                srv.delete_message(user_id, room_id, room.as_deref(), id)
+                    .await;
+            }
+            CommandResult::Quit => {
+                srv.notify(user_id, MsgId::Goodbye, &[]);
+                return Some(DisconnectReason::Quit);
+            }
+            CommandResult::Set { setting, on } => {
+                self.settings.set(setting, on);
+                let value = if on { "on" } else { "off" };
+                srv.notify(
+                    user_id,
+                    MsgId::SettingChanged,
+                    &[("setting", setting.name()), ("value", value)],
+                );
+            }
+            CommandResult::Help => {
+                srv.notify(user_id, MsgId::Help, &[]);
+            }
+            CommandResult::Language { code } => {
+                srv.choose_language(user_id, code);
+            }
+            CommandResult::Reply(text) => {
+                srv.send_system(user_id, text);
+            }
+            CommandResult::Broadcast(text) => {
+                srv.send_room_system(current_room, text).await;
+            }
+        }
+        None
+    }
+
+    /// `/join`: switch to a room already joined, or go into one, making
+    /// it first if there's none by that name.
+    async fn join(&self, room: String, password: Option<String>) {
+        let user_id = self.user_id;
+        let mut srv = self.server.lock().await;
+        srv.unarchive(&room);
+        if let Some(room_id) = srv.find_room_by_name(&room)
+            && srv.joined_rooms(user_id).contains(&room_id)
+        {
+            srv.switch_room(user_id, room_id);
+            return;
+        }
+        let creating = srv.find_room_by_name(&room).is_none();
+        if creating {
+            // Only /msg makes DM rooms; a /join that did would be
+            // readable by anyone.
+            if room::is_dm_name(&room) {
+                srv.report(user_id, &ChatError::UnknownRoom(room.to_string()));
+                return;
+            }
+            if !srv.may_create_room(user_id) {
+                return;
+            }
+        }
+        // Hashing is slow on purpose, so like sign-in it happens with
+        // the lock let go. The room may have been made meanwhile: look
+        // again.
+        let mut hashed = None;
+        if creating && let Some(password) = password.clone() {
+            drop(srv);
+            let result = hash_password(password).await;
+            srv = self.server.lock().await;
+            match result {
+                Ok(credentials) => hashed = Some(credentials),
+                Err(e) => {
+                    srv.report(user_id, &e);
+                    return;
+                }
+            }
+        }
+        let creating = creating && srv.find_room_by_name(&room).is_none();
+        // Others may have made rooms while we hashed.
+        if creating && hashed.is_some() && !srv.may_create_room(user_id) {
+            return;
+        }
+        if !creating
+            && let Some(room_id) = srv.find_room_by_name(&room)
+            && let Some(stored) = srv.room_password(user_id, room_id)
+        {
+            let Some(password) = password else {
+                srv.report(user_id, &ChatError::WrongPassword(room));
+                return;
+            };
+            drop(srv);
+            let verified = verify_password(stored, password).await;
+            srv = self.server.lock().await;
+            if !verified {
+                srv.report(user_id, &ChatError::WrongPassword(room));
+                return;
+            }
+        }
+        let room_id = srv.find_or_create_room(&room);
+        if creating {
+            let holder = srv.holder(user_id);
+            srv.rooms[room_id].set_role(&holder, RoomRole::Owner);
+            srv.rooms[room_id].password = hashed;
+            srv.save_rooms(user_id).await;
+        }
+        if !srv.may_enter(user_id, room_id) {
+            return;
+        }
+        // Before joining, so any replay follows it.
+        srv.notify(user_id, MsgId::YouJoined, &[("room", &room)]);
+        srv.join_room(user_id, room_id).await;
+    }
+
+    /// `/setpass`: hash the new password with the lock let go, then
+    /// put it on the room.
+    async fn set_password(&self, room_id: RoomId, password: Option<String>) {
+        let user_id = self.user_id;
+        let mut srv = self.server.lock().await;
+        if !srv.authorize_in_room(user_id, room_id, "setpass", RoomRole::Operator) {
+            return;
+        }
+        let hashed = match password {
+            Some(password) => {
+                drop(srv);
+                let result = hash_password(password).await;
+                srv = self.server.lock().await;
+                match result {
+                    Ok(credentials) => Some(credentials),
+                    Err(e) => {
+                        srv.report(user_id, &e);
+                        return;
+                    }
+                }
+            }
+            None => None,
+        };
+        srv.set_room_password(user_id, room_id, hashed).await;
+    }
+}
+
+/// Carry out a bot's actions until its Bot is dropped or the server
+/// closes it, then sign it off: the bot's side of what `handle_client`
+/// does for a connection.
+///
+/// A kick or a shutdown comes as a Close on the bot's channel, the way
+/// a writer task would see it, so this listens there too. Waiting for
+/// the Bot to be dropped instead would leave a drain waiting on it.
+pub async fn run_bot(
+    server: Arc<Mutex<Server>>,
+    user_id: UserId,
+    mut actions: mpsc::UnboundedReceiver<BotAction>,
+) {
+    let connected_at = Instant::now();
+    let Some(mut events) = server
+        .lock()
+        .await
+        .clients
+        .get(user_id)
+        .map(|client| client.tx.subscribe())
+    else {
+        return;
+    };
+    let reason = loop {
+        tokio::select! {
+            action = actions.recv() => match action {
+                Some(action) => server.lock().await.bot_action(user_id, action).await,
+                None => break DisconnectReason::Closed,
+            },
+            event = events.recv() => match event {
+                Ok(Event::Close(reason)) => break reason,
+                Err(broadcast::error::RecvError::Closed) => break DisconnectReason::Closed,
+                _ => {}
+            },
+        }
+    };
+    let mut srv = server.lock().await;
+    let name = srv.client_name(user_id);
+    info!(username = %name, %reason, "bot signed off");
+    srv.disconnect(user_id, name, connected_at.elapsed(), reason)
+        .await;
+}
+
+/// Append `text` and a newline to the file at `path`, creating it.
+async fn append_line(path: &Path, text: &str) -> std::io::Result<()> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(format!("{text}\n").as_bytes()).await
 }
@@ -0,0 +1,81 @@
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::time::SystemTime;
+
+use crate::types::UserId;
+
+/// How many sessions to remember. Old ones fall off the front.
+const KEEP: usize = 10_000;
+
+/// One connection, from login to disconnect.
+#[derive(Debug, Clone)]
+pub struct SessionRecord {
+    pub user_id: UserId,
+    pub ip: IpAddr,
+    /// Every name used during the session, first to last.
+    pub names: Vec<String>,
+    pub ended: Option<SystemTime>,
+}
+
+/// Recent sessions, oldest first.
+///
+/// This is the memory moderation tools work from: who connected from
+/// where, under which names. It's bounded, so a busy server forgets its
+/// oldest history rather than growing forever.
+pub struct SessionLog {
+    records: VecDeque<SessionRecord>,
+}
+
+impl SessionLog {
+    pub fn new() -> Self {
+        Self {
+            records: VecDeque::new(),
+        }
+    }
+
+    pub fn start(&mut self, user_id: UserId, ip: IpAddr, name: &str) {
+        if self.records.len() == KEEP {
+            self.records.pop_front();
+        }
+        self.records.push_back(SessionRecord {
+            user_id,
+            ip,
+            names: vec![name.to_string()],
+            ended: None,
+        });
+    }
+
+    pub fn rename(&mut self, user_id: UserId, name: &str) {
+        if let Some(record) = self.current(user_id) {
+            record.names.push(name.to_string());
+        }
+    }
+
+    pub fn end(&mut self, user_id: UserId) {
+        if let Some(record) = self.current(user_id) {
+            record.ended = Some(SystemTime::now());
+        }
+    }
+
+    /// Every name seen from `ip`, oldest first, without repeats.
+    pub fn names_from(&self, ip: IpAddr) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for record in self.records.iter().filter(|r| r.ip == ip) {
+            for name in &record.names {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+        }
+        names
+    }
+
+    /// The open session for `user_id`. User ids aren't reused while a
+    /// session is open, so the newest match is the live one.
+    fn current(&mut self, user_id: UserId) -> Option<&mut SessionRecord> {
+        self.records
+            .iter_mut()
+            .rev()
+            .find(|r| r.user_id == user_id && r.ended.is_none())
+    }
+}
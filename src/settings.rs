@@ -0,0 +1,165 @@
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(unix)]
+use tokio::sync::Mutex;
+use toml::{Table, Value};
+#[cfg(unix)]
+use tracing::warn;
+
+use crate::dedup::{Dedup, DedupMode};
+use crate::error::ChatError;
+use crate::handshake::Banner;
+use crate::ratelimit::RateLimit;
+#[cfg(unix)]
+use crate::server::Server;
+
+/// What a config file may set. Everything is optional: a setting the
+/// file leaves out keeps the value given in code at startup, and its
+/// current value on a reload.
+///
+///   addr = "0.0.0.0"
+///   port = 8080
+///   max_users = 200
+///   motd = "Welcome!"              # or motd_file = "motd.txt"
+///   max_message_len = 4096
+///   message_rate = { per_sec = 2.0, burst = 10 }
+///   command_rate = { per_sec = 1.0, burst = 5 }
+///   dedup = { window_secs = 10, mode = "drop" }   # or dedup = false
+///
+/// The first three are read once, when the server binds and sizes
+/// itself; changing them on a reload is reported as needing a restart.
+/// The rest take effect as soon as the file is reloaded.
+#[derive(Debug, Default)]
+pub struct FileSettings {
+    pub addr: Option<String>,
+    pub port: Option<u16>,
+    pub max_users: Option<usize>,
+    pub motd: Option<Banner>,
+    pub max_message_len: Option<usize>,
+    pub message_rate: Option<RateLimit>,
+    pub command_rate: Option<RateLimit>,
+    /// `Some(None)` is `dedup = false`: turn it off.
+    pub dedup: Option<Option<Dedup>>,
+}
+
+/// Read and check a config file. A setting it doesn't know is an
+/// error, not ignored: a typo'd name would otherwise do nothing,
+/// silently.
+pub fn load(path: &Path) -> Result<FileSettings, ChatError> {
+    let in_file = |e: &dyn std::fmt::Display| ChatError::Config(format!("{}: {e}", path.display()));
+    let text = std::fs::read_to_string(path).map_err(|e| in_file(&e))?;
+    let table: Table = text.parse().map_err(|e| in_file(&e))?;
+
+    let mut settings = FileSettings::default();
+    for (key, value) in &table {
+        let key = key.as_str();
+        match key {
+            "addr" => settings.addr = Some(string(key, value)?.to_string()),
+            "port" => settings.port = Some(number(key, value)?),
+            "max_users" => settings.max_users = Some(number(key, value)?),
+            "motd" => settings.motd = Some(Banner::Text(string(key, value)?.to_string())),
+            "motd_file" => settings.motd = Some(Banner::File(PathBuf::from(string(key, value)?))),
+            "max_message_len" => settings.max_message_len = Some(number(key, value)?),
+            "message_rate" => settings.message_rate = Some(rate(key, value)?),
+            "command_rate" => settings.command_rate = Some(rate(key, value)?),
+            "dedup" => settings.dedup = Some(dedup(value)?),
+            other => return Err(in_file(&format!("unknown setting: {other}"))),
+        }
+    }
+    Ok(settings)
+}
+
+fn wrong(key: &str, expected: &str) -> ChatError {
+    ChatError::Config(format!("{key}: expected {expected}"))
+}
+
+fn string<'a>(key: &str, value: &'a Value) -> Result<&'a str, ChatError> {
+    value.as_str().ok_or_else(|| wrong(key, "a string"))
+}
+
+fn number<T: TryFrom<i64>>(key: &str, value: &Value) -> Result<T, ChatError> {
+    value
+        .as_integer()
+        .and_then(|n| T::try_from(n).ok())
+        .ok_or_else(|| wrong(key, "a whole number in range"))
+}
+
+/// `{ per_sec = 2.0, burst = 10 }`. A whole per_sec will do too.
+fn rate(key: &str, value: &Value) -> Result<RateLimit, ChatError> {
+    let expected = "{ per_sec = <number>, burst = <whole number> }";
+    let table = value.as_table().ok_or_else(|| wrong(key, expected))?;
+    let per_sec = match table.get("per_sec") {
+        Some(Value::Float(n)) => *n,
+        Some(Value::Integer(n)) => *n as f64,
+        _ => return Err(wrong(key, expected)),
+    };
+    let burst = table
+        .get("burst")
+        .ok_or_else(|| wrong(key, expected))
+        .and_then(|burst| number(&format!("{key}.burst"), burst))?;
+    Ok(RateLimit::new(per_sec, burst))
+}
+
+/// `false`, or `{ window_secs = 10, mode = "drop" }` with the mode
+/// optional.
+fn dedup(value: &Value) -> Result<Option<Dedup>, ChatError> {
+    let expected = "false, or { window_secs = <n>, mode = \"drop\" | \"flag\" }";
+    let table = match value {
+        Value::Boolean(false) => return Ok(None),
+        Value::Table(table) => table,
+        _ => return Err(wrong("dedup", expected)),
+    };
+    let secs: u64 = table
+        .get("window_secs")
+        .ok_or_else(|| wrong("dedup", expected))
+        .and_then(|secs| number("dedup.window_secs", secs))?;
+    let mode = match table.get("mode").map(Value::as_str) {
+        None | Some(Some("drop")) => DedupMode::Drop,
+        Some(Some("flag")) => DedupMode::Flag,
+        _ => return Err(wrong("dedup.mode", "\"drop\" or \"flag\"")),
+    };
+    Ok(Some(Dedup {
+        window: Duration::from_secs(secs),
+        mode,
+    }))
+}
+
+/// What a reload did, for the console or the log: one line for what
+/// changed, one for what's waiting on a restart.
+pub fn describe_reload(result: Result<(Vec<&str>, Vec<&str>), ChatError>) -> String {
+    let (applied, restart) = match result {
+        Ok(reloaded) => reloaded,
+        Err(e) => return format!("error: {e}\n"),
+    };
+    let mut out = if applied.is_empty() {
+        "reloaded: nothing to apply\n".to_string()
+    } else {
+        format!("reloaded: {}\n", applied.join(", "))
+    };
+    if !restart.is_empty() {
+        out.push_str(&format!("needs a restart: {}\n", restart.join(", ")));
+    }
+    out
+}
+
+/// Reload the config file on every SIGHUP, the usual way to tell a
+/// daemon its config has changed.
+#[cfg(unix)]
+pub async fn reload_on_hangup(server: Arc<Mutex<Server>>) {
+    use tokio::signal::unix::{SignalKind, signal};
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            warn!(error = %e, "can't listen for SIGHUP; reload from the admin console instead");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        if let Err(e) = server.lock().await.reload_config() {
+            warn!(error = %e, "config reload failed; keeping the settings in use");
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::error::ChatError;
+use crate::invite;
+use crate::types::RoomId;
+
+/// Characters in a file token. Longer than an invite code: a token is
+/// all it takes to read the file, and nobody types it by hand.
+const TOKEN_LEN: usize = 12;
+
+/// How big shared files may be, and how long they're kept.
+#[derive(Debug, Clone, Copy)]
+pub struct FileLimits {
+    /// Largest file, in bytes as sent: after the client's encoding.
+    pub max_size: usize,
+    /// Largest single FILE_CHUNK payload.
+    pub max_chunk: usize,
+    /// All shared files together. Past this, new ones are refused
+    /// until old ones expire.
+    pub max_stored: usize,
+    /// How long a file can be fetched after it's shared.
+    pub ttl: Duration,
+}
+
+impl Default for FileLimits {
+    fn default() -> Self {
+        Self {
+            max_size: 1024 * 1024,
+            max_chunk: 8 * 1024,
+            max_stored: 64 * 1024 * 1024,
+            ttl: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// A file on its way in: FILE_START seen, FILE_END not yet.
+///
+/// Kept by the connection, not the server. Chunks arrive one line at a
+/// time and there's nothing to tell anyone until the last one, so
+/// there's no reason to take the server lock for each.
+pub struct Upload {
+    name: String,
+    declared: usize,
+    received: usize,
+    chunks: Vec<String>,
+    limits: FileLimits,
+}
+
+impl Upload {
+    /// FILE_START: a file called `name`, `size` bytes long.
+    pub fn start(name: &str, size: usize, limits: FileLimits) -> Result<Self, ChatError> {
+        if size > limits.max_size {
+            return Err(ChatError::FileTooLarge {
+                size,
+                max: limits.max_size,
+            });
+        }
+        Ok(Self {
+            name: name.to_string(),
+            declared: size,
+            received: 0,
+            chunks: Vec::new(),
+            limits,
+        })
+    }
+
+    /// FILE_CHUNK: the next piece, as sent. The server doesn't look
+    /// inside: base64 for a binary file, a line of text for a paste.
+    pub fn push(&mut self, chunk: &str) -> Result<(), ChatError> {
+        if chunk.len() > self.limits.max_chunk {
+            return Err(ChatError::MessageTooLong {
+                len: chunk.len(),
+                max: self.limits.max_chunk,
+            });
+        }
+        if self.received + chunk.len() > self.declared {
+            return Err(ChatError::Parse(format!(
+                "FILE_CHUNK: more than the {} bytes FILE_START said",
+                self.declared
+            )));
+        }
+        self.received += chunk.len();
+        self.chunks.push(chunk.to_string());
+        Ok(())
+    }
+
+    /// FILE_END: check it all arrived, and hand over the file.
+    pub fn finish(self, room_id: RoomId) -> Result<SharedFile, ChatError> {
+        if self.received != self.declared {
+            return Err(ChatError::Parse(format!(
+                "FILE_END: got {} of the {} bytes FILE_START said",
+                self.received, self.declared
+            )));
+        }
+        Ok(SharedFile {
+            name: self.name,
+            room_id,
+            size: self.received,
+            chunks: self.chunks,
+        })
+    }
+}
+
+/// A file someone shared, waiting to be fetched.
+pub struct SharedFile {
+    pub name: String,
+    /// Where it was shared. Only that room's members may fetch it.
+    pub room_id: RoomId,
+    pub size: usize,
+    /// The chunks as they came, so a fetch gets back exactly what was
+    /// sent, piece for piece.
+    pub chunks: Vec<String>,
+}
+
+/// Shared files by token.
+///
+/// Memory only, on purpose: these are for passing something round a
+/// room, not for keeping. Each goes when the scheduler expires it.
+pub struct SharedFiles {
+    files: HashMap<String, SharedFile>,
+    /// Bytes held across every file, against `max_stored`.
+    stored: usize,
+}
+
+/// A fresh token in the invite alphabet, from the system's secure
+/// generator as session tokens are: a token is all it takes to read
+/// the file, so it mustn't be guessable from the ones before.
+fn random_token() -> Result<String, ChatError> {
+    let alphabet = invite::ALPHABET;
+    // Bytes past the last whole run of the alphabet are thrown back,
+    // so each character is as likely as any other.
+    let limit = 256 - 256 % alphabet.len();
+    let rng = SystemRandom::new();
+    let mut token = String::with_capacity(TOKEN_LEN);
+    while token.len() < TOKEN_LEN {
+        let mut bytes = [0u8; TOKEN_LEN];
+        rng.fill(&mut bytes)
+            .map_err(|_| ChatError::Config("no randomness for a file token".into()))?;
+        for byte in bytes.map(usize::from).into_iter().filter(|&b| b < limit) {
+            if token.len() < TOKEN_LEN {
+                token.push(alphabet[byte % alphabet.len()] as char);
+            }
+        }
+    }
+    Ok(token)
+}
+
+impl SharedFiles {
+    pub fn new() -> Self {
+        Self {
+            files: HashMap::new(),
+            stored: 0,
+        }
+    }
+
+    /// Keep `file` and return its token, or refuse if it would take
+    /// more than `limits` allow.
+    pub fn insert(&mut self, file: SharedFile, limits: &FileLimits) -> Result<String, ChatError> {
+        if self.stored + file.size > limits.max_stored {
+            return Err(ChatError::FileStoreFull);
+        }
+        let token = loop {
+            let token = random_token()?;
+            if !self.files.contains_key(&token) {
+                break token;
+            }
+        };
+        self.stored += file.size;
+        self.files.insert(token.clone(), file);
+        Ok(token)
+    }
+
+    /// Tokens are matched case-insensitively, like invite codes.
+    pub fn get(&self, token: &str) -> Option<&SharedFile> {
+        self.files.get(&token.trim().to_ascii_uppercase())
+    }
+
+    pub fn expire(&mut self, token: &str) {
+        if let Some(file) = self.files.remove(token) {
+            self.stored -= file.size;
+        }
+    }
+}
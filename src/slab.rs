@@ -0,0 +1,129 @@
+use std::marker::PhantomData;
+use std::ops::{Index, IndexMut};
+
+/// A key into a slab: a slot index plus the generation that slot was on
+/// when the key was handed out.
+pub trait Key: Copy {
+    fn from_parts(index: usize, generation: u32) -> Self;
+    fn index(self) -> usize;
+    fn generation(self) -> u32;
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+/// An arena of values addressed by generational keys.
+///
+/// Removing a value frees its slot for the next insert, which bumps the
+/// slot's generation. Keys to the old value then stop matching: `get`
+/// returns None rather than whatever moved in. That check lives here,
+/// once, instead of at every lookup in the server.
+pub struct Slab<K, T> {
+    slots: Vec<Slot<T>>,
+    /// Empty slots, reused most-recently-freed first.
+    free: Vec<usize>,
+    len: usize,
+    _key: PhantomData<K>,
+}
+
+impl<K: Key, T> Slab<K, T> {
+    /// An empty slab with room for `capacity` values before it has to
+    /// grow.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            slots: Vec::with_capacity(capacity),
+            free: Vec::new(),
+            len: 0,
+            _key: PhantomData,
+        }
+    }
+
+    pub fn insert(&mut self, value: T) -> K {
+        self.len += 1;
+        if let Some(index) = self.free.pop() {
+            let slot = &mut self.slots[index];
+            slot.generation = slot.generation.wrapping_add(1);
+            let key = K::from_parts(index, slot.generation);
+            slot.value = Some(value);
+            return key;
+        }
+        let key = K::from_parts(self.slots.len(), 0);
+        self.slots.push(Slot {
+            generation: 0,
+            value: Some(value),
+        });
+        key
+    }
+
+    /// Take the value out, freeing its slot. A stale key removes nothing.
+    pub fn remove(&mut self, key: K) -> Option<T> {
+        let slot = self.slots.get_mut(key.index())?;
+        if slot.generation != key.generation() {
+            return None;
+        }
+        let value = slot.value.take()?;
+        self.free.push(key.index());
+        self.len -= 1;
+        Some(value)
+    }
+
+    pub fn get(&self, key: K) -> Option<&T> {
+        let slot = self.slots.get(key.index())?;
+        if slot.generation != key.generation() {
+            return None;
+        }
+        slot.value.as_ref()
+    }
+
+    pub fn get_mut(&mut self, key: K) -> Option<&mut T> {
+        let slot = self.slots.get_mut(key.index())?;
+        if slot.generation != key.generation() {
+            return None;
+        }
+        slot.value.as_mut()
+    }
+
+    /// Live values with their keys, in slot order.
+    pub fn iter(&self) -> impl Iterator<Item = (K, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let value = slot.value.as_ref()?;
+            Some((K::from_parts(index, slot.generation), value))
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (K, &mut T)> {
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                let value = slot.value.as_mut()?;
+                Some((K::from_parts(index, slot.generation), value))
+            })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// `slab[key]` for a key known to be live. Panics on a stale one, like
+/// indexing a Vec out of bounds — use `get` when in doubt.
+impl<K: Key, T> Index<K> for Slab<K, T> {
+    type Output = T;
+
+    fn index(&self, key: K) -> &T {
+        self.get(key).expect("stale or unknown slab key")
+    }
+}
+
+impl<K: Key, T> IndexMut<K> for Slab<K, T> {
+    fn index_mut(&mut self, key: K) -> &mut T {
+        self.get_mut(key).expect("stale or unknown slab key")
+    }
+}
@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::TcpStream;
+
+use crate::error::ChatError;
+
+/// TCP tuning applied to every accepted connection.
+#[derive(Debug, Clone, Copy)]
+pub struct SocketOptions {
+    /// Send small writes immediately instead of batching them (Nagle).
+    /// Chat lines are tiny and latency matters more than packet count.
+    pub nodelay: bool,
+    /// Probe an idle connection after this long, and again at the same
+    /// interval, so a peer that vanished without a FIN is noticed.
+    pub keepalive: Option<Duration>,
+    /// Disconnect a client that sends nothing for this long.
+    pub read_timeout: Option<Duration>,
+    /// Disconnect a client whose socket won't accept a line for this long.
+    pub write_timeout: Option<Duration>,
+    /// PING a protocol client that's been quiet this long...
+    pub ping_interval: Option<Duration>,
+    /// ...and disconnect it if nothing comes back within this.
+    pub pong_timeout: Duration,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            keepalive: Some(Duration::from_secs(60)),
+            read_timeout: None,
+            write_timeout: Some(Duration::from_secs(30)),
+            ping_interval: Some(Duration::from_secs(30)),
+            pong_timeout: Duration::from_secs(15),
+        }
+    }
+}
+
+/// Apply the kernel-level options to a freshly accepted stream.
+///
+/// tokio exposes nodelay directly, but not keepalive timing; SockRef
+/// borrows the same file descriptor so socket2 can set it without
+/// taking the stream apart. The timeouts and pings aren't socket
+/// options at all in async code — the read and write paths enforce them.
+pub fn apply(stream: &TcpStream, options: &SocketOptions) -> Result<(), ChatError> {
+    stream.set_nodelay(options.nodelay)?;
+
+    let socket = SockRef::from(stream);
+    match options.keepalive {
+        Some(idle) => {
+            let keepalive = TcpKeepalive::new().with_time(idle).with_interval(idle);
+            socket.set_tcp_keepalive(&keepalive)?;
+        }
+        None => socket.set_keepalive(false)?,
+    }
+    Ok(())
+}
@@ -0,0 +1,646 @@
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::time::{Duration, UNIX_EPOCH};
+
+use tokio::io::AsyncWriteExt;
+
+use crate::auth::Credentials;
+use crate::ban::Ban;
+use crate::config::ServerConfig;
+use crate::error::ChatError;
+use crate::history::Entry;
+use crate::persistence;
+
+type StorageFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, ChatError>> + Send + 'a>>;
+
+/// Where accounts, bans, message history and read markers are kept
+/// between runs.
+#[derive(Debug, Clone, Default)]
+pub enum StorageBackend {
+    /// Nowhere: everything is gone when the server stops. Nothing to
+    /// wait on, nothing to set up.
+    Memory,
+    /// The accounts, bans, history and read markers files in the
+    /// config. Any left unset is kept in memory only.
+    #[default]
+    Files,
+    /// One sqlite database for all of them.
+    #[cfg(feature = "sqlite")]
+    Sqlite(PathBuf),
+}
+
+/// What the server keeps beyond its own lifetime.
+///
+/// The server works from its own copies — `Accounts`, `BanList`, each
+/// room's `History` — and a backend never answers a lookup while it
+/// runs. It's read once at startup (and on a reload), and told of each
+/// change as it happens, so how slow it is shows only in how long a
+/// registration, a ban or a message takes to be safe, never in how
+/// long a lookup takes.
+///
+/// Loading is synchronous because it happens before the server starts
+/// serving. Saving returns a future, like `AsyncFilter`, so a backend
+/// that has to block can do it off the async threads.
+pub trait Storage: Send + Sync {
+    fn load_accounts(&self) -> Result<Vec<(String, Credentials)>, ChatError>;
+
+    /// A new account. Names are unique; the server checks first.
+    fn add_account<'a>(
+        &'a self,
+        name: &'a str,
+        credentials: &'a Credentials,
+    ) -> StorageFuture<'a, ()>;
+
+    fn load_bans(&self) -> Result<Vec<Ban>, ChatError>;
+
+    /// Replace the stored bans with `bans`. The list is short and
+    /// lifting a ban changes it anywhere, so it goes whole.
+    fn save_bans<'a>(&'a self, bans: &'a [Ban]) -> StorageFuture<'a, ()>;
+
+    /// Each room's newest `keep` messages, oldest first. A backend may
+    /// drop older ones while it's at it: nobody will ask for them.
+    fn load_history(&self, keep: usize) -> Result<HashMap<String, Vec<Entry>>, ChatError>;
+
+    /// A message, or a change to one: an edit comes again with the same
+    /// `seq` and its new body, a delete with an empty body. A backend
+    /// can replace the old record or keep both — `History::restore`
+    /// takes the last.
+    fn append_message<'a>(&'a self, room: &'a str, entry: &'a Entry) -> StorageFuture<'a, ()>;
+
+    /// Forget everything said in `room`, and how far anyone read: it
+    /// has been deleted.
+    fn delete_history<'a>(&'a self, room: &'a str) -> StorageFuture<'a, ()>;
+
+    /// Every account's read markers: account, room, sequence.
+    fn load_read_markers(&self) -> Result<Vec<(String, String, u64)>, ChatError>;
+
+    /// How far `account` had read in each room, as they leave. Rooms
+    /// missing from `rooms` keep what was stored for them.
+    fn save_read_markers<'a>(
+        &'a self,
+        account: &'a str,
+        rooms: &'a HashMap<String, u64>,
+    ) -> StorageFuture<'a, ()>;
+}
+
+/// The backend `config` asks for.
+pub fn open(config: &ServerConfig) -> Result<Box<dyn Storage>, ChatError> {
+    Ok(match &config.storage {
+        StorageBackend::Memory => Box::new(MemoryStorage),
+        StorageBackend::Files => Box::new(FileStorage {
+            accounts: config.accounts_file.clone(),
+            bans: config.bans_file.clone(),
+            history: config.history_file.clone(),
+            read_markers: config.read_markers_file.clone(),
+        }),
+        #[cfg(feature = "sqlite")]
+        StorageBackend::Sqlite(path) => Box::new(SqliteStorage::open(path)?),
+    })
+}
+
+/// Keeps nothing. The server's own copies are all there is.
+pub struct MemoryStorage;
+
+impl Storage for MemoryStorage {
+    fn load_accounts(&self) -> Result<Vec<(String, Credentials)>, ChatError> {
+        Ok(Vec::new())
+    }
+
+    fn add_account<'a>(&'a self, _: &'a str, _: &'a Credentials) -> StorageFuture<'a, ()> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn load_bans(&self) -> Result<Vec<Ban>, ChatError> {
+        Ok(Vec::new())
+    }
+
+    fn save_bans<'a>(&'a self, _: &'a [Ban]) -> StorageFuture<'a, ()> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn load_history(&self, _: usize) -> Result<HashMap<String, Vec<Entry>>, ChatError> {
+        Ok(HashMap::new())
+    }
+
+    fn append_message<'a>(&'a self, _: &'a str, _: &'a Entry) -> StorageFuture<'a, ()> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn delete_history<'a>(&'a self, _: &'a str) -> StorageFuture<'a, ()> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn load_read_markers(&self) -> Result<Vec<(String, String, u64)>, ChatError> {
+        Ok(Vec::new())
+    }
+
+    fn save_read_markers<'a>(
+        &'a self,
+        _: &'a str,
+        _: &'a HashMap<String, u64>,
+    ) -> StorageFuture<'a, ()> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Plain text files, one record per line, easy to read and to fix by
+/// hand. Accounts, history and read markers are appended to; bans are
+/// rewritten.
+pub struct FileStorage {
+    accounts: Option<PathBuf>,
+    bans: Option<PathBuf>,
+    history: Option<PathBuf>,
+    read_markers: Option<PathBuf>,
+}
+
+impl FileStorage {
+    /// Each non-blank line of `path` through `parse`. A missing file
+    /// is an empty one.
+    fn read<T>(
+        path: &Path,
+        what: &str,
+        parse: impl Fn(&str) -> Option<T>,
+    ) -> Result<Vec<T>, ChatError> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut records = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = parse(line).ok_or_else(|| {
+                ChatError::Storage(format!(
+                    "{}:{}: bad {what} line",
+                    path.display(),
+                    number + 1
+                ))
+            })?;
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    async fn append(path: &Option<PathBuf>, line: String) -> Result<(), ChatError> {
+        let Some(path) = path else {
+            return Ok(());
+        };
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Rewrite `path` without the lines whose first field is `room`.
+    async fn drop_room(path: &Option<PathBuf>, room: &str) -> Result<(), ChatError> {
+        let Some(path) = path else {
+            return Ok(());
+        };
+        let text = match tokio::fs::read_to_string(path).await {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let kept: String = text
+            .lines()
+            .filter(|line| line.split('\t').next() != Some(room))
+            .map(|line| format!("{line}\n"))
+            .collect();
+        persistence::replace(path, kept).await
+    }
+}
+
+/// One line of the read markers file, tab-separated, room first as in
+/// the history file: `room  account  seq`.
+fn marker_line(room: &str, account: &str, seq: u64) -> String {
+    format!("{room}\t{account}\t{seq}\n")
+}
+
+fn parse_marker_line(line: &str) -> Option<(String, String, u64)> {
+    let mut fields = line.split('\t');
+    let room = fields.next()?.to_string();
+    let account = fields.next()?.to_string();
+    let seq = fields.next()?.parse().ok()?;
+    Some((account, room, seq))
+}
+
+/// One line of the history file, tab-separated, body last:
+/// `room  seq  seconds  from  body`.
+fn history_line(room: &str, entry: &Entry) -> String {
+    let body = entry.body.replace(['\n', '\r'], " ");
+    format!(
+        "{room}\t{}\t{}\t{}\t{body}\n",
+        entry.seq,
+        entry.timestamp(),
+        entry.from
+    )
+}
+
+fn parse_history_line(line: &str) -> Option<(String, Entry)> {
+    let mut fields = line.splitn(5, '\t');
+    let room = fields.next()?.to_string();
+    let seq = fields.next()?.parse().ok()?;
+    let at = UNIX_EPOCH + Duration::from_secs(fields.next()?.parse().ok()?);
+    let from = fields.next()?.to_string();
+    let body = fields.next()?.to_string();
+    Some((
+        room,
+        Entry {
+            seq,
+            at,
+            from,
+            body,
+        },
+    ))
+}
+
+impl Storage for FileStorage {
+    fn load_accounts(&self) -> Result<Vec<(String, Credentials)>, ChatError> {
+        match &self.accounts {
+            Some(path) => Self::read(path, "account", Credentials::from_line),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn add_account<'a>(
+        &'a self,
+        name: &'a str,
+        credentials: &'a Credentials,
+    ) -> StorageFuture<'a, ()> {
+        Box::pin(Self::append(&self.accounts, credentials.to_line(name)))
+    }
+
+    fn load_bans(&self) -> Result<Vec<Ban>, ChatError> {
+        match &self.bans {
+            Some(path) => Self::read(path, "ban", Ban::from_line),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn save_bans<'a>(&'a self, bans: &'a [Ban]) -> StorageFuture<'a, ()> {
+        Box::pin(async move {
+            if let Some(path) = &self.bans {
+                let text: String = bans.iter().map(Ban::to_line).collect();
+                persistence::replace(path, text).await?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Appending forever would grow the file without end, so this is
+    /// also where it's cut back to what's kept.
+    fn load_history(&self, keep: usize) -> Result<HashMap<String, Vec<Entry>>, ChatError> {
+        let Some(path) = &self.history else {
+            return Ok(HashMap::new());
+        };
+        let mut rooms: HashMap<String, VecDeque<Entry>> = HashMap::new();
+        let mut dropped = false;
+        for (room, entry) in Self::read(path, "history", parse_history_line)? {
+            let entries = rooms.entry(room).or_default();
+            entries.push_back(entry);
+            if entries.len() > keep {
+                entries.pop_front();
+                dropped = true;
+            }
+        }
+        if dropped {
+            let text: String = rooms
+                .iter()
+                .flat_map(|(room, entries)| entries.iter().map(|e| history_line(room, e)))
+                .collect();
+            std::fs::write(path, text)?;
+        }
+        Ok(rooms
+            .into_iter()
+            .map(|(room, entries)| (room, entries.into()))
+            .collect())
+    }
+
+    fn append_message<'a>(&'a self, room: &'a str, entry: &'a Entry) -> StorageFuture<'a, ()> {
+        Box::pin(Self::append(&self.history, history_line(room, entry)))
+    }
+
+    /// The room's lines are spread through the files, so they're
+    /// rewritten without them. Rooms are deleted far less often than
+    /// spoken in.
+    fn delete_history<'a>(&'a self, room: &'a str) -> StorageFuture<'a, ()> {
+        Box::pin(async move {
+            Self::drop_room(&self.history, room).await?;
+            Self::drop_room(&self.read_markers, room).await
+        })
+    }
+
+    /// Later lines replace earlier ones, so this is where the file is
+    /// cut back to one line a marker, as history is.
+    fn load_read_markers(&self) -> Result<Vec<(String, String, u64)>, ChatError> {
+        let Some(path) = &self.read_markers else {
+            return Ok(Vec::new());
+        };
+        let lines = Self::read(path, "read marker", parse_marker_line)?;
+        let count = lines.len();
+        let mut latest: HashMap<(String, String), u64> = HashMap::new();
+        for (account, room, seq) in lines {
+            latest.insert((account, room), seq);
+        }
+        if latest.len() < count {
+            let text: String = latest
+                .iter()
+                .map(|((account, room), seq)| marker_line(room, account, *seq))
+                .collect();
+            std::fs::write(path, text)?;
+        }
+        Ok(latest
+            .into_iter()
+            .map(|((account, room), seq)| (account, room, seq))
+            .collect())
+    }
+
+    fn save_read_markers<'a>(
+        &'a self,
+        account: &'a str,
+        rooms: &'a HashMap<String, u64>,
+    ) -> StorageFuture<'a, ()> {
+        let text: String = rooms
+            .iter()
+            .map(|(room, seq)| marker_line(room, account, *seq))
+            .collect();
+        Box::pin(Self::append(&self.read_markers, text))
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStorage;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use std::collections::HashMap;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex, PoisonError};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use rusqlite::{Connection, params};
+
+    use super::{Storage, StorageFuture};
+    use crate::auth::Credentials;
+    use crate::ban::Ban;
+    use crate::error::ChatError;
+    use crate::history::Entry;
+
+    const SCHEMA: &str = "
+        CREATE TABLE IF NOT EXISTS accounts (
+            name        TEXT PRIMARY KEY,
+            credentials TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS bans (
+            username TEXT NOT NULL,
+            ip       TEXT NOT NULL,
+            at       INTEGER NOT NULL,
+            by       TEXT NOT NULL,
+            reason   TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS history (
+            room   TEXT NOT NULL,
+            seq    INTEGER NOT NULL,
+            at     INTEGER NOT NULL,
+            sender TEXT NOT NULL,
+            body   TEXT NOT NULL,
+            PRIMARY KEY (room, seq)
+        );
+        CREATE TABLE IF NOT EXISTS read_markers (
+            account TEXT NOT NULL,
+            room    TEXT NOT NULL,
+            seq     INTEGER NOT NULL,
+            PRIMARY KEY (account, room)
+        );
+    ";
+
+    /// Everything in one sqlite database. Each write is its own
+    /// transaction, so a crash loses at most the message in flight.
+    ///
+    /// sqlite calls block, so saves run on tokio's blocking pool; the
+    /// mutex is for that pool's threads, never held across an await.
+    pub struct SqliteStorage {
+        db: Arc<Mutex<Connection>>,
+    }
+
+    fn db_error(e: rusqlite::Error) -> ChatError {
+        ChatError::Storage(e.to_string())
+    }
+
+    fn seconds(secs: i64) -> std::time::SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)
+    }
+
+    impl SqliteStorage {
+        pub fn open(path: &Path) -> Result<Self, ChatError> {
+            let db = Connection::open(path).map_err(db_error)?;
+            db.execute_batch(SCHEMA).map_err(db_error)?;
+            Ok(Self {
+                db: Arc::new(Mutex::new(db)),
+            })
+        }
+
+        fn with_db<T>(
+            &self,
+            work: impl FnOnce(&Connection) -> rusqlite::Result<T>,
+        ) -> Result<T, ChatError> {
+            let db = self.db.lock().unwrap_or_else(PoisonError::into_inner);
+            work(&db).map_err(db_error)
+        }
+
+        /// Run `work` on the blocking pool.
+        fn run<T: Send + 'static>(
+            &self,
+            work: impl FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+        ) -> StorageFuture<'static, T> {
+            let db = Arc::clone(&self.db);
+            Box::pin(async move {
+                tokio::task::spawn_blocking(move || {
+                    let db = db.lock().unwrap_or_else(PoisonError::into_inner);
+                    work(&db).map_err(db_error)
+                })
+                .await
+                .map_err(|e| ChatError::Storage(e.to_string()))?
+            })
+        }
+    }
+
+    impl Storage for SqliteStorage {
+        fn load_accounts(&self) -> Result<Vec<(String, Credentials)>, ChatError> {
+            let rows = self.with_db(|db| {
+                let mut query = db.prepare("SELECT name, credentials FROM accounts")?;
+                let rows = query.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                rows.collect::<rusqlite::Result<Vec<(String, String)>>>()
+            })?;
+            rows.into_iter()
+                .map(|(name, text)| match Credentials::decode(&text) {
+                    Some(credentials) => Ok((name, credentials)),
+                    None => Err(ChatError::Storage(format!("bad credentials for {name}"))),
+                })
+                .collect()
+        }
+
+        fn add_account<'a>(
+            &'a self,
+            name: &'a str,
+            credentials: &'a Credentials,
+        ) -> StorageFuture<'a, ()> {
+            let (name, text) = (name.to_string(), credentials.encode());
+            self.run(move |db| {
+                db.execute(
+                    "INSERT INTO accounts (name, credentials) VALUES (?1, ?2)",
+                    params![name, text],
+                )
+                .map(drop)
+            })
+        }
+
+        fn load_bans(&self) -> Result<Vec<Ban>, ChatError> {
+            let rows = self.with_db(|db| {
+                let mut query = db.prepare("SELECT username, ip, at, by, reason FROM bans")?;
+                let rows = query.query_map([], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, i64>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, String>(4)?,
+                    ))
+                })?;
+                rows.collect::<rusqlite::Result<Vec<_>>>()
+            })?;
+            rows.into_iter()
+                .map(|(username, ip, at, by, reason)| {
+                    let ip = ip
+                        .parse()
+                        .map_err(|_| ChatError::Storage(format!("bad address for {username}")))?;
+                    Ok(Ban {
+                        username,
+                        ip,
+                        reason,
+                        by,
+                        at: seconds(at),
+                    })
+                })
+                .collect()
+        }
+
+        fn save_bans<'a>(&'a self, bans: &'a [Ban]) -> StorageFuture<'a, ()> {
+            let bans = bans.to_vec();
+            self.run(move |db| {
+                let tx = db.unchecked_transaction()?;
+                tx.execute("DELETE FROM bans", [])?;
+                for ban in &bans {
+                    tx.execute(
+                        "INSERT INTO bans (username, ip, at, by, reason)
+                         VALUES (?1, ?2, ?3, ?4, ?5)",
+                        params![
+                            ban.username,
+                            ban.ip.to_string(),
+                            ban.timestamp() as i64,
+                            ban.by,
+                            ban.reason
+                        ],
+                    )?;
+                }
+                tx.commit()
+            })
+        }
+
+        fn load_history(&self, keep: usize) -> Result<HashMap<String, Vec<Entry>>, ChatError> {
+            self.with_db(|db| {
+                // Sequence numbers run unbroken within a room, so the
+                // newest `keep` are the ones within `keep` of the top.
+                db.execute(
+                    "DELETE FROM history AS old WHERE seq <=
+                     (SELECT MAX(seq) FROM history WHERE room = old.room) - ?1",
+                    params![keep as i64],
+                )?;
+                let mut query = db.prepare(
+                    "SELECT room, seq, at, sender, body FROM history ORDER BY room, seq",
+                )?;
+                let rows = query.query_map([], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        Entry {
+                            seq: row.get::<_, i64>(1)? as u64,
+                            at: seconds(row.get(2)?),
+                            from: row.get(3)?,
+                            body: row.get(4)?,
+                        },
+                    ))
+                })?;
+                let mut rooms: HashMap<String, Vec<Entry>> = HashMap::new();
+                for row in rows {
+                    let (room, entry) = row?;
+                    rooms.entry(room).or_default().push(entry);
+                }
+                Ok(rooms)
+            })
+        }
+
+        fn append_message<'a>(&'a self, room: &'a str, entry: &'a Entry) -> StorageFuture<'a, ()> {
+            let (room, entry) = (room.to_string(), entry.clone());
+            self.run(move |db| {
+                db.execute(
+                    "INSERT OR REPLACE INTO history (room, seq, at, sender, body)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![
+                        room,
+                        entry.seq as i64,
+                        entry.timestamp() as i64,
+                        entry.from,
+                        entry.body
+                    ],
+                )
+                .map(drop)
+            })
+        }
+
+        fn delete_history<'a>(&'a self, room: &'a str) -> StorageFuture<'a, ()> {
+            let room = room.to_string();
+            self.run(move |db| {
+                let tx = db.unchecked_transaction()?;
+                tx.execute("DELETE FROM history WHERE room = ?1", params![room])?;
+                tx.execute("DELETE FROM read_markers WHERE room = ?1", params![room])?;
+                tx.commit()
+            })
+        }
+
+        fn load_read_markers(&self) -> Result<Vec<(String, String, u64)>, ChatError> {
+            self.with_db(|db| {
+                let mut query = db.prepare("SELECT account, room, seq FROM read_markers")?;
+                let rows = query.query_map([], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get::<_, i64>(2)? as u64))
+                })?;
+                rows.collect()
+            })
+        }
+
+        fn save_read_markers<'a>(
+            &'a self,
+            account: &'a str,
+            rooms: &'a HashMap<String, u64>,
+        ) -> StorageFuture<'a, ()> {
+            let (account, rooms) = (account.to_string(), rooms.clone());
+            self.run(move |db| {
+                let tx = db.unchecked_transaction()?;
+                for (room, seq) in &rooms {
+                    tx.execute(
+                        "INSERT OR REPLACE INTO read_markers (account, room, seq)
+                         VALUES (?1, ?2, ?3)",
+                        params![account, room, *seq as i64],
+                    )?;
+                }
+                tx.commit()
+            })
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+use crate::metrics::DailyCounters;
+
+/// How many names the "top talkers" line shows.
+pub const TOP_TALKERS: usize = 5;
+
+/// Where the daily summary goes. Any number can be configured.
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub enum SummaryTarget {
+    /// Post it into a room, created if it doesn't exist — an admin
+    /// room, typically.
+    Room(String),
+    /// Append it to a file.
+    File(PathBuf),
+}
+
+/// A day on the server, gathered for the summary.
+pub struct DailyReport {
+    pub counters: DailyCounters,
+    /// Messages per room over the last day, busiest first.
+    pub rooms: Vec<(String, usize)>,
+}
+
+impl DailyReport {
+    /// The summary as lines of text, the same for a room or a file.
+    pub fn render(&self) -> String {
+        let counters = &self.counters;
+        let mut text = String::from("* Daily summary\n");
+        let _ = writeln!(text, "*   new users: {}", counters.new_users);
+        let _ = writeln!(text, "*   peak online: {}", counters.peak_online);
+        let _ = writeln!(text, "*   filter blocks: {}", counters.filter_blocks);
+
+        let rooms: Vec<String> = self
+            .rooms
+            .iter()
+            .map(|(room, count)| format!("#{room} {count}"))
+            .collect();
+        let _ = writeln!(text, "*   messages: {}", list_or_none(&rooms));
+
+        let talkers: Vec<String> = counters
+            .top_talkers(TOP_TALKERS)
+            .into_iter()
+            .map(|(name, count)| format!("{name} {count}"))
+            .collect();
+        let _ = write!(text, "*   top talkers: {}", list_or_none(&talkers));
+        text
+    }
+}
+
+fn list_or_none(items: &[String]) -> String {
+    if items.is_empty() {
+        "none".to_string()
+    } else {
+        items.join(", ")
+    }
+}
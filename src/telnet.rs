@@ -0,0 +1,96 @@
+/// Telnet "Interpret As Command": every negotiation starts with it.
+const IAC: u8 = 255;
+const SE: u8 = 240;
+const SB: u8 = 250;
+const WILL: u8 = 251;
+const WONT: u8 = 252;
+const DO: u8 = 253;
+const DONT: u8 = 254;
+
+/// Where we are inside a telnet command.
+#[derive(Clone, Copy)]
+enum State {
+    Data,
+    /// Saw IAC, waiting for the command byte.
+    Iac,
+    /// Saw IAC WILL/WONT/DO/DONT, waiting for the option byte.
+    Option(u8),
+    /// Inside IAC SB ... IAC SE.
+    Sub,
+    /// Saw IAC inside a subnegotiation.
+    SubIac,
+}
+
+/// Strips telnet negotiation out of the byte stream.
+///
+/// A real telnet client may open with bytes like `FF FD 03` ("please
+/// suppress go-ahead") before the user types anything. Left alone they
+/// end up in front of the username. This is a small state machine over
+/// the raw bytes — it keeps its state between reads, so a command split
+/// across two packets is still recognised.
+///
+/// We don't support any options. Every "will you?" or "I will" gets a
+/// polite refusal, which keeps the client in plain line mode.
+pub struct TelnetFilter {
+    state: State,
+    replies: Vec<u8>,
+}
+
+impl TelnetFilter {
+    pub fn new() -> Self {
+        Self {
+            state: State::Data,
+            replies: Vec::new(),
+        }
+    }
+
+    /// Remove telnet commands from `bytes` in place.
+    pub fn strip(&mut self, bytes: &mut Vec<u8>) {
+        let mut data = Vec::with_capacity(bytes.len());
+
+        for &byte in bytes.iter() {
+            self.state = match (self.state, byte) {
+                (State::Data, IAC) => State::Iac,
+                (State::Data, _) => {
+                    data.push(byte);
+                    State::Data
+                }
+                // IAC IAC is an escaped literal 255.
+                (State::Iac, IAC) => {
+                    data.push(IAC);
+                    State::Data
+                }
+                (State::Iac, WILL | WONT | DO | DONT) => State::Option(byte),
+                (State::Iac, SB) => State::Sub,
+                // Any other two-byte command (NOP, AYT, ...) is dropped.
+                (State::Iac, _) => State::Data,
+                (State::Option(verb), option) => {
+                    self.refuse(verb, option);
+                    State::Data
+                }
+                (State::Sub, IAC) => State::SubIac,
+                (State::Sub, _) => State::Sub,
+                (State::SubIac, SE) => State::Data,
+                (State::SubIac, _) => State::Sub,
+            };
+        }
+
+        *bytes = data;
+    }
+
+    /// Answer WILL with DONT and DO with WONT. WONT and DONT need no
+    /// answer — replying to them is how negotiation loops start.
+    fn refuse(&mut self, verb: u8, option: u8) {
+        let reply = match verb {
+            WILL => DONT,
+            DO => WONT,
+            _ => return,
+        };
+        self.replies.extend_from_slice(&[IAC, reply, option]);
+    }
+
+    /// Replies queued since the last call, ready to write to the client.
+    pub fn take_replies(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.replies)
+    }
+}
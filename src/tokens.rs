@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use ring::digest::{SHA256, digest};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::error::ChatError;
+use crate::permissions::Role;
+use crate::persistence;
+
+/// Random bytes in a token. Hex-encoded, it's twice as many characters.
+const TOKEN_LEN: usize = 32;
+
+/// Who a token signs in as, and with what role.
+#[derive(Debug, Clone)]
+pub struct Grant {
+    pub username: String,
+    pub role: Role,
+}
+
+/// Tokens for bots and other programs, issued from the admin console.
+///
+/// A program that answers the username prompt with `AUTH:token` is
+/// signed in as the token's user, with the token's role, and no
+/// password to hash: a token is long and random, so it can't be
+/// guessed the way a password can, and a plain SHA-256 of it is all
+/// that needs keeping. The token itself is shown once, when it's
+/// issued, and never stored.
+///
+/// Unlike a person's role, a token's stands as issued: a bot's name
+/// doesn't start out as a guest and earn trust by talking.
+pub struct Tokens {
+    /// By the hex SHA-256 of the token.
+    grants: HashMap<String, Grant>,
+}
+
+impl Tokens {
+    pub fn new() -> Self {
+        Self {
+            grants: HashMap::new(),
+        }
+    }
+
+    /// A new token for `username`. The caller hands it over, then
+    /// forgets it.
+    pub fn issue(&mut self, username: &str, role: Role) -> Result<String, ChatError> {
+        let token = random_token()?;
+        let grant = Grant {
+            username: username.to_string(),
+            role,
+        };
+        self.grants.insert(fingerprint(&token), grant);
+        Ok(token)
+    }
+
+    /// Who `token` signs in as, if it's one of ours.
+    pub fn check(&self, token: &str) -> Option<&Grant> {
+        self.grants.get(&fingerprint(token))
+    }
+
+    /// Take back every token for `username`. Returns how many there
+    /// were. Anyone already signed in with one stays so.
+    pub fn revoke(&mut self, username: &str) -> usize {
+        let before = self.grants.len();
+        self.grants.retain(|_, grant| grant.username != username);
+        before - self.grants.len()
+    }
+
+    /// Whether some token signs in as `name`, which keeps it from
+    /// guests the way an account does.
+    pub fn holds(&self, name: &str) -> bool {
+        self.grants.values().any(|grant| grant.username == name)
+    }
+
+    /// Every grant, by username, for the console.
+    pub fn list(&self) -> Vec<&Grant> {
+        let mut grants: Vec<&Grant> = self.grants.values().collect();
+        grants.sort_by(|a, b| a.username.cmp(&b.username));
+        grants
+    }
+
+    /// Read the tokens kept in `path`. A missing file means none yet.
+    ///
+    /// One per line, tab-separated:
+    ///
+    /// ```text
+    /// <user>  guest|user|op|admin  <sha256 of the token, in hex>
+    /// ```
+    pub fn load(path: &Path) -> Result<Self, ChatError> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut tokens = Self::new();
+        for (number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let bad =
+                || ChatError::Config(format!("{}:{}: bad token line", path.display(), number + 1));
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [username, role, digest] = fields.as_slice() else {
+                return Err(bad());
+            };
+            let role = Role::parse(role).ok_or_else(bad)?;
+            let grant = Grant {
+                username: username.to_string(),
+                role,
+            };
+            tokens.grants.insert(digest.to_string(), grant);
+        }
+        Ok(tokens)
+    }
+
+    /// Write every token out, replacing what was there.
+    pub async fn save(&self, path: &Path) -> Result<(), ChatError> {
+        let mut lines: Vec<String> = self
+            .grants
+            .iter()
+            .map(|(digest, grant)| format!("{}\t{}\t{digest}\n", grant.username, grant.role))
+            .collect();
+        lines.sort();
+        persistence::replace(path, lines.concat()).await
+    }
+}
+
+/// A fresh token: long, random, and hex so it survives any framing.
+pub fn random_token() -> Result<String, ChatError> {
+    let mut bytes = [0u8; TOKEN_LEN];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| ChatError::Config("no randomness for a token".into()))?;
+    Ok(hex(&bytes))
+}
+
+/// What's kept of a token: its SHA-256, in hex.
+fn fingerprint(token: &str) -> String {
+    hex(digest(&SHA256, token.as_bytes()).as_ref())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(text, "{byte:02x}");
+    }
+    text
+}
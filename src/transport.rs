@@ -0,0 +1,170 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream};
+
+/// The two directions of a client connection, whatever carries it.
+///
+/// Plain TCP, TLS and WebSocket are different types, but the chat code
+/// only needs "something to read lines from" and "something to write
+/// lines to". Boxing them as trait objects keeps handle_client (and
+/// everything it calls) free of a transport type parameter.
+pub type BoxedReader = Box<dyn AsyncRead + Send + Unpin>;
+pub type BoxedWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// A connection that's been accepted and unwrapped down to bytes.
+pub struct ClientStream {
+    pub peer: SocketAddr,
+    pub reader: BoxedReader,
+    pub writer: BoxedWriter,
+}
+
+impl ClientStream {
+    pub fn new<S>(stream: S, peer: SocketAddr) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
+        Self {
+            peer,
+            reader: Box::new(reader),
+            writer: Box::new(writer),
+        }
+    }
+
+    /// A connection with no socket under it: the server's end, and the
+    /// client's end of an in-memory pipe. Nothing above this line can
+    /// tell the difference, which is what lets a test run whole
+    /// sessions without a port.
+    pub fn pipe(peer: SocketAddr) -> (Self, DuplexStream) {
+        let (server_side, client_side) = tokio::io::duplex(64 * 1024);
+        (Self::new(server_side, peer), client_side)
+    }
+}
+
+/// How clients on one listener talk to us.
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub enum Transport {
+    /// Raw lines over TCP — telnet, netcat.
+    Plain,
+    /// Lines over TLS, with the certificate chain and key as PEM files.
+    /// Needs the `tls` feature.
+    Tls { cert: PathBuf, key: PathBuf },
+    /// One chat line per WebSocket text message, for browsers.
+    /// Needs the `websocket` feature.
+    WebSocket,
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transport::Plain => write!(f, "plain"),
+            Transport::Tls { .. } => write!(f, "tls"),
+            Transport::WebSocket => write!(f, "websocket"),
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+pub mod tls {
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use tokio_rustls::TlsAcceptor;
+    use tokio_rustls::rustls::ServerConfig;
+
+    use crate::error::ChatError;
+
+    /// Load a PEM certificate chain and private key into an acceptor.
+    /// Done once at startup, so a bad path fails loudly before anyone
+    /// connects.
+    pub fn acceptor(cert: &Path, key: &Path) -> Result<TlsAcceptor, ChatError> {
+        let open = |path: &Path| {
+            File::open(path)
+                .map(BufReader::new)
+                .map_err(|e| ChatError::Config(format!("{}: {e}", path.display())))
+        };
+
+        let certs = rustls_pemfile::certs(&mut open(cert)?)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| ChatError::Config(format!("{}: {e}", cert.display())))?;
+        let key = rustls_pemfile::private_key(&mut open(key)?)
+            .map_err(|e| ChatError::Config(format!("{}: {e}", key.display())))?
+            .ok_or_else(|| ChatError::Config(format!("{}: no private key", key.display())))?;
+
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| ChatError::Config(format!("TLS: {e}")))?;
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+#[cfg(feature = "websocket")]
+pub mod websocket {
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream};
+    use tokio::net::TcpStream;
+    use tokio_tungstenite::tungstenite::Message;
+
+    use crate::error::ChatError;
+
+    /// Complete the WebSocket upgrade and bridge it to a byte pipe.
+    ///
+    /// WebSocket is message-framed, the chat code is line-oriented. A
+    /// small task sits between them: each incoming text message becomes
+    /// a line on the pipe, and each line the server writes goes out as
+    /// one text message. The returned end of the pipe behaves like any
+    /// other stream.
+    pub async fn accept(stream: TcpStream) -> Result<DuplexStream, ChatError> {
+        let ws = tokio_tungstenite::accept_async(stream)
+            .await
+            .map_err(|e| ChatError::Parse(format!("websocket upgrade: {e}")))?;
+        let (server_side, client_side) = tokio::io::duplex(8 * 1024);
+
+        tokio::spawn(async move {
+            let (mut ws_tx, mut ws_rx) = ws.split();
+            let (pipe_rx, mut pipe_tx) = tokio::io::split(client_side);
+
+            let inbound = async {
+                while let Some(Ok(message)) = ws_rx.next().await {
+                    let mut bytes = match message {
+                        Message::Text(text) => text.into_bytes(),
+                        Message::Binary(bytes) => bytes,
+                        Message::Close(_) => break,
+                        _ => continue,
+                    };
+                    if bytes.last() != Some(&b'\n') {
+                        bytes.push(b'\n');
+                    }
+                    if pipe_tx.write_all(&bytes).await.is_err() {
+                        break;
+                    }
+                }
+            };
+
+            let outbound = async {
+                let mut lines = BufReader::new(pipe_rx).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if ws_tx.send(Message::Text(line)).await.is_err() {
+                        break;
+                    }
+                }
+                let _ = ws_tx.close().await;
+            };
+
+            // Either side finishing ends the session; dropping the pipe
+            // is what tells the chat loop the client is gone.
+            tokio::select! {
+                _ = inbound => {}
+                _ = outbound => {}
+            }
+        });
+
+        Ok(server_side)
+    }
+}
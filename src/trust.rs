@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// How far the server trusts a user, earned by sticking around and
+/// taking part. Ordered: New < Basic < Trusted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Tier {
+    New,
+    Basic,
+    Trusted,
+}
+
+impl fmt::Display for Tier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tier::New => write!(f, "new"),
+            Tier::Basic => write!(f, "basic"),
+            Tier::Trusted => write!(f, "trusted"),
+        }
+    }
+}
+
+/// Things a tier can unlock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    PostLinks,
+    CreateRooms,
+    SendDms,
+}
+
+impl Capability {
+    /// What the user was trying to do, for the refusal message.
+    pub fn describe(self) -> &'static str {
+        match self {
+            Capability::PostLinks => "post links",
+            Capability::CreateRooms => "create rooms",
+            Capability::SendDms => "send direct messages",
+        }
+    }
+}
+
+/// What it takes to reach a tier: both the age and the message count.
+#[derive(Debug, Clone, Copy)]
+pub struct Threshold {
+    pub age: Duration,
+    pub messages: u64,
+}
+
+/// The operator's rules: when tiers are reached and what each gates.
+#[derive(Debug, Clone)]
+pub struct TrustPolicy {
+    pub basic: Threshold,
+    pub trusted: Threshold,
+    /// The lowest tier allowed each capability. Anything missing is
+    /// open to everyone, so a server that configures nothing behaves
+    /// exactly as it did before tiers existed.
+    pub gates: HashMap<Capability, Tier>,
+    /// Most messages a day for each tier. A tier that isn't listed has
+    /// no quota — usually only the untrusted ones are.
+    pub quotas: HashMap<Tier, u64>,
+}
+
+impl Default for TrustPolicy {
+    fn default() -> Self {
+        Self {
+            basic: Threshold {
+                age: Duration::from_secs(10 * 60),
+                messages: 5,
+            },
+            trusted: Threshold {
+                age: Duration::from_secs(24 * 60 * 60),
+                messages: 100,
+            },
+            gates: HashMap::new(),
+            quotas: HashMap::new(),
+        }
+    }
+}
+
+/// A name's track record.
+struct Standing {
+    first_seen: Instant,
+    messages: u64,
+    /// Messages since the last daily reset.
+    today: u64,
+}
+
+/// Track records by username.
+///
+/// There are no accounts yet, so the name is the identity: it's what
+/// someone has to keep using to build up trust. Records outlive the
+/// connection, so reconnecting doesn't reset the clock.
+pub struct TrustLedger {
+    policy: TrustPolicy,
+    standings: HashMap<String, Standing>,
+}
+
+impl TrustLedger {
+    pub fn new(policy: TrustPolicy) -> Self {
+        Self {
+            policy,
+            standings: HashMap::new(),
+        }
+    }
+
+    /// Start the clock for `name` if it's never been seen. Returns true
+    /// if it hadn't been.
+    pub fn seen(&mut self, name: &str) -> bool {
+        if self.standings.contains_key(name) {
+            return false;
+        }
+        self.standings.insert(
+            name.to_string(),
+            Standing {
+                first_seen: Instant::now(),
+                messages: 0,
+                today: 0,
+            },
+        );
+        true
+    }
+
+    /// Whether `name` has ever connected.
+    pub fn knows(&self, name: &str) -> bool {
+        self.standings.contains_key(name)
+    }
+
+    pub fn record_message(&mut self, name: &str) {
+        self.seen(name);
+        if let Some(standing) = self.standings.get_mut(name) {
+            standing.messages += 1;
+            standing.today += 1;
+        }
+    }
+
+    /// Messages `name` has sent, ever.
+    pub fn messages(&self, name: &str) -> u64 {
+        self.standings.get(name).map_or(0, |s| s.messages)
+    }
+
+    pub fn tier(&self, name: &str) -> Tier {
+        let Some(standing) = self.standings.get(name) else {
+            return Tier::New;
+        };
+        let reached = |t: &Threshold| {
+            standing.first_seen.elapsed() >= t.age && standing.messages >= t.messages
+        };
+        if reached(&self.policy.trusted) {
+            Tier::Trusted
+        } else if reached(&self.policy.basic) {
+            Tier::Basic
+        } else {
+            Tier::New
+        }
+    }
+
+    /// If `name` has used up its tier's daily quota, the quota.
+    pub fn quota_reached(&self, name: &str) -> Option<u64> {
+        let quota = *self.policy.quotas.get(&self.tier(name))?;
+        let today = self.standings.get(name).map_or(0, |s| s.today);
+        (today >= quota).then_some(quota)
+    }
+
+    pub fn has_quotas(&self) -> bool {
+        !self.policy.quotas.is_empty()
+    }
+
+    /// Start a new day for everyone's quota.
+    pub fn reset_daily(&mut self) {
+        for standing in self.standings.values_mut() {
+            standing.today = 0;
+        }
+    }
+
+    /// The tier `capability` needs, if it's gated at all.
+    pub fn required(&self, capability: Capability) -> Option<Tier> {
+        self.policy.gates.get(&capability).copied()
+    }
+}
+
+/// Does `body` look like it contains a link?
+pub fn has_link(body: &str) -> bool {
+    body.split_whitespace().any(|word| {
+        let word = word.to_ascii_lowercase();
+        word.starts_with("http://") || word.starts_with("https://") || word.starts_with("www.")
+    })
+}
@@ -1,46 +1,72 @@
 use std::fmt;
 
+use crate::slab::Key;
+
 /// A unique identifier for a connected user.
 ///
-/// Wrapping `u64` in a newtype prevents accidentally passing a raw
+/// Wrapping the numbers in a newtype prevents accidentally passing a raw
 /// integer where a user ID is expected — the compiler catches it.
+///
+/// Generational: `index` is the user's slot, which is reused once they
+/// leave, and `generation` counts how many times that slot has been
+/// filled. An ID kept past its user's departure — in a scheduled task,
+/// a mute, a queued message — still names the old slot but not the new
+/// generation, so a lookup with it finds nobody instead of a stranger.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub struct UserId(u64);
+pub struct UserId {
+    index: u32,
+    generation: u32,
+}
+
+impl Key for UserId {
+    fn from_parts(index: usize, generation: u32) -> Self {
+        Self {
+            index: index as u32,
+            generation,
+        }
+    }
 
-impl UserId {
-    pub fn new(id: u64) -> Self {
-        Self(id)
+    fn index(self) -> usize {
+        self.index as usize
     }
 
-    /// Return the raw index for Vec-based lookup.
-    pub fn index(self) -> usize {
-        self.0 as usize
+    fn generation(self) -> u32 {
+        self.generation
     }
 }
 
 impl fmt::Display for UserId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "user#{}", self.0)
+        write!(f, "user#{}.{}", self.index, self.generation)
     }
 }
 
-/// A unique identifier for a chat room.
+/// A unique identifier for a chat room. Generational, like UserId.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub struct RoomId(u64);
+pub struct RoomId {
+    index: u32,
+    generation: u32,
+}
+
+impl Key for RoomId {
+    fn from_parts(index: usize, generation: u32) -> Self {
+        Self {
+            index: index as u32,
+            generation,
+        }
+    }
 
-impl RoomId {
-    pub fn new(id: u64) -> Self {
-        Self(id)
+    fn index(self) -> usize {
+        self.index as usize
     }
 
-    /// Return the raw index for Vec-based lookup.
-    pub fn index(self) -> usize {
-        self.0 as usize
+    fn generation(self) -> u32 {
+        self.generation
     }
 }
 
 impl fmt::Display for RoomId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "room#{}", self.0)
+        write!(f, "room#{}.{}", self.index, self.generation)
     }
 }
@@ -0,0 +1,84 @@
+use crate::error::ChatError;
+
+/// Characters with a job of their own: `:` separates wire fields, `#`
+/// marks a room in chat lines, `/` starts a command and separates the
+/// names in a DM room.
+const RESERVED: &[char] = &[':', '#', '/'];
+
+/// What happens when a newcomer asks for a name someone online has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateNames {
+    /// Refuse the connection with NickInUse.
+    #[default]
+    Reject,
+    /// Let them in as the first free alice2, alice3, ...
+    Suffix,
+}
+
+/// What a username may look like.
+///
+/// A name ends up inside server notices ("* alice joined #lobby"), so
+/// one carrying a line break, a bidi override or an invisible character
+/// could pass for something the server said. Whitespace is out
+/// altogether, not just at the ends: commands split their arguments on
+/// spaces, so "/kick a b" could never reach a user called "a b".
+#[derive(Debug, Clone, Copy)]
+pub struct NameRules {
+    /// Longest name, in characters rather than bytes.
+    pub max_len: usize,
+    /// Only printable ASCII. Off, any script is welcome.
+    pub ascii_only: bool,
+}
+
+impl Default for NameRules {
+    fn default() -> Self {
+        Self {
+            max_len: 32,
+            ascii_only: false,
+        }
+    }
+}
+
+impl NameRules {
+    /// Ok if `name` may be used. The error says which rule it broke,
+    /// without repeating the name — that's what's being guarded against.
+    pub fn check(&self, name: &str) -> Result<(), ChatError> {
+        let invalid = |why: String| Err(ChatError::InvalidName(why));
+        if name.is_empty() {
+            return invalid("a name can't be empty".into());
+        }
+        if name.chars().count() > self.max_len {
+            return invalid(format!("names are at most {} characters", self.max_len));
+        }
+        if name.chars().any(|c| c.is_control() || is_invisible(c)) {
+            return invalid("names can't contain control or invisible characters".into());
+        }
+        if name.chars().any(char::is_whitespace) {
+            return invalid("names can't contain spaces".into());
+        }
+        if self.ascii_only && !name.is_ascii() {
+            return invalid("names are limited to ASCII letters, digits and punctuation".into());
+        }
+        if let Some(c) = name.chars().find(|c| RESERVED.contains(c)) {
+            return invalid(format!("'{c}' isn't allowed in names"));
+        }
+        Ok(())
+    }
+}
+
+/// Format characters that take up no space but change how text around
+/// them displays: zero-width spaces and joiners, direction marks and
+/// overrides, the byte order mark. Not control characters to
+/// `char::is_control`, which only knows the C0 and C1 ranges.
+fn is_invisible(c: char) -> bool {
+    matches!(c,
+        '\u{00AD}'
+        | '\u{061C}'
+        | '\u{180E}'
+        | '\u{200B}'..='\u{200F}'
+        | '\u{202A}'..='\u{202E}'
+        | '\u{2060}'..='\u{2064}'
+        | '\u{2066}'..='\u{2069}'
+        | '\u{FEFF}'
+    )
+}
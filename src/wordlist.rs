@@ -0,0 +1,51 @@
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+
+use crate::filter::FilterContext;
+use crate::server::{AsyncFilter, FilterAction};
+
+/// Refuses messages containing any of a list of words.
+///
+/// Whole words, ignoring case: blocking "ass" mustn't stop anyone
+/// talking about their class. A word is a run of letters and digits,
+/// so "darn!" and "DARN" both count as "darn".
+pub struct WordFilter {
+    words: HashSet<String>,
+}
+
+impl WordFilter {
+    pub fn new<I, S>(words: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self {
+            words: words
+                .into_iter()
+                .map(|word| word.as_ref().to_lowercase())
+                .collect(),
+        }
+    }
+
+    fn matches(&self, body: &str) -> bool {
+        body.split(|c: char| !c.is_alphanumeric())
+            .any(|word| !word.is_empty() && self.words.contains(&word.to_lowercase()))
+    }
+}
+
+impl AsyncFilter for WordFilter {
+    fn apply<'a>(
+        &'a self,
+        ctx: &'a FilterContext<'a>,
+        body: &'a str,
+    ) -> Pin<Box<dyn Future<Output = FilterAction> + Send + 'a>> {
+        Box::pin(async move {
+            if self.matches(body) {
+                FilterAction::Block(format!("language not allowed in #{}", ctx.room))
+            } else {
+                FilterAction::Allow
+            }
+        })
+    }
+}
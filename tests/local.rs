@@ -0,0 +1,515 @@
+//! End-to-end tests over `connect_local`: real handshake, real command
+//! handling, real broadcast, no sockets.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use rust_chat_server::server::{connect_local, spawn_scheduler};
+use rust_chat_server::{Server, ServerConfig};
+use tokio::io::{
+    AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, Lines, ReadHalf, WriteHalf,
+};
+use tokio::sync::Mutex;
+
+struct Client {
+    lines: Lines<BufReader<ReadHalf<DuplexStream>>>,
+    writer: WriteHalf<DuplexStream>,
+}
+
+impl Client {
+    /// Connect from `port` on localhost, up to the username prompt.
+    async fn connect(server: &Arc<Mutex<Server>>, port: u16) -> Self {
+        let stream = connect_local(server, ([127, 0, 0, 1], port).into());
+        let (reader, writer) = tokio::io::split(stream);
+        let mut client = Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+        };
+        client.expect("Enter your username:").await;
+        client
+    }
+
+    /// Connect from `port` on localhost and sign in as `name`.
+    async fn join(server: &Arc<Mutex<Server>>, port: u16, name: &str) -> Self {
+        let mut client = Self::connect(server, port).await;
+        client.send(name).await;
+        client.expect(&format!("Welcome, {name}!")).await;
+        client
+    }
+
+    async fn send(&mut self, line: &str) {
+        self.writer
+            .write_all(format!("{line}\n").as_bytes())
+            .await
+            .unwrap();
+    }
+
+    /// Read until a line containing `needle` turns up, and return it.
+    async fn expect(&mut self, needle: &str) -> String {
+        self.read_until(needle).await.pop().unwrap()
+    }
+
+    /// Every line read up to and including one containing `needle`.
+    async fn read_until(&mut self, needle: &str) -> Vec<String> {
+        let wait = async {
+            let mut read = Vec::new();
+            loop {
+                let line = self
+                    .lines
+                    .next_line()
+                    .await
+                    .unwrap()
+                    .expect("connection closed");
+                let found = line.contains(needle);
+                read.push(line);
+                if found {
+                    return read;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), wait)
+            .await
+            .unwrap_or_else(|_| panic!("no line containing {needle:?}"))
+    }
+}
+
+fn server() -> Arc<Mutex<Server>> {
+    with_config(ServerConfig::builder().build())
+}
+
+/// A server as `run` would have it, short of the listener: timed
+/// work runs.
+fn with_config(config: ServerConfig) -> Arc<Mutex<Server>> {
+    let server = Arc::new(Mutex::new(Server::new(config).unwrap()));
+    spawn_scheduler(&server);
+    server
+}
+
+#[tokio::test]
+async fn message_reaches_the_other_client_in_the_room() {
+    let server = server();
+    let mut alice = Client::join(&server, 50001, "alice").await;
+    let mut bob = Client::join(&server, 50002, "bob").await;
+    alice.expect("bob joined #lobby").await;
+
+    alice.send("hello bob").await;
+
+    let line = bob.expect("hello bob").await;
+    assert!(line.contains("<alice>"), "unexpected frame: {line}");
+}
+
+#[tokio::test]
+async fn command_gets_its_reply() {
+    let server = server();
+    let mut alice = Client::join(&server, 50003, "alice").await;
+    let _bob = Client::join(&server, 50004, "bob").await;
+
+    alice.send("/who").await;
+
+    let line = alice.expect("In #lobby").await;
+    assert!(
+        line.contains("alice") && line.contains("bob"),
+        "unexpected reply: {line}"
+    );
+}
+
+#[tokio::test]
+async fn resume_replays_no_more_than_the_outbox_holds() {
+    let server = with_config(
+        ServerConfig::builder()
+            .outbox_size(16)
+            .disconnect_slow_clients(true)
+            .message_rate(1000.0, 100)
+            .build(),
+    );
+    let mut bob = Client::join(&server, 50005, "bob").await;
+
+    let mut alice = Client::connect(&server, 50006).await;
+    alice.send("CAP:resume").await;
+    alice.send("alice").await;
+    alice.expect("Welcome, alice!").await;
+    let session = alice.expect("SESSION:").await;
+    let token = session.trim_start_matches("SESSION:").to_string();
+    drop(alice);
+    bob.expect("alice").await;
+
+    for n in 1..=40 {
+        bob.send(&format!("message {n}")).await;
+        bob.expect(&format!("message {n}")).await;
+    }
+
+    let mut alice = Client::connect(&server, 50007).await;
+    alice.send(&format!("RESUME:{token}")).await;
+    let hint = alice.expect("weren't replayed").await;
+    assert!(
+        hint.contains("HISTORY:lobby:before="),
+        "unexpected hint: {hint}"
+    );
+    alice.expect("message 40").await;
+
+    // Still connected: nothing overflowed.
+    alice.send("/who").await;
+    alice.expect("In #lobby").await;
+}
+
+#[tokio::test]
+async fn shadow_muted_user_reaches_nobody() {
+    let server = with_config(ServerConfig::builder().oper_password("sekrit").build());
+    let mut alice = Client::join(&server, 50008, "alice").await;
+    let mut bob = Client::join(&server, 50009, "bob").await;
+    bob.send("/oper sekrit").await;
+    bob.send("/shadowmute alice").await;
+    bob.send("/who").await;
+    bob.expect("In #lobby").await;
+
+    alice.send("anyone there?").await;
+    alice.expect("<alice> anyone there?").await;
+    alice.send("/msg bob psst").await;
+    alice.expect("psst").await;
+
+    // Nothing from alice got through, and she left no gap in the room's
+    // numbering: bob's message is the room's first.
+    bob.send("just me").await;
+    let read = bob.read_until("just me").await;
+    assert!(
+        !read
+            .iter()
+            .any(|l| l.contains("alice>") || l.contains("psst")),
+        "leaked: {read:?}"
+    );
+    let line = read.last().unwrap();
+    assert!(line.contains("#lobby/1 <bob>"), "unexpected frame: {line}");
+}
+
+#[tokio::test]
+async fn banned_name_cannot_register() {
+    let server = with_config(ServerConfig::builder().oper_password("sekrit").build());
+    let mut bob = Client::join(&server, 50010, "bob").await;
+    let _mallory = Client::join(&server, 50011, "mallory").await;
+    bob.send("/oper sekrit").await;
+    bob.send("/ban mallory spam").await;
+    bob.expect("mallory is banned").await;
+
+    let mut mallory = Client::connect(&server, 50012).await;
+    mallory.send("REGISTER:mallory:hunter2hunter2").await;
+    mallory.expect("You are banned").await;
+
+    // Refused before the account was made, so there's none to sign in to.
+    let mut mallory = Client::connect(&server, 50013).await;
+    mallory.send("LOGIN:mallory:hunter2hunter2").await;
+    mallory.expect("authentication failed").await;
+}
+
+#[tokio::test]
+async fn account_read_markers_outlast_a_restart() {
+    let dir = std::env::temp_dir().join(format!("chat-markers-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = || {
+        ServerConfig::builder()
+            .accounts_file(dir.join("accounts"))
+            .history_file(dir.join("history"))
+            .read_markers_file(dir.join("read_markers"))
+            .build()
+    };
+    let open = || {
+        let mut server = Server::new(config()).unwrap();
+        server.open_storage().unwrap();
+        let server = Arc::new(Mutex::new(server));
+        spawn_scheduler(&server);
+        server
+    };
+
+    let server = open();
+    let mut alice = Client::connect(&server, 50014).await;
+    alice.send("REGISTER:alice:hunter2hunter2").await;
+    alice.expect("Welcome, alice!").await;
+    let mut bob = Client::join(&server, 50015, "bob").await;
+    bob.send("seen by alice").await;
+    alice.expect("seen by alice").await;
+    alice.send("/quit").await;
+    bob.expect("alice left").await;
+    bob.send("missed by alice").await;
+    // Stored only once delivered; the reply to a later line means
+    // it's been stored.
+    bob.send("/who").await;
+    bob.expect("In #lobby").await;
+    drop(bob);
+
+    let server = open();
+    let mut alice = Client::connect(&server, 50016).await;
+    alice.send("LOGIN:alice:hunter2hunter2").await;
+    let line = alice.expect("since you were last here").await;
+    assert!(
+        line.ends_with("limit=1 to catch up."),
+        "unexpected notice: {line}"
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn guest_read_markers_go_with_the_guest() {
+    let server = server();
+    let mut bob = Client::join(&server, 50017, "bob").await;
+    let mut carol = Client::join(&server, 50018, "carol").await;
+    carol.send("/quit").await;
+    bob.expect("carol left").await;
+    bob.send("after carol").await;
+    bob.expect("after carol").await;
+
+    // Someone else entirely, under the same name.
+    let mut carol = Client::join(&server, 50019, "carol").await;
+    carol.send("/who").await;
+    let read = carol.read_until("In #lobby").await;
+    assert!(
+        !read.iter().any(|l| l.contains("since you were last here")),
+        "inherited markers: {read:?}"
+    );
+}
+
+#[tokio::test]
+async fn rooms_come_back_with_a_new_server() {
+    let path = std::env::temp_dir().join(format!("chat-rooms-{}.toml", std::process::id()));
+    let config = || ServerConfig::builder().rooms_file(&path).build();
+
+    let server = with_config(config());
+    let mut alice = Client::join(&server, 50020, "alice").await;
+    alice.send("/join rust").await;
+    alice.expect("joined #rust").await;
+    alice.send("/topic All things Rust").await;
+    alice.expect("All things Rust").await;
+
+    let server = with_config(config());
+    let mut bob = Client::join(&server, 50021, "bob").await;
+    bob.send("/join rust").await;
+    bob.expect("Topic for #rust: All things Rust").await;
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn room_ban_outlasts_a_restart() {
+    let path = std::env::temp_dir().join(format!("chat-bans-{}.toml", std::process::id()));
+    let config = || ServerConfig::builder().rooms_file(&path).build();
+
+    let server = with_config(config());
+    let mut alice = Client::join(&server, 50022, "alice").await;
+    let mut mallory = Client::join(&server, 50023, "mallory").await;
+    alice.send("/join rust").await;
+    alice.expect("joined #rust").await;
+    mallory.send("/join rust").await;
+    alice.expect("mallory joined #rust").await;
+    alice.send("/roomban mallory spam").await;
+    alice.expect("mallory is banned from #rust").await;
+    mallory.expect("You were kicked from #rust").await;
+
+    let server = with_config(config());
+    let mut mallory = Client::join(&server, 50024, "mallory").await;
+    mallory.send("/join rust").await;
+    mallory.expect("You are banned from #rust (spam)").await;
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn mute_wears_off() {
+    let server = with_config(ServerConfig::builder().oper_password("sekrit").build());
+    let mut alice = Client::join(&server, 50025, "alice").await;
+    let mut bob = Client::join(&server, 50026, "bob").await;
+    bob.send("/oper sekrit").await;
+    bob.send("/mute alice 1s").await;
+    bob.expect("alice muted for 1s").await;
+    alice.expect("You have been muted for 1s").await;
+
+    alice.expect("You are no longer muted").await;
+    bob.expect("alice is no longer muted").await;
+}
+
+#[tokio::test]
+async fn history_requests_are_throttled_like_commands() {
+    let server = with_config(ServerConfig::builder().command_rate(0.1, 3).build());
+    let mut alice = Client::join(&server, 50027, "alice").await;
+
+    for _ in 0..4 {
+        alice.send("HISTORY:lobby").await;
+    }
+
+    alice.expect("ERROR 104").await;
+}
+
+#[tokio::test]
+async fn huge_duration_is_refused_not_fatal() {
+    let server = server();
+    let mut alice = Client::join(&server, 50028, "alice").await;
+
+    alice.send("/remind me 18446744073709551615 hi").await;
+    alice.expect("invalid duration").await;
+    alice.send("/remind me 31d hi").await;
+    alice.expect("invalid duration").await;
+
+    // Still here, and still the only alice.
+    alice.send("/who").await;
+    alice.expect("In #lobby: alice").await;
+
+    let scheduled = server.lock().await.schedule(Duration::MAX, |_| async {});
+    assert!(scheduled.is_none());
+}
+
+#[tokio::test]
+async fn huge_mute_is_refused_not_fatal() {
+    let server = with_config(ServerConfig::builder().oper_password("sekrit").build());
+    let mut alice = Client::join(&server, 50029, "alice").await;
+    let _bob = Client::join(&server, 50030, "bob").await;
+    alice.send("/oper sekrit").await;
+
+    alice.send("/mute bob 18446744073709551615").await;
+    alice.expect("ERROR").await;
+
+    alice.send("/who").await;
+    alice.expect("In #lobby").await;
+}
+
+#[tokio::test]
+async fn reminders_reach_only_rooms_the_sender_is_in() {
+    let server = server();
+    let mut alice = Client::join(&server, 50031, "alice").await;
+    let mut mallory = Client::join(&server, 50032, "mallory").await;
+    alice.send("/join secret").await;
+    alice.expect("joined #secret").await;
+
+    mallory.send("/remind #secret 1s boo").await;
+    mallory.expect("You're not in #secret").await;
+
+    alice.send("/remind #secret 1s standup").await;
+    alice.expect("remind #secret").await;
+    alice.expect("Reminder from alice: standup").await;
+}
+
+#[tokio::test]
+async fn pending_reminders_are_capped() {
+    let server = with_config(ServerConfig::builder().command_rate(100.0, 20).build());
+    let mut alice = Client::join(&server, 50033, "alice").await;
+
+    for n in 1..=10 {
+        alice.send(&format!("/remind me 1h note {n}")).await;
+        alice.expect("I'll remind you in 1h").await;
+    }
+    alice.send("/remind me 1h one too many").await;
+    alice.expect("10 reminders waiting already").await;
+}
+
+#[tokio::test]
+async fn guest_room_roles_go_with_the_guest() {
+    let server = server();
+    let mut alice = Client::join(&server, 50034, "alice").await;
+    let mut bob = Client::join(&server, 50035, "bob").await;
+    alice.send("/join rust").await;
+    alice.expect("joined #rust").await;
+    bob.send("/join rust").await;
+    alice.expect("bob joined #rust").await;
+    alice.send("/quit").await;
+    bob.expect("alice left").await;
+
+    // Someone else entirely, under the same name.
+    let mut alice = Client::join(&server, 50036, "alice").await;
+    alice.send("/join rust").await;
+    alice.expect("joined #rust").await;
+    alice.send("/kick bob").await;
+    alice.expect("ERROR 111").await;
+}
+
+#[tokio::test]
+async fn account_room_roles_outlast_the_session() {
+    let dir = std::env::temp_dir().join(format!("chat-roles-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let server = with_config(
+        ServerConfig::builder()
+            .accounts_file(dir.join("accounts"))
+            .build(),
+    );
+    server.lock().await.open_storage().unwrap();
+
+    let mut alice = Client::connect(&server, 50037).await;
+    alice.send("REGISTER:alice:hunter2hunter2").await;
+    alice.expect("Welcome, alice!").await;
+    let mut bob = Client::join(&server, 50038, "bob").await;
+    alice.send("/join rust").await;
+    alice.expect("joined #rust").await;
+    bob.send("/join rust").await;
+    alice.expect("bob joined #rust").await;
+    alice.send("/quit").await;
+    bob.expect("alice left").await;
+
+    let mut alice = Client::connect(&server, 50039).await;
+    alice.send("LOGIN:alice:hunter2hunter2").await;
+    alice.expect("Welcome, alice!").await;
+    alice.send("/join rust").await;
+    alice.expect("joined #rust").await;
+    alice.send("/op bob").await;
+    alice.expect("bob is now an operator of #rust").await;
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn corrupt_rooms_file_is_set_aside() {
+    let path = std::env::temp_dir().join(format!("chat-corrupt-{}.toml", std::process::id()));
+    std::fs::write(&path, "[[rooms]\nname = ").unwrap();
+
+    let server = with_config(ServerConfig::builder().rooms_file(&path).build());
+    Client::join(&server, 50040, "alice").await;
+
+    let aside = path.with_extension("toml.corrupt");
+    assert_eq!(
+        std::fs::read_to_string(&aside).unwrap(),
+        "[[rooms]\nname = "
+    );
+    assert!(!path.exists());
+    std::fs::remove_file(&aside).unwrap();
+}
+
+#[tokio::test]
+async fn one_vote_however_often_the_voter_reconnects() {
+    let server = server();
+    let mut alice = Client::join(&server, 50041, "alice").await;
+    let mut bob = Client::join(&server, 50042, "bob").await;
+    bob.send("/poll \"Lunch?\" pizza sushi").await;
+    alice.expect("Lunch?").await;
+    alice.send("/vote 1").await;
+    alice.expect("Vote counted for pizza").await;
+    alice.send("/quit").await;
+    bob.expect("alice left").await;
+
+    let mut alice = Client::join(&server, 50043, "alice").await;
+    alice.send("/vote 2").await;
+    alice.expect("already voted").await;
+    alice.send("/nick alicia").await;
+    alice.expect("alicia").await;
+    alice.send("/vote 2").await;
+    alice.expect("already voted").await;
+}
+
+#[tokio::test]
+async fn shared_file_comes_back_by_its_token() {
+    let server = server();
+    let mut alice = Client::join(&server, 50044, "alice").await;
+    let mut bob = Client::join(&server, 50045, "bob").await;
+    alice.send("FILE_START:notes.txt:5").await;
+    alice.send("FILE_CHUNK:hello").await;
+    alice.send("FILE_END:").await;
+    let line = bob.expect("shared notes.txt").await;
+    let token = line
+        .split("FILE_GET:")
+        .nth(1)
+        .and_then(|rest| rest.split_whitespace().next())
+        .unwrap();
+    assert_eq!(token.len(), 12, "unexpected token: {token}");
+    assert!(
+        token
+            .bytes()
+            .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+    );
+
+    bob.send(&format!("FILE_GET:{token}")).await;
+    bob.expect("FILE_CHUNK:hello").await;
+}